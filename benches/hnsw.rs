@@ -0,0 +1,67 @@
+//! Criterion benchmarks on pinned synthetic datasets: cargo bench --bench hnsw
+//!
+//! Datasets are generated from fixed seeds so results can be compared across versions with
+//! `cargo bench --bench hnsw -- --save-baseline v0.3.2` then `-- --baseline v0.3.2`.
+//! A JSON summary of build throughput, qps and recall is given by hnsw_rs::bench::export_baseline.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use rand::SeedableRng;
+
+use anndists::dist::DistL2;
+use hnsw_rs::datasets::synthetic;
+use hnsw_rs::prelude::*;
+
+const SEED: u64 = 4664397;
+const NB_POINT: usize = 10_000;
+const NB_QUERY: usize = 100;
+
+// gaussian mixture of NB_POINT points and NB_QUERY queries
+fn dataset(dim: usize) -> (Vec<Vec<f32>>, Vec<Vec<f32>>) {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(SEED);
+    let (mut data, _) = synthetic::gaussian_mixture(NB_POINT + NB_QUERY, dim, 16, 0.05, &mut rng);
+    let queries = data.split_off(NB_POINT);
+    (data, queries)
+}
+
+fn build(data: &[Vec<f32>]) -> Hnsw<'static, f32, DistL2> {
+    let hnsw = Hnsw::<f32, DistL2>::new(24, data.len(), 16, 200, DistL2 {});
+    let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+    hnsw.parallel_insert(&data_with_id);
+    hnsw
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let mut group = c.benchmark_group("insert");
+    group.sample_size(10);
+    for dim in [16, 128] {
+        let (data, _) = dataset(dim);
+        let data = &data[..2000];
+        group.bench_function(format!("parallel_insert d{} n2000", dim), |b| {
+            b.iter(|| build(data))
+        });
+    }
+    group.finish();
+}
+
+fn bench_search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("search");
+    for dim in [16, 128] {
+        let (data, queries) = dataset(dim);
+        let hnsw = build(&data);
+        for ef in [32, 128] {
+            group.bench_function(format!("search d{} k10 ef{}", dim, ef), |b| {
+                let mut q = queries.iter().cycle();
+                b.iter(|| hnsw.search(q.next().unwrap(), 10, ef))
+            });
+        }
+        let searcher = hnsw.into_searcher();
+        group.bench_function(format!("searcher d{} k10 ef32", dim), |b| {
+            let mut q = queries.iter().cycle();
+            b.iter(|| searcher.search(q.next().unwrap(), 10, 32))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_search);
+criterion_main!(benches);
@@ -0,0 +1,68 @@
+//! Index of time series with a banded DTW distance, compared with L2 on delayed queries.
+//! To run : cargo run --release --example dtw --features dtw
+
+use anndists::dist::*;
+use rand::Rng;
+use std::time::Instant;
+
+use hnsw_rs::dtw::DistDtw;
+use hnsw_rs::prelude::*;
+
+const SERIES_LEN: usize = 128;
+
+// a sum of 2 sines with noise
+fn generate(rng: &mut impl Rng) -> Vec<f32> {
+    let (f1, f2) = (rng.random_range(1. ..3.), rng.random_range(4. ..8.));
+    let (p1, p2) = (rng.random_range(0. ..6.28), rng.random_range(0. ..6.28));
+    (0..SERIES_LEN)
+        .map(|t| {
+            let x = t as f32 / SERIES_LEN as f32 * 6.28;
+            (f1 * x + p1).sin() + 0.3 * (f2 * x + p2).sin() + rng.random_range(-0.05..0.05)
+        })
+        .collect()
+}
+
+// the series delayed by delay steps, its first value repeated
+fn delay(series: &[f32], delay: usize) -> Vec<f32> {
+    std::iter::repeat_n(series[0], delay)
+        .chain(series[..series.len() - delay].iter().copied())
+        .collect()
+}
+
+fn recall<D: Distance<f32> + Send + Sync>(
+    hnsw: &Hnsw<f32, D>,
+    data: &[Vec<f32>],
+    nb_query: usize,
+) -> f32 {
+    let mut rng = rand::rng();
+    let nb_found = (0..nb_query)
+        .filter(|i| {
+            let query = delay(&data[*i], rng.random_range(1..8));
+            hnsw.search(&query, 10, 64)[0].d_id == *i
+        })
+        .count();
+    nb_found as f32 / nb_query as f32
+}
+
+fn main() {
+    let nb_series = 20000;
+    let nb_query = 1000;
+    let mut rng = rand::rng();
+    let data: Vec<Vec<f32>> = (0..nb_series).map(|_| generate(&mut rng)).collect();
+    let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+    //
+    let start = Instant::now();
+    let hnsw_dtw = Hnsw::<f32, DistDtw>::new(24, nb_series, 16, 200, DistDtw::with_band(10));
+    hnsw_dtw.parallel_insert(&data_with_id);
+    println!("dtw index built in {:?}", start.elapsed());
+    let start = Instant::now();
+    let hnsw_l2 = Hnsw::<f32, DistL2>::new(24, nb_series, 16, 200, DistL2 {});
+    hnsw_l2.parallel_insert(&data_with_id);
+    println!("l2 index built in {:?}", start.elapsed());
+    // queries are series of the index delayed by 1 to 7 steps
+    println!(
+        "original series found first for delayed queries, dtw : {:.3}, l2 : {:.3}",
+        recall(&hnsw_dtw, &data, nb_query),
+        recall(&hnsw_l2, &data, nb_query)
+    );
+}
@@ -0,0 +1,229 @@
+//! Per point access statistics for hot/cold tiering.
+//!
+//! With [Hnsw::set_access_stats] the structure counts, for each point, the searches that visited it (computed its
+//! distance to the query in layer 0) and the searches that returned it. Counts are atomics by layer and rank,
+//! a search takes a read lock and does not allocate. Searches counted are the ones of [Hnsw::search_with_params]
+//! (so [Hnsw::search], [Hnsw::search_filter], [Hnsw::parallel_search]) and of the searches on codes
+//! ([Hnsw::search_asymmetric], and [Hnsw::search_reranked] which returns its ef candidates before reranking).
+//! A point inserted in the slot of a deleted one starts from 0.
+//!
+//! [Hnsw::access_stats] gives the counts, [Hnsw::hot_ids] the ids of the points most often returned.
+//! On a quantized index searched by [Hnsw::search_reranked], the hot ids preload a
+//! [CachedSource](crate::rerank::CachedSource) (see [CachedSource::preload](crate::rerank::CachedSource::preload)):
+//! hot vectors are kept in memory in full precision, cold ones stay on disk and are only seen through their codes.
+//! [Hnsw::decay_access_stats] halves counts so that the hot set follows changes of the queries.
+
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Neighbour, PointId};
+
+/// accesses to a point, see module doc
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PointAccess {
+    pub d_id: DataId,
+    pub p_id: PointId,
+    /// searches that computed the distance of the point to their query
+    pub nb_visited: u32,
+    /// searches that returned the point
+    pub nb_returned: u32,
+}
+
+#[derive(Default)]
+struct Counts {
+    visited: AtomicU32,
+    returned: AtomicU32,
+}
+
+/// counts by layer and rank in layer
+#[derive(Default)]
+pub(crate) struct AccessCounts {
+    layers: RwLock<Vec<Vec<Counts>>>,
+}
+
+impl AccessCounts {
+    // applies f to the counts of ids, the tables grow to the largest rank
+    fn with_counts<'a, I, F>(&self, ids: I, f: F)
+    where
+        I: Iterator<Item = &'a PointId> + Clone,
+        F: Fn(&Counts),
+    {
+        {
+            let layers = self.layers.read();
+            let present = |p_id: &PointId| {
+                layers
+                    .get(p_id.0 as usize)
+                    .is_some_and(|l| (p_id.1 as usize) < l.len())
+            };
+            if ids.clone().all(present) {
+                ids.for_each(|p_id| f(&layers[p_id.0 as usize][p_id.1 as usize]));
+                return;
+            }
+        }
+        let mut layers = self.layers.write();
+        for p_id in ids {
+            let (layer, rank) = (p_id.0 as usize, p_id.1 as usize);
+            if layers.len() <= layer {
+                layers.resize_with(layer + 1, Vec::new);
+            }
+            if layers[layer].len() <= rank {
+                layers[layer].resize_with(rank + 1, Counts::default);
+            }
+            f(&layers[layer][rank]);
+        }
+    }
+
+    pub(crate) fn count_visited<'a, I>(&self, ids: I)
+    where
+        I: Iterator<Item = &'a PointId> + Clone,
+    {
+        self.with_counts(ids, |c| {
+            c.visited.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    pub(crate) fn count_returned(&self, neighbours: &[Neighbour]) {
+        self.with_counts(neighbours.iter().map(|n| &n.p_id), |c| {
+            c.returned.fetch_add(1, Ordering::Relaxed);
+        });
+    }
+
+    // counts of a slot taken by a new point
+    pub(crate) fn reset(&self, p_id: PointId) {
+        self.with_counts(std::iter::once(&p_id), |c| {
+            c.visited.store(0, Ordering::Relaxed);
+            c.returned.store(0, Ordering::Relaxed);
+        });
+    }
+
+    fn get(&self, p_id: PointId) -> (u32, u32) {
+        self.layers
+            .read()
+            .get(p_id.0 as usize)
+            .and_then(|l| l.get(p_id.1 as usize))
+            .map_or((0, 0), |c| {
+                (
+                    c.visited.load(Ordering::Relaxed),
+                    c.returned.load(Ordering::Relaxed),
+                )
+            })
+    }
+} // end of impl AccessCounts
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// starts (or stops and forgets) counting accesses to points, see module doc
+    pub fn set_access_stats(&mut self, on: bool) {
+        self.access_stats = on.then(AccessCounts::default);
+    }
+
+    /// accesses to each point not deleted, in the order of the point indexation. Empty if counts are not set.
+    pub fn access_stats(&self) -> Vec<PointAccess> {
+        let Some(access) = &self.access_stats else {
+            return Vec::new();
+        };
+        self.get_point_indexation()
+            .into_iter()
+            .filter(|point| !self.is_deleted(point))
+            .map(|point| {
+                let p_id = point.get_point_id();
+                let (nb_visited, nb_returned) = access.get(p_id);
+                PointAccess {
+                    d_id: point.get_origin_id(),
+                    p_id,
+                    nb_visited,
+                    nb_returned,
+                }
+            })
+            .collect()
+    }
+
+    /// ids of the fraction (in \[0, 1\]) of points most often returned, then visited. Points never accessed are not hot.
+    pub fn hot_ids(&self, fraction: f32) -> Vec<DataId> {
+        assert!(
+            (0. ..=1.).contains(&fraction),
+            "hot fraction must be in [0, 1]"
+        );
+        let mut stats = self.access_stats();
+        let nb_hot = (fraction * stats.len() as f32).ceil() as usize;
+        stats.retain(|a| a.nb_visited > 0 || a.nb_returned > 0);
+        stats.sort_unstable_by(|a, b| {
+            (b.nb_returned, b.nb_visited, a.d_id).cmp(&(a.nb_returned, a.nb_visited, b.d_id))
+        });
+        stats.truncate(nb_hot);
+        stats.into_iter().map(|a| a.d_id).collect()
+    }
+
+    /// halves all counts
+    pub fn decay_access_stats(&self) {
+        if let Some(access) = &self.access_stats {
+            for counts in access.layers.read().iter().flatten() {
+                for c in [&counts.visited, &counts.returned] {
+                    c.store(c.load(Ordering::Relaxed) / 2, Ordering::Relaxed);
+                }
+            }
+        }
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::rerank::{CachedSource, VectorFile, VectorSource};
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_access_stats() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(985);
+        let data = synthetic::uniform(2000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 2000, 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        assert!(hnsw.access_stats().is_empty());
+        hnsw.set_access_stats(true);
+        // queries hit the first 20 points
+        let queries: Vec<Vec<f32>> = (0..20).map(|i| data[i].clone()).collect();
+        for _ in 0..5 {
+            hnsw.parallel_search(&queries, 1, 32);
+        }
+        let stats = hnsw.access_stats();
+        assert_eq!(stats.len(), 2000);
+        assert!(stats.iter().all(|a| a.nb_visited >= a.nb_returned));
+        let nb_returned: u32 = stats.iter().map(|a| a.nb_returned).sum();
+        assert_eq!(nb_returned, 100);
+        assert!(stats.iter().filter(|a| a.nb_visited > 0).count() > 20);
+        let hot = hnsw.hot_ids(0.01);
+        assert_eq!(hot.len(), 20);
+        assert!(hot.iter().filter(|d_id| **d_id < 20).count() >= 18);
+        // counts are halved
+        let before: Vec<u32> = stats.iter().map(|a| a.nb_visited).collect();
+        hnsw.decay_access_stats();
+        assert!(
+            hnsw.access_stats()
+                .iter()
+                .zip(&before)
+                .all(|(a, b)| a.nb_visited == b / 2)
+        );
+        // the hot set preloads a cache
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("vectors.bin");
+        VectorFile::write(&path, &data).unwrap();
+        let cache = CachedSource::new(VectorFile::open(&path, 8).unwrap(), 0.01, 2000);
+        cache.preload(&hot).unwrap();
+        let vectors = cache.get_vectors(&hot).unwrap();
+        assert_eq!(vectors[0], data[hot[0]]);
+        assert_eq!(cache.get_stats().nb_hit, 20);
+        // stopped counts are forgotten
+        hnsw.set_access_stats(false);
+        assert!(hnsw.hot_ids(0.5).is_empty());
+    } // end of test_access_stats
+} // end of mod tests
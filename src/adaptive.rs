@@ -0,0 +1,258 @@
+//! Number of connections adapted to the local density of points (experimental).
+//!
+//! With [ConnectionStrategy::DensityAdaptive] set by [Hnsw::set_connection_strategy], the number of neighbours
+//! of a point in layer 0 depends on the distance r from the point to its max_nb_connection-th nearest candidate
+//! found at insertion, compared to the mean m of this distance over points inserted before.
+//! The point gets about max_nb_connection * r / m neighbours, between min_nb_connection and
+//! 2 * max_nb_connection (the usual number). The first [WARMUP] points keep 2 * max_nb_connection.
+//!
+//! Points in dense regions (inside clusters) get fewer links and isolated points keep more, so on clustered data
+//! the graph is smaller for a similar recall. The bound of a point also holds for neighbours added by later
+//! insertions. Upper layers are unchanged.
+//!
+//! [Hnsw::get_degree_stats] describes layer 0 degrees, to compare an index built with the default strategy
+//! and one built with the adaptive strategy. Bounds are not kept in dumps nor by [Hnsw::extract]: there
+//! points use 2 * max_nb_connection again.
+
+use parking_lot::{Mutex, RwLock};
+use serde::{Deserialize, Serialize};
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use hashbrown::HashMap;
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, PointId, PointWithOrder};
+
+/// number of insertions done with 2 * max_nb_connection neighbours before bounds are adapted
+pub const WARMUP: usize = 100;
+
+/// number of neighbours of points in layer 0
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub enum ConnectionStrategy {
+    /// 2 * max_nb_connection neighbours for all points (default)
+    #[default]
+    Fixed,
+    /// between min_nb_connection and 2 * max_nb_connection neighbours depending on density, see module doc
+    DensityAdaptive { min_nb_connection: usize },
+}
+
+/// layer 0 degrees of a structure, see [Hnsw::get_degree_stats]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DegreeStats {
+    pub nb_point: usize,
+    /// number of links in layer 0
+    pub nb_link: usize,
+    pub min_degree: usize,
+    pub max_degree: usize,
+    pub mean_degree: f32,
+}
+
+/// state of the adaptive strategy
+pub(crate) struct AdaptiveDegree {
+    min_nb_connection: usize,
+    // sum and number of distances to the max_nb_connection-th candidate
+    radii: Mutex<(f64, usize)>,
+    // bounds of points that got less than 2 * max_nb_connection neighbours
+    bounds: RwLock<HashMap<PointId, u16>>,
+}
+
+impl Clone for AdaptiveDegree {
+    fn clone(&self) -> Self {
+        AdaptiveDegree {
+            min_nb_connection: self.min_nb_connection,
+            radii: Mutex::new(*self.radii.lock()),
+            bounds: RwLock::new(self.bounds.read().clone()),
+        }
+    }
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// sets the number of neighbours of points in layer 0 for next insertions, see module doc.
+    /// min_nb_connection of [ConnectionStrategy::DensityAdaptive] must be in 1..=2 * max_nb_connection.
+    pub fn set_connection_strategy(&mut self, strategy: ConnectionStrategy) {
+        self.adaptive = match strategy {
+            ConnectionStrategy::Fixed => None,
+            ConnectionStrategy::DensityAdaptive { min_nb_connection } => {
+                assert!(
+                    min_nb_connection >= 1 && min_nb_connection <= 2 * self.max_nb_connection,
+                    "min_nb_connection must be in 1..=2 * max_nb_connection"
+                );
+                Some(AdaptiveDegree {
+                    min_nb_connection,
+                    radii: Mutex::new((0., 0)),
+                    bounds: RwLock::new(HashMap::new()),
+                })
+            }
+        };
+    }
+
+    pub fn get_connection_strategy(&self) -> ConnectionStrategy {
+        match &self.adaptive {
+            None => ConnectionStrategy::Fixed,
+            Some(adaptive) => ConnectionStrategy::DensityAdaptive {
+                min_nb_connection: adaptive.min_nb_connection,
+            },
+        }
+    }
+
+    /// maximum number of neighbours of point p_id at layer
+    #[inline]
+    pub(crate) fn get_max_nb_neighbours(&self, p_id: PointId, layer: usize) -> usize {
+        if layer > 0 {
+            return self.max_nb_connection;
+        }
+        match &self.adaptive {
+            None => 2 * self.max_nb_connection,
+            Some(adaptive) => adaptive
+                .bounds
+                .read()
+                .get(&p_id)
+                .map_or(2 * self.max_nb_connection, |bound| *bound as usize),
+        }
+    }
+
+    /// computes and records the layer 0 bound of point p_id from its candidates at insertion
+    /// (a heap with negative distances, as given to select_neighbours)
+    pub(crate) fn adapt_nb_neighbours(
+        &self,
+        p_id: PointId,
+        candidates: &BinaryHeap<Arc<PointWithOrder<T>>>,
+    ) -> usize {
+        let max_nb = 2 * self.max_nb_connection;
+        let Some(adaptive) = &self.adaptive else {
+            return max_nb;
+        };
+        if candidates.is_empty() {
+            return max_nb;
+        }
+        let mut dists: Vec<f32> = candidates.iter().map(|c| -c.dist_to_ref).collect();
+        let k = self.max_nb_connection.min(dists.len()) - 1;
+        let r = *dists.select_nth_unstable_by(k, f32::total_cmp).1 as f64;
+        let mean = {
+            let mut radii = adaptive.radii.lock();
+            let mean = radii.0 / radii.1.max(1) as f64;
+            let warm = radii.1 >= WARMUP;
+            radii.0 += r;
+            radii.1 += 1;
+            if !warm {
+                return max_nb;
+            }
+            mean
+        };
+        let bound = if mean > 0. {
+            ((self.max_nb_connection as f64 * r / mean).round() as usize)
+                .clamp(adaptive.min_nb_connection, max_nb)
+        } else {
+            max_nb
+        };
+        let mut bounds = adaptive.bounds.write();
+        if bound < max_nb {
+            bounds.insert(p_id, bound as u16);
+        } else {
+            bounds.remove(&p_id);
+        }
+        bound
+    }
+
+    /// returns the number of points and of links in layer 0 and the spread of layer 0 degrees
+    pub fn get_degree_stats(&self) -> DegreeStats {
+        let layers = self.layer_indexed_points.points_by_layer.read();
+        let (nb_point, nb_link, min_degree, max_degree) = layers
+            .par_iter()
+            .flat_map_iter(|layer| layer.iter())
+            .map(|p| {
+                let degree = p.neighbours.read()[0].len();
+                (1, degree, degree, degree)
+            })
+            .reduce(
+                || (0, 0, usize::MAX, 0),
+                |a, b| (a.0 + b.0, a.1 + b.1, a.2.min(b.2), a.3.max(b.3)),
+            );
+        if nb_point == 0 {
+            return DegreeStats::default();
+        }
+        DegreeStats {
+            nb_point,
+            nb_link,
+            min_degree,
+            max_degree,
+            mean_degree: nb_link as f32 / nb_point as f32,
+        }
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_density_adaptive() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(954);
+        // tight clusters and a uniform background
+        let (mut data, _) = synthetic::gaussian_mixture(4500, 16, 20, 0.01, &mut rng);
+        data.extend(synthetic::uniform(500, 16, &mut rng));
+        // serial builds with seeded levels, so that the recalls below are reproducible
+        let build = |strategy: ConnectionStrategy| {
+            let mut hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 200, DistL2 {});
+            hnsw.set_connection_strategy(strategy);
+            hnsw.set_level_seed(954);
+            for (i, v) in data.iter().enumerate() {
+                hnsw.insert((v, i));
+            }
+            hnsw
+        };
+        let fixed = build(ConnectionStrategy::Fixed);
+        let strategy = ConnectionStrategy::DensityAdaptive {
+            min_nb_connection: 8,
+        };
+        let adaptive = build(strategy);
+        assert_eq!(adaptive.get_connection_strategy(), strategy);
+        let (before, after) = (fixed.get_degree_stats(), adaptive.get_degree_stats());
+        log::info!("degrees fixed {:?}, adaptive {:?}", before, after);
+        assert_eq!(after.nb_point, 5000);
+        assert!(after.max_degree <= 32);
+        assert!(after.nb_link < before.nb_link, "{:?} {:?}", before, after);
+        // no list grew above its bound
+        for p in adaptive.get_point_indexation() {
+            let bound = adaptive.get_max_nb_neighbours(p.get_point_id(), 0);
+            assert!(p.neighbours.read()[0].len() <= bound);
+        }
+        // background points can keep more links than clustered ones
+        let mean_bound = |range: std::ops::Range<usize>| {
+            let nb = range.len() as f32;
+            adaptive
+                .get_point_indexation()
+                .iter_ids()
+                .filter(|(_, d_id)| range.contains(d_id))
+                .map(|(p_id, _)| adaptive.get_max_nb_neighbours(p_id, 0))
+                .sum::<usize>() as f32
+                / nb
+        };
+        assert!(mean_bound(4500..5000) > 2. * mean_bound(0..4500));
+        // a recall close to the one of the fixed strategy
+        let nb_found = |hnsw: &Hnsw<f32, DistL2>| {
+            (0..5000)
+                .step_by(10)
+                .filter(|i| hnsw.search(&data[*i], 10, 64)[0].d_id == *i)
+                .count()
+        };
+        let (nb_fixed, nb_adaptive) = (nb_found(&fixed), nb_found(&adaptive));
+        assert!(
+            nb_adaptive + 25 >= nb_fixed && nb_adaptive >= 450,
+            "nb_found fixed {} adaptive {} / 500",
+            nb_fixed,
+            nb_adaptive
+        );
+    } // end of test_density_adaptive
+} // end of mod tests
@@ -0,0 +1,176 @@
+//! Alias ids: several external ids resolving to one stored point.
+//!
+//! [Hnsw::alias] maps a new id to an existing point (its canonical id) without storing the vector again.
+//! Deduplication (see [crate::dedup]) records duplicates the same way.
+//! Searches report canonical ids, [Hnsw::search_with_aliases] reports each neighbour followed by its aliases.
+//!
+//! Aliases are saved in dumps (format v7).
+
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, HnswError, Neighbour};
+
+/// alias -> canonical id and canonical id -> aliases
+#[derive(Default, Clone)]
+pub(crate) struct AliasTable {
+    canonical: HashMap<DataId, DataId>,
+    aliases: HashMap<DataId, Vec<DataId>>,
+}
+
+impl AliasTable {
+    pub(crate) fn insert(&mut self, alias: DataId, canonical: DataId) {
+        self.canonical.insert(alias, canonical);
+        self.aliases.entry(canonical).or_default().push(alias);
+    }
+
+    pub(crate) fn get_canonical(&self, id: DataId) -> Option<DataId> {
+        self.canonical.get(&id).copied()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.canonical.len()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.canonical.is_empty()
+    }
+
+    /// pairs (alias, canonical) as u64, in native endianness as the rest of the dump
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(16 * self.canonical.len());
+        for (alias, canonical) in &self.canonical {
+            bytes.extend_from_slice(&(*alias as u64).to_ne_bytes());
+            bytes.extend_from_slice(&(*canonical as u64).to_ne_bytes());
+        }
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        if !bytes.len().is_multiple_of(16) {
+            return Err(anyhow!(
+                "alias block of dump has bad length {}",
+                bytes.len()
+            ));
+        }
+        let mut table = AliasTable::default();
+        for pair in bytes.chunks_exact(16) {
+            let alias = u64::from_ne_bytes(pair[..8].try_into().unwrap());
+            let canonical = u64::from_ne_bytes(pair[8..].try_into().unwrap());
+            table.insert(alias as DataId, canonical as DataId);
+        }
+        Ok(table)
+    }
+} // end of impl AliasTable
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// makes new_id resolve to the point of existing_id (or to its canonical id if existing_id is itself an alias).
+    /// Returns the canonical id.
+    /// Fails if new_id is already an alias, with [HnswError::ReadOnly] if the index is read only.
+    /// existing_id is not checked to be a stored point.
+    pub fn alias(&self, existing_id: DataId, new_id: DataId) -> anyhow::Result<DataId> {
+        if self.read_only {
+            return Err(HnswError::ReadOnly.into());
+        }
+        let mut table = self.aliases.write();
+        let canonical = table.get_canonical(existing_id).unwrap_or(existing_id);
+        if let Some(other) = table.get_canonical(new_id) {
+            return Err(anyhow!("id {} is already an alias of {}", new_id, other));
+        }
+        if canonical == new_id {
+            return Err(anyhow!("id {} cannot be an alias of itself", new_id));
+        }
+        table.insert(new_id, canonical);
+        Ok(canonical)
+    }
+
+    /// returns the id of the stored point an id resolves to (the id itself if it is not an alias)
+    pub fn get_canonical_id(&self, origin_id: DataId) -> DataId {
+        self.aliases
+            .read()
+            .get_canonical(origin_id)
+            .unwrap_or(origin_id)
+    }
+
+    /// returns the aliases of a stored point
+    pub fn get_aliases(&self, canonical_id: DataId) -> Vec<DataId> {
+        self.aliases
+            .read()
+            .aliases
+            .get(&canonical_id)
+            .cloned()
+            .unwrap_or_default()
+    }
+
+    /// number of alias ids
+    pub fn get_nb_aliases(&self) -> usize {
+        self.aliases.read().len()
+    }
+
+    /// each neighbour is followed by its aliases, with same distance and point id
+    pub fn expand_aliases(&self, neighbours: &[Neighbour]) -> Vec<Neighbour> {
+        let table = self.aliases.read();
+        let mut expanded = Vec::with_capacity(neighbours.len());
+        for n in neighbours {
+            expanded.push(*n);
+            if let Some(aliases) = table.aliases.get(&n.d_id) {
+                expanded.extend(
+                    aliases
+                        .iter()
+                        .map(|a| Neighbour::new(*a, n.distance, n.p_id)),
+                );
+            }
+        }
+        expanded
+    }
+
+    /// search as [Hnsw::search] but reports all alias ids of the knbn points found
+    pub fn search_with_aliases(&self, data: &[T], knbn: usize, ef_arg: usize) -> Vec<Neighbour> {
+        self.expand_aliases(&self.search(data, knbn, ef_arg))
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::hnswio::HnswIo;
+    use anndists::dist::DistL1;
+
+    #[test]
+    fn test_alias_dump_reload() {
+        let hnsw = Hnsw::<f32, DistL1>::new(16, 100, 16, 100, DistL1 {});
+        let data: Vec<Vec<f32>> = (0..100).map(|i| vec![i as f32, 0.5, -1.]).collect();
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        assert_eq!(hnsw.alias(10, 1000).unwrap(), 10);
+        // alias of an alias resolves to the canonical id
+        assert_eq!(hnsw.alias(1000, 1001).unwrap(), 10);
+        assert!(hnsw.alias(20, 1000).is_err());
+        let found: Vec<DataId> = hnsw
+            .search_with_aliases(&data[10], 1, 32)
+            .iter()
+            .map(|n| n.d_id)
+            .collect();
+        assert_eq!(found, vec![10, 1000, 1001]);
+        //
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "alias").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "alias");
+        let reloaded: Hnsw<f32, DistL1> = reloader.load_hnsw().unwrap();
+        assert_eq!(reloaded.get_nb_aliases(), 2);
+        assert_eq!(reloaded.get_canonical_id(1001), 10);
+        let mut aliases = reloaded.get_aliases(10);
+        aliases.sort_unstable();
+        assert_eq!(aliases, vec![1000, 1001]);
+    } // end of test_alias_dump_reload
+} // end of mod tests
@@ -17,6 +17,18 @@ pub trait AnnT {
     fn insert_data(&mut self, data: &[Self::Val], id: usize);
     //
     fn search_neighbours(&self, data: &[Self::Val], knbn: usize, ef_s: usize) -> Vec<Neighbour>;
+    /// as search_neighbours, neighbours are written in out (cleared first).
+    /// The default calls search_neighbours, implementations can avoid its allocation.
+    fn search_neighbours_into(
+        &self,
+        data: &[Self::Val],
+        knbn: usize,
+        ef_s: usize,
+        out: &mut Vec<Neighbour>,
+    ) {
+        out.clear();
+        out.extend(self.search_neighbours(data, knbn, ef_s));
+    }
     //
     fn parallel_insert_data(&mut self, data: &[(&Vec<Self::Val>, usize)]);
     //
@@ -50,6 +62,15 @@ where
     fn search_neighbours(&self, data: &[T], knbn: usize, ef_s: usize) -> Vec<Neighbour> {
         self.search(data, knbn, ef_s)
     }
+    fn search_neighbours_into(
+        &self,
+        data: &[T],
+        knbn: usize,
+        ef_s: usize,
+        out: &mut Vec<Neighbour>,
+    ) {
+        self.search_into(data, knbn, ef_s, out);
+    }
     fn parallel_insert_data(&mut self, data: &[(&Vec<Self::Val>, usize)]) {
         self.parallel_insert(data);
     }
@@ -71,17 +92,11 @@ where
         //
         // do not overwrite if mmap is active
         let overwrite = !self.get_datamap_opt();
-        let mut dumpinit = DumpInit::new(path, file_basename, overwrite);
+        let dumpinit = DumpInit::new(path, file_basename, overwrite);
         let dumpname = dumpinit.get_basename().clone();
         //
-        let res = self.dump(DumpMode::Full, &mut dumpinit);
-        //
-        dumpinit.flush()?;
+        self.dump_to_writers(&dumpinit.graph_out, &dumpinit.data_out)?;
         info!("\n End of dump, file basename : {}\n", &dumpname);
-        if res.is_ok() {
-            Ok(dumpname)
-        } else {
-            Err(anyhow::anyhow!("unexpected error"))
-        }
+        Ok(dumpname)
     } // end of dump
 } // end of impl block AnnT for Hnsw<T,D>
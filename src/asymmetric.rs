@@ -0,0 +1,170 @@
+//! Search with queries of a type different from the type of stored data.
+//!
+//! When vectors are stored compressed (u8 codes of a scalar quantization ...) queries are still f32 vectors.
+//! An [AsymmetricDistance] prepares a query once (conversion, lookup table) and then evaluates the
+//! distance from the prepared query to stored codes. See [Hnsw::search_asymmetric].
+//!
+//! [Sq8L2] is an example: a scalar quantizer to u8 with a L2 distance computed via a lookup table.
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Neighbour};
+use crate::searchparams::SearchParams;
+
+/// Distance between a query of type Q and stored data of type T.
+pub trait AsymmetricDistance<Q, T>: Send + Sync {
+    /// the query after conversion (or its lookup table)
+    type Prepared;
+    /// done once by query
+    fn prepare(&self, query: &[Q]) -> Self::Prepared;
+    /// distance between prepared query and stored data
+    fn eval(&self, prepared: &Self::Prepared, stored: &[T]) -> f32;
+}
+
+/// A scalar quantizer of f32 to u8 : value = min + code * step.
+/// The L2 distance to an f32 query is computed with a table of dim * 256 squared differences
+/// built once by query.
+#[derive(Copy, Clone, Debug)]
+pub struct Sq8L2 {
+    min: f32,
+    step: f32,
+}
+
+impl Sq8L2 {
+    /// quantizer on values in \[min, max\]
+    pub fn new(min: f32, max: f32) -> Self {
+        assert!(max > min, "Sq8L2 needs max > min");
+        Sq8L2 {
+            min,
+            step: (max - min) / 255.,
+        }
+    }
+
+    /// quantizer with range of values in data
+    pub fn from_data(data: &[Vec<f32>]) -> Self {
+        let (min, max) = data
+            .iter()
+            .flatten()
+            .fold((f32::MAX, f32::MIN), |(m, mx), &x| (m.min(x), mx.max(x)));
+        Sq8L2::new(min, max.max(min + f32::EPSILON))
+    }
+
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        v.iter()
+            .map(|x| ((x - self.min) / self.step).round().clamp(0., 255.) as u8)
+            .collect()
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        codes
+            .iter()
+            .map(|&c| self.min + c as f32 * self.step)
+            .collect()
+    }
+} // end of impl Sq8L2
+
+impl AsymmetricDistance<f32, u8> for Sq8L2 {
+    type Prepared = Vec<f32>;
+
+    fn prepare(&self, query: &[f32]) -> Vec<f32> {
+        let mut lut = Vec::with_capacity(query.len() * 256);
+        for q in query {
+            lut.extend((0..256).map(|c| {
+                let diff = q - (self.min + c as f32 * self.step);
+                diff * diff
+            }));
+        }
+        lut
+    }
+
+    fn eval(&self, lut: &Vec<f32>, stored: &[u8]) -> f32 {
+        assert_eq!(lut.len(), stored.len() * 256);
+        stored
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| lut[i * 256 + c as usize])
+            .sum::<f32>()
+            .sqrt()
+    }
+} // end of impl AsymmetricDistance for Sq8L2
+
+/// Distance between codes of a Sq8L2 quantizer, to build a Hnsw on codes.
+impl Distance<u8> for Sq8L2 {
+    fn eval(&self, va: &[u8], vb: &[u8]) -> f32 {
+        let sum: f32 = va
+            .iter()
+            .zip(vb)
+            .map(|(&a, &b)| {
+                let diff = (a as f32 - b as f32) * self.step;
+                diff * diff
+            })
+            .sum();
+        sum.sqrt()
+    }
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// search knbn neighbours of a query of type Q in a structure storing data of type T.
+    /// The query is prepared once by dist, all distances of the search are then given by dist.
+    /// Graph construction must have used a distance between stored data consistent with dist.
+    pub fn search_asymmetric<Q, A>(
+        &self,
+        query: &[Q],
+        knbn: usize,
+        ef_arg: usize,
+        dist: &A,
+    ) -> Vec<Neighbour>
+    where
+        A: AsymmetricDistance<Q, T>,
+    {
+        let prepared = dist.prepare(query);
+        let dist_to_query = |v: &[T], _bound: f32| dist.eval(&prepared, v);
+        self.search_with(&dist_to_query, None, knbn, ef_arg, &SearchParams::new())
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use rand::SeedableRng;
+    use rand::distr::{Distribution, Uniform};
+
+    #[test]
+    fn test_sq8_asymmetric_search() {
+        let nb_elem = 2000;
+        let dim = 16;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(4664397);
+        let unif = Uniform::<f32>::new(-1., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..nb_elem)
+            .map(|_| (0..dim).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let sq = Sq8L2::from_data(&data);
+        let codes: Vec<Vec<u8>> = data.iter().map(|v| sq.encode(v)).collect();
+        // a serial build with seeded levels, so that the searches below are exact
+        let mut hnsw = Hnsw::<u8, Sq8L2>::new(16, nb_elem, 16, 200, sq);
+        hnsw.set_level_seed(910);
+        for (i, c) in codes.iter().enumerate() {
+            hnsw.insert((c, i));
+        }
+        // lut distance equals distance to decoded vector
+        let lut = sq.prepare(&data[3]);
+        let decoded = sq.decode(&codes[7]);
+        let direct: f32 = data[3]
+            .iter()
+            .zip(&decoded)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f32>()
+            .sqrt();
+        assert!((AsymmetricDistance::eval(&sq, &lut, &codes[7]) - direct).abs() < 1.0e-4);
+        // f32 queries find their own code
+        for i in [0, 1000, 1999] {
+            let res = hnsw.search_asymmetric(&data[i], 1, 64, &sq);
+            assert_eq!(res[0].d_id, i);
+        }
+    } // end of test_sq8_asymmetric_search
+} // end of mod tests
@@ -0,0 +1,118 @@
+//! Searches with an asynchronous rerank of candidates.
+//!
+//! A cross-encoder (or any scoring service reached by the network) rescores candidates better than vector distances
+//! but is too slow for a whole index. [Hnsw::search_async] searches the graph for ef candidates and gives their ids
+//! to the callback set by [SearchParams::rerank_async], then returns the knbn best candidates by score when the future
+//! of the scores completes. The application gets its final top-k from one call instead of over-fetching,
+//! calling the service and sorting itself.
+//!
+//! The graph search is cpu bound and is done at the call of search_async, the returned future only waits
+//! for the scores. It does not depend on an async runtime.
+
+use std::future::Future;
+
+use anyhow::anyhow;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Neighbour};
+use crate::searchparams::SearchParams;
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// searches ef candidates with params and returns a future of the knbn best ones after the rerank
+    /// of [SearchParams::rerank_async] (the knbn nearest if params has no rerank).
+    /// Fails if the rerank fails or does not give one score by candidate.
+    pub fn search_async(
+        &self,
+        data: &[T],
+        knbn: usize,
+        ef_arg: usize,
+        params: &SearchParams,
+    ) -> impl Future<Output = anyhow::Result<Vec<Neighbour>>> + Send + use<T, D> {
+        let rerank = params.get_rerank();
+        let nb_candidates = match rerank {
+            Some(_) => self.get_search_ef(knbn, ef_arg),
+            None => knbn,
+        };
+        let candidates = self.search_with_params(data, nb_candidates, ef_arg, params);
+        let scores = rerank.map(|rerank| rerank(candidates.iter().map(|n| n.d_id).collect()));
+        async move {
+            let Some(scores) = scores else {
+                return Ok(candidates);
+            };
+            let scores = scores.await?;
+            if scores.len() != candidates.len() {
+                return Err(anyhow!(
+                    "rerank gave {} scores for {} candidates",
+                    scores.len(),
+                    candidates.len()
+                ));
+            }
+            let mut reranked: Vec<Neighbour> = candidates
+                .into_iter()
+                .zip(scores)
+                .map(|(n, score)| Neighbour::new(n.d_id, score, n.p_id))
+                .collect();
+            reranked.sort_unstable_by(|a, b| a.cmp_by_distance(b));
+            reranked.truncate(knbn);
+            Ok(reranked)
+        }
+    } // end of search_async
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::DataId;
+    use crate::searchparams::RerankFuture;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+    use std::task::{Context, Poll, Waker};
+
+    // polls a future until it is ready, the futures of the test never wait
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        let mut context = Context::from_waker(Waker::noop());
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+                return output;
+            }
+        }
+    }
+
+    #[test]
+    fn test_search_async() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(974);
+        let data = synthetic::uniform(2000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 2000, 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let query = &data[0];
+        // without rerank, a plain search
+        let plain = hnsw.search(query, 5, 64);
+        let res = block_on(hnsw.search_async(query, 5, 64, &SearchParams::new())).unwrap();
+        assert_eq!(res, plain);
+        // a service preferring high ids
+        let rerank = |ids: Vec<DataId>| -> RerankFuture {
+            Box::pin(async move { Ok(ids.iter().map(|id| -(*id as f32)).collect()) })
+        };
+        let params = SearchParams::new().rerank_async(&rerank);
+        let res = block_on(hnsw.search_async(query, 5, 64, &params)).unwrap();
+        let mut candidates: Vec<DataId> =
+            hnsw.search(query, 64, 64).iter().map(|n| n.d_id).collect();
+        candidates.sort_unstable();
+        let best: Vec<DataId> = candidates.iter().rev().take(5).copied().collect();
+        assert_eq!(res.iter().map(|n| n.d_id).collect::<Vec<_>>(), best);
+        assert_eq!(res[0].distance, -(best[0] as f32));
+        // a failing service
+        let wrong = |_: Vec<DataId>| -> RerankFuture { Box::pin(async { Ok(vec![0.]) }) };
+        let params = SearchParams::new().rerank_async(&wrong);
+        assert!(block_on(hnsw.search_async(query, 5, 64, &params)).is_err());
+    } // end of test_search_async
+} // end of mod tests
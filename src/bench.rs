@@ -0,0 +1,285 @@
+//! A quick benchmark to run in one call on user hardware.
+//!
+//! [quick] generates a gaussian mixture, builds a Hnsw in parallel and reports build throughput,
+//! queries per second and recall against a brute force search.
+//!
+//! [Baseline::run] runs [quick] on the pinned datasets of [BASELINE_CASES] and [export_baseline] writes the reports
+//! in a JSON file. The baseline of a release, reloaded by [load_baseline], is compared to the current one by
+//! [Baseline::regressions] to detect performance regressions across versions (on the same machine).
+//! Statistics over many runs of each operation are given by the criterion benchmarks: `cargo bench --bench hnsw`.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+use std::time::Instant;
+
+use anyhow::Result;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use anndists::dist::distances::DistL2;
+
+use crate::datasets::synthetic::gaussian_mixture;
+use crate::hnsw::Hnsw;
+use crate::layout::VectorStore;
+
+/// parameters of a quick benchmark
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct BenchParams {
+    pub max_nb_connection: usize,
+    pub ef_construction: usize,
+    pub ef_search: usize,
+    /// number of neighbours asked
+    pub knbn: usize,
+    pub nb_query: usize,
+    /// number of clusters of the gaussian mixture
+    pub nb_cluster: usize,
+    pub seed: u64,
+}
+
+impl Default for BenchParams {
+    fn default() -> Self {
+        BenchParams {
+            max_nb_connection: 24,
+            ef_construction: 200,
+            ef_search: 64,
+            knbn: 10,
+            nb_query: 500,
+            nb_cluster: 16,
+            seed: 4664397,
+        }
+    }
+}
+
+/// result of a quick benchmark
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+pub struct BenchReport {
+    pub nb_point: usize,
+    pub dim: usize,
+    /// build time in seconds
+    pub build_time: f64,
+    /// points inserted by second
+    pub build_throughput: f64,
+    /// queries by second (sequential search, one thread)
+    pub qps: f64,
+    /// mean fraction of true knbn neighbours found
+    pub recall: f64,
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nb point : {}, dim : {}, build time : {:.2} s ({:.0} points/s), qps : {:.0}, recall : {:.3}",
+            self.nb_point, self.dim, self.build_time, self.build_throughput, self.qps, self.recall
+        )
+    }
+}
+
+/// builds a Hnsw with L2 distance on nb_point vectors of dimension dim and measures it.
+pub fn quick(dim: usize, nb_point: usize, params: &BenchParams) -> BenchReport {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(params.seed);
+    // queries are drawn from the same mixture as data
+    let nb_total = nb_point + params.nb_query;
+    let (mut data, _) = gaussian_mixture(nb_total, dim, params.nb_cluster, 0.05, &mut rng);
+    let queries = data.split_off(nb_point);
+    //
+    let nb_layer = 16.min((nb_point.max(2) as f32).ln().trunc() as usize);
+    let hnsw = Hnsw::<f32, DistL2>::new(
+        params.max_nb_connection,
+        nb_point,
+        nb_layer,
+        params.ef_construction,
+        DistL2 {},
+    );
+    let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+    let start = Instant::now();
+    hnsw.parallel_insert(&data_with_id);
+    let build_time = start.elapsed().as_secs_f64();
+    //
+    let start = Instant::now();
+    let answers: Vec<Vec<usize>> = queries
+        .iter()
+        .map(|q| {
+            hnsw.search(q, params.knbn, params.ef_search)
+                .iter()
+                .map(|n| n.d_id)
+                .collect()
+        })
+        .collect();
+    let search_time = start.elapsed().as_secs_f64();
+    // brute force
+    let store = VectorStore::from_rows(dim, &data);
+    let recalls: Vec<f64> = store
+        .knn_l2_batch(&queries, params.knbn)
+        .into_iter()
+        .zip(&answers)
+        .map(|(exact, answer)| {
+            if exact.is_empty() {
+                return 1.;
+            }
+            let nb_found = exact.iter().filter(|(i, _)| answer.contains(i)).count();
+            nb_found as f64 / exact.len() as f64
+        })
+        .collect();
+    //
+    let report = BenchReport {
+        nb_point,
+        dim,
+        build_time,
+        build_throughput: nb_point as f64 / build_time.max(f64::MIN_POSITIVE),
+        qps: queries.len() as f64 / search_time.max(f64::MIN_POSITIVE),
+        recall: recalls.iter().sum::<f64>() / recalls.len().max(1) as f64,
+    };
+    log::info!("bench::quick {}", report);
+    report
+} // end of quick
+
+/// name, dimension and number of points of the datasets of a baseline. Data are generated from the seed of
+/// [BenchParams], the datasets do not change between versions.
+pub const BASELINE_CASES: [(&str, usize, usize); 3] = [
+    ("mixture-d16-n20000", 16, 20_000),
+    ("mixture-d128-n20000", 128, 20_000),
+    ("mixture-d384-n10000", 384, 10_000),
+];
+
+/// reports of [quick] on several datasets, with the version of the crate that produced them
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Baseline {
+    pub version: String,
+    pub params: BenchParams,
+    /// name of the dataset and report
+    pub reports: Vec<(String, BenchReport)>,
+}
+
+/// a measure of a baseline worse than in a previous one
+#[derive(Clone, Debug, PartialEq)]
+pub struct Regression {
+    pub case: String,
+    /// build_throughput, qps or recall
+    pub measure: &'static str,
+    pub previous: f64,
+    pub current: f64,
+}
+
+impl fmt::Display for Regression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} : {} {:.3} -> {:.3} ({:+.1}%)",
+            self.case,
+            self.measure,
+            self.previous,
+            self.current,
+            100. * (self.current - self.previous) / self.previous
+        )
+    }
+}
+
+impl Baseline {
+    /// runs [quick] on each case (name, dim, nb_point), [BASELINE_CASES] for a baseline to compare across versions
+    pub fn run(cases: &[(&str, usize, usize)], params: &BenchParams) -> Self {
+        let reports = cases
+            .iter()
+            .map(|&(name, dim, nb_point)| (name.to_string(), quick(dim, nb_point, params)))
+            .collect();
+        Baseline {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            params: *params,
+            reports,
+        }
+    }
+
+    /// returns measures of cases of self lower than those of previous by more than the fraction tolerance
+    /// for throughputs (timings vary between runs, 0.1 is a reasonable value) and than 0.01 for recall.
+    /// Cases not in previous are ignored.
+    pub fn regressions(&self, previous: &Baseline, tolerance: f64) -> Vec<Regression> {
+        let mut regressions = Vec::new();
+        for (case, report) in &self.reports {
+            let Some((_, old)) = previous.reports.iter().find(|(name, _)| name == case) else {
+                continue;
+            };
+            let measures = [
+                (
+                    "build_throughput",
+                    old.build_throughput,
+                    report.build_throughput,
+                    tolerance,
+                ),
+                ("qps", old.qps, report.qps, tolerance),
+                ("recall", old.recall, report.recall, 0.01),
+            ];
+            for (measure, previous, current, tolerance) in measures {
+                if current < previous * (1. - tolerance) {
+                    regressions.push(Regression {
+                        case: case.clone(),
+                        measure,
+                        previous,
+                        current,
+                    });
+                }
+            }
+        }
+        regressions
+    }
+} // end of impl Baseline
+
+/// writes baseline in a JSON file
+pub fn export_baseline(baseline: &Baseline, path: &Path) -> Result<()> {
+    let out = BufWriter::new(File::create(path)?);
+    serde_json::to_writer_pretty(out, baseline)?;
+    Ok(())
+}
+
+/// reads a baseline written by [export_baseline]
+pub fn load_baseline(path: &Path) -> Result<Baseline> {
+    let input = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(input)?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_quick() {
+        let params = BenchParams {
+            nb_query: 50,
+            ..Default::default()
+        };
+        let report = quick(16, 2000, &params);
+        println!("{}", report);
+        assert_eq!(report.nb_point, 2000);
+        assert!(report.qps > 0.);
+        assert!(report.recall > 0.8);
+    } // end of test_quick
+
+    #[test]
+    fn test_baseline() {
+        let params = BenchParams {
+            nb_query: 20,
+            ..Default::default()
+        };
+        let baseline = Baseline::run(&[("small", 8, 500), ("tiny", 4, 200)], &params);
+        assert_eq!(baseline.reports.len(), 2);
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("baseline.json");
+        export_baseline(&baseline, &path).unwrap();
+        let previous = load_baseline(&path).unwrap();
+        assert_eq!(previous.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(previous.reports[1].1.nb_point, 200);
+        assert!(baseline.regressions(&previous, 0.1).is_empty());
+        // a slower version
+        let mut slower = previous.clone();
+        slower.reports[0].1.qps /= 2.;
+        let regressions = slower.regressions(&previous, 0.1);
+        assert_eq!(regressions.len(), 1);
+        assert_eq!(
+            (regressions[0].case.as_str(), regressions[0].measure),
+            ("small", "qps")
+        );
+        assert!(previous.regressions(&slower, 0.1).is_empty());
+    } // end of test_baseline
+} // end of mod tests
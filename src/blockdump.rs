@@ -0,0 +1,462 @@
+//! Incremental dumps of an index.
+//!
+//! [Hnsw::dump_dirty] writes the index in a block structured dump and, from its first call, keeps track of the
+//! points whose links change. Each following call only rewrites the blocks of these points and appends the new
+//! points, so frequent checkpoints of an index receiving few insertions cost little.
+//! [Hnsw::load_blocks] reloads such a dump.
+//!
+//! A dump of prefix dir/name is made of:
+//! - dir/name.blocks : the [Description] of the index, the number of points of each layer and the entry point.
+//! - dir/name.l\<k\>.graph : the links of the points of layer k, in a slot of fixed size by point.
+//! - dir/name.l\<k\>.data : the vectors of the points of layer k, dim * size_of::\<T\>() bytes by point.
+//!
+//! A graph slot holds the origin id of the point as a u64 then, for each layer up to the highest one reached, the
+//! number of neighbours as a u16 and room for 2 * max_nb_connection neighbours at layer 0 (max_nb_connection above),
+//! each one as a layer u8, a rank i32 and a distance f32. Slots are written by blocks of [POINTS_BY_BLOCK] points.
+//! When the highest layer reached changes (or the prefix) the whole dump is rewritten.
+//!
+//! Graph and data files are synced before the header is replaced by a rename. A dump interrupted by a crash
+//! reloads with the points of the previous one, links to points not yet in the header are dropped.
+//! Deleted points, aliases, payloads and namespaces are not in these dumps, see [file_dump](crate::api::AnnT::file_dump).
+//! Tracking takes a lock at each change of a neighbour list, parallel insertions are a bit slower.
+
+use parking_lot::Mutex;
+use std::any::type_name;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use anyhow::{Result, anyhow};
+use rayon::prelude::*;
+use roaring::RoaringBitmap;
+use serde::{Serialize, de::DeserializeOwned};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, OwnedHnsw, Point, PointId, PointWithOrder};
+use crate::hnswio::{Description, DumpMode, load_description};
+
+const MAGICBLOCKS: u32 = 0x00b10c5d;
+
+/// number of points written together in a graph or data file
+pub const POINTS_BY_BLOCK: usize = 16;
+
+// bytes of a neighbour in a graph slot : layer, rank and distance
+const NEIGHBOUR_SIZE: usize = 9;
+
+/// points whose links changed since the last [Hnsw::dump_dirty]
+#[derive(Default)]
+pub(crate) struct DirtyPoints {
+    tracking: AtomicBool,
+    // ranks of changed points, by layer
+    ranks: Mutex<Vec<RoaringBitmap>>,
+    // what is on disk, None before the first dump or after a failed one
+    last: Mutex<Option<LastDump>>,
+}
+
+struct LastDump {
+    prefix: PathBuf,
+    nb_list: usize,
+    nb_point_by_layer: Vec<usize>,
+}
+
+impl DirtyPoints {
+    /// records that the links of p_id changed, once tracking started
+    #[inline]
+    pub(crate) fn mark(&self, p_id: PointId) {
+        if self.tracking.load(Ordering::Relaxed) {
+            let mut ranks = self.ranks.lock();
+            let layer = p_id.0 as usize;
+            if ranks.len() <= layer {
+                ranks.resize(layer + 1, RoaringBitmap::new());
+            }
+            ranks[layer].insert(p_id.1 as u32);
+        }
+    }
+}
+
+fn file_path(prefix: &Path, extension: &str) -> PathBuf {
+    let mut name = prefix.as_os_str().to_owned();
+    name.push(".");
+    name.push(extension);
+    PathBuf::from(name)
+}
+
+// room for neighbours at layer l
+fn list_capacity(max_nb_connection: usize, l: usize) -> usize {
+    if l == 0 {
+        2 * max_nb_connection
+    } else {
+        max_nb_connection
+    }
+}
+
+fn graph_slot_size(max_nb_connection: usize, nb_list: usize) -> usize {
+    8 + (0..nb_list)
+        .map(|l| 2 + list_capacity(max_nb_connection, l) * NEIGHBOUR_SIZE)
+        .sum::<usize>()
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// writes in the block dump of prefix the new points and the points whose links changed since the last call,
+    /// the whole index at the first call or if prefix changed (see module doc). Returns the number of blocks written.
+    pub fn dump_dirty(&self, prefix: &Path) -> Result<usize> {
+        let mut last = self.dirty.last.lock();
+        self.dirty.tracking.store(true, Ordering::Relaxed);
+        let mut dirty = std::mem::take(&mut *self.dirty.ranks.lock());
+        let layers = self.layer_indexed_points.points_by_layer.read();
+        let nb_list = self.get_max_level_observed() as usize + 1;
+        let previous = match last.take() {
+            Some(l) if l.prefix == prefix && l.nb_list == nb_list => l.nb_point_by_layer,
+            _ => vec![0; layers.len()],
+        };
+        let nb_point_by_layer: Vec<usize> = layers.iter().map(|layer| layer.len()).collect();
+        let data_size = self.layer_indexed_points.get_data_dimension() * std::mem::size_of::<T>();
+        let slot_size = graph_slot_size(self.max_nb_connection, nb_list);
+        let mut nb_block = 0;
+        for (k, layer) in layers.iter().enumerate() {
+            let open = |extension: &str| {
+                OpenOptions::new()
+                    .write(true)
+                    .create(true)
+                    .truncate(previous[k] == 0)
+                    .open(file_path(prefix, &format!("l{}.{}", k, extension)))
+            };
+            if layer.is_empty() {
+                continue;
+            }
+            let (mut graph_out, mut data_out) = (open("graph")?, open("data")?);
+            let mut ranks = dirty.get_mut(k).map(std::mem::take).unwrap_or_default();
+            ranks.insert_range(previous[k] as u32..layer.len() as u32);
+            // changed points inserted after the snapshot of layers are new points of the next dump
+            ranks.remove_range(layer.len() as u32..);
+            let mut blocks: Vec<usize> =
+                ranks.iter().map(|r| r as usize / POINTS_BY_BLOCK).collect();
+            blocks.dedup();
+            let mut buffer = Vec::new();
+            for b in blocks {
+                let start = b * POINTS_BY_BLOCK;
+                let end = layer.len().min(start + POINTS_BY_BLOCK);
+                buffer.clear();
+                for point in &layer[start..end] {
+                    write_graph_slot(point, self.max_nb_connection, nb_list, &mut buffer)?;
+                }
+                graph_out.seek(SeekFrom::Start((start * slot_size) as u64))?;
+                graph_out.write_all(&buffer)?;
+                nb_block += 1;
+                // vectors do not change, only new points are written
+                let new_start = start.max(previous[k]);
+                if new_start < end {
+                    buffer.clear();
+                    for point in &layer[new_start..end] {
+                        write_data_slot(point, data_size, &mut buffer)?;
+                    }
+                    data_out.seek(SeekFrom::Start((new_start * data_size) as u64))?;
+                    data_out.write_all(&buffer)?;
+                    nb_block += 1;
+                }
+            }
+            graph_out.sync_data()?;
+            data_out.sync_data()?;
+        }
+        let entry_point = self
+            .layer_indexed_points
+            .entry_point
+            .read()
+            .as_ref()
+            .map(|p| p.get_point_id());
+        drop(layers);
+        self.write_header(prefix, nb_list, &nb_point_by_layer, entry_point)?;
+        log::info!("dump_dirty : {} blocks written in {:?}", nb_block, prefix);
+        *last = Some(LastDump {
+            prefix: prefix.to_path_buf(),
+            nb_list,
+            nb_point_by_layer,
+        });
+        Ok(nb_block)
+    } // end of dump_dirty
+
+    // writes the header in a temporary file renamed once synced
+    fn write_header(
+        &self,
+        prefix: &Path,
+        nb_list: usize,
+        nb_point_by_layer: &[usize],
+        entry_point: Option<PointId>,
+    ) -> Result<()> {
+        let description = Description {
+            format_version: 7,
+            dumpmode: 1,
+            max_nb_connection: self.get_max_nb_connection(),
+            level_scale: self.layer_indexed_points.get_level_scale(),
+            nb_layer: self.get_max_level() as u8,
+            ef: self.get_ef_construction(),
+            nb_point: nb_point_by_layer.iter().sum(),
+            dimension: self.layer_indexed_points.get_data_dimension(),
+            distname: self.get_distance_name(),
+            t_name: type_name::<T>().to_string(),
+            metadata: self.metadata.clone(),
+        };
+        let tmp_path = file_path(prefix, "blocks.tmp");
+        let mut out = BufWriter::new(File::create(&tmp_path)?);
+        out.write_all(&MAGICBLOCKS.to_ne_bytes())?;
+        description.dump(DumpMode::Full, &mut out)?;
+        out.write_all(&[nb_list as u8, nb_point_by_layer.len() as u8])?;
+        for nb_point in nb_point_by_layer {
+            out.write_all(&(*nb_point as u64).to_ne_bytes())?;
+        }
+        let entry_point = entry_point.unwrap_or(PointId(0, -1));
+        out.write_all(&[entry_point.0])?;
+        out.write_all(&entry_point.1.to_ne_bytes())?;
+        out.into_inner()
+            .map_err(|e| anyhow!("writing {:?} : {}", tmp_path, e))?
+            .sync_all()?;
+        std::fs::rename(&tmp_path, file_path(prefix, "blocks"))?;
+        Ok(())
+    }
+} // end of impl Hnsw
+
+fn write_graph_slot<T: Clone + Send + Sync>(
+    point: &Point<T>,
+    max_nb_connection: usize,
+    nb_list: usize,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    out.extend_from_slice(&(point.get_origin_id() as u64).to_ne_bytes());
+    let neighbours = point.neighbours.read();
+    for (l, neighbours_l) in neighbours.iter().enumerate().take(nb_list) {
+        let capacity = list_capacity(max_nb_connection, l);
+        if neighbours_l.len() > capacity {
+            return Err(anyhow!(
+                "point {:?} has {} neighbours at layer {}, more than a slot can hold",
+                point.get_point_id(),
+                neighbours_l.len(),
+                l
+            ));
+        }
+        out.extend_from_slice(&(neighbours_l.len() as u16).to_ne_bytes());
+        for n in neighbours_l {
+            let p_id = n.point_ref.get_point_id();
+            out.push(p_id.0);
+            out.extend_from_slice(&p_id.1.to_ne_bytes());
+            out.extend_from_slice(&n.dist_to_ref.to_ne_bytes());
+        }
+        out.resize(
+            out.len() + (capacity - neighbours_l.len()) * NEIGHBOUR_SIZE,
+            0,
+        );
+    }
+    Ok(())
+}
+
+fn write_data_slot<T: Clone + Send + Sync>(
+    point: &Point<T>,
+    data_size: usize,
+    out: &mut Vec<u8>,
+) -> Result<()> {
+    // as in file dumps vectors are written as they are in memory
+    let serialized = unsafe {
+        std::slice::from_raw_parts(
+            point.get_v().as_ptr() as *const u8,
+            std::mem::size_of_val(point.get_v()),
+        )
+    };
+    if serialized.len() != data_size {
+        return Err(anyhow!(
+            "vector of {} has {} bytes, expected {}",
+            point.get_origin_id(),
+            serialized.len(),
+            data_size
+        ));
+    }
+    out.extend_from_slice(serialized);
+    Ok(())
+}
+
+fn read_u64(input: &mut dyn Read) -> Result<u64> {
+    let mut bytes = [0u8; 8];
+    input.read_exact(&mut bytes)?;
+    Ok(u64::from_ne_bytes(bytes))
+}
+
+impl<T, D> Hnsw<'static, T, D>
+where
+    T: 'static + Serialize + DeserializeOwned + Clone + Send + Sync,
+    D: Distance<T> + Default + Send + Sync,
+{
+    /// reloads the block dump of prefix written by [Hnsw::dump_dirty]
+    pub fn load_blocks(prefix: &Path) -> Result<OwnedHnsw<T, D>> {
+        let mut header = BufReader::new(File::open(file_path(prefix, "blocks"))?);
+        let mut magic = [0u8; 4];
+        header.read_exact(&mut magic)?;
+        if u32::from_ne_bytes(magic) != MAGICBLOCKS {
+            return Err(anyhow!("{:?} is not a block dump", prefix));
+        }
+        let description = load_description(&mut header)?;
+        // as at reload of a file dump, short names of distances must be equal
+        let short_name = |name: &str| name.rsplit("::").next().unwrap_or_default().to_string();
+        if short_name(&description.distname) != short_name(type_name::<D>()) {
+            return Err(anyhow!(
+                "dump made with distance {}, asked distance is {}",
+                description.distname,
+                type_name::<D>()
+            ));
+        }
+        let mut sizes = [0u8; 2];
+        header.read_exact(&mut sizes)?;
+        let (nb_list, nb_layer) = (sizes[0] as usize, sizes[1] as usize);
+        let nb_point_by_layer = (0..nb_layer)
+            .map(|_| read_u64(&mut header).map(|n| n as usize))
+            .collect::<Result<Vec<usize>>>()?;
+        let mut entry = [0u8; 5];
+        header.read_exact(&mut entry)?;
+        let entry_id = PointId(entry[0], i32::from_ne_bytes(entry[1..].try_into().unwrap()));
+        //
+        let max_nb_connection = description.max_nb_connection as usize;
+        let slot_size = graph_slot_size(max_nb_connection, nb_list);
+        let data_size = description.dimension * std::mem::size_of::<T>();
+        let mut layers: Vec<Vec<Arc<Point<'static, T>>>> = Vec::with_capacity(nb_layer);
+        let mut graphs: Vec<Vec<u8>> = Vec::with_capacity(nb_layer);
+        for (k, nb_point) in nb_point_by_layer.iter().enumerate() {
+            let mut layer = Vec::with_capacity(*nb_point);
+            let mut graph = vec![0u8; nb_point * slot_size];
+            if *nb_point > 0 {
+                File::open(file_path(prefix, &format!("l{}.graph", k)))?.read_exact(&mut graph)?;
+                let mut data_in =
+                    BufReader::new(File::open(file_path(prefix, &format!("l{}.data", k)))?);
+                let mut data = vec![0u8; data_size];
+                for r in 0..*nb_point {
+                    data_in.read_exact(&mut data)?;
+                    let v = unsafe {
+                        std::slice::from_raw_parts(data.as_ptr() as *const T, description.dimension)
+                    };
+                    let slot = &graph[r * slot_size..];
+                    let origin_id = u64::from_ne_bytes(slot[..8].try_into().unwrap()) as usize;
+                    layer.push(Arc::new(Point::new(
+                        v.to_vec(),
+                        origin_id,
+                        PointId(k as u8, r as i32),
+                    )));
+                }
+            }
+            layers.push(layer);
+            graphs.push(graph);
+        }
+        let get = |p_id: PointId| {
+            layers
+                .get(p_id.0 as usize)
+                .and_then(|layer| layer.get(usize::try_from(p_id.1).ok()?))
+        };
+        // links, those to points not in the header come from an interrupted dump
+        layers
+            .par_iter()
+            .zip(graphs.par_iter())
+            .flat_map_iter(|(layer, graph)| layer.iter().zip(graph.chunks_exact(slot_size)))
+            .for_each(|(point, slot)| {
+                let mut neighbours = point.neighbours.write();
+                let mut pos = 8;
+                for (l, neighbours_l) in neighbours.iter_mut().enumerate().take(nb_list) {
+                    let nb = u16::from_ne_bytes(slot[pos..pos + 2].try_into().unwrap()) as usize;
+                    for n in slot[pos + 2..].chunks_exact(NEIGHBOUR_SIZE).take(nb) {
+                        let n_id = PointId(n[0], i32::from_ne_bytes(n[1..5].try_into().unwrap()));
+                        if let Some(n_point) = get(n_id) {
+                            let dist = f32::from_ne_bytes(n[5..].try_into().unwrap());
+                            neighbours_l.push(Arc::new(PointWithOrder::new(n_point, dist)));
+                        }
+                    }
+                    neighbours_l.sort_unstable();
+                    pos += 2 + list_capacity(max_nb_connection, l) * NEIGHBOUR_SIZE;
+                }
+            });
+        let entry_point = match entry_id.1 {
+            -1 => None,
+            _ => Some(Arc::clone(get(entry_id).ok_or_else(|| {
+                anyhow!("entry point {:?} not in dump", entry_id)
+            })?)),
+        };
+        //
+        let nb_point = nb_point_by_layer.iter().sum();
+        let hnsw = Hnsw::try_new(
+            max_nb_connection,
+            nb_point,
+            description.nb_layer as usize,
+            description.ef,
+            D::default(),
+        )?;
+        if hnsw.layer_indexed_points.points_by_layer.read().len() != nb_layer {
+            return Err(anyhow!(
+                "{} layers in dump, {} expected",
+                nb_layer,
+                description.nb_layer
+            ));
+        }
+        let mut hnsw = hnsw;
+        hnsw.data_dimension = description.dimension;
+        hnsw.metadata = description.metadata;
+        {
+            let indexation = &hnsw.layer_indexed_points;
+            *indexation.points_by_layer.write() = layers;
+            *indexation.nb_point.write() = nb_point;
+            *indexation.entry_point.write() = entry_point;
+            indexation.reset_insertion_order();
+            indexation.reset_ids();
+        }
+        Ok(hnsw)
+    } // end of load_blocks
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_dump_dirty() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(949);
+        let data = synthetic::uniform(3000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id[..2900]);
+        let dir = tempfile::tempdir().unwrap();
+        let prefix = dir.path().join("blocks");
+        let nb_full = hnsw.dump_dirty(&prefix).unwrap();
+        assert!(nb_full >= 2 * 2900 / POINTS_BY_BLOCK);
+        assert_eq!(hnsw.dump_dirty(&prefix).unwrap(), 0);
+        // a few insertions touch a few blocks
+        for (v, id) in &data_with_id[2900..2903] {
+            hnsw.insert((v.as_slice(), *id));
+        }
+        let nb_partial = hnsw.dump_dirty(&prefix).unwrap();
+        assert!(
+            nb_partial > 0 && nb_partial < nb_full / 2,
+            "{} / {}",
+            nb_partial,
+            nb_full
+        );
+        let found = |h: &Hnsw<f32, DistL2>| {
+            (0..2903)
+                .step_by(10)
+                .map(|i| h.search(&data[i], 5, 32)[0].d_id == i)
+                .collect::<Vec<bool>>()
+        };
+        let reloaded = Hnsw::<f32, DistL2>::load_blocks(&prefix).unwrap();
+        assert_eq!(reloaded.get_nb_point(), 2903);
+        assert_eq!(found(&hnsw), found(&reloaded));
+        // a dump after many insertions reloads as the index
+        hnsw.parallel_insert(&data_with_id[2903..]);
+        hnsw.dump_dirty(&prefix).unwrap();
+        let reloaded = Hnsw::<f32, DistL2>::load_blocks(&prefix).unwrap();
+        assert_eq!(reloaded.get_nb_point(), 3000);
+        assert_eq!(found(&hnsw), found(&reloaded));
+        assert!(Hnsw::<f32, anndists::dist::DistL1>::load_blocks(&prefix).is_err());
+    } // end of test_dump_dirty
+} // end of mod tests
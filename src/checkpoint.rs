@@ -0,0 +1,250 @@
+//! Checkpointed builds.
+//!
+//! [Hnsw::build_with_checkpoints] inserts data in chunks of every_n points and dumps the structure after each chunk.
+//! After a crash (or a preemption), [Checkpoint::last] finds the last complete dump, the structure is reloaded
+//! from it with [Checkpoint::reloader] and [Hnsw::resume_build] inserts the remaining data.
+//!
+//! A checkpoint of prefix dir/name is the dump dir/name-nb_done plus a marker file dir/name.ckpt giving
+//! nb_done and the dump basename. The marker is replaced (by a rename) only once the dump is complete,
+//! then the dump of the previous checkpoint is removed.
+//!
+//! A structure with seeded levels (see [Hnsw::set_level_seed]) is built serially, so that the build is reproducible.
+//! The seed is not dumped, it must be set again on the reloaded structure.
+
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use anyhow::anyhow;
+use rayon::prelude::*;
+use serde::{Serialize, de::DeserializeOwned};
+
+use anndists::dist::distances::Distance;
+
+use crate::api::AnnT;
+use crate::hnsw::Hnsw;
+use crate::hnswio::HnswIo;
+
+/// a complete checkpoint of a build
+#[derive(Clone, Debug, PartialEq)]
+pub struct Checkpoint {
+    /// directory of the dump
+    pub dir: PathBuf,
+    /// basename of the dump
+    pub basename: String,
+    /// number of data items inserted when the dump was done
+    pub nb_done: usize,
+}
+
+// splits prefix in directory and name
+fn split_prefix(prefix: &Path) -> anyhow::Result<(PathBuf, String)> {
+    let name = prefix
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| anyhow!("checkpoint prefix {:?} has no file name", prefix))?;
+    let dir = match prefix.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    Ok((dir, name.to_string()))
+}
+
+fn marker_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.ckpt", name))
+}
+
+impl Checkpoint {
+    /// last complete checkpoint of prefix, None if no checkpoint was done
+    pub fn last(prefix: &Path) -> anyhow::Result<Option<Checkpoint>> {
+        let (dir, name) = split_prefix(prefix)?;
+        let marker = match std::fs::read_to_string(marker_path(&dir, &name)) {
+            Ok(marker) => marker,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let mut fields = marker.split_whitespace();
+        let nb_done = fields
+            .next()
+            .and_then(|f| f.parse::<usize>().ok())
+            .ok_or_else(|| anyhow!("bad checkpoint marker : {}", marker))?;
+        let basename = fields
+            .next()
+            .ok_or_else(|| anyhow!("bad checkpoint marker : {}", marker))?
+            .to_string();
+        Ok(Some(Checkpoint {
+            dir,
+            basename,
+            nb_done,
+        }))
+    }
+
+    /// a reloader of the dump of the checkpoint
+    pub fn reloader(&self) -> HnswIo {
+        HnswIo::new(&self.dir, &self.basename)
+    }
+
+    fn remove_dump(&self) {
+        for suffix in [".hnsw.graph", ".hnsw.data"] {
+            let path = self.dir.join(format!("{}{}", self.basename, suffix));
+            if let Err(e) = std::fs::remove_file(&path) {
+                log::warn!("could not remove old checkpoint {:?} : {}", path, e);
+            }
+        }
+    }
+} // end of impl Checkpoint
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// parallel insertion (serial with seeded levels) of datas, the structure is dumped (see module doc) each time every_n more items are inserted
+    /// and at the end.
+    pub fn build_with_checkpoints(
+        &self,
+        datas: &[(&[T], usize)],
+        every_n: usize,
+        prefix: &Path,
+    ) -> anyhow::Result<Checkpoint> {
+        self.build_from(datas, 0, every_n, prefix, None)
+    }
+
+    /// resumes a build from checkpoint: self must be reloaded from it and datas must be the whole input
+    /// given to [Hnsw::build_with_checkpoints]. Items from checkpoint.nb_done are inserted.
+    pub fn resume_build(
+        &self,
+        datas: &[(&[T], usize)],
+        every_n: usize,
+        prefix: &Path,
+        checkpoint: &Checkpoint,
+    ) -> anyhow::Result<Checkpoint> {
+        if checkpoint.nb_done > datas.len() {
+            return Err(anyhow!(
+                "checkpoint has {} items done, input has only {}",
+                checkpoint.nb_done,
+                datas.len()
+            ));
+        }
+        self.build_from(
+            datas,
+            checkpoint.nb_done,
+            every_n,
+            prefix,
+            Some(checkpoint.clone()),
+        )
+    }
+
+    fn build_from(
+        &self,
+        datas: &[(&[T], usize)],
+        start: usize,
+        every_n: usize,
+        prefix: &Path,
+        mut last: Option<Checkpoint>,
+    ) -> anyhow::Result<Checkpoint> {
+        if every_n == 0 {
+            return Err(anyhow!("build_with_checkpoints : every_n must be positive"));
+        }
+        let (dir, name) = split_prefix(prefix)?;
+        let mut nb_done = start;
+        loop {
+            let end = (nb_done + every_n).min(datas.len());
+            if self.get_level_seed().is_some() {
+                datas[nb_done..end]
+                    .iter()
+                    .for_each(|&item| self.insert_slice(item));
+            } else {
+                datas[nb_done..end]
+                    .par_iter()
+                    .for_each(|&item| self.insert_slice(item));
+            }
+            nb_done = end;
+            // a resumed build with nothing left to insert keeps its checkpoint
+            if let Some(checkpoint) = last.as_ref().filter(|c| c.nb_done == nb_done) {
+                return Ok(checkpoint.clone());
+            }
+            let checkpoint = self.checkpoint(&dir, &name, nb_done)?;
+            log::info!("build checkpoint {} items done", nb_done);
+            if let Some(old) = last.replace(checkpoint.clone()) {
+                old.remove_dump();
+            }
+            if nb_done == datas.len() {
+                return Ok(checkpoint);
+            }
+        }
+    } // end of build_from
+
+    fn checkpoint(&self, dir: &Path, name: &str, nb_done: usize) -> anyhow::Result<Checkpoint> {
+        let basename = self.file_dump(dir, &format!("{}-{}", name, nb_done))?;
+        for suffix in [".hnsw.graph", ".hnsw.data"] {
+            std::fs::File::open(dir.join(format!("{}{}", basename, suffix)))?.sync_all()?;
+        }
+        // the marker is written aside and renamed so that it always names a complete dump
+        let marker = marker_path(dir, name);
+        let tmp_marker = dir.join(format!("{}.ckpt.tmp", name));
+        {
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(&tmp_marker)?;
+            writeln!(file, "{} {}", nb_done, basename)?;
+            file.sync_all()?;
+        }
+        std::fs::rename(&tmp_marker, &marker)?;
+        Ok(Checkpoint {
+            dir: dir.to_path_buf(),
+            basename,
+            nb_done,
+        })
+    } // end of checkpoint
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_checkpoint_resume() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(924);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let datas: Vec<(&[f32], usize)> = data.iter().map(|v| v.as_slice()).zip(0..).collect();
+        let directory = tempfile::tempdir().unwrap();
+        let prefix = directory.path().join("build");
+        assert!(Checkpoint::last(&prefix).unwrap().is_none());
+        // a build interrupted after 600 items, serial with seeded levels
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        hnsw.set_level_seed(924);
+        let checkpoint = hnsw
+            .build_with_checkpoints(&datas[..600], 250, &prefix)
+            .unwrap();
+        assert_eq!(checkpoint.nb_done, 600);
+        assert_eq!(Checkpoint::last(&prefix).unwrap(), Some(checkpoint.clone()));
+        // only the last dump is kept
+        assert!(!directory.path().join("build-250.hnsw.graph").exists());
+        drop(hnsw);
+        //
+        let mut reloader = checkpoint.reloader();
+        let mut hnsw: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert_eq!(hnsw.get_level_seed(), None);
+        hnsw.set_level_seed(924);
+        let done = hnsw
+            .resume_build(&datas, 250, &prefix, &checkpoint)
+            .unwrap();
+        assert_eq!(done.nb_done, 1000);
+        assert_eq!(hnsw.get_nb_point(), 1000);
+        for i in (0..1000).step_by(37) {
+            assert_eq!(hnsw.search(&data[i], 1, 32)[0].d_id, i);
+        }
+        // resuming a finished build does nothing
+        assert_eq!(
+            hnsw.resume_build(&datas, 250, &prefix, &done).unwrap(),
+            done
+        );
+        assert_eq!(hnsw.get_nb_point(), 1000);
+    } // end of test_checkpoint_resume
+} // end of mod tests
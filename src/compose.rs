@@ -0,0 +1,75 @@
+//! Construction of query vectors from several vectors.
+//!
+//! A query of a recommendation is often the centroid of liked items, or a Rocchio combination
+//! `a * mean(positives) - b * mean(negatives)`. [mean] and [weighted] compute them, for example
+//! `weighted(&[(1., &mean(&liked)), (-0.5, &mean(&disliked))])`.
+//!
+//! Vectors are not normalized by the structure at insertion, results are returned as computed.
+//! With a distance expecting unit vectors (DistDot) the query must be normalized, see [normalize].
+
+/// returns the mean of vectors.
+/// Panics if vectors is empty or if they do not have the same dimension.
+pub fn mean(vectors: &[&[f32]]) -> Vec<f32> {
+    assert!(!vectors.is_empty(), "compose::mean of no vector");
+    let w = 1. / vectors.len() as f32;
+    let weighted_vectors: Vec<(f32, &[f32])> = vectors.iter().map(|v| (w, *v)).collect();
+    weighted(&weighted_vectors)
+}
+
+/// returns the sum of vectors multiplied by their weight. Weights can be negative.
+/// Panics if vectors is empty or if they do not have the same dimension.
+pub fn weighted(vectors: &[(f32, &[f32])]) -> Vec<f32> {
+    assert!(!vectors.is_empty(), "compose::weighted of no vector");
+    let dim = vectors[0].1.len();
+    let mut sum = vec![0f32; dim];
+    for (w, v) in vectors {
+        assert_eq!(v.len(), dim, "compose of vectors of different dimensions");
+        for (s, x) in sum.iter_mut().zip(v.iter()) {
+            *s += w * x;
+        }
+    }
+    sum
+}
+
+/// scales v to norm 1, a null vector is left unchanged
+pub fn normalize(v: &mut [f32]) {
+    let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0. {
+        v.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::Hnsw;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_compose() {
+        assert_eq!(mean(&[&[1., 2.], &[3., 6.]]), vec![2., 4.]);
+        assert_eq!(
+            weighted(&[(1., &[1., 2.]), (-0.5, &[2., 2.])]),
+            vec![0., 1.]
+        );
+        let mut v = vec![3., 4.];
+        normalize(&mut v);
+        assert_eq!(v, vec![0.6, 0.8]);
+        // the centroid of a cluster finds points of the cluster
+        let mut rng = rand::rngs::StdRng::seed_from_u64(969);
+        let (data, labels) = synthetic::gaussian_mixture(1000, 16, 8, 0.02, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        hnsw.parallel_insert(&data_with_id);
+        let liked: Vec<&[f32]> = (0..1000)
+            .filter(|i| labels[*i] == labels[0])
+            .take(5)
+            .map(|i| data[i].as_slice())
+            .collect();
+        let neighbours = hnsw.search(&mean(&liked), 10, 64);
+        assert!(neighbours.iter().all(|n| labels[n.d_id] == labels[0]));
+    } // end of test_compose
+} // end of mod tests
@@ -0,0 +1,161 @@
+//! Thread safety contract of a shared Hnsw, enforced by [ConcurrentHnsw].
+//!
+//! A Hnsw is Send + Sync and its methods taking &self can be called from several threads, but they are not all
+//! safe together:
+//!
+//! | operations            | concurrently |
+//! |-----------------------|--------------|
+//! | insert + insert       | yes, neighbour lists and entry point are locked |
+//! | insert + search       | yes, a search sees a point once it is linked |
+//! | search + search       | yes |
+//! | search + dump         | yes |
+//! | insert + dump         | no, a dump must see a structure that does not change |
+//! | setters (&mut self)   | no, only on an exclusively owned structure |
+//!
+//! A ConcurrentHnsw wraps a Hnsw, gives only the operations above and makes dumps wait for running
+//! insertions (and insertions wait for a running dump) with an internal lock.
+//! Setters are reached by [ConcurrentHnsw::into_inner] when the structure is no longer shared.
+
+use parking_lot::RwLock;
+use std::path::Path;
+
+use rayon::prelude::*;
+use serde::{Serialize, de::DeserializeOwned};
+
+use anndists::dist::distances::Distance;
+
+use crate::api::AnnT;
+use crate::hnsw::{DataId, Hnsw, Neighbour};
+use crate::payload::Payload;
+use crate::searchparams::SearchParams;
+
+/// a Hnsw shared between threads, see module doc
+pub struct ConcurrentHnsw<'b, T: Clone + Send + Sync + 'static, D: Distance<T>> {
+    hnsw: Hnsw<'b, T, D>,
+    // insertions hold it in read mode, dumps in write mode
+    dump_gate: RwLock<()>,
+}
+
+impl<'b, T, D> ConcurrentHnsw<'b, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    pub fn new(hnsw: Hnsw<'b, T, D>) -> Self {
+        ConcurrentHnsw {
+            hnsw,
+            dump_gate: RwLock::new(()),
+        }
+    }
+
+    /// returns the structure, to modify its parameters once it is not shared anymore
+    pub fn into_inner(self) -> Hnsw<'b, T, D> {
+        self.hnsw
+    }
+
+    pub fn get_nb_point(&self) -> usize {
+        self.hnsw.get_nb_point()
+    }
+
+    pub fn insert(&self, data_with_id: (&[T], DataId)) {
+        let _gate = self.dump_gate.read();
+        self.hnsw.insert_slice(data_with_id);
+    }
+
+    pub fn insert_with_payload(&self, data_with_id: (&[T], DataId), payload: Payload) {
+        let _gate = self.dump_gate.read();
+        self.hnsw.insert_with_payload(data_with_id, payload);
+    }
+
+    /// parallel insertion, a dump asked meanwhile waits for the end of the current insertions
+    pub fn parallel_insert(&self, datas: &[(&[T], DataId)]) {
+        datas.par_iter().for_each(|&item| self.insert(item));
+    }
+
+    pub fn search(&self, data: &[T], knbn: usize, ef_arg: usize) -> Vec<Neighbour> {
+        self.hnsw.search(data, knbn, ef_arg)
+    }
+
+    pub fn search_with_params(
+        &self,
+        data: &[T],
+        knbn: usize,
+        ef_arg: usize,
+        params: &SearchParams,
+    ) -> Vec<Neighbour> {
+        self.hnsw.search_with_params(data, knbn, ef_arg, params)
+    }
+
+    pub fn parallel_search(&self, datas: &[Vec<T>], knbn: usize, ef: usize) -> Vec<Vec<Neighbour>> {
+        self.hnsw.parallel_search(datas, knbn, ef)
+    }
+} // end of impl ConcurrentHnsw
+
+impl<T, D> ConcurrentHnsw<'_, T, D>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// dumps the structure (see [AnnT::file_dump]). Insertions wait for the end of the dump, searches go on.
+    pub fn file_dump(&self, path: &Path, file_basename: &str) -> anyhow::Result<String> {
+        let _gate = self.dump_gate.write();
+        self.hnsw.file_dump(path, file_basename)
+    }
+} // end of impl ConcurrentHnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnswio::HnswIo;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    // the contract relies on these
+    fn assert_send_sync<S: Send + Sync>() {}
+
+    #[test]
+    fn test_concurrent_insert_search_dump() {
+        assert_send_sync::<Hnsw<'static, f32, DistL2>>();
+        assert_send_sync::<ConcurrentHnsw<'static, f32, DistL2>>();
+        //
+        let mut rng = rand::rngs::StdRng::seed_from_u64(935);
+        let data = synthetic::uniform(3000, 8, &mut rng);
+        let datas: Vec<(&[f32], usize)> = data.iter().map(|v| v.as_slice()).zip(0..).collect();
+        let shared = ConcurrentHnsw::new(Hnsw::<f32, DistL2>::new(16, 3000, 16, 100, DistL2 {}));
+        shared.parallel_insert(&datas[..1000]);
+        let directory = tempfile::tempdir().unwrap();
+        let mut dumps = Vec::new();
+        std::thread::scope(|s| {
+            s.spawn(|| shared.parallel_insert(&datas[1000..]));
+            s.spawn(|| {
+                for i in (0..1000).step_by(10) {
+                    assert!(!shared.search(&data[i], 5, 32).is_empty());
+                }
+            });
+            for k in 0..3 {
+                let nb_point = shared.get_nb_point();
+                let name = shared
+                    .file_dump(directory.path(), &format!("concurrent-{}", k))
+                    .unwrap();
+                dumps.push((name, nb_point));
+            }
+        });
+        assert_eq!(shared.get_nb_point(), 3000);
+        // each dump is a coherent structure
+        for (name, nb_before) in dumps {
+            let reloader = HnswIo::new(directory.path(), &name);
+            let reloaded = reloader.load_owned::<f32, DistL2>().unwrap();
+            assert!(reloaded.get_nb_point() >= nb_before);
+            let nb_linked = reloaded
+                .get_point_indexation()
+                .par_iter()
+                .filter(|p| !p.get_neighborhood_id()[0].is_empty())
+                .count();
+            assert!(nb_linked + 1 >= reloaded.get_nb_point());
+        }
+        let hnsw = shared.into_inner();
+        assert_eq!(hnsw.get_nb_point(), 3000);
+    } // end of test_concurrent_insert_search_dump
+} // end of mod tests
@@ -0,0 +1,214 @@
+//! Estimation of search and build times from a calibration run.
+//!
+//! [Hnsw::calibrate_costs] measures on the structure (and the machine) the time of a distance evaluation,
+//! the number of distances computed by a search as a function of ef, the time by distance of a search and the
+//! time of an insertion in a small structure with the same parameters. The resulting [CostModel] is kept by
+//! the structure and gives [Hnsw::estimate_search_cost] and [Hnsw::estimate_build_cost], so capacity planning
+//! does not need a run at full scale. A model is serializable, it can be saved and set on another process
+//! with [Hnsw::set_cost_model].
+//!
+//! Estimates are rough: the build cost of n points scales the measured insertion time by n * ln(n),
+//! the search cost is linear in ef (and does not depend on constraints of a search).
+
+use serde::{Deserialize, Serialize};
+use std::cell::Cell;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::Hnsw;
+use crate::searchparams::SearchParams;
+
+// ef of the two series of searches of a calibration
+const EF_LOW: usize = 16;
+const EF_HIGH: usize = 128;
+
+/// costs measured by [Hnsw::calibrate_costs]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct CostModel {
+    /// number of vectors used as queries and inserted
+    pub nb_sample: usize,
+    /// time of one distance evaluation in nanoseconds
+    pub distance_nanos: f64,
+    /// distances computed by a search: distances_base + distances_by_ef * ef
+    pub distances_base: f64,
+    pub distances_by_ef: f64,
+    /// time of a search by distance computed (distance and traversal) in nanoseconds
+    pub search_nanos_by_distance: f64,
+    /// time of an insertion in a structure of nb_sample points in nanoseconds
+    pub insert_nanos: f64,
+}
+
+impl CostModel {
+    /// number of distances computed by a search with ef
+    pub fn get_nb_distances(&self, ef: usize) -> f64 {
+        (self.distances_base + self.distances_by_ef * ef as f64).max(1.)
+    }
+
+    /// time of a search with ef
+    pub fn search_cost(&self, ef: usize) -> Duration {
+        Duration::from_nanos((self.get_nb_distances(ef) * self.search_nanos_by_distance) as u64)
+    }
+
+    /// time of the insertion of nb_point points by one thread
+    pub fn build_cost(&self, nb_point: usize) -> Duration {
+        let n = nb_point as f64;
+        let scale = match nb_point {
+            0 | 1 => 1.,
+            _ => n.ln() / (self.nb_sample.max(2) as f64).ln(),
+        };
+        Duration::from_nanos((n * self.insert_nanos * scale) as u64)
+    }
+} // end of impl CostModel
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// returns the model set by the last calibration or [Hnsw::set_cost_model]
+    pub fn get_cost_model(&self) -> Option<CostModel> {
+        *self.cost_model.read()
+    }
+
+    /// sets a model measured on another structure with the same parameters and type of data
+    pub fn set_cost_model(&self, model: CostModel) {
+        *self.cost_model.write() = Some(model);
+    }
+
+    /// estimated time of a search of knbn neighbours with ef, None if no cost model is set
+    pub fn estimate_search_cost(&self, ef: usize, knbn: usize) -> Option<Duration> {
+        self.get_cost_model()
+            .map(|model| model.search_cost(self.get_search_ef(knbn, ef)))
+    }
+
+    /// estimated time of the insertion of nb_point points by one thread (parallel insertion divides it by
+    /// about the number of threads), None if no cost model is set
+    pub fn estimate_build_cost(&self, nb_point: usize) -> Option<Duration> {
+        self.get_cost_model()
+            .map(|model| model.build_cost(nb_point))
+    }
+} // end of impl Hnsw
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Clone + Send + Sync,
+{
+    /// measures costs with nb_sample stored vectors (a few hundreds are enough), keeps the model and returns it.
+    /// The structure is not modified. Fails if it has less than 2 points.
+    pub fn calibrate_costs(&self, nb_sample: usize) -> anyhow::Result<CostModel> {
+        let indexation = self.get_point_indexation();
+        let step = (self.get_nb_point() / nb_sample.max(1)).max(1);
+        let samples: Vec<Vec<T>> = indexation
+            .iter_ids()
+            .step_by(step)
+            .take(nb_sample)
+            .filter_map(|(p_id, _)| indexation.get_point_data(&p_id))
+            .collect();
+        if samples.len() < 2 {
+            return Err(anyhow!("calibration needs at least 2 points"));
+        }
+        // distances between all samples
+        let start = Instant::now();
+        let mut sum = 0f32;
+        for a in &samples {
+            for b in &samples {
+                sum += self.dist_f.eval(a, b);
+            }
+        }
+        std::hint::black_box(sum);
+        let distance_nanos =
+            start.elapsed().as_nanos() as f64 / (samples.len() * samples.len()) as f64;
+        // searches with 2 values of ef, sample vectors as queries
+        let params = SearchParams::new();
+        let searches = |ef: usize| {
+            let nb_distances = Cell::new(0usize);
+            let start = Instant::now();
+            for query in &samples {
+                let dist_to_query = |v: &[T], _bound: f32| {
+                    nb_distances.set(nb_distances.get() + 1);
+                    self.dist_f.eval(query, v)
+                };
+                self.search_with(&dist_to_query, None, 10.min(ef), ef, &params);
+            }
+            let nb_search = samples.len() as f64;
+            (
+                nb_distances.get() as f64 / nb_search,
+                start.elapsed().as_nanos() as f64 / nb_search,
+            )
+        };
+        let (distances_low, nanos_low) = searches(EF_LOW);
+        let (distances_high, nanos_high) = searches(EF_HIGH);
+        let distances_by_ef =
+            ((distances_high - distances_low) / (EF_HIGH - EF_LOW) as f64).max(0.);
+        let distances_base = distances_low - distances_by_ef * EF_LOW as f64;
+        let search_nanos_by_distance = (nanos_low + nanos_high) / (distances_low + distances_high);
+        // insertions in a structure with the same parameters
+        let sample_hnsw = Hnsw::<T, D>::new(
+            self.max_nb_connection,
+            samples.len(),
+            self.max_layer,
+            self.ef_construction,
+            self.dist_f.clone(),
+        );
+        let start = Instant::now();
+        for (id, v) in samples.iter().enumerate() {
+            sample_hnsw.insert_slice((v, id));
+        }
+        let insert_nanos = start.elapsed().as_nanos() as f64 / samples.len() as f64;
+        let model = CostModel {
+            nb_sample: samples.len(),
+            distance_nanos,
+            distances_base,
+            distances_by_ef,
+            search_nanos_by_distance,
+            insert_nanos,
+        };
+        log::info!("calibrate_costs : {:?}", model);
+        self.set_cost_model(model);
+        Ok(model)
+    } // end of calibrate_costs
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_calibrate_costs() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(973);
+        let data = synthetic::uniform(3000, 16, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 3000, 16, 100, DistL2 {});
+        assert!(hnsw.calibrate_costs(100).is_err());
+        hnsw.parallel_insert(&data_with_id);
+        assert!(hnsw.estimate_search_cost(64, 10).is_none());
+        let model = hnsw.calibrate_costs(200).unwrap();
+        assert_eq!(model.nb_sample, 200);
+        assert!(model.distance_nanos > 0. && model.insert_nanos > 0.);
+        assert!(model.distances_by_ef > 0.);
+        assert!(model.get_nb_distances(EF_HIGH) > EF_HIGH as f64);
+        let (low, high) = (
+            hnsw.estimate_search_cost(16, 10).unwrap(),
+            hnsw.estimate_search_cost(256, 10).unwrap(),
+        );
+        assert!(low < high);
+        let build = hnsw.estimate_build_cost(100_000).unwrap();
+        assert!(build > 2 * hnsw.estimate_build_cost(50_000).unwrap());
+        // a model set on another structure gives the same estimates
+        let other = Hnsw::<f32, DistL2>::new(16, 3000, 16, 100, DistL2 {});
+        other
+            .set_cost_model(serde_json::from_str(&serde_json::to_string(&model).unwrap()).unwrap());
+        assert_eq!(
+            other.estimate_build_cost(1000).unwrap().as_micros(),
+            hnsw.estimate_build_cost(1000).unwrap().as_micros()
+        );
+    } // end of test_calibrate_costs
+} // end of mod tests
@@ -123,7 +123,8 @@ impl DataMap {
         //
         // where are we in decoding mmap slice?
         let mut current_mmap_addr = 0usize;
-        let mut usize_slice = [0u8; std::mem::size_of::<usize>()];
+        // sizes are dumped as u64, see hnswio
+        let mut u64_slice = [0u8; std::mem::size_of::<u64>()];
         // check magic
         let mut u32_slice = [0u8; std::mem::size_of::<u32>()];
         u32_slice.copy_from_slice(
@@ -133,11 +134,11 @@ impl DataMap {
         let magic = u32::from_ne_bytes(u32_slice);
         assert_eq!(magic, MAGICDATAP, "magic not equal to MAGICDATAP in mmap");
         // get dimension
-        usize_slice.copy_from_slice(
-            &mapped_slice[current_mmap_addr..current_mmap_addr + std::mem::size_of::<usize>()],
+        u64_slice.copy_from_slice(
+            &mapped_slice[current_mmap_addr..current_mmap_addr + std::mem::size_of::<u64>()],
         );
-        current_mmap_addr += std::mem::size_of::<usize>();
-        let dimension = usize::from_ne_bytes(usize_slice);
+        current_mmap_addr += std::mem::size_of::<u64>();
+        let dimension = u64::from_ne_bytes(u64_slice) as usize;
         if dimension != descr_dimension {
             error!("Description and data do not agree on dimension, data got : {:?}, description got : {:?}",dimension, descr_dimension);
             return Err(String::from(
@@ -165,7 +166,6 @@ impl DataMap {
         // allocate hmap with correct capacity
         let mut hmap = IndexMap::<DataId, usize>::with_capacity(nb_record);
         // fill hmap to have address of each data point in file
-        let mut u64_slice = [0u8; std::mem::size_of::<u64>()];
         //
         // now we loop on records
         //
@@ -0,0 +1,125 @@
+//! Datasets to test or validate parameter choices.
+//!
+//! The module synthetic generates data with known structure: uniform hypercube,
+//! gaussian mixtures and classes separated by hyperplanes.
+
+pub mod synthetic {
+    //! Synthetic data generators. All of them take the random generator so that runs can be reproduced
+    //! with a seeded rng.
+
+    use rand::Rng;
+    use rand::distr::{Distribution, Uniform};
+
+    /// a sample of the normal law N(0,1) (Box-Muller)
+    pub fn normal(rng: &mut impl Rng) -> f32 {
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let u1 = unif.sample(rng).max(f32::MIN_POSITIVE);
+        let u2 = unif.sample(rng);
+        (-2. * u1.ln()).sqrt() * (2. * std::f32::consts::PI * u2).cos()
+    }
+
+    /// nb vectors uniformly distributed in \[0,1\]^dim
+    pub fn uniform(nb: usize, dim: usize, rng: &mut impl Rng) -> Vec<Vec<f32>> {
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        (0..nb)
+            .map(|_| (0..dim).map(|_| unif.sample(rng)).collect())
+            .collect()
+    }
+
+    /// nb vectors around nb_cluster centers drawn uniformly in \[0,1\]^dim.
+    /// Each component of a vector is its center component plus a gaussian noise of standard deviation std_dev.
+    /// Returns vectors and the rank of their cluster.
+    pub fn gaussian_mixture(
+        nb: usize,
+        dim: usize,
+        nb_cluster: usize,
+        std_dev: f32,
+        rng: &mut impl Rng,
+    ) -> (Vec<Vec<f32>>, Vec<usize>) {
+        let centers = uniform(nb_cluster.max(1), dim, rng);
+        let mut labels = Vec::with_capacity(nb);
+        let data = (0..nb)
+            .map(|_| {
+                let label = rng.random_range(0..centers.len());
+                labels.push(label);
+                centers[label]
+                    .iter()
+                    .map(|c| c + std_dev * normal(rng))
+                    .collect()
+            })
+            .collect();
+        (data, labels)
+    }
+
+    /// nb vectors drawn in \[-1,1\]^dim then moved along a random unit direction w into the slab of their class.
+    /// The nb_class slabs divide \[-1,1\] along w.
+    /// Classes are separated by parallel hyperplanes and points are at least margin away from them.
+    /// Returns vectors and their class.
+    pub fn hyperplane_classes(
+        nb: usize,
+        dim: usize,
+        nb_class: usize,
+        margin: f32,
+        rng: &mut impl Rng,
+    ) -> (Vec<Vec<f32>>, Vec<usize>) {
+        let nb_class = nb_class.max(1);
+        // unit normal of hyperplanes
+        let mut w: Vec<f32> = (0..dim).map(|_| normal(rng)).collect();
+        let norm = w
+            .iter()
+            .map(|x| x * x)
+            .sum::<f32>()
+            .sqrt()
+            .max(f32::MIN_POSITIVE);
+        w.iter_mut().for_each(|x| *x /= norm);
+        // slabs have width 2/nb_class along w, centered on 0
+        let width = 2. / nb_class as f32;
+        let margin = margin.clamp(0., width / 2.);
+        let unif = Uniform::<f32>::new(-1., 1.).unwrap();
+        let mut labels = Vec::with_capacity(nb);
+        let data = (0..nb)
+            .map(|_| {
+                let label = rng.random_range(0..nb_class);
+                labels.push(label);
+                let mut x: Vec<f32> = (0..dim).map(|_| unif.sample(rng)).collect();
+                let proj: f32 = x.iter().zip(&w).map(|(a, b)| a * b).sum();
+                let low = -1. + label as f32 * width + margin;
+                let s = low + rng.random::<f32>() * (width - 2. * margin);
+                x.iter_mut()
+                    .zip(&w)
+                    .for_each(|(xi, wi)| *xi += (s - proj) * wi);
+                x
+            })
+            .collect();
+        (data, labels)
+    }
+} // end of mod synthetic
+
+#[cfg(test)]
+mod tests {
+
+    use super::synthetic::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_synthetic() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(17);
+        let data = uniform(100, 5, &mut rng);
+        assert!(data.iter().flatten().all(|x| (0. ..1.).contains(x)));
+        //
+        let (data, labels) = gaussian_mixture(1000, 8, 4, 0.01, &mut rng);
+        assert_eq!(data.len(), labels.len());
+        // points of same cluster are close
+        let j = (1..labels.len()).find(|&j| labels[j] == labels[0]).unwrap();
+        let d: f32 = data[0]
+            .iter()
+            .zip(&data[j])
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum();
+        assert!(d.sqrt() < 0.2);
+        //
+        let (data, labels) = hyperplane_classes(500, 10, 3, 0.05, &mut rng);
+        assert_eq!(data.len(), 500);
+        assert!(labels.iter().all(|&l| l < 3));
+    } // end of test_synthetic
+} // end of mod tests
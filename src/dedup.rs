@@ -0,0 +1,287 @@
+//! Deduplication of vectors at insertion.
+//!
+//! Once a policy is set with [Hnsw::set_dedup], an insertion of a vector with the same bytes as an already
+//! inserted one does not create a new point: its id becomes an alias (see [crate::alias]) of the first point
+//! (the canonical id).
+//! With [DedupPolicy::Near] a vector closer than epsilon to an existing point (found by a quick search)
+//! is also skipped or mapped to it.
+//!
+//! A vector is known to the deduplicator once its point is linked, so with parallel insertion two identical
+//! or close vectors inserted at the same time can both be kept. A deleted point is forgotten, its vector can be
+//! inserted again.
+//! Only points inserted after the policy is set are known to the deduplicator.
+//! Duplicates are searched in the namespace of the inserted vector (see [crate::namespace]).
+//!
+//! [Hnsw::contains_within] is the check used for near duplicates, for streams deduplicated by the client.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Neighbour, Point};
+use crate::payload::Namespace;
+use crate::searchparams::SearchParams;
+
+/// what to do with a vector that is a near duplicate of an existing point
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum NearAction {
+    /// the vector is not inserted, its id is forgotten
+    Skip,
+    /// the vector is not inserted, its id becomes an alias of the existing point
+    Alias,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DedupPolicy {
+    /// vectors with same bytes as an existing point are mapped to it
+    Exact,
+    /// exact duplicates are mapped as with Exact, and vectors at distance less than epsilon
+    /// of an existing point are handled according to action
+    Near { epsilon: f32, action: NearAction },
+}
+
+/// state of deduplication stored in a Hnsw
+pub(crate) struct Dedup {
+    policy: DedupPolicy,
+    /// namespace and hash of vector bytes to canonical id
+    hashes: Mutex<HashMap<(Namespace, u128), DataId>>,
+    nb_duplicates: AtomicUsize,
+}
+
+impl Clone for Dedup {
+    fn clone(&self) -> Self {
+        Dedup {
+            policy: self.policy,
+            hashes: Mutex::new(self.hashes.lock().clone()),
+            nb_duplicates: AtomicUsize::new(self.nb_duplicates.load(Ordering::Relaxed)),
+        }
+    }
+}
+
+impl Dedup {
+    fn new(policy: DedupPolicy) -> Self {
+        Dedup {
+            policy,
+            hashes: Mutex::new(HashMap::new()),
+            nb_duplicates: AtomicUsize::new(0),
+        }
+    }
+}
+
+// 128 bits from 2 differently seeded hashers, so collisions can be ignored
+pub(crate) fn hash_bytes<T>(data: &[T]) -> u128 {
+    // same view of data as in dumps
+    let bytes = unsafe {
+        std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+    };
+    let mut h1 = DefaultHasher::new();
+    bytes.hash(&mut h1);
+    let mut h2 = DefaultHasher::new();
+    0x9e3779b97f4a7c15u64.hash(&mut h2);
+    bytes.hash(&mut h2);
+    ((h1.finish() as u128) << 64) | h2.finish() as u128
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// deduplicate vectors inserted from now on according to policy
+    pub fn set_dedup(&mut self, policy: DedupPolicy) {
+        self.dedup = Some(Dedup::new(policy));
+    }
+
+    /// number of vectors not inserted since the policy was set as they were duplicates
+    pub fn get_nb_duplicates(&self) -> usize {
+        self.dedup
+            .as_ref()
+            .map(|dedup| dedup.nb_duplicates.load(Ordering::Relaxed))
+            .unwrap_or(0)
+    }
+
+    fn record_duplicate(&self, dedup: &Dedup, origin_id: DataId, canonical: DataId, alias: bool) {
+        dedup.nb_duplicates.fetch_add(1, Ordering::Relaxed);
+        if alias && origin_id != canonical {
+            self.aliases.write().insert(origin_id, canonical);
+        }
+    }
+
+    /// returns a point within distance epsilon of query, or None if the search finds none.  
+    /// The search has a small ef and stops at the first point found within epsilon (see
+    /// [SearchParams::stop_at_distance]), so the point is not necessarily the nearest one.
+    /// As any search it can miss a point, but rarely for a point very close to the query.
+    pub fn contains_within(&self, query: &[T], epsilon: f32) -> Option<Neighbour> {
+        self.find_within(query, epsilon, SearchParams::new())
+    }
+
+    fn find_within(&self, query: &[T], epsilon: f32, params: SearchParams) -> Option<Neighbour> {
+        let ef = self.max_nb_connection.max(16);
+        let params = params.stop_at_distance(epsilon);
+        self.search_with_params(query, 1, ef, &params)
+            .into_iter()
+            .next()
+            .filter(|n| n.distance <= epsilon)
+    }
+
+    // returns the canonical id if data must not be inserted in namespace
+    pub(crate) fn find_duplicate(
+        &self,
+        data: &[T],
+        origin_id: DataId,
+        namespace: Namespace,
+    ) -> Option<DataId> {
+        let dedup = self.dedup.as_ref()?;
+        if let Some(&canonical) = dedup.hashes.lock().get(&(namespace, hash_bytes(data))) {
+            self.record_duplicate(dedup, origin_id, canonical, true);
+            return Some(canonical);
+        }
+        if let DedupPolicy::Near { epsilon, action } = dedup.policy {
+            // the namespace constraint is only needed once namespaces are used
+            let params = match namespace == 0 && self.namespaces.is_empty() {
+                true => SearchParams::new(),
+                false => SearchParams::new().in_namespace(namespace),
+            };
+            if let Some(near) = self.find_within(data, epsilon, params) {
+                if near.distance < epsilon {
+                    let alias = action == NearAction::Alias;
+                    self.record_duplicate(dedup, origin_id, near.d_id, alias);
+                    return Some(near.d_id);
+                }
+            }
+        }
+        None
+    } // end of find_duplicate
+
+    // makes the vector of a linked point known to the deduplicator
+    pub(crate) fn record_vector(&self, data: &[T], origin_id: DataId, namespace: Namespace) {
+        if let Some(dedup) = self.dedup.as_ref() {
+            dedup
+                .hashes
+                .lock()
+                .entry((namespace, hash_bytes(data)))
+                .or_insert(origin_id);
+        }
+    }
+
+    // forgets vectors of deleted points, they can be inserted again
+    pub(crate) fn forget_vectors(&self, points: &[Arc<Point<T>>]) {
+        let Some(dedup) = self.dedup.as_ref() else {
+            return;
+        };
+        let payloads = self.layer_indexed_points.payloads.read();
+        let mut hashes = dedup.hashes.lock();
+        for point in points {
+            let key = (
+                payloads.get(point.get_point_id()).get_namespace(),
+                hash_bytes(point.get_v()),
+            );
+            if hashes.get(&key) == Some(&point.get_origin_id()) {
+                hashes.remove(&key);
+            }
+        }
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::HnswError;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_dedup() {
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 100, 16, 100, DistL2 {});
+        hnsw.set_dedup(DedupPolicy::Near {
+            epsilon: 0.01,
+            action: NearAction::Alias,
+        });
+        let data: Vec<Vec<f32>> = (0..50).map(|i| vec![i as f32, 1., 2.]).collect();
+        for (i, v) in data.iter().enumerate() {
+            assert_eq!(hnsw.insert_dedup((v, i)), Ok(i));
+        }
+        // exact duplicate
+        assert_eq!(hnsw.insert_dedup((&data[7], 100)), Ok(7));
+        // near duplicate
+        assert_eq!(hnsw.insert_dedup((&[12.001, 1., 2.], 101)), Ok(12));
+        assert_eq!(hnsw.insert_dedup((&[12.5, 1., 2.], 102)), Ok(102));
+        assert_eq!(hnsw.get_nb_point(), 51);
+        assert_eq!(hnsw.get_canonical_id(100), 7);
+        assert_eq!(hnsw.get_canonical_id(101), 12);
+        assert_eq!(hnsw.get_canonical_id(102), 102);
+        assert_eq!(hnsw.get_nb_duplicates(), 2);
+        //
+        hnsw.set_dedup(DedupPolicy::Near {
+            epsilon: 0.01,
+            action: NearAction::Skip,
+        });
+        assert_eq!(hnsw.insert_dedup((&[3.001, 1., 2.], 103)), Ok(3));
+        assert_eq!(hnsw.get_canonical_id(103), 103);
+        assert_eq!(hnsw.get_nb_duplicates(), 1);
+        assert_eq!(hnsw.get_nb_point(), 51);
+    } // end of test_dedup
+
+    #[test]
+    fn test_dedup_removed() {
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 100, 16, 100, DistL2 {});
+        hnsw.set_dedup(DedupPolicy::Exact);
+        hnsw.set_level_seed(921);
+        let data: Vec<Vec<f32>> = (0..30).map(|i| vec![i as f32, 9., 21.]).collect();
+        for (i, v) in data.iter().enumerate() {
+            assert_eq!(hnsw.insert_dedup((v, i)), Ok(i));
+        }
+        assert_eq!(hnsw.insert_dedup((&data[5], 100)), Ok(5));
+        // a deleted point is forgotten, its vector can be inserted again
+        assert_eq!(hnsw.remove_batch(&[5]), Ok(1));
+        assert_eq!(hnsw.insert_dedup((&data[5], 101)), Ok(101));
+        assert_eq!(hnsw.insert_dedup((&data[5], 102)), Ok(101));
+        assert_eq!(hnsw.search(&data[5], 1, 16)[0].d_id, 101);
+        // also with its old id and after remove_where
+        assert_eq!(hnsw.remove_where(|id, _| id == 101), Ok(1));
+        assert_eq!(hnsw.insert_dedup((&data[5], 5)), Ok(5));
+        assert_eq!(hnsw.remove_where(|id, _| id == 5 || id == 7), Ok(2));
+        assert_eq!(hnsw.insert_dedup((&data[7], 7)), Ok(7));
+        assert_eq!(hnsw.insert_dedup((&data[5], 5)), Ok(5));
+        assert_eq!(hnsw.search(&data[5], 1, 16)[0].d_id, 5);
+        assert_eq!(hnsw.get_nb_duplicates(), 2);
+        // a vector whose insertion panicked is not known
+        let nan = vec![f32::NAN, 9., 21.];
+        for _ in 0..2 {
+            assert!(matches!(
+                hnsw.try_insert((&nan, 200)),
+                Err(HnswError::Panic { origin_id: 200, .. })
+            ));
+        }
+    } // end of test_dedup_removed
+
+    #[test]
+    fn test_contains_within() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(963);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        assert!(hnsw.contains_within(&data[0], 1.).is_none());
+        hnsw.parallel_insert(&data_with_id);
+        // slightly moved points are found
+        let nb_found = (0..1000)
+            .step_by(10)
+            .filter(|&i| {
+                let moved: Vec<f32> = data[i].iter().map(|x| x + 1.0e-4).collect();
+                hnsw.contains_within(&moved, 1.0e-2)
+                    .is_some_and(|n| n.d_id == i && n.distance <= 1.0e-2)
+            })
+            .count();
+        assert!(nb_found >= 95, "nb_found {} / 100", nb_found);
+        // nothing is close to a point far from the unit cube
+        assert!(hnsw.contains_within(&[10.; 8], 1.).is_none());
+        let far = hnsw.search(&[10.; 8], 1, 16)[0].distance;
+        assert!(hnsw.contains_within(&[10.; 8], far * 1.01).is_some());
+    } // end of test_contains_within
+} // end of mod tests
@@ -0,0 +1,425 @@
+//! Comparison of the neighbourhoods given by two indexes.
+//!
+//! [compare] searches the same queries (vectors sampled from the first index) in two indexes holding
+//! the same data, for example before and after a crate upgrade or a change of parameters, and reports in an
+//! [OverlapReport] how much the neighbours found agree and how their distances differ. [compare_queries] does it
+//! with given queries and ef.
+//!
+//! Overlaps are between data ids, distances are those of each index, so the deltas only make sense
+//! if both indexes have the same distance.
+//!
+//! [Hnsw::explain] runs a search recording each point it evaluates, layer by layer, in a [SearchTrace].
+//! [SearchTrace::get_steps_of] then answers "why did point x not come back": it was never reached, or it was
+//! reached but pruned as farther than the ef best points found at that time.
+//! A trace is serializable (serde_json::to_string) to be looked at outside of the program.
+
+use std::collections::BinaryHeap;
+use std::fmt;
+use std::sync::Arc;
+
+use hashbrown::HashSet;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Point, PointWithOrder};
+
+/// agreement of the neighbours of two indexes, see [compare]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct OverlapReport {
+    pub nb_query: usize,
+    pub knbn: usize,
+    /// mean over queries of the fraction of neighbours found by a also found by b
+    pub mean_overlap: f64,
+    /// lowest overlap of a query
+    pub min_overlap: f64,
+    /// number of queries with the same neighbours in the same order
+    pub nb_identical: usize,
+    /// mean over queries and ranks of the distance found by b minus the one found by a,
+    /// positive if b finds farther neighbours
+    pub mean_distance_delta: f64,
+    /// largest absolute difference between distances at the same rank
+    pub max_distance_delta: f64,
+}
+
+impl fmt::Display for OverlapReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nb query : {}, knbn : {}, overlap mean : {:.3} min : {:.3}, identical : {}, distance delta mean : {:.3e} max : {:.3e}",
+            self.nb_query,
+            self.knbn,
+            self.mean_overlap,
+            self.min_overlap,
+            self.nb_identical,
+            self.mean_distance_delta,
+            self.max_distance_delta
+        )
+    }
+}
+
+/// compares the knbn neighbours found by a and b for sample vectors of a, evenly spaced in its layers.
+/// The search ef is max(2 * knbn, 64) for both.
+pub fn compare<T, D1, D2>(
+    a: &Hnsw<T, D1>,
+    b: &Hnsw<T, D2>,
+    sample: usize,
+    knbn: usize,
+) -> OverlapReport
+where
+    T: Clone + Send + Sync,
+    D1: Distance<T> + Send + Sync,
+    D2: Distance<T> + Send + Sync,
+{
+    let indexation = a.get_point_indexation();
+    let step = (a.get_nb_point() / sample.max(1)).max(1);
+    let queries: Vec<Vec<T>> = indexation
+        .iter_ids()
+        .step_by(step)
+        .take(sample)
+        .filter_map(|(p_id, _)| indexation.get_point_data(&p_id))
+        .collect();
+    compare_queries(a, b, &queries, knbn, (2 * knbn).max(64))
+}
+
+/// compares the knbn neighbours found by a and b for queries, with search parameter ef
+pub fn compare_queries<T, D1, D2>(
+    a: &Hnsw<T, D1>,
+    b: &Hnsw<T, D2>,
+    queries: &[Vec<T>],
+    knbn: usize,
+    ef: usize,
+) -> OverlapReport
+where
+    T: Clone + Send + Sync,
+    D1: Distance<T> + Send + Sync,
+    D2: Distance<T> + Send + Sync,
+{
+    // by query overlap, identical, sum and max of distance deltas, number of ranks compared
+    let by_query: Vec<(f64, bool, f64, f64, usize)> = queries
+        .par_iter()
+        .map(|q| {
+            let res_a = a.search(q, knbn, ef);
+            let res_b = b.search(q, knbn, ef);
+            let nb_common = res_a
+                .iter()
+                .filter(|n| res_b.iter().any(|m| m.d_id == n.d_id))
+                .count();
+            let overlap = if res_a.is_empty() {
+                if res_b.is_empty() { 1. } else { 0. }
+            } else {
+                nb_common as f64 / res_a.len() as f64
+            };
+            let identical = res_a.len() == res_b.len()
+                && res_a.iter().zip(&res_b).all(|(n, m)| n.d_id == m.d_id);
+            let deltas = res_a
+                .iter()
+                .zip(&res_b)
+                .map(|(n, m)| m.distance as f64 - n.distance as f64);
+            let (sum, max, nb) = deltas.fold((0., 0f64, 0), |(sum, max, nb), d| {
+                (sum + d, max.max(d.abs()), nb + 1)
+            });
+            (overlap, identical, sum, max, nb)
+        })
+        .collect();
+    if by_query.is_empty() {
+        return OverlapReport {
+            knbn,
+            ..Default::default()
+        };
+    }
+    let nb_ranks: usize = by_query.iter().map(|q| q.4).sum();
+    OverlapReport {
+        nb_query: by_query.len(),
+        knbn,
+        mean_overlap: by_query.iter().map(|q| q.0).sum::<f64>() / by_query.len() as f64,
+        min_overlap: by_query.iter().map(|q| q.0).fold(1., f64::min),
+        nb_identical: by_query.iter().filter(|q| q.1).count(),
+        mean_distance_delta: by_query.iter().map(|q| q.2).sum::<f64>() / nb_ranks.max(1) as f64,
+        max_distance_delta: by_query.iter().map(|q| q.3).fold(0., f64::max),
+    }
+} // end of compare_queries
+
+/// a point evaluated by a search, see [Hnsw::explain]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TraceStep {
+    /// data id of the point whose neighbours were scanned
+    pub from: DataId,
+    pub d_id: DataId,
+    pub distance: f32,
+    /// in layer 0 the point entered the candidates, in upper layers it became the best point.
+    /// Otherwise it was pruned as too far.
+    pub kept: bool,
+    /// deleted points are traversed but not returned
+    pub deleted: bool,
+}
+
+/// points evaluated in a layer, in the order of the search
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerTrace {
+    pub layer: u8,
+    /// data id of the point the search of the layer starts from and its distance to the query
+    pub entry: DataId,
+    pub entry_distance: f32,
+    pub steps: Vec<TraceStep>,
+    /// number of points whose neighbours were scanned
+    pub nb_expanded: usize,
+}
+
+/// the path of a search, given by [Hnsw::explain]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SearchTrace {
+    pub knbn: usize,
+    /// ef used in layer 0, after the raise of [Hnsw::get_search_ef]
+    pub ef: usize,
+    /// from the layer of the entry point down to layer 0
+    pub layers: Vec<LayerTrace>,
+    /// (data id, distance) of neighbours returned, as by [Hnsw::search]
+    pub neighbours: Vec<(DataId, f32)>,
+}
+
+impl SearchTrace {
+    /// returns the layers and steps where point d_id was evaluated. Empty if the search never reached it.
+    pub fn get_steps_of(&self, d_id: DataId) -> Vec<(u8, TraceStep)> {
+        self.layers
+            .iter()
+            .flat_map(|l| l.steps.iter().map(move |s| (l.layer, *s)))
+            .filter(|(_, s)| s.d_id == d_id)
+            .collect()
+    }
+
+    /// number of distances computed
+    pub fn get_nb_distances(&self) -> usize {
+        self.layers.iter().map(|l| 1 + l.steps.len()).sum()
+    }
+} // end of impl SearchTrace
+
+impl<'b, T, D> Hnsw<'b, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// searches as [Hnsw::search] and records in a [SearchTrace] every point evaluated, layer by layer.  
+    /// It is a separate (slower) implementation of the same algorithm, made to understand a result, not to search.
+    pub fn explain(&self, query: &[T], knbn: usize, ef_arg: usize) -> SearchTrace {
+        let ef = self.get_search_ef(knbn, ef_arg);
+        let mut trace = SearchTrace {
+            knbn,
+            ef,
+            layers: Vec::new(),
+            neighbours: Vec::new(),
+        };
+        let Some(entry_point) = self.layer_indexed_points.entry_point.read().clone() else {
+            return trace;
+        };
+        let dist = |p: &Point<T>| self.get_distance().eval(query, p.get_v());
+        // greedy descent: one pass on the neighbours of the pivot in each layer
+        let mut pivot = entry_point;
+        let mut dist_to_pivot = dist(&pivot);
+        for layer in (1..=pivot.get_point_id().0).rev() {
+            let mut layer_trace = self.new_layer_trace(layer, &pivot, dist_to_pivot);
+            let mut best = Arc::clone(&pivot);
+            for n in pivot.neighbours.read()[layer as usize].iter() {
+                let d = dist(&n.point_ref);
+                let kept = d < dist_to_pivot;
+                layer_trace
+                    .steps
+                    .push(self.new_step(&pivot, &n.point_ref, d, kept));
+                if kept {
+                    best = Arc::clone(&n.point_ref);
+                    dist_to_pivot = d;
+                }
+            }
+            layer_trace.nb_expanded = 1;
+            trace.layers.push(layer_trace);
+            pivot = best;
+        }
+        // lowest non empty layer, as in search
+        let layer = (0..)
+            .find(|l| self.get_point_indexation().get_layer_nb_point(*l as usize) > 0)
+            .unwrap();
+        let (layer_trace, mut found) = self.trace_layer(&dist, pivot, dist_to_pivot, ef, layer);
+        trace.layers.push(layer_trace);
+        found.sort_by(|a, b| {
+            a.dist_to_ref.total_cmp(&b.dist_to_ref).then(
+                a.point_ref
+                    .get_origin_id()
+                    .cmp(&b.point_ref.get_origin_id()),
+            )
+        });
+        trace.neighbours = found
+            .iter()
+            .filter(|p| !(self.has_deleted() && self.is_deleted(&p.point_ref)))
+            .take(knbn)
+            .map(|p| (p.point_ref.get_origin_id(), p.dist_to_ref))
+            .collect();
+        trace
+    } // end of explain
+
+    fn new_layer_trace(&self, layer: u8, entry: &Point<T>, entry_distance: f32) -> LayerTrace {
+        LayerTrace {
+            layer,
+            entry: entry.get_origin_id(),
+            entry_distance,
+            steps: Vec::new(),
+            nb_expanded: 0,
+        }
+    }
+
+    fn new_step(&self, from: &Point<T>, point: &Point<T>, distance: f32, kept: bool) -> TraceStep {
+        TraceStep {
+            from: from.get_origin_id(),
+            d_id: point.get_origin_id(),
+            distance,
+            kept,
+            deleted: self.has_deleted() && self.is_deleted(point),
+        }
+    }
+
+    // the search of a layer as search_layer, without constraints other than deletions
+    #[allow(clippy::type_complexity)]
+    fn trace_layer<F>(
+        &self,
+        dist: &F,
+        entry: Arc<Point<'b, T>>,
+        dist_to_entry: f32,
+        ef: usize,
+        layer: u8,
+    ) -> (LayerTrace, Vec<Arc<PointWithOrder<'b, T>>>)
+    where
+        F: Fn(&Point<T>) -> f32,
+    {
+        let mut layer_trace = self.new_layer_trace(layer, &entry, dist_to_entry);
+        let has_deleted = self.has_deleted();
+        let accept = |p: &Point<T>| !(has_deleted && self.is_deleted(p));
+        let mut visited = HashSet::new();
+        visited.insert(entry.get_point_id());
+        // candidates have negative distances, the top of found is the farthest point
+        let mut candidates = BinaryHeap::new();
+        let mut found: BinaryHeap<Arc<PointWithOrder<'b, T>>> = BinaryHeap::new();
+        candidates.push(Arc::new(PointWithOrder::new(&entry, -dist_to_entry)));
+        found.push(Arc::new(PointWithOrder::new(&entry, dist_to_entry)));
+        while let Some(c) = candidates.pop() {
+            let farthest = found.peek().unwrap().dist_to_ref;
+            if -c.dist_to_ref > farthest && (!has_deleted || found.len() >= ef) {
+                break;
+            }
+            layer_trace.nb_expanded += 1;
+            for e in c.point_ref.neighbours.read()[layer as usize].iter() {
+                if !visited.insert(e.point_ref.get_point_id()) {
+                    continue;
+                }
+                let farthest = found.peek().unwrap().dist_to_ref;
+                let d = dist(&e.point_ref);
+                let kept = d < farthest || found.len() < ef;
+                layer_trace
+                    .steps
+                    .push(self.new_step(&c.point_ref, &e.point_ref, d, kept));
+                if !kept {
+                    continue;
+                }
+                candidates.push(Arc::new(PointWithOrder::new(&e.point_ref, -d)));
+                if accept(&e.point_ref) {
+                    if found.len() == 1 && !accept(&found.peek().unwrap().point_ref) {
+                        found.clear();
+                    }
+                    found.push(Arc::new(PointWithOrder::new(&e.point_ref, d)));
+                }
+                if found.len() > ef {
+                    found.pop();
+                }
+            }
+        }
+        (layer_trace, found.into_vec())
+    } // end of trace_layer
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_compare() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(965);
+        let data = synthetic::uniform(2000, 16, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let good = Hnsw::<f32, DistL2>::new(24, 2000, 16, 200, DistL2 {});
+        good.parallel_insert(&data_with_id);
+        // a copy gives the same neighbours
+        let report = compare(&good, &good.deep_clone(), 100, 10);
+        assert_eq!(report.nb_query, 100);
+        assert_eq!(report.nb_identical, 100);
+        assert_eq!(report.mean_overlap, 1.);
+        assert_eq!(report.max_distance_delta, 0.);
+        // a sparse graph searched with a small ef finds farther neighbours
+        let poor = Hnsw::<f32, DistL2>::new(3, 2000, 16, 10, DistL2 {});
+        poor.parallel_insert(&data_with_id);
+        let queries = synthetic::uniform(100, 16, &mut rng);
+        let report = compare_queries(&good, &poor, &queries, 10, 10);
+        log::info!("{}", report);
+        assert!(report.mean_overlap < 0.95, "{}", report);
+        assert!(report.min_overlap <= report.mean_overlap);
+        assert!(report.nb_identical < 100);
+        assert!(report.mean_distance_delta > 0., "{}", report);
+        let json = serde_json::to_string(&report).unwrap();
+        let back: OverlapReport = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.nb_identical, report.nb_identical);
+        assert!((back.mean_distance_delta - report.mean_distance_delta).abs() < 1.0e-9);
+    } // end of test_compare
+
+    #[test]
+    fn test_explain() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(966);
+        let data = synthetic::uniform(2000, 16, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 2000, 16, 100, DistL2 {});
+        assert!(hnsw.explain(&data[0], 10, 32).layers.is_empty());
+        hnsw.parallel_insert(&data_with_id);
+        hnsw.remove_batch(&(0..2000).step_by(7).collect::<Vec<usize>>())
+            .unwrap();
+        // the trace gives the result of search
+        let queries = synthetic::uniform(50, 16, &mut rng);
+        for q in &queries {
+            let trace = hnsw.explain(q, 10, 32);
+            let res: Vec<(DataId, f32)> = hnsw
+                .search(q, 10, 32)
+                .iter()
+                .map(|n| (n.d_id, n.distance))
+                .collect();
+            assert_eq!(trace.neighbours, res);
+            assert_eq!(trace.layers.last().unwrap().layer, 0);
+            for (d_id, _) in &res {
+                let steps = trace.get_steps_of(*d_id);
+                let is_entry = trace.layers.iter().any(|l| l.entry == *d_id);
+                assert!(is_entry || steps.iter().any(|(l, s)| *l == 0 && s.kept));
+            }
+        }
+        // points pruned in layer 0 are not returned
+        let trace = hnsw.explain(&queries[0], 10, 32);
+        assert!(trace.get_nb_distances() < 2000);
+        let pruned: Vec<DataId> = trace
+            .layers
+            .last()
+            .unwrap()
+            .steps
+            .iter()
+            .filter(|s| !s.kept)
+            .map(|s| s.d_id)
+            .collect();
+        assert!(!pruned.is_empty());
+        assert!(
+            pruned
+                .iter()
+                .all(|id| trace.neighbours.iter().all(|n| n.0 != *id))
+        );
+        let json = serde_json::to_string(&trace).unwrap();
+        let back: SearchTrace = serde_json::from_str(&json).unwrap();
+        assert_eq!(back.neighbours.len(), 10);
+        assert_eq!(back.layers.len(), trace.layers.len());
+    } // end of test_explain
+} // end of mod tests
@@ -0,0 +1,162 @@
+//! Dynamic time warping distance between time series, with a Sakoe-Chiba band (feature *dtw*).
+//!
+//! The DTW aligns two series, each point of one matched to one or more consecutive points of the other, and sums
+//! the squared differences of matched points along the best alignment. The band limits matches of point i
+//! to points j with |i - j| <= band: the cost is O(len * band) instead of O(len²) and pathological alignments
+//! (a whole series matched to one point) are excluded. With band 0 and series of equal lengths it is the L2 distance.
+//!
+//! [DistDtw] is a [DistKernel] (see [crate::kernel]): the 2 rows of the dynamic programming are a buffer
+//! of each thread, an evaluation does not allocate. Series can have different lengths, the band is then widened
+//! to the difference of lengths so that an alignment exists.
+//!
+//! Caveats: DTW is not a metric, the triangle inequality does not hold (and distinct series can be at distance 0,
+//! a series and its repeated points for example). Hnsw graphs rely on neighbours of neighbours being close,
+//! with DTW the construction still works on real series but recall is lower for the same ef than with a metric:
+//! measure it and increase ef (or ef_construction) if needed. A narrow band is closer to L2 and better behaved.
+
+use crate::kernel::{DistKernel, DistanceKernel};
+
+/// DTW between f32 series restricted to a Sakoe-Chiba band, to be used through [DistDtw].
+#[derive(Copy, Clone, Debug)]
+pub struct DtwBanded {
+    band: usize,
+}
+
+impl DtwBanded {
+    pub fn new(band: usize) -> Self {
+        DtwBanded { band }
+    }
+
+    pub fn get_band(&self) -> usize {
+        self.band
+    }
+} // end of impl DtwBanded
+
+impl DistanceKernel<f32> for DtwBanded {
+    /// 2 rows of cumulated costs
+    type State = Vec<f32>;
+
+    fn new_state(&self) -> Vec<f32> {
+        Vec::new()
+    }
+
+    fn eval(&self, rows: &mut Vec<f32>, va: &[f32], vb: &[f32]) -> f32 {
+        let (n, m) = (va.len(), vb.len());
+        if n == 0 || m == 0 {
+            return if n == m { 0. } else { f32::INFINITY };
+        }
+        let band = self.band.max(n.abs_diff(m));
+        rows.clear();
+        rows.resize(2 * (m + 1), f32::INFINITY);
+        let (prev, curr) = rows.split_at_mut(m + 1);
+        let (mut prev, mut curr) = (prev, curr);
+        prev[0] = 0.;
+        // cells outside the band of a row are never written, the ones just outside are set to infinity
+        // as the next row reads them
+        for i in 1..=n {
+            let lo = i.saturating_sub(band).max(1);
+            let hi = (i + band).min(m);
+            curr[lo - 1] = f32::INFINITY;
+            for j in lo..=hi {
+                let diff = va[i - 1] - vb[j - 1];
+                curr[j] = diff * diff + prev[j - 1].min(prev[j]).min(curr[j - 1]);
+            }
+            if hi < m {
+                curr[hi + 1] = f32::INFINITY;
+            }
+            std::mem::swap(&mut prev, &mut curr);
+        }
+        prev[m].sqrt()
+    }
+} // end of impl DistanceKernel for DtwBanded
+
+/// banded DTW as a [Distance](anndists::dist::distances::Distance) of a Hnsw, see module doc
+pub type DistDtw = DistKernel<DtwBanded>;
+
+impl DistDtw {
+    /// DTW with a Sakoe-Chiba band of width band
+    pub fn with_band(band: usize) -> Self {
+        DistKernel::new(DtwBanded::new(band))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hnsw::Hnsw;
+    use anndists::dist::{DistL2, Distance};
+    use rand::{Rng, SeedableRng};
+
+    // full quadratic DTW restricted to the band
+    fn dtw_naive(va: &[f32], vb: &[f32], band: usize) -> f32 {
+        let (n, m) = (va.len(), vb.len());
+        let mut cost = vec![vec![f32::INFINITY; m + 1]; n + 1];
+        cost[0][0] = 0.;
+        for i in 1..=n {
+            for j in 1..=m {
+                if i.abs_diff(j) <= band {
+                    let diff = va[i - 1] - vb[j - 1];
+                    cost[i][j] =
+                        diff * diff + cost[i - 1][j - 1].min(cost[i - 1][j]).min(cost[i][j - 1]);
+                }
+            }
+        }
+        cost[n][m].sqrt()
+    }
+
+    // sine of random frequency and phase, with noise
+    fn series(len: usize, rng: &mut impl Rng) -> Vec<f32> {
+        let (freq, phase) = (rng.random_range(1. ..4.), rng.random_range(0. ..6.28));
+        (0..len)
+            .map(|t| {
+                (freq * t as f32 / len as f32 * 6.28 + phase).sin() + rng.random_range(-0.05..0.05)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_dtw() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(981);
+        let data: Vec<Vec<f32>> = (0..500).map(|_| series(64, &mut rng)).collect();
+        // same results as the quadratic computation, lengths can differ
+        for band in [0, 1, 5, 64] {
+            let dist = DistDtw::with_band(band);
+            for (va, vb) in data.iter().zip(data.iter().skip(1)).take(20) {
+                let expected = dtw_naive(va, vb, band);
+                assert!((dist.eval(va, vb) - expected).abs() <= 1.0e-4 * expected.max(1.));
+                let expected = dtw_naive(va, &vb[..50], band.max(14));
+                assert!((dist.eval(va, &vb[..50]) - expected).abs() <= 1.0e-4 * expected.max(1.));
+            }
+        }
+        let (l2, dtw) = (DistL2 {}, DistDtw::with_band(8));
+        assert!(
+            (DistDtw::with_band(0).eval(&data[0], &data[1]) - l2.eval(&data[0], &data[1])).abs()
+                < 1.0e-4
+        );
+        // a delayed series is close under DTW, not under L2
+        let delayed: Vec<f32> = std::iter::repeat_n(data[0][0], 4)
+            .chain(data[0][..60].iter().copied())
+            .collect();
+        assert!(dtw.eval(&data[0], &delayed) < 0.5 * l2.eval(&data[0], &delayed));
+        // an index on DTW finds the nearest series of delayed queries, as an exhaustive search does
+        let hnsw = Hnsw::<f32, DistDtw>::new(16, 500, 16, 100, DistDtw::with_band(8));
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        hnsw.parallel_insert(&data_with_id);
+        let nb_found = (0..100)
+            .filter(|i| {
+                let query: Vec<f32> = std::iter::repeat_n(data[*i][0], 3)
+                    .chain(data[*i][..61].iter().copied())
+                    .collect();
+                let nearest = (0..data.len())
+                    .min_by(|a, b| {
+                        dtw.eval(&query, &data[*a])
+                            .total_cmp(&dtw.eval(&query, &data[*b]))
+                    })
+                    .unwrap();
+                hnsw.search(&query, 10, 64)[0].d_id == nearest
+            })
+            .count();
+        assert!(nb_found >= 90, "nb_found {}", nb_found);
+    } // end of test_dtw
+} // end of mod tests
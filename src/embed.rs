@@ -0,0 +1,223 @@
+//! Insertion of raw items (text, images...) embedded by a model of the application.
+//!
+//! An [Embedder] turns an item into the vector inserted in the Hnsw. [Hnsw::insert_item] embeds and inserts one
+//! item, [Hnsw::parallel_insert_items] goes by batches of [Embedder::batch_size] items: a batch is embedded with one
+//! call to [Embedder::embed_batch] (a model call on a batch of inputs) while the previous batch is inserted, so at
+//! most 2 batches of vectors are in memory and the model is not called faster than the graph takes the vectors.
+//!
+//! Errors are the ones of [Hnsw::try_insert]: an item whose embedder panics (or returns no vector for it) gets an
+//! [HnswError::Embedding] and is not inserted, the other items go on. A panic in [Embedder::embed_batch] rejects
+//! all the items of the batch.
+
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, HnswError, PointId, panic_cause};
+
+/// computes the vector of an item, see module doc
+pub trait Embedder<I, T>: Sync {
+    fn embed(&self, item: &I) -> Vec<T>;
+
+    /// vectors of a batch of items, in the order of items. The default embeds items one by one in parallel.
+    fn embed_batch(&self, items: &[&I]) -> Vec<Vec<T>>
+    where
+        I: Sync,
+        T: Send,
+    {
+        items.par_iter().map(|item| self.embed(item)).collect()
+    }
+
+    /// number of items given to each call of embed_batch
+    fn batch_size(&self) -> usize {
+        64
+    }
+}
+
+/// a function is an embedder
+impl<I, T, F> Embedder<I, T> for F
+where
+    F: Fn(&I) -> Vec<T> + Sync,
+{
+    fn embed(&self, item: &I) -> Vec<T> {
+        self(item)
+    }
+}
+
+// vectors of a batch or the error of each of its items
+fn embed_batch<I, T, E>(embedder: &E, batch: &[(&I, DataId)]) -> Vec<Result<Vec<T>, HnswError>>
+where
+    I: Sync,
+    T: Send,
+    E: Embedder<I, T> + ?Sized,
+{
+    let items: Vec<&I> = batch.iter().map(|(item, _)| *item).collect();
+    let failed = |cause: String| -> Vec<Result<Vec<T>, HnswError>> {
+        batch
+            .iter()
+            .map(|(_, origin_id)| {
+                Err(HnswError::Embedding {
+                    origin_id: *origin_id,
+                    cause: cause.clone(),
+                })
+            })
+            .collect()
+    };
+    match std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        embedder.embed_batch(&items)
+    })) {
+        Ok(vectors) if vectors.len() == batch.len() => vectors.into_iter().map(Ok).collect(),
+        Ok(vectors) => failed(format!(
+            "{} vectors for a batch of {} items",
+            vectors.len(),
+            batch.len()
+        )),
+        Err(cause) => failed(panic_cause(cause)),
+    }
+} // end of embed_batch
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// embeds item and inserts its vector with id origin_id as [Hnsw::try_insert]
+    pub fn insert_item<I, E>(
+        &self,
+        item: &I,
+        origin_id: DataId,
+        embedder: &E,
+    ) -> Result<PointId, HnswError>
+    where
+        E: Embedder<I, T> + ?Sized,
+    {
+        let vector =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| embedder.embed(item)))
+                .map_err(|cause| HnswError::Embedding {
+                    origin_id,
+                    cause: panic_cause(cause),
+                })?;
+        self.try_insert((vector.as_slice(), origin_id))
+    } // end of insert_item
+
+    /// embeds items by batches and inserts them in parallel, see module doc.
+    /// Returns for each item, in the order of items, its [PointId] or the reason it was rejected.
+    pub fn parallel_insert_items<I, E>(
+        &self,
+        items: &[(&I, DataId)],
+        embedder: &E,
+    ) -> Vec<Result<PointId, HnswError>>
+    where
+        I: Sync,
+        E: Embedder<I, T> + ?Sized,
+    {
+        let mut results = Vec::with_capacity(items.len());
+        let mut batches = items.chunks(embedder.batch_size().max(1));
+        let mut embedded = batches
+            .next()
+            .map(|batch| (batch, embed_batch(embedder, batch)));
+        // the next batch is embedded while the current one is inserted
+        while let Some((batch, vectors)) = embedded.take() {
+            let next = batches.next();
+            let (inserted, next_embedded) = rayon::join(
+                || {
+                    batch
+                        .par_iter()
+                        .zip(vectors)
+                        .map(|((_, origin_id), vector)| {
+                            vector.and_then(|v| self.try_insert((v.as_slice(), *origin_id)))
+                        })
+                        .collect::<Vec<_>>()
+                },
+                || next.map(|batch| (batch, embed_batch(embedder, batch))),
+            );
+            results.extend(inserted);
+            embedded = next_embedded;
+        }
+        results
+    } // end of parallel_insert_items
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // embeds a word as the vector of its rank in a table, counts calls of embed_batch
+    struct TableEmbedder {
+        words: Vec<String>,
+        vectors: Vec<Vec<f32>>,
+        nb_batches: AtomicUsize,
+    }
+
+    impl Embedder<String, f32> for TableEmbedder {
+        fn embed(&self, item: &String) -> Vec<f32> {
+            let rank = self.words.iter().position(|w| w == item).unwrap();
+            self.vectors[rank].clone()
+        }
+
+        fn embed_batch(&self, items: &[&String]) -> Vec<Vec<f32>> {
+            self.nb_batches.fetch_add(1, Ordering::Relaxed);
+            items.iter().map(|item| self.embed(item)).collect()
+        }
+
+        fn batch_size(&self) -> usize {
+            10
+        }
+    }
+
+    #[test]
+    fn test_insert_items() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(991);
+        let vectors = synthetic::uniform(500, 8, &mut rng);
+        let words: Vec<String> = (0..500).map(|i| format!("word{}", i)).collect();
+        let embedder = TableEmbedder {
+            words: words.clone(),
+            vectors: vectors.clone(),
+            nb_batches: AtomicUsize::new(0),
+        };
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 500, 16, 100, DistL2 {});
+        // word 13 is unknown to the model, its batch is rejected
+        let mut items: Vec<String> = words[..495].to_vec();
+        items[13] = "unknown".to_string();
+        let items_with_id: Vec<(&String, usize)> = items.iter().zip(0..).collect();
+        let results = hnsw.parallel_insert_items(&items_with_id, &embedder);
+        assert_eq!(results.len(), 495);
+        assert_eq!(embedder.nb_batches.load(Ordering::Relaxed), 50);
+        for (i, result) in results.iter().enumerate() {
+            if (10..20).contains(&i) {
+                assert!(
+                    matches!(result, Err(HnswError::Embedding { origin_id, .. }) if *origin_id == i)
+                );
+            } else {
+                assert!(result.is_ok());
+            }
+        }
+        assert_eq!(hnsw.get_nb_point(), 485);
+        // one item
+        hnsw.insert_item(&words[495], 495, &embedder).unwrap();
+        // the graph is built in parallel, a search can miss the point
+        assert!(hnsw.contains(495));
+        assert_eq!(hnsw.get_nb_point(), 486);
+        assert!(matches!(
+            hnsw.insert_item(&"unknown".to_string(), 496, &embedder),
+            Err(HnswError::Embedding { origin_id: 496, .. })
+        ));
+        // a function embedder, with the errors of try_insert
+        let by_rank =
+            |i: &usize| -> Vec<f32> { vectors[*i].iter().take(8 - i % 2).copied().collect() };
+        assert!(hnsw.insert_item(&496, 496, &by_rank).is_ok());
+        assert_eq!(
+            hnsw.insert_item(&497, 497, &by_rank),
+            Err(HnswError::Dimension {
+                origin_id: 497,
+                expected: 8,
+                got: 7
+            })
+        );
+    } // end of test_insert_items
+} // end of mod tests
@@ -0,0 +1,148 @@
+//! Search in an ensemble of indexes and merge results.
+//!
+//! Teams comparing or combining embedding models keep one index per model, all indexing the same
+//! objects with the same DataId. [search] fans out the request to each index and merges the
+//! neighbour lists, a DataId found by several indexes is returned once with a fused score.
+
+use std::collections::HashMap;
+
+use crate::api::AnnT;
+use crate::hnsw::{DataId, Neighbour};
+
+/// How neighbour lists of each index are merged.
+#[derive(Clone, Debug)]
+pub enum Fusion {
+    /// Reciprocal rank fusion: score of an id is the sum over lists of 1/(k + rank), rank beginning at 1.
+    /// k = 60. is the usual value.
+    Rrf(f32),
+    /// Distances of each list are min-max normalized to a similarity in \[0,1\] (1 for the nearest) and summed
+    /// with one weight by index. An id absent from a list contributes 0.
+    Weighted(Vec<f32>),
+}
+
+impl Default for Fusion {
+    fn default() -> Self {
+        Fusion::Rrf(60.)
+    }
+}
+
+/// A neighbour returned by an ensemble search. The higher the score the better.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct FusedNeighbour {
+    pub d_id: DataId,
+    pub score: f32,
+}
+
+/// merges neighbour lists (each sorted by increasing distance) and returns the knbn best ids by decreasing score
+pub fn fuse(lists: &[Vec<Neighbour>], knbn: usize, fusion: &Fusion) -> Vec<FusedNeighbour> {
+    let mut scores = HashMap::<DataId, f32>::new();
+    for (i, list) in lists.iter().enumerate() {
+        match fusion {
+            Fusion::Rrf(k) => {
+                for (rank, n) in list.iter().enumerate() {
+                    *scores.entry(n.d_id).or_insert(0.) += 1. / (k + (rank + 1) as f32);
+                }
+            }
+            Fusion::Weighted(weights) => {
+                assert_eq!(weights.len(), lists.len(), "one weight by index is needed");
+                if list.is_empty() {
+                    continue;
+                }
+                let dmin = list.iter().map(|n| n.distance).fold(f32::MAX, f32::min);
+                let dmax = list.iter().map(|n| n.distance).fold(f32::MIN, f32::max);
+                let range = dmax - dmin;
+                for n in list {
+                    let sim = if range > 0. {
+                        1. - (n.distance - dmin) / range
+                    } else {
+                        1.
+                    };
+                    *scores.entry(n.d_id).or_insert(0.) += weights[i] * sim;
+                }
+            }
+        }
+    }
+    let mut fused: Vec<FusedNeighbour> = scores
+        .into_iter()
+        .map(|(d_id, score)| FusedNeighbour { d_id, score })
+        .collect();
+    // decreasing score, ties broken by id to get a deterministic order
+    fused.sort_unstable_by(|a, b| b.score.total_cmp(&a.score).then(a.d_id.cmp(&b.d_id)));
+    fused.truncate(knbn);
+    fused
+} // end of fuse
+
+/// Searches knbn neighbours in each index and merges them.
+/// As indexes can come from different embedding models, one query by index is given (the same slice can
+/// be repeated). Each index is searched with knbn neighbours and ef_s.
+pub fn search<V>(
+    indexes: &[&dyn AnnT<Val = V>],
+    queries: &[&[V]],
+    knbn: usize,
+    ef_s: usize,
+    fusion: &Fusion,
+) -> Vec<FusedNeighbour> {
+    assert_eq!(indexes.len(), queries.len(), "one query by index is needed");
+    let lists: Vec<Vec<Neighbour>> = indexes
+        .iter()
+        .zip(queries)
+        .map(|(index, query)| index.search_neighbours(query, knbn, ef_s))
+        .collect();
+    fuse(&lists, knbn, fusion)
+} // end of search
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hnsw::{Hnsw, PointId};
+    use anndists::dist::DistL2;
+
+    fn list(ids_dists: &[(usize, f32)]) -> Vec<Neighbour> {
+        ids_dists
+            .iter()
+            .map(|&(id, d)| Neighbour::new(id, d, PointId(0, id as i32)))
+            .collect()
+    }
+
+    #[test]
+    fn test_fusion() {
+        let l1 = list(&[(1, 0.1), (2, 0.2), (3, 0.3)]);
+        let l2 = list(&[(2, 1.), (4, 2.), (1, 3.)]);
+        // rrf : 1 and 2 are found twice, 2 has better ranks
+        let res = fuse(&[l1.clone(), l2.clone()], 3, &Fusion::default());
+        assert_eq!(res.len(), 3);
+        assert_eq!(res[0].d_id, 2);
+        assert_eq!(res[1].d_id, 1);
+        // weighted, only first index counts
+        let res = fuse(&[l1, l2], 4, &Fusion::Weighted(vec![1., 0.]));
+        assert_eq!(res[0].d_id, 1);
+        assert_eq!(res[0].score, 1.);
+        assert_eq!(res[2].d_id, 3);
+        assert_eq!(res[2].score, 0.);
+    } // end of test_fusion
+
+    #[test]
+    fn test_ensemble_search() {
+        let nb_elem = 200;
+        let data: Vec<Vec<f32>> = (0..nb_elem).map(|i| vec![i as f32, 0.]).collect();
+        let data_rev: Vec<Vec<f32>> = (0..nb_elem).map(|i| vec![0., -(i as f32)]).collect();
+        let mut h1 = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 100, DistL2 {});
+        let mut h2 = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 100, DistL2 {});
+        for i in 0..nb_elem {
+            h1.insert_data(&data[i], i);
+            h2.insert_data(&data_rev[i], i);
+        }
+        let indexes: Vec<&dyn AnnT<Val = f32>> = vec![&h1, &h2];
+        let q1 = [10.2f32, 0.];
+        let q2 = [0f32, -10.4];
+        let res = search(&indexes, &[&q1, &q2], 5, 32, &Fusion::default());
+        assert_eq!(res.len(), 5);
+        assert_eq!(res[0].d_id, 10);
+        // no duplicate
+        let mut ids: Vec<usize> = res.iter().map(|n| n.d_id).collect();
+        ids.sort_unstable();
+        ids.dedup();
+        assert_eq!(ids.len(), 5);
+    } // end of test_ensemble_search
+} // end of mod tests
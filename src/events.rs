@@ -0,0 +1,347 @@
+//! Event log of graph construction.
+//!
+//! Once enabled with [Hnsw::record_events], every insertion and every change of a neighbour list made
+//! during insertion (a link added, a link pruned when a list is full) is appended to a compact binary trace.
+//! [EventReader] reads a trace back and [Replay] applies its events to rebuild the graph topology,
+//! so construction dynamics can be studied offline and traces of two crate versions compared.
+//!
+//! Events touching one neighbour list are recorded under the lock of that list, so with parallel insertion
+//! the trace is interleaved but replays to the same graph.
+//! Only insertions are recorded: [Hnsw::repair](crate::hnsw::Hnsw::repair()) and reloads are not.
+//!
+//! Trace format: a magic u32, then events as a tag byte followed by varint encoded fields
+//! (point ids are a layer byte and a varint rank) and the distance as a f32 for links.
+
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::OpenOptions;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::anyhow;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, PointId};
+use crate::hnswio::{read_varint, write_varint};
+
+const MAGIC_EVENTS: u32 = 0x0e7e_0001;
+
+const TAG_INSERT: u8 = 0;
+const TAG_LINK: u8 = 1;
+const TAG_PRUNE: u8 = 2;
+
+/// an event of construction
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Event {
+    /// a point was created for data id origin_id
+    Insert { origin_id: DataId, p_id: PointId },
+    /// to was added to the neighbours of from at layer
+    Link {
+        from: PointId,
+        to: PointId,
+        layer: u8,
+        distance: f32,
+    },
+    /// to was removed from the neighbours of from at layer
+    Prune {
+        from: PointId,
+        to: PointId,
+        layer: u8,
+        distance: f32,
+    },
+}
+
+fn write_point_id<W: Write>(out: &mut W, p_id: PointId) -> anyhow::Result<()> {
+    out.write_all(&[p_id.0])?;
+    write_varint(out, p_id.1 as u64)
+}
+
+fn read_point_id(io_in: &mut dyn Read) -> anyhow::Result<PointId> {
+    let mut layer = [0u8; 1];
+    io_in.read_exact(&mut layer)?;
+    Ok(PointId(layer[0], read_varint(io_in)? as i32))
+}
+
+impl Event {
+    fn write<W: Write>(&self, out: &mut W) -> anyhow::Result<()> {
+        match *self {
+            Event::Insert { origin_id, p_id } => {
+                out.write_all(&[TAG_INSERT])?;
+                write_varint(out, origin_id as u64)?;
+                write_point_id(out, p_id)?;
+            }
+            Event::Link {
+                from,
+                to,
+                layer,
+                distance,
+            }
+            | Event::Prune {
+                from,
+                to,
+                layer,
+                distance,
+            } => {
+                let tag = if matches!(self, Event::Link { .. }) {
+                    TAG_LINK
+                } else {
+                    TAG_PRUNE
+                };
+                out.write_all(&[tag, layer])?;
+                write_point_id(out, from)?;
+                write_point_id(out, to)?;
+                out.write_all(&distance.to_ne_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    // returns None at end of trace
+    fn read(io_in: &mut dyn Read) -> anyhow::Result<Option<Event>> {
+        let mut tag = [0u8; 1];
+        if io_in.read(&mut tag)? == 0 {
+            return Ok(None);
+        }
+        let event = match tag[0] {
+            TAG_INSERT => {
+                let origin_id = read_varint(io_in)? as DataId;
+                let p_id = read_point_id(io_in)?;
+                Event::Insert { origin_id, p_id }
+            }
+            TAG_LINK | TAG_PRUNE => {
+                let mut layer = [0u8; 1];
+                io_in.read_exact(&mut layer)?;
+                let from = read_point_id(io_in)?;
+                let to = read_point_id(io_in)?;
+                let mut distance = [0u8; 4];
+                io_in.read_exact(&mut distance)?;
+                let (layer, distance) = (layer[0], f32::from_ne_bytes(distance));
+                if tag[0] == TAG_LINK {
+                    Event::Link {
+                        from,
+                        to,
+                        layer,
+                        distance,
+                    }
+                } else {
+                    Event::Prune {
+                        from,
+                        to,
+                        layer,
+                        distance,
+                    }
+                }
+            }
+            other => return Err(anyhow!("unknown event tag {} in trace", other)),
+        };
+        Ok(Some(event))
+    }
+} // end of impl Event
+
+/// writes events of a Hnsw to a file
+pub(crate) struct EventRecorder {
+    out: Mutex<BufWriter<std::fs::File>>,
+    nb_events: AtomicUsize,
+}
+
+impl EventRecorder {
+    fn create(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)
+            .map_err(|e| anyhow!("cannot create event trace {:?} : {}", path, e))?;
+        let mut out = BufWriter::new(file);
+        out.write_all(&MAGIC_EVENTS.to_ne_bytes())?;
+        Ok(EventRecorder {
+            out: Mutex::new(out),
+            nb_events: AtomicUsize::new(0),
+        })
+    }
+
+    pub(crate) fn record(&self, event: Event) {
+        // construction must not fail because of the trace, errors are logged
+        if let Err(e) = event.write(&mut *self.out.lock()) {
+            log::error!("could not record event {:?} : {}", event, e);
+            return;
+        }
+        self.nb_events.fetch_add(1, Ordering::Relaxed);
+    }
+} // end of impl EventRecorder
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// records construction events from now on in file at path (truncated if it exists)
+    pub fn record_events(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(EventRecorder::create(path)?);
+        Ok(())
+    }
+
+    /// stops recording, flushes the trace and returns the number of events recorded
+    pub fn stop_recording(&mut self) -> anyhow::Result<usize> {
+        match self.recorder.take() {
+            Some(recorder) => {
+                recorder.out.into_inner().flush()?;
+                Ok(recorder.nb_events.into_inner())
+            }
+            None => Ok(0),
+        }
+    }
+} // end of impl Hnsw
+
+/// iterator over events of a trace file
+pub struct EventReader {
+    io_in: BufReader<std::fs::File>,
+}
+
+impl EventReader {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .open(path)
+            .map_err(|e| anyhow!("cannot open event trace {:?} : {}", path, e))?;
+        let mut io_in = BufReader::new(file);
+        let mut magic = [0u8; 4];
+        io_in.read_exact(&mut magic)?;
+        if u32::from_ne_bytes(magic) != MAGIC_EVENTS {
+            return Err(anyhow!("{:?} is not an event trace", path));
+        }
+        Ok(EventReader { io_in })
+    }
+}
+
+impl Iterator for EventReader {
+    type Item = anyhow::Result<Event>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Event::read(&mut self.io_in).transpose()
+    }
+}
+
+/// graph topology rebuilt from events
+#[derive(Default)]
+pub struct Replay {
+    /// data id of points
+    origin_ids: HashMap<PointId, DataId>,
+    /// neighbours of points, by layer
+    neighbours: HashMap<PointId, Vec<Vec<PointId>>>,
+    nb_links: usize,
+    nb_prunes: usize,
+}
+
+impl Replay {
+    pub fn new() -> Self {
+        Replay::default()
+    }
+
+    /// replays all events of a trace file
+    pub fn from_file(path: &Path) -> anyhow::Result<Self> {
+        let mut replay = Replay::new();
+        for event in EventReader::open(path)? {
+            replay.apply(&event?);
+        }
+        Ok(replay)
+    }
+
+    pub fn apply(&mut self, event: &Event) {
+        match *event {
+            Event::Insert { origin_id, p_id } => {
+                self.origin_ids.insert(p_id, origin_id);
+            }
+            Event::Link {
+                from, to, layer, ..
+            } => {
+                let lists = self.neighbours.entry(from).or_default();
+                if lists.len() <= layer as usize {
+                    lists.resize(layer as usize + 1, Vec::new());
+                }
+                lists[layer as usize].push(to);
+                self.nb_links += 1;
+            }
+            Event::Prune {
+                from, to, layer, ..
+            } => {
+                if let Some(list) = self
+                    .neighbours
+                    .get_mut(&from)
+                    .and_then(|lists| lists.get_mut(layer as usize))
+                {
+                    if let Some(pos) = list.iter().position(|p| *p == to) {
+                        list.remove(pos);
+                    }
+                }
+                self.nb_prunes += 1;
+            }
+        }
+    }
+
+    /// number of points inserted
+    pub fn get_nb_point(&self) -> usize {
+        self.origin_ids.len()
+    }
+
+    /// data id of a point
+    pub fn get_origin_id(&self, p_id: &PointId) -> Option<DataId> {
+        self.origin_ids.get(p_id).copied()
+    }
+
+    /// neighbours of a point at a layer, in order of addition
+    pub fn get_neighbours(&self, p_id: &PointId, layer: u8) -> &[PointId] {
+        self.neighbours
+            .get(p_id)
+            .and_then(|lists| lists.get(layer as usize))
+            .map(|list| list.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// number of links added and pruned
+    pub fn get_nb_links_prunes(&self) -> (usize, usize) {
+        (self.nb_links, self.nb_prunes)
+    }
+} // end of impl Replay
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_record_replay() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(923);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(8, data.len(), 16, 100, DistL2 {});
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("build.events");
+        hnsw.record_events(&path).unwrap();
+        hnsw.parallel_insert(&data_with_id);
+        let nb_events = hnsw.stop_recording().unwrap();
+        //
+        let replay = Replay::from_file(&path).unwrap();
+        let (nb_links, nb_prunes) = replay.get_nb_links_prunes();
+        assert_eq!(nb_events, 1000 + nb_links + nb_prunes);
+        assert!(nb_prunes > 0);
+        assert_eq!(replay.get_nb_point(), 1000);
+        for point in hnsw.get_point_indexation() {
+            let p_id = point.get_point_id();
+            assert_eq!(replay.get_origin_id(&p_id), Some(point.get_origin_id()));
+            for (l, neighbours) in point.get_neighborhood_id().iter().enumerate() {
+                let mut expected: Vec<PointId> = neighbours.iter().map(|n| n.p_id).collect();
+                let mut replayed = replay.get_neighbours(&p_id, l as u8).to_vec();
+                expected.sort_unstable();
+                replayed.sort_unstable();
+                assert_eq!(expected, replayed);
+            }
+        }
+    } // end of test_record_replay
+} // end of mod tests
@@ -0,0 +1,249 @@
+//! Extraction of a subset of points in a new index.
+//!
+//! [Hnsw::extract] builds an index with the selected points of a master index (a tenant, a time window...)
+//! without reinserting them: vectors and payloads are copied (vectors of a mmap or shared stay shared) and
+//! links between selected points are kept. As in [Hnsw::repair], a link to a point not selected is replaced by
+//! the selected neighbours of this point. The points that are then not reachable from the entry point
+//! (few if the selection is not too sparse) are detached and linked again by a usual insertion.
+//!
+//! Deleted points and aliases are not extracted.
+
+use hashbrown::{HashMap, HashSet};
+use std::sync::Arc;
+
+use rayon::prelude::*;
+use roaring::RoaringBitmap;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Point, PointId, PointWithOrder};
+use crate::payload::PayloadColumn;
+
+impl<'b, T, D> Hnsw<'b, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Clone + Send + Sync,
+{
+    /// returns a new structure with the points of self whose id is in ids (ids above u32::MAX are never extracted).
+    /// Parameters, distance, payloads and metadata are those of self, see module doc.
+    pub fn extract(&self, ids: &RoaringBitmap) -> Hnsw<'b, T, D> {
+        let selected = |p: &Point<'b, T>| {
+            u32::try_from(p.get_origin_id()).is_ok_and(|id| ids.contains(id)) && !self.is_deleted(p)
+        };
+        let layers = self.layer_indexed_points.points_by_layer.read();
+        // copies of selected points without links, renumbered in their layer
+        let mut new_ids = HashMap::<PointId, PointId>::new();
+        let payloads = PayloadColumn::default();
+        let mut old_points = Vec::new();
+        let mut new_layers: Vec<Vec<Arc<Point<'b, T>>>> = Vec::with_capacity(layers.len());
+        for (l, layer) in layers.iter().enumerate() {
+            let mut new_layer = Vec::new();
+            for p in layer.iter().filter(|p| selected(p)) {
+                let p_id = PointId(l as u8, new_layer.len() as i32);
+                new_ids.insert(p.get_point_id(), p_id);
+                payloads.set(
+                    p_id,
+                    self.layer_indexed_points.get_payload(&p.get_point_id()),
+                );
+                new_layer.push(Arc::new(p.copy_unlinked(p_id)));
+                old_points.push(Arc::clone(p));
+            }
+            new_layers.push(new_layer);
+        }
+        let nb_point = old_points.len();
+        let get_new = |p_id: &PointId| {
+            new_ids
+                .get(p_id)
+                .map(|n| &new_layers[n.0 as usize][n.1 as usize])
+        };
+        // links between copies
+        old_points.par_iter().for_each(|p| {
+            let new_point = get_new(&p.get_point_id()).unwrap();
+            let neighbours = p.neighbours.read().clone();
+            let mut new_neighbours = Vec::with_capacity(neighbours.len());
+            for (l, neighbours_l) in neighbours.iter().enumerate() {
+                let mut kept: Vec<Arc<PointWithOrder<'b, T>>> =
+                    Vec::with_capacity(neighbours_l.len());
+                let mut candidates: Vec<PointId> = Vec::new();
+                for n in neighbours_l {
+                    match get_new(&n.point_ref.get_point_id()) {
+                        Some(new_n) => {
+                            kept.push(Arc::new(PointWithOrder::new(new_n, n.dist_to_ref)))
+                        }
+                        None => candidates.extend(
+                            n.point_ref.neighbours.read()[l]
+                                .iter()
+                                .map(|nn| nn.point_ref.get_point_id()),
+                        ),
+                    }
+                }
+                for c_id in candidates.iter().filter(|c_id| **c_id != p.get_point_id()) {
+                    if let Some(new_c) = get_new(c_id) {
+                        if kept
+                            .iter()
+                            .any(|k| k.point_ref.get_point_id() == new_c.get_point_id())
+                        {
+                            continue;
+                        }
+                        let dist = self.dist_f.eval(p.get_v(), new_c.get_v());
+                        kept.push(Arc::new(PointWithOrder::new(new_c, dist)));
+                    }
+                }
+                let max_nb = if l == 0 {
+                    2 * self.max_nb_connection
+                } else {
+                    self.max_nb_connection
+                };
+                kept.sort_unstable();
+                kept.truncate(max_nb);
+                new_neighbours.push(kept);
+            }
+            *new_point.neighbours.write() = new_neighbours;
+        });
+        let entry_point = new_layers
+            .iter()
+            .rev()
+            .find_map(|layer| layer.first())
+            .map(Arc::clone);
+        drop(layers);
+        //
+        let mut extracted = Hnsw::new(
+            self.max_nb_connection,
+            nb_point,
+            self.max_layer,
+            self.ef_construction,
+            self.dist_f.clone(),
+        );
+        extracted.set_extend_candidates(self.extend_candidates);
+        extracted.set_keeping_pruned(self.keep_pruned);
+        extracted.set_exact_capacity(self.exact_capacity);
+        extracted.set_connection_strategy(self.get_connection_strategy());
+        extracted.data_dimension = self.data_dimension;
+        extracted.metadata = self.metadata.clone();
+        extracted.bounded_dist = self.bounded_dist;
+        extracted.tiled_dist = self.tiled_dist;
+        extracted.min_ef_factor = self.min_ef_factor;
+        let unreached = match &entry_point {
+            Some(entry) => unreachable(&new_layers, entry),
+            None => Vec::new(),
+        };
+        // unreached points are detached from the graph and linked again as new points
+        let unreached_ids: HashSet<PointId> = unreached.iter().map(|p| p.get_point_id()).collect();
+        if !unreached.is_empty() {
+            new_layers
+                .par_iter()
+                .flat_map_iter(|layer| layer.iter())
+                .for_each(|p| {
+                    let mut neighbours = p.neighbours.write();
+                    if unreached_ids.contains(&p.get_point_id()) {
+                        neighbours
+                            .iter_mut()
+                            .for_each(|neighbours_l| neighbours_l.clear());
+                    } else {
+                        // only links of upper layers can go to an unreached point
+                        for neighbours_l in neighbours.iter_mut().skip(1) {
+                            neighbours_l
+                                .retain(|n| !unreached_ids.contains(&n.point_ref.get_point_id()));
+                        }
+                    }
+                });
+        }
+        extracted.layer_indexed_points.payloads = payloads;
+        {
+            let indexation = &extracted.layer_indexed_points;
+            *indexation.points_by_layer.write() = new_layers;
+            *indexation.nb_point.write() = nb_point;
+            *indexation.entry_point.write() = entry_point;
+            indexation.reset_insertion_order();
+            indexation.reset_ids();
+        }
+        unreached
+            .par_iter()
+            .for_each(|p| extracted.link_point(p, false, 0));
+        // insertion adds reverse links at the level of the point, a relinked point of an upper layer
+        // must also be in layer 0 lists of its neighbours to be reached at layer 0
+        for p in &unreached {
+            for n in p.neighbours.read()[0].iter() {
+                let mut n_neighbours = n.point_ref.neighbours.write();
+                if !n_neighbours[0]
+                    .iter()
+                    .any(|m| m.point_ref.get_point_id() == p.get_point_id())
+                {
+                    n_neighbours[0].push(Arc::new(PointWithOrder::new(p, n.dist_to_ref)));
+                    n_neighbours[0].sort_unstable();
+                    n_neighbours[0].truncate(2 * self.max_nb_connection);
+                }
+            }
+        }
+        log::info!(
+            "extract : {} points extracted, {} linked by insertion",
+            nb_point,
+            unreached.len()
+        );
+        extracted
+    } // end of extract
+} // end of impl Hnsw
+
+// points not reached from entry by links at layer 0
+fn unreachable<'b, T: Clone + Send + Sync>(
+    layers: &[Vec<Arc<Point<'b, T>>>],
+    entry: &Arc<Point<'b, T>>,
+) -> Vec<Arc<Point<'b, T>>> {
+    let mut reached: HashSet<PointId> = HashSet::new();
+    reached.insert(entry.get_point_id());
+    let mut to_visit = vec![Arc::clone(entry)];
+    while let Some(p) = to_visit.pop() {
+        for n in &p.neighbours.read()[0] {
+            if reached.insert(n.point_ref.get_point_id()) {
+                to_visit.push(Arc::clone(&n.point_ref));
+            }
+        }
+    }
+    layers
+        .iter()
+        .flatten()
+        .filter(|p| !reached.contains(&p.get_point_id()))
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_extract() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(940);
+        let data = synthetic::uniform(2000, 10, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 200, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        hnsw.remove_batch(&[0]).unwrap();
+        //
+        let ids: RoaringBitmap = (0..2000u32).filter(|i| i % 2 == 0).collect();
+        let extracted = hnsw.extract(&ids);
+        assert_eq!(extracted.get_nb_point(), 999);
+        assert_eq!(hnsw.get_nb_point(), 2000);
+        let mut nb_found = 0;
+        for i in (2..2000).step_by(2) {
+            let res = extracted.search(&data[i], 10, 64);
+            assert_eq!(res.len(), 10);
+            assert!(res.iter().all(|n| n.d_id % 2 == 0 && n.d_id != 0));
+            if res[0].d_id == i {
+                nb_found += 1;
+            }
+        }
+        assert!(nb_found >= 950, "nb_found {} / 999", nb_found);
+        // a few scattered points, linked again by insertion
+        let ids: RoaringBitmap = [5u32, 700, 1400, 1999].into_iter().collect();
+        let small = hnsw.extract(&ids);
+        assert_eq!(small.get_nb_point(), 4);
+        let res = small.search(&data[700], 4, 16);
+        assert_eq!(res.len(), 4);
+        assert_eq!(res[0].d_id, 700);
+    } // end of test_extract
+} // end of mod tests
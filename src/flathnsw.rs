@@ -0,0 +1,804 @@
+//! A flat copy of a Hnsw: data in one vector and neighbourhoods in compressed rows.
+//!
+//! A [FlatHnsw] has no lock nor Arc, it is meant for read only use of an index that is not modified anymore.
+//! Point are identified by their rank in the flat structure.
+//! Ranks and offsets of neighbour lists are u32, so an adjacency takes half the memory of usize indexes.
+//! A FlatHnsw holds less than u32::MAX points and links, [FlatHnsw::try_from_hnsw] returns an error
+//! for a larger Hnsw.
+//!
+//! Distances of links are not needed by searches and are not kept by default, saving 4 bytes by link.
+//! [FlatHnsw::try_from_hnsw_with_distances] keeps them: [FlatHnsw::to_hnsw], which gives back a Hnsw accepting
+//! insertions (and pruning lists with these distances), then does not recompute them.
+//! Most links of layer 0 are reciprocal and the two links of a pair have the same distance: it is kept once,
+//! in the list of the lower rank, the link of the higher rank refers to it. With a distance giving different
+//! values in the two directions all distances are kept. Ranks are not shared: a reference to a shared edge
+//! would take the 4 bytes of the rank it replaces.
+//!
+//! With the feature *rkyv* a FlatHnsw can be dumped with [rkyv](https://docs.rs/rkyv). The dumped file is directly
+//! usable after validation (see [RkyvMmap]): loading a large index just needs a mmap, not a deserialization.
+//! Data types supported for zero-copy access are u8, u16, u32, i32, f32, f64 on little endian targets.
+
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use hashbrown::HashMap;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, NB_LAYER_MAX, Neighbour, Point, PointId, PointWithOrder};
+
+/// Flat representation of a Hnsw. See module doc.
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(
+    feature = "rkyv",
+    derive(rkyv::Archive, rkyv::Serialize, rkyv::Deserialize)
+)]
+pub struct FlatHnsw<T> {
+    /// dimension of vectors
+    pub(crate) dim: u32,
+    /// origin id of point of each rank
+    pub(crate) origin_ids: Vec<u64>,
+    /// level (max layer) of each point
+    pub(crate) levels: Vec<u8>,
+    /// rank in its layer of each point. With levels it gives the PointId in the original Hnsw
+    pub(crate) layer_ranks: Vec<i32>,
+    /// data of point of rank r in data\[r * dim..(r+1) * dim\]
+    pub(crate) data: Vec<T>,
+    /// point of rank r has its layers 0..=level in slots first_slot\[r\]..first_slot\[r+1\]
+    pub(crate) first_slot: Vec<u32>,
+    /// neighbours of slot s are neighbours\[slot_offsets\[s\]..slot_offsets\[s+1\]\]
+    pub(crate) slot_offsets: Vec<u32>,
+    /// ranks of neighbours
+    pub(crate) neighbours: Vec<u32>,
+    /// distances of neighbours, in the order of neighbours, empty if not kept.
+    /// If distance_offsets is not empty, only distances of links that are not mirrors (see module doc).
+    pub(crate) distances: Vec<f32>,
+    /// distances kept for slot s are distances\[distance_offsets\[s\]..distance_offsets\[s+1\]\],
+    /// empty if reciprocal links do not share their distance
+    pub(crate) distance_offsets: Vec<u32>,
+    /// rank of entry point, u32::MAX if empty
+    pub(crate) entry_point: u32,
+}
+
+// n as a u32 index, what is named in the error
+fn to_u32(n: usize, what: &str) -> anyhow::Result<u32> {
+    u32::try_from(n)
+        .ok()
+        .filter(|n| *n < u32::MAX)
+        .ok_or_else(|| {
+            anyhow!(
+                "{} {} too large for a FlatHnsw, limit is {}",
+                n,
+                what,
+                u32::MAX - 1
+            )
+        })
+}
+
+impl<T: Clone + Send + Sync> FlatHnsw<T> {
+    /// copies graph and data of hnsw. Panics on a Hnsw too large, see [FlatHnsw::try_from_hnsw].
+    pub fn from_hnsw<D: Distance<T> + Send + Sync>(hnsw: &Hnsw<T, D>) -> Self {
+        Self::try_from_hnsw(hnsw).unwrap_or_else(|e| panic!("FlatHnsw::from_hnsw : {}", e))
+    }
+
+    /// copies graph and data of hnsw, returns an error if hnsw has too many points or links for u32 indexes.
+    pub fn try_from_hnsw<D: Distance<T> + Send + Sync>(hnsw: &Hnsw<T, D>) -> anyhow::Result<Self> {
+        Self::flatten(hnsw, false)
+    }
+
+    /// as [FlatHnsw::try_from_hnsw] and keeps the distances of links
+    pub fn try_from_hnsw_with_distances<D: Distance<T> + Send + Sync>(
+        hnsw: &Hnsw<T, D>,
+    ) -> anyhow::Result<Self> {
+        Self::flatten(hnsw, true)
+    }
+
+    fn flatten<D: Distance<T> + Send + Sync>(
+        hnsw: &Hnsw<T, D>,
+        keep_distances: bool,
+    ) -> anyhow::Result<Self> {
+        let mut flat = FlatHnsw {
+            dim: 0,
+            origin_ids: Vec::new(),
+            levels: Vec::new(),
+            layer_ranks: Vec::new(),
+            data: Vec::new(),
+            first_slot: vec![0],
+            slot_offsets: vec![0],
+            neighbours: Vec::new(),
+            distances: Vec::new(),
+            distance_offsets: Vec::new(),
+            entry_point: u32::MAX,
+        };
+        if hnsw.get_nb_point() == 0 {
+            return Ok(flat);
+        }
+        to_u32(hnsw.get_nb_point(), "points")?;
+        let points: Vec<_> = hnsw.get_point_indexation().par_iter().collect();
+        let rank_of: HashMap<PointId, u32> = points
+            .iter()
+            .enumerate()
+            .map(|(r, p)| (p.get_point_id(), r as u32))
+            .collect();
+        flat.dim = points[0].get_v().len() as u32;
+        flat.data.reserve(points.len() * flat.dim as usize);
+        for p in &points {
+            let p_id = p.get_point_id();
+            flat.origin_ids.push(p.get_origin_id() as u64);
+            flat.levels.push(p_id.0);
+            flat.layer_ranks.push(p_id.1);
+            flat.data.extend_from_slice(p.get_v());
+            let neighbours = p.neighbours.read();
+            for layer in neighbours.iter().take(p_id.0 as usize + 1) {
+                flat.neighbours
+                    .extend(layer.iter().map(|n| rank_of[&n.point_ref.get_point_id()]));
+                if keep_distances {
+                    flat.distances.extend(layer.iter().map(|n| n.dist_to_ref));
+                }
+                flat.slot_offsets
+                    .push(to_u32(flat.neighbours.len(), "links")?);
+            }
+            flat.first_slot
+                .push(to_u32(flat.slot_offsets.len() - 1, "neighbour lists")?);
+        }
+        let entry = hnsw.get_point_indexation().entry_point.read().clone();
+        if let Some(entry) = entry {
+            flat.entry_point = rank_of[&entry.get_point_id()];
+        }
+        if keep_distances {
+            flat.share_reciprocal_distances();
+        }
+        Ok(flat)
+    } // end of flatten
+
+    // a link from rank to a lower rank n linking back is a mirror, its distance is kept by n
+    fn is_mirror(&self, rank: u32, layer: u8, n: u32) -> bool {
+        n < rank && self.neighbours(n, layer).contains(&rank)
+    }
+
+    // mirror flags of the links of rank at layer
+    fn row_mirrors(&self, rank: u32, layer: u8) -> Vec<bool> {
+        self.neighbours(rank, layer)
+            .iter()
+            .map(|n| self.is_mirror(rank, layer, *n))
+            .collect()
+    }
+
+    // mirror flags of all links, in the order of neighbours
+    fn mirror_flags(&self) -> Vec<bool> {
+        (0..self.nb_point() as u32)
+            .into_par_iter()
+            .flat_map_iter(|rank| {
+                (0..=self.level(rank)).flat_map(move |layer| self.row_mirrors(rank, layer))
+            })
+            .collect()
+    }
+
+    // distance of the link from rank to n, not a mirror, once distances are shared.
+    // mirrors are the flags of the links of rank at layer
+    fn kept_distance(&self, rank: u32, layer: u8, mirrors: &[bool], n: u32) -> f32 {
+        let slot = self.first_slot[rank as usize] as usize + layer as usize;
+        let pos = self
+            .neighbours(rank, layer)
+            .iter()
+            .position(|m| *m == n)
+            .unwrap();
+        let index = mirrors[..pos].iter().filter(|m| !**m).count();
+        self.distances[self.distance_offsets[slot] as usize + index]
+    }
+
+    // keeps one distance by pair of reciprocal links, unless the two distances differ
+    fn share_reciprocal_distances(&mut self) {
+        let mirrors = self.mirror_flags();
+        let mut kept = Vec::with_capacity(self.distances.len());
+        let mut distance_offsets = Vec::with_capacity(self.slot_offsets.len());
+        distance_offsets.push(0);
+        for rank in 0..self.nb_point() as u32 {
+            for layer in 0..=self.level(rank) {
+                let slot = self.first_slot[rank as usize] as usize + layer as usize;
+                let start = self.slot_offsets[slot] as usize;
+                for (i, n) in self.neighbours(rank, layer).iter().enumerate() {
+                    let dist = self.distances[start + i];
+                    if !mirrors[start + i] {
+                        kept.push(dist);
+                        continue;
+                    }
+                    let n_slot = self.first_slot[*n as usize] as usize + layer as usize;
+                    let n_pos = self
+                        .neighbours(*n, layer)
+                        .iter()
+                        .position(|m| *m == rank)
+                        .unwrap();
+                    let back = self.distances[self.slot_offsets[n_slot] as usize + n_pos];
+                    if back.to_bits() != dist.to_bits() {
+                        log::debug!("asymmetric distance between ranks {} and {}", rank, n);
+                        return;
+                    }
+                }
+                distance_offsets.push(kept.len() as u32);
+            }
+        }
+        log::debug!(
+            "{} distances kept for {} links",
+            kept.len(),
+            self.distances.len()
+        );
+        self.distances = kept;
+        self.distance_offsets = distance_offsets;
+    } // end of share_reciprocal_distances
+
+    /// one distance by link, in the order of neighbours. Empty if distances are not kept.
+    fn expand_distances(&self) -> Vec<f32> {
+        if self.distance_offsets.is_empty() {
+            return self.distances.clone();
+        }
+        let mirrors = self.mirror_flags();
+        let row = |rank: u32, layer: u8| {
+            let slot = self.first_slot[rank as usize] as usize + layer as usize;
+            &mirrors[self.slot_offsets[slot] as usize..self.slot_offsets[slot + 1] as usize]
+        };
+        (0..self.nb_point() as u32)
+            .into_par_iter()
+            .flat_map_iter(|rank| {
+                (0..=self.level(rank)).flat_map(move |layer| {
+                    let rank_mirrors = row(rank, layer);
+                    self.neighbours(rank, layer)
+                        .iter()
+                        .zip(rank_mirrors)
+                        .map(move |(n, mirror)| {
+                            if *mirror {
+                                self.kept_distance(*n, layer, row(*n, layer), rank)
+                            } else {
+                                self.kept_distance(rank, layer, rank_mirrors, *n)
+                            }
+                        })
+                })
+            })
+            .collect()
+    } // end of expand_distances
+
+    /// distances of the links of rank at layer, in the order of its neighbours. None if distances are not kept.
+    pub fn get_link_distances(&self, rank: u32, layer: u8) -> Option<Vec<f32>> {
+        if self.distances.is_empty() {
+            return None;
+        }
+        let slot = self.first_slot[rank as usize] as usize + layer as usize;
+        if self.distance_offsets.is_empty() {
+            let range = self.slot_offsets[slot] as usize..self.slot_offsets[slot + 1] as usize;
+            return Some(self.distances[range].to_vec());
+        }
+        let mirrors = self.row_mirrors(rank, layer);
+        let distances = self
+            .neighbours(rank, layer)
+            .iter()
+            .zip(&mirrors)
+            .map(|(n, mirror)| {
+                if *mirror {
+                    self.kept_distance(*n, layer, &self.row_mirrors(*n, layer), rank)
+                } else {
+                    self.kept_distance(rank, layer, &mirrors, *n)
+                }
+            })
+            .collect();
+        Some(distances)
+    } // end of get_link_distances
+
+    /// a Hnsw with the points and links of self, in which new points can be inserted.
+    /// Distances of links are those kept by [FlatHnsw::try_from_hnsw_with_distances], else they are recomputed
+    /// with dist that must be the distance of the graph.
+    pub fn to_hnsw<D: Distance<T> + Send + Sync>(
+        &self,
+        max_nb_connection: usize,
+        ef_construction: usize,
+        dist: D,
+    ) -> anyhow::Result<Hnsw<'static, T, D>>
+    where
+        T: 'static,
+    {
+        let nb_point = self.nb_point();
+        let hnsw = Hnsw::try_new(
+            max_nb_connection,
+            nb_point,
+            NB_LAYER_MAX as usize,
+            ef_construction,
+            dist,
+        )?;
+        let mut layers: Vec<Vec<Arc<Point<'static, T>>>> = vec![Vec::new(); NB_LAYER_MAX as usize];
+        let mut points = Vec::with_capacity(nb_point);
+        for rank in 0..nb_point as u32 {
+            let p_id = self.point_id(rank);
+            let layer = &mut layers[p_id.0 as usize];
+            if p_id.1 as usize != layer.len() {
+                return Err(anyhow!("point {} has rank {:?} out of order", rank, p_id));
+            }
+            let point = Arc::new(Point::new(
+                self.vector(rank).to_vec(),
+                self.origin_id(rank),
+                p_id,
+            ));
+            layer.push(Arc::clone(&point));
+            points.push(point);
+        }
+        let distances = self.expand_distances();
+        points.par_iter().enumerate().for_each(|(rank, point)| {
+            let mut neighbours = point.neighbours.write();
+            for l in 0..=self.level(rank as u32) {
+                let slot = self.first_slot[rank] as usize + l as usize;
+                let start = self.slot_offsets[slot] as usize;
+                neighbours[l as usize] = self
+                    .neighbours(rank as u32, l)
+                    .iter()
+                    .enumerate()
+                    .map(|(i, n)| {
+                        let n_point = &points[*n as usize];
+                        let dist = match distances.get(start + i) {
+                            Some(dist) => *dist,
+                            None => hnsw.get_distance().eval(point.get_v(), n_point.get_v()),
+                        };
+                        Arc::new(PointWithOrder::new(n_point, dist))
+                    })
+                    .collect();
+                neighbours[l as usize].sort_unstable();
+            }
+        });
+        let mut hnsw = hnsw;
+        hnsw.data_dimension = self.dim as usize;
+        {
+            let indexation = &hnsw.layer_indexed_points;
+            *indexation.entry_point.write() = self.entry().map(|e| Arc::clone(&points[e as usize]));
+            *indexation.points_by_layer.write() = layers;
+            *indexation.nb_point.write() = nb_point;
+            indexation.reset_insertion_order();
+            indexation.reset_ids();
+        }
+        Ok(hnsw)
+    } // end of to_hnsw
+
+    pub fn get_nb_point(&self) -> usize {
+        self.nb_point()
+    }
+
+    /// search knbn neighbours of query. dist must be the distance used to build the graph.
+    pub fn search<D: Distance<T>>(
+        &self,
+        query: &[T],
+        knbn: usize,
+        ef: usize,
+        dist: &D,
+    ) -> Vec<Neighbour> {
+        flat_search(self, query, knbn, ef, dist)
+    }
+} // end of impl FlatHnsw
+
+/// read access to a flat graph, owned or archived
+pub(crate) trait FlatView<T> {
+    fn nb_point(&self) -> usize;
+    fn entry(&self) -> Option<u32>;
+    fn level(&self, rank: u32) -> u8;
+    fn point_id(&self, rank: u32) -> PointId;
+    fn origin_id(&self, rank: u32) -> usize;
+    fn vector(&self, rank: u32) -> &[T];
+    fn neighbours(&self, rank: u32, layer: u8) -> &[u32];
+}
+
+impl<T: Send + Sync> FlatView<T> for FlatHnsw<T> {
+    fn nb_point(&self) -> usize {
+        self.origin_ids.len()
+    }
+    fn entry(&self) -> Option<u32> {
+        (self.entry_point != u32::MAX).then_some(self.entry_point)
+    }
+    fn level(&self, rank: u32) -> u8 {
+        self.levels[rank as usize]
+    }
+    fn point_id(&self, rank: u32) -> PointId {
+        PointId(self.levels[rank as usize], self.layer_ranks[rank as usize])
+    }
+    fn origin_id(&self, rank: u32) -> usize {
+        self.origin_ids[rank as usize] as usize
+    }
+    fn vector(&self, rank: u32) -> &[T] {
+        let dim = self.dim as usize;
+        &self.data[rank as usize * dim..(rank as usize + 1) * dim]
+    }
+    fn neighbours(&self, rank: u32, layer: u8) -> &[u32] {
+        let slot = self.first_slot[rank as usize] as usize + layer as usize;
+        &self.neighbours[self.slot_offsets[slot] as usize..self.slot_offsets[slot + 1] as usize]
+    }
+}
+
+// (rank, distance) ordered by distance
+#[derive(Copy, Clone)]
+struct RankDist(u32, f32);
+
+impl PartialEq for RankDist {
+    fn eq(&self, other: &Self) -> bool {
+        self.1 == other.1
+    }
+}
+
+impl Eq for RankDist {}
+
+impl PartialOrd for RankDist {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RankDist {
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        self.1.total_cmp(&other.1)
+    }
+}
+
+// greedy descent in upper layers then search with ef candidates in layer 0.
+pub(crate) fn flat_search<T, D, V>(
+    view: &V,
+    query: &[T],
+    knbn: usize,
+    ef: usize,
+    dist: &D,
+) -> Vec<Neighbour>
+where
+    T: Send + Sync,
+    D: Distance<T>,
+    V: FlatView<T> + ?Sized,
+{
+    let Some(mut pivot) = view.entry() else {
+        return Vec::new();
+    };
+    let mut pivot_dist = dist.eval(query, view.vector(pivot));
+    for layer in (1..=view.level(pivot)).rev() {
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &n in view.neighbours(pivot, layer) {
+                let d = dist.eval(query, view.vector(n));
+                if d < pivot_dist {
+                    pivot = n;
+                    pivot_dist = d;
+                    changed = true;
+                }
+            }
+        }
+    }
+    //
+    let ef = ef.max(knbn);
+    let mut visited = hashbrown::HashSet::<u32>::with_capacity(ef * 16);
+    visited.insert(pivot);
+    // candidates with negative distances to pop nearest first, results with positive ones to pop farthest
+    let mut candidates = BinaryHeap::new();
+    let mut results = BinaryHeap::new();
+    candidates.push(RankDist(pivot, -pivot_dist));
+    results.push(RankDist(pivot, pivot_dist));
+    while let Some(c) = candidates.pop() {
+        let farthest = results.peek().map(|f: &RankDist| f.1).unwrap();
+        if -c.1 > farthest && results.len() >= ef {
+            break;
+        }
+        for &n in view.neighbours(c.0, 0) {
+            if !visited.insert(n) {
+                continue;
+            }
+            let d = dist.eval(query, view.vector(n));
+            if results.len() < ef || d < results.peek().unwrap().1 {
+                candidates.push(RankDist(n, -d));
+                results.push(RankDist(n, d));
+                if results.len() > ef {
+                    results.pop();
+                }
+            }
+        }
+    }
+    let mut sorted: Vec<Neighbour> = results
+        .into_sorted_vec()
+        .iter()
+        .map(|p| Neighbour::new(view.origin_id(p.0), p.1, view.point_id(p.0)))
+        .collect();
+    // as in Hnsw::search, equal distances are ordered by data id
+    sorted.sort_by(|a, b| a.cmp_by_distance(b));
+    sorted.truncate(knbn);
+    sorted
+} // end of flat_search
+
+//===================================================================================================
+
+#[cfg(feature = "rkyv")]
+pub use self::zero_copy::*;
+
+#[cfg(feature = "rkyv")]
+mod zero_copy {
+
+    use std::fs::OpenOptions;
+    use std::io::Write;
+    use std::path::Path;
+
+    use anyhow::anyhow;
+    use mmap_rs::{Mmap, MmapOptions};
+    use rkyv::rancor;
+
+    use super::*;
+
+    /// Types of data whose archived form can be read as native values.
+    pub trait NativeScalar: rkyv::Archive + Send + Sync + Sized {
+        fn as_native(archived: &[rkyv::Archived<Self>]) -> &[Self];
+    }
+
+    impl NativeScalar for u8 {
+        fn as_native(archived: &[u8]) -> &[u8] {
+            archived
+        }
+    }
+
+    macro_rules! native_scalar {
+        ($ty:ty) => {
+            #[cfg(target_endian = "little")]
+            impl NativeScalar for $ty {
+                fn as_native(archived: &[rkyv::Archived<$ty>]) -> &[$ty] {
+                    // SAFETY: archived primitives are repr(C) wrappers of the primitive with same size and
+                    // alignment, stored little endian as is the target.
+                    unsafe {
+                        std::slice::from_raw_parts(archived.as_ptr() as *const $ty, archived.len())
+                    }
+                }
+            }
+        };
+    }
+
+    native_scalar!(u16);
+    native_scalar!(u32);
+    native_scalar!(i32);
+    native_scalar!(f32);
+    native_scalar!(f64);
+
+    impl<T: NativeScalar> FlatView<T> for ArchivedFlatHnsw<T> {
+        fn nb_point(&self) -> usize {
+            self.origin_ids.len()
+        }
+        fn entry(&self) -> Option<u32> {
+            let entry = self.entry_point.to_native();
+            (entry != u32::MAX).then_some(entry)
+        }
+        fn level(&self, rank: u32) -> u8 {
+            self.levels[rank as usize]
+        }
+        fn point_id(&self, rank: u32) -> PointId {
+            PointId(
+                self.levels[rank as usize],
+                self.layer_ranks[rank as usize].to_native(),
+            )
+        }
+        fn origin_id(&self, rank: u32) -> usize {
+            self.origin_ids[rank as usize].to_native() as usize
+        }
+        fn vector(&self, rank: u32) -> &[T] {
+            let dim = self.dim.to_native() as usize;
+            T::as_native(&self.data[rank as usize * dim..(rank as usize + 1) * dim])
+        }
+        fn neighbours(&self, rank: u32, layer: u8) -> &[u32] {
+            let slot = self.first_slot[rank as usize].to_native() as usize + layer as usize;
+            let start = self.slot_offsets[slot].to_native() as usize;
+            let end = self.slot_offsets[slot + 1].to_native() as usize;
+            u32::as_native(&self.neighbours[start..end])
+        }
+    }
+
+    impl<T: NativeScalar> ArchivedFlatHnsw<T> {
+        pub fn get_nb_point(&self) -> usize {
+            self.nb_point()
+        }
+
+        /// search knbn neighbours of query directly in the archive.
+        pub fn search<D: Distance<T>>(
+            &self,
+            query: &[T],
+            knbn: usize,
+            ef: usize,
+            dist: &D,
+        ) -> Vec<Neighbour> {
+            flat_search(self, query, knbn, ef, dist)
+        }
+    }
+
+    impl<T> FlatHnsw<T>
+    where
+        T: NativeScalar
+            + for<'a> rkyv::Serialize<
+                rkyv::api::high::HighSerializer<
+                    rkyv::util::AlignedVec,
+                    rkyv::ser::allocator::ArenaHandle<'a>,
+                    rancor::Error,
+                >,
+            >,
+    {
+        /// serializes in a buffer
+        pub fn to_rkyv_bytes(&self) -> anyhow::Result<rkyv::util::AlignedVec> {
+            rkyv::to_bytes::<rancor::Error>(self)
+                .map_err(|e| anyhow!("rkyv serialization failed : {}", e))
+        }
+
+        /// dumps in file path. The file can be mapped with [RkyvMmap].
+        pub fn dump_rkyv(&self, path: &Path) -> anyhow::Result<()> {
+            let bytes = self.to_rkyv_bytes()?;
+            let mut file = OpenOptions::new()
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(path)?;
+            file.write_all(&bytes)?;
+            file.flush()?;
+            Ok(())
+        }
+    }
+
+    /// validates bytes and returns the archived FlatHnsw they contain.
+    /// bytes must be aligned as they were serialized (a mmap or an AlignedVec is).
+    pub fn access_rkyv<T>(bytes: &[u8]) -> anyhow::Result<&ArchivedFlatHnsw<T>>
+    where
+        T: rkyv::Archive,
+        ArchivedFlatHnsw<T>:
+            for<'a> rkyv::bytecheck::CheckBytes<rkyv::api::high::HighValidator<'a, rancor::Error>>,
+    {
+        rkyv::access::<ArchivedFlatHnsw<T>, rancor::Error>(bytes)
+            .map_err(|e| anyhow!("invalid rkyv FlatHnsw : {}", e))
+    }
+
+    /// A FlatHnsw dump mapped in memory.
+    ///
+    /// ```text
+    ///     let mapped = RkyvMmap::open(&path)?;
+    ///     let archived = mapped.get::<f32>()?;
+    ///     let res = archived.search(&query, 10, 64, &DistL2{});
+    /// ```
+    pub struct RkyvMmap {
+        mmap: Mmap,
+    }
+
+    impl RkyvMmap {
+        pub fn open(path: &Path) -> anyhow::Result<Self> {
+            let file = OpenOptions::new().read(true).open(path)?;
+            let size = file.metadata()?.len() as usize;
+            let mmap_opt = MmapOptions::new(size)?;
+            let mmap_opt = unsafe { mmap_opt.with_file(&file, 0) };
+            let mmap = mmap_opt
+                .map()
+                .map_err(|e| anyhow!("could not mmap {:?} : {:?}", path, e))?;
+            Ok(RkyvMmap { mmap })
+        }
+
+        /// validates the mapped file (once by call) and gives access to it
+        pub fn get<T>(&self) -> anyhow::Result<&ArchivedFlatHnsw<T>>
+        where
+            T: rkyv::Archive,
+            ArchivedFlatHnsw<T>: for<'a> rkyv::bytecheck::CheckBytes<
+                    rkyv::api::high::HighValidator<'a, rancor::Error>,
+                >,
+        {
+            access_rkyv::<T>(self.mmap.as_slice())
+        }
+    }
+} // end of mod zero_copy
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_flat_search() {
+        let nb_elem = 2000;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(915);
+        let data = synthetic::uniform(nb_elem, 12, &mut rng);
+        // a serial build with seeded levels, so that the searches below are exact
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        hnsw.set_level_seed(915);
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        let flat = FlatHnsw::from_hnsw(&hnsw);
+        assert_eq!(flat.get_nb_point(), nb_elem);
+        for i in [0, 999, 1999] {
+            let res = flat.search(&data[i], 5, 64, &DistL2 {});
+            assert_eq!(res[0].d_id, i);
+            assert_eq!(res.len(), 5);
+            assert!(res.windows(2).all(|w| w[0].distance <= w[1].distance));
+        }
+        //
+        #[cfg(feature = "rkyv")]
+        {
+            let directory = tempfile::tempdir().unwrap();
+            let path = directory.path().join("flat.rkyv");
+            flat.dump_rkyv(&path).unwrap();
+            let mapped = RkyvMmap::open(&path).unwrap();
+            let archived = mapped.get::<f32>().unwrap();
+            assert_eq!(archived.get_nb_point(), nb_elem);
+            let query = &data[77];
+            let from_archive = archived.search(query, 5, 64, &DistL2 {});
+            let from_flat = flat.search(query, 5, 64, &DistL2 {});
+            assert_eq!(from_archive, from_flat);
+            // corrupted bytes are rejected
+            let mut bytes = flat.to_rkyv_bytes().unwrap();
+            let len = bytes.len();
+            bytes[len - 8..].copy_from_slice(&[0xff; 8]);
+            assert!(access_rkyv::<f32>(&bytes).is_err());
+        }
+    } // end of test_flat_search
+
+    #[test]
+    fn test_u32_limit() {
+        assert_eq!(to_u32(1000, "points").unwrap(), 1000);
+        // u32::MAX marks an empty structure
+        let err = to_u32(u32::MAX as usize, "points").unwrap_err();
+        assert!(err.to_string().contains("too large"));
+        assert!(to_u32(1 << 33, "links").is_err());
+    } // end of test_u32_limit
+
+    #[test]
+    fn test_to_hnsw() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(952);
+        let data = synthetic::uniform(1500, 8, &mut rng);
+        // serial insertions with seeded levels, so that the graphs compared below are reproducible
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 1500, 16, 100, DistL2 {});
+        hnsw.set_level_seed(952);
+        for (i, v) in data.iter().enumerate().take(1000) {
+            hnsw.insert((v, i));
+        }
+        let flat = FlatHnsw::from_hnsw(&hnsw);
+        assert!(flat.distances.is_empty());
+        let with_distances = FlatHnsw::try_from_hnsw_with_distances(&hnsw).unwrap();
+        // reciprocal links keep one distance
+        let nb_link = flat.neighbours.len();
+        assert!(with_distances.distances.len() < nb_link * 9 / 10);
+        assert_eq!(with_distances.expand_distances().len(), nb_link);
+        for rank in (0..1000).step_by(7) {
+            let point = hnsw
+                .get_point_indexation()
+                .get_point(&with_distances.point_id(rank))
+                .unwrap();
+            let nb_layer = with_distances.level(rank) as usize + 1;
+            for (layer, expected) in point
+                .get_neighborhood_id()
+                .iter()
+                .take(nb_layer)
+                .enumerate()
+            {
+                let distances = with_distances
+                    .get_link_distances(rank, layer as u8)
+                    .unwrap();
+                assert!(distances.iter().eq(expected.iter().map(|n| &n.distance)));
+            }
+        }
+        assert!(flat.get_link_distances(0, 0).is_none());
+        // kept and recomputed distances give the graph of hnsw
+        let mut thawed = flat.to_hnsw(16, 100, DistL2 {}).unwrap();
+        let kept = with_distances.to_hnsw(16, 100, DistL2 {}).unwrap();
+        assert_eq!(thawed.get_nb_point(), 1000);
+        for q in data.iter().take(1000).step_by(10) {
+            let res = thawed.search(q, 5, 32);
+            assert_eq!(res, kept.search(q, 5, 32));
+            assert_eq!(res, hnsw.search(q, 5, 32));
+        }
+        // the thawed graph accepts insertions, with the recall of the structure it comes from
+        thawed.set_level_seed(952);
+        hnsw.set_level_seed(952);
+        for (i, v) in data.iter().enumerate().skip(1000) {
+            thawed.insert((v, i));
+            hnsw.insert((v, i));
+        }
+        assert_eq!(thawed.get_nb_point(), 1500);
+        let self_found = |h: &Hnsw<f32, DistL2>| {
+            (0..1500)
+                .filter(|i| h.search(&data[*i], 1, 32)[0].d_id == *i)
+                .count()
+        };
+        let nb_found = self_found(&thawed);
+        assert_eq!(nb_found, self_found(&hnsw));
+        assert!(nb_found >= 1470, "nb_found {} / 1500", nb_found);
+    } // end of test_to_hnsw
+} // end of mod tests
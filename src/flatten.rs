@@ -112,9 +112,12 @@ impl<T: Clone + Send + Sync, D: Distance<T> + Send + Sync> From<&Hnsw<'_, T, D>>
     /// Useful after reloading from a dump with T=NoData and D = NoDist as points are then reloaded with neighbourhood information only.
     fn from(hnsw: &Hnsw<T, D>) -> Self {
         let mut hash_t = HashMap::new();
-        let pt_iter = hnsw.get_point_indexation().into_iter();
+        let points = hnsw
+            .get_point_indexation()
+            .iter_points_chunked(4096)
+            .flatten();
         //
-        for point in pt_iter {
+        for point in points {
             //    println!("point : {:?}", _point.p_id);
             let res_insert = hash_t.insert(point.get_origin_id(), flatten_point(&point));
             if let Some(old_point) = res_insert {
@@ -0,0 +1,233 @@
+//! Flat (single layer) graphs.
+//!
+//! A structure set to [GraphKind::Flat] by [Hnsw::set_graph_kind] stores all its points in layer 0, as a NSW graph:
+//! no upper layer is built, so insertions are faster and, for small to medium datasets, searches are as accurate.
+//! Without the descent of upper layers a search starts from the entry point and from [FLAT_RESTARTS] other points
+//! spread over the layer, and the results of these traversals are merged.
+//! [SearchParams::restarts] asks for such traversals in a search of any graph: in a hierarchical graph the added
+//! starts are points of an upper layer, each one descending to layer 0 as the entry point does.
+//!
+//! The kind is kept by dumps (in an extension block) and by copies.
+
+use hashbrown::HashSet;
+use std::sync::Arc;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, LayerGenerator, LayerSearch, Point, PointId, PointWithOrder};
+use crate::searchparams::SearchParams;
+
+/// number of traversals added to the one from the entry point in a search of a flat graph
+pub const FLAT_RESTARTS: usize = 3;
+
+/// structure of the graph, see module doc
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum GraphKind {
+    /// layers of decreasing density, a search descends them to layer 0
+    #[default]
+    Hierarchical,
+    /// all points in layer 0, searches start from several points
+    Flat,
+}
+
+impl<'b, T, D> Hnsw<'b, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// sets the kind of graph. Must be called before insertions (it panics otherwise).
+    pub fn set_graph_kind(&mut self, kind: GraphKind) {
+        assert_eq!(
+            self.get_nb_point(),
+            0,
+            "the graph kind must be set before insertions"
+        );
+        let nb_level = match kind {
+            GraphKind::Flat => 1,
+            GraphKind::Hierarchical => self.max_layer,
+        };
+        self.layer_indexed_points.layer_g = LayerGenerator::new(self.max_nb_connection, nb_level);
+        self.graph_kind = kind;
+    }
+
+    pub fn get_graph_kind(&self) -> GraphKind {
+        self.graph_kind
+    }
+
+    /// starts of the traversals of layer 0 for a search that reached pivot by a descent from start_layer.
+    /// Pivot alone in a hierarchical graph, pivot and FLAT_RESTARTS points at regular ranks of layer 0 in a flat graph.
+    /// With [SearchParams::restarts] the points at regular ranks are taken in the highest layer with enough points
+    /// and descend as pivot did.
+    pub(crate) fn get_search_starts<F>(
+        &self,
+        dist_to_query: &F,
+        pivot: Arc<Point<'b, T>>,
+        start_layer: u8,
+        params: &SearchParams,
+    ) -> Vec<Arc<Point<'b, T>>>
+    where
+        F: Fn(&[T], f32) -> f32,
+    {
+        let nb_restart = match (params.get_restarts(), self.graph_kind) {
+            (Some(nb), _) => nb,
+            (None, GraphKind::Flat) => FLAT_RESTARTS,
+            (None, GraphKind::Hierarchical) => 0,
+        };
+        let mut starts = vec![pivot];
+        if nb_restart == 0 {
+            return starts;
+        }
+        let (layer, sampled) = {
+            let layers = self.layer_indexed_points.points_by_layer.read();
+            let layer = (0..=start_layer as usize)
+                .rev()
+                .find(|l| layers[*l].len() > nb_restart)
+                .unwrap_or(0);
+            let points = &layers[layer];
+            let sampled: Vec<Arc<Point<'b, T>>> = (1..=nb_restart)
+                .map(|i| i * points.len() / (nb_restart + 1))
+                .filter(|rank| *rank < points.len())
+                .map(|rank| Arc::clone(&points[rank]))
+                .collect();
+            (layer as u8, sampled)
+        };
+        starts.extend(
+            sampled
+                .into_iter()
+                .map(|point| self.descend(dist_to_query, point, layer)),
+        );
+        // descents can end at the same point
+        let mut seen = HashSet::<PointId>::new();
+        starts.retain(|p| seen.insert(p.get_point_id()));
+        starts
+    } // end of get_search_starts
+
+    /// searches layer from each point of starts and returns the ef best points found by all traversals,
+    /// by increasing distance
+    pub(crate) fn search_layer_from<F>(
+        &self,
+        dist_to_query: &F,
+        starts: Vec<Arc<Point<'b, T>>>,
+        ef: usize,
+        layer: u8,
+        search: LayerSearch<T>,
+    ) -> Vec<Arc<PointWithOrder<'b, T>>>
+    where
+        F: Fn(&[T], f32) -> f32,
+    {
+        let mut starts = starts.into_iter();
+        let first = starts.next().expect("search_layer_from needs a start");
+        let mut best = self
+            .search_layer_with(dist_to_query, first, ef, layer, search, true)
+            .into_sorted_vec();
+        if starts.len() == 0 {
+            return best;
+        }
+        let mut found: HashSet<PointId> = best.iter().map(|p| p.point_ref.get_point_id()).collect();
+        for start in starts {
+            let other = self.search_layer_with(dist_to_query, start, ef, layer, search, true);
+            best.extend(
+                other
+                    .into_sorted_vec()
+                    .into_iter()
+                    .filter(|p| found.insert(p.point_ref.get_point_id())),
+            );
+        }
+        best.sort_by(|a, b| a.dist_to_ref.total_cmp(&b.dist_to_ref));
+        best.truncate(ef);
+        best
+    } // end of search_layer_from
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use crate::hnswio::HnswIo;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_flat_graph() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(975);
+        let (data, _) = synthetic::gaussian_mixture(3000, 16, 10, 0.05, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 3000, 16, 100, DistL2 {});
+        hnsw.set_graph_kind(GraphKind::Flat);
+        hnsw.parallel_insert(&data_with_id);
+        assert_eq!(hnsw.get_graph_kind(), GraphKind::Flat);
+        assert_eq!(hnsw.get_point_indexation().get_layer_nb_point(0), 3000);
+        // recall against brute force
+        let queries = synthetic::uniform(50, 16, &mut rng);
+        let mut nb_found = 0;
+        for query in &queries {
+            let mut exact: Vec<(f32, usize)> = data
+                .iter()
+                .enumerate()
+                .map(|(i, v)| (DistL2 {}.eval(query, v), i))
+                .collect();
+            exact.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+            let res = hnsw.search(query, 10, 64);
+            nb_found += res
+                .iter()
+                .filter(|n| exact[..10].iter().any(|e| e.1 == n.d_id))
+                .count();
+        }
+        let recall = nb_found as f64 / 500.;
+        log::info!("flat graph recall {:.3}", recall);
+        assert!(recall > 0.9, "recall {}", recall);
+        // the kind is kept by a dump
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "flat").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "flat");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert_eq!(reloaded.get_graph_kind(), GraphKind::Flat);
+        assert_eq!(
+            reloaded.search(&queries[0], 10, 64),
+            hnsw.search(&queries[0], 10, 64)
+        );
+    } // end of test_flat_graph
+
+    #[test]
+    fn test_restarts() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(976);
+        let (data, _) = synthetic::gaussian_mixture(4000, 16, 40, 0.02, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        // a sparse graph on clustered data misses clusters
+        let hnsw = Hnsw::<f32, DistL2>::new(4, 4000, 16, 24, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let queries = synthetic::uniform(100, 16, &mut rng);
+        let recall = |params: &SearchParams| {
+            let mut nb_found = 0;
+            for query in &queries {
+                let mut exact: Vec<(f32, usize)> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(i, v)| (DistL2 {}.eval(query, v), i))
+                    .collect();
+                exact.sort_unstable_by(|a, b| a.0.total_cmp(&b.0));
+                let res = hnsw.search_with_params(query, 10, 16, params);
+                nb_found += res
+                    .iter()
+                    .filter(|n| exact[..10].iter().any(|e| e.1 == n.d_id))
+                    .count();
+            }
+            nb_found as f64 / 1000.
+        };
+        let plain = recall(&SearchParams::new());
+        let restarted = recall(&SearchParams::new().restarts(8));
+        log::info!(
+            "recall without restart {:.3}, with 8 restarts {:.3}",
+            plain,
+            restarted
+        );
+        assert!(restarted > plain, "recall {} <= {}", restarted, plain);
+        // no restart is a plain search
+        assert_eq!(
+            hnsw.search_with_params(&queries[0], 10, 16, &SearchParams::new().restarts(0)),
+            hnsw.search(&queries[0], 10, 16)
+        );
+    } // end of test_restarts
+} // end of mod tests
@@ -0,0 +1,220 @@
+//! Health of an index in one call, to back the readiness probe of a service.
+//!
+//! [Hnsw::health] checks, on a sample of points taken at a regular stride of the point indexation:
+//! - integrity: neighbour lists hold neither their own point nor a point twice (see [Hnsw::check_links]),
+//! - reachability: a search from the entry point for the vector of a sampled point (not deleted) finds it,
+//!
+//! and on the whole structure the presence of the entry point, the fraction of deleted points and the memory taken
+//! by vectors and links (estimated from the sample) against a budget. Each failed check adds a reason to the
+//! [Health], the index is ok when there is none. The cost is the one of [HealthLimits::nb_sample] searches.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Point, PointWithOrder};
+use crate::linkcheck::check_point;
+
+/// thresholds of [Hnsw::health_with]
+#[derive(Copy, Clone, Debug)]
+pub struct HealthLimits {
+    /// number of points checked
+    pub nb_sample: usize,
+    /// minimum fraction of sampled points found by a search of their own vector
+    pub min_reachable: f64,
+    /// maximum fraction of deleted points (see [Hnsw::get_deleted_fraction])
+    pub max_deleted_fraction: f64,
+    /// bytes the index may take, not checked if None
+    pub memory_budget: Option<usize>,
+    /// fraction of the budget that must stay free
+    pub min_headroom: f64,
+}
+
+impl Default for HealthLimits {
+    fn default() -> Self {
+        HealthLimits {
+            nb_sample: 100,
+            min_reachable: 0.9,
+            max_deleted_fraction: 0.5,
+            memory_budget: None,
+            min_headroom: 0.1,
+        }
+    }
+}
+
+/// result of a health check, see module doc
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Health {
+    /// true if no check failed
+    pub ok: bool,
+    /// failed checks
+    pub reasons: Vec<String>,
+    pub nb_point: usize,
+    /// number of points checked
+    pub nb_sample: usize,
+    /// fraction of sampled points (not deleted) found by a search, 1 if none
+    pub reachable_fraction: f64,
+    pub deleted_fraction: f64,
+    /// estimated bytes of vectors and links
+    pub memory: usize,
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// health of the index with default limits (no memory budget)
+    pub fn health(&self) -> Health {
+        self.health_with(&HealthLimits::default())
+    }
+
+    /// health of the index, see module doc
+    pub fn health_with(&self, limits: &HealthLimits) -> Health {
+        let nb_point = self.get_nb_point();
+        let mut health = Health {
+            nb_point,
+            reachable_fraction: 1.,
+            deleted_fraction: self.get_deleted_fraction(),
+            ..Default::default()
+        };
+        if nb_point > 0 && self.layer_indexed_points.entry_point.read().is_none() {
+            health.reasons.push("no entry point".to_string());
+        }
+        // the iterator keeps a lock on the indexation, searches are done after
+        let stride = (nb_point / limits.nb_sample.max(1)).max(1);
+        let sample: Vec<Arc<Point<T>>> = self
+            .get_point_indexation()
+            .into_iter()
+            .step_by(stride)
+            .take(limits.nb_sample)
+            .collect();
+        health.nb_sample = sample.len();
+        let mut nb_links = 0;
+        let mut nb_searched = 0;
+        let mut nb_found = 0;
+        for point in &sample {
+            if let Err(e) = check_point(point) {
+                health.reasons.push(format!("integrity: {}", e));
+            }
+            nb_links += point
+                .neighbours
+                .read()
+                .iter()
+                .map(|list| list.len())
+                .sum::<usize>();
+            if self.is_deleted(point) {
+                continue;
+            }
+            nb_searched += 1;
+            let found = self.search(point.get_v(), 1, self.get_ef_construction());
+            if found
+                .first()
+                .is_some_and(|n| n.d_id == point.get_origin_id() || n.distance <= 0.)
+            {
+                nb_found += 1;
+            }
+        }
+        if nb_searched > 0 {
+            health.reachable_fraction = nb_found as f64 / nb_searched as f64;
+        }
+        if health.reachable_fraction < limits.min_reachable {
+            health.reasons.push(format!(
+                "reachability: {} of {} sampled points found",
+                nb_found, nb_searched
+            ));
+        }
+        if health.deleted_fraction > limits.max_deleted_fraction {
+            health.reasons.push(format!(
+                "deleted fraction {:.3} above {}",
+                health.deleted_fraction, limits.max_deleted_fraction
+            ));
+        }
+        // vectors and points, links extrapolated from the sample
+        let point_size = std::mem::size_of::<Point<T>>()
+            + self.get_point_indexation().get_data_dimension() * std::mem::size_of::<T>();
+        let link_size = std::mem::size_of::<Arc<PointWithOrder<T>>>()
+            + std::mem::size_of::<PointWithOrder<T>>();
+        let links_by_point = nb_links as f64 / sample.len().max(1) as f64;
+        health.memory =
+            nb_point * point_size + (links_by_point * nb_point as f64) as usize * link_size;
+        if let Some(budget) = limits.memory_budget {
+            let allowed = (budget as f64 * (1. - limits.min_headroom)) as usize;
+            if health.memory > allowed {
+                health.reasons.push(format!(
+                    "memory: {} bytes estimated, {} allowed by budget {}",
+                    health.memory, allowed, budget
+                ));
+            }
+        }
+        health.ok = health.reasons.is_empty();
+        health
+    } // end of health_with
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::PointId;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_health() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(992);
+        let data = synthetic::uniform(2000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 2000, 16, 100, DistL2 {});
+        let health = hnsw.health();
+        assert!(health.ok);
+        assert_eq!(health.nb_sample, 0);
+        hnsw.parallel_insert(&data_with_id);
+        let health = hnsw.health();
+        assert!(health.ok, "{:?}", health.reasons);
+        assert_eq!(health.nb_sample, 100);
+        assert!(health.reachable_fraction >= 0.9);
+        assert!(health.memory > 2000 * 8 * 4);
+        // a tight memory budget
+        let limits = HealthLimits {
+            memory_budget: Some(health.memory),
+            ..Default::default()
+        };
+        let health = hnsw.health_with(&limits);
+        assert!(!health.ok);
+        assert!(health.reasons[0].starts_with("memory"));
+        // too many deleted points
+        let deleted: Vec<usize> = (0..1200).collect();
+        hnsw.remove_batch(&deleted).unwrap();
+        let health = hnsw.health();
+        assert!(!health.ok);
+        assert_eq!(health.reasons.len(), 1);
+        assert!(health.reasons[0].starts_with("deleted fraction"));
+        // a broken list of a sampled point
+        let point = hnsw
+            .get_point_indexation()
+            .get_point(&PointId(0, 0))
+            .unwrap();
+        {
+            let mut neighbours = point.neighbours.write();
+            let first = Arc::clone(&neighbours[0][0]);
+            neighbours[0].push(first);
+        }
+        let limits = HealthLimits {
+            max_deleted_fraction: 1.,
+            nb_sample: 2000,
+            ..Default::default()
+        };
+        let health = hnsw.health_with(&limits);
+        assert!(!health.ok);
+        assert!(health.reasons.iter().any(|r| r.starts_with("integrity")));
+        // the result goes to a probe as json
+        assert!(
+            serde_json::to_string(&health)
+                .unwrap()
+                .contains("\"ok\":false")
+        );
+    } // end of test_health
+} // end of mod tests
@@ -6,18 +6,20 @@
 use serde::{Deserialize, Serialize};
 
 use cpu_time::ProcessTime;
-use std::time::SystemTime;
+use std::cell::Cell;
+use std::time::{Instant, SystemTime};
 
 use std::cmp::Ordering;
 
 use parking_lot::{Mutex, RwLock, RwLockReadGuard};
 use rayon::prelude::*;
 use std::sync::Arc;
-use std::sync::mpsc::channel;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 
 use std::any::type_name;
 
 use hashbrown::HashMap;
+use std::collections::BTreeMap;
 #[allow(unused)]
 use std::collections::HashSet;
 use std::collections::binary_heap::BinaryHeap;
@@ -25,7 +27,31 @@ use std::collections::binary_heap::BinaryHeap;
 use log::trace;
 use log::{debug, info};
 
+use crate::access::AccessCounts;
+use crate::adaptive::AdaptiveDegree;
+use crate::alias::AliasTable;
+use crate::blockdump::DirtyPoints;
+use crate::costmodel::CostModel;
+use crate::dedup::Dedup;
+use crate::events::{Event, EventRecorder};
 pub use crate::filter::FilterT;
+use crate::graphkind::GraphKind;
+use crate::idset::IdSet;
+use crate::layergrowth::LayerPlan;
+use crate::namespace::Namespaces;
+use crate::payload::{Payload, PayloadColumn, Tags};
+use crate::quality::QualityState;
+use crate::quiesce::WriteGate;
+use crate::readrepair::ReadRepair;
+use crate::rebuild::RebuildLog;
+use crate::scratch::{Scratch, ScratchPool};
+use crate::searchparams::SearchParams;
+use crate::sequence::AsOf;
+use crate::slowquery::{SearchStats, SlowQueryHook};
+use crate::statshistory::StatsHistory;
+use crate::tiebreak::TieBreak;
+use crate::tombstone::Tombstones;
+use crate::topk::{ResultBuffer, TopK};
 use anndists::dist::distances::Distance;
 
 // TODO
@@ -40,17 +66,39 @@ pub struct NoData;
 /// maximum number of layers
 pub(crate) const NB_LAYER_MAX: u8 = 16; // so max layer is 15!!
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
 /// The 2-uple represent layer as u8  and rank in layer as a i32 as stored in our structure
 pub struct PointId(pub u8, pub i32);
 
 /// this type is for an identificateur of each data vector, given by client.
 /// Can be the rank of data in an array, a hash value or anything that permits
-/// retrieving the data.
+/// retrieving the data.  
+/// Ids are u64 in dumps and in the C api whatever the target, so that a dump of a 64 bit target reloads on a
+/// 32 bit one (wasm32) when its ids fit. [data_id_of] converts an id of 64 bits.
 pub type DataId = usize;
 
+/// the DataId of an id of 64 bits, an error if it does not fit in a usize of the target
+pub fn data_id_of(id: u64) -> Result<DataId, HnswError> {
+    DataId::try_from(id).map_err(|_| HnswError::IdRange(id))
+}
+
 pub type PointDistance<T> = Box<dyn Distance<T>>;
 
+/// A distance that can abandon its computation: bound is the distance above which the caller
+/// rejects the point, so once a partial result exceeds it any value greater than bound can be returned.
+/// See [Hnsw::set_bounded_distance] and [crate::simd::l2_bounded].
+pub type BoundedDistance<T> = fn(&[T], &[T], f32) -> f32;
+
+/// A distance from a query to a batch of vectors, written in out (of the length of vectors), that can go through
+/// the components by blocks across the batch to reuse the cache on large dimensions.
+/// See [Hnsw::set_tiled_distance] and [crate::simd::l2_tiled].
+pub type TiledDistance<T> = fn(&[T], &[&[T]], &mut [f32]);
+
+/// max number of vectors given at once to a [TiledDistance], twice the usual max_nb_connection at layer 0
+const TILED_BATCH: usize = 64;
+
 /// A structure containing internal pointId with distance to this pointId.
 /// The order is given by ordering the distance to the point it refers to.
 /// So points ordering has a meaning only has points refers to the same point
@@ -121,21 +169,46 @@ impl Neighbour {
     pub fn get_distance(&self) -> f32 {
         self.distance
     }
+    /// order of search results : increasing distance then, for equal distances, increasing data id
+    pub fn cmp_by_distance(&self, other: &Neighbour) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.d_id.cmp(&other.d_id))
+    }
+}
+
+/// What an insertion did, returned by [Hnsw::insert_verbose].
+#[derive(Debug, Clone)]
+pub struct InsertReport {
+    /// point identification inside layers, its first field is the layer of the point
+    pub p_id: PointId,
+    /// neighbours chosen at insertion for each layer from 0 to the layer of the point, sorted by distance.
+    /// Lists can change later as points are inserted.
+    pub neighbours: Vec<Vec<Neighbour>>,
+}
+
+impl InsertReport {
+    /// layer of the point
+    pub fn get_layer(&self) -> u8 {
+        self.p_id.0
+    }
 }
 
 //=======================================================================================
 
 #[derive(Debug, Clone)]
-enum PointData<'b, T: Clone + Send + Sync + 'b> {
+pub(crate) enum PointData<'b, T: Clone + Send + Sync + 'b> {
     // full data
     V(Vec<T>),
     // areference to a mmaped slice
     S(&'b [T]),
+    // data shared with the client (and possibly other structures)
+    A(Arc<[T]>),
 } // end of enum PointData
 
 impl<'b, T: Clone + Send + Sync + 'b> PointData<'b, T> {
     // allocate a point stored in structure
-    fn new_v(v: Vec<T>) -> Self {
+    pub(crate) fn new_v(v: Vec<T>) -> Self {
         PointData::V(v)
     }
 
@@ -144,10 +217,11 @@ impl<'b, T: Clone + Send + Sync + 'b> PointData<'b, T> {
         PointData::S(s)
     }
 
-    fn get_v(&self) -> &[T] {
+    pub(crate) fn get_v(&self) -> &[T] {
         match self {
             PointData::V(v) => v.as_slice(),
             PointData::S(s) => s,
+            PointData::A(a) => a,
         }
     } // end of get_v
 } // end of impl block for PointData
@@ -173,33 +247,37 @@ pub struct Point<'b, T: Clone + Send + Sync> {
 
 impl<'b, T: Clone + Send + Sync> Point<'b, T> {
     pub fn new(v: Vec<T>, origin_id: usize, p_id: PointId) -> Self {
-        let mut neighbours = Vec::with_capacity(NB_LAYER_MAX as usize);
-        // CAVEAT, perhaps pass nb layer as arg ?
-        for _ in 0..NB_LAYER_MAX {
-            neighbours.push(Vec::<Arc<PointWithOrder<T>>>::new());
-        }
-        Point {
-            data: PointData::new_v(v),
-            origin_id,
-            p_id,
-            neighbours: Arc::new(RwLock::new(neighbours)),
-        }
+        Point::from_data(PointData::new_v(v), origin_id, p_id)
     }
 
     pub fn new_from_mmap(s: &'b [T], origin_id: usize, p_id: PointId) -> Self {
+        Point::from_data(PointData::new_s(s), origin_id, p_id)
+    }
+
+    /// a point sharing its data with the client, the vector is not copied
+    pub fn new_from_arc(a: Arc<[T]>, origin_id: usize, p_id: PointId) -> Self {
+        Point::from_data(PointData::A(a), origin_id, p_id)
+    }
+
+    pub(crate) fn from_data(data: PointData<'b, T>, origin_id: usize, p_id: PointId) -> Self {
         let mut neighbours = Vec::with_capacity(NB_LAYER_MAX as usize);
         // CAVEAT, perhaps pass nb layer as arg ?
         for _ in 0..NB_LAYER_MAX {
             neighbours.push(Vec::<Arc<PointWithOrder<T>>>::new());
         }
         Point {
-            data: PointData::new_s(s),
+            data,
             origin_id,
             p_id,
             neighbours: Arc::new(RwLock::new(neighbours)),
         }
     }
 
+    /// a copy of the point, without links, with point id p_id. Vectors of a mmap or shared stay shared.
+    pub(crate) fn copy_unlinked(&self, p_id: PointId) -> Self {
+        Point::from_data(self.data.clone(), self.origin_id, p_id)
+    }
+
     /// get a reference to vector data
     pub fn get_v(&self) -> &[T] {
         self.data.get_v()
@@ -239,6 +317,33 @@ impl<'b, T: Clone + Send + Sync> Point<'b, T> {
         neighborhood
     }
 
+    /// returns the neighbours of point at layer, sorted by increasing distance and by increasing origin id
+    /// for equal distances, so that the order does not depend on insertion order.
+    /// Returns an empty vector for a layer above the point.
+    pub fn get_neighbors_sorted(&self, layer: usize) -> Vec<Neighbour> {
+        let ref_neighbours = self.neighbours.read();
+        let mut neighbours: Vec<Neighbour> = match ref_neighbours.get(layer) {
+            Some(neighbours_l) => neighbours_l
+                .iter()
+                .map(|pointwo| {
+                    Neighbour::new(
+                        pointwo.point_ref.get_origin_id(),
+                        pointwo.dist_to_ref,
+                        pointwo.point_ref.get_point_id(),
+                    )
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        neighbours.sort_by(|a, b| {
+            a.distance
+                .total_cmp(&b.distance)
+                .then(a.d_id.cmp(&b.d_id))
+                .then(a.p_id.cmp(&b.p_id))
+        });
+        neighbours
+    }
+
     /// prints minimal information on neighbours of point.
     pub fn debug_dump(&self) {
         println!(" \n dump of point id : {:?}", self.p_id);
@@ -264,9 +369,9 @@ impl<'b, T: Clone + Send + Sync> Point<'b, T> {
 pub(crate) struct PointWithOrder<'b, T: Clone + Send + Sync> {
     /// the identificateur of the point for which we store a distance to a point for which
     ///  we made a request.
-    point_ref: Arc<Point<'b, T>>,
+    pub(crate) point_ref: Arc<Point<'b, T>>,
     /// The distance to a point_ref to the request point (not represented in the structure)
-    dist_to_ref: f32,
+    pub(crate) dist_to_ref: f32,
 }
 
 impl<T: Clone + Send + Sync> PartialEq for PointWithOrder<'_, T> {
@@ -304,6 +409,25 @@ impl<'b, T: Clone + Send + Sync> PointWithOrder<'b, T> {
     }
 } // end of impl block
 
+/// what a traversal of a layer does beyond finding the ef nearest points, see Hnsw::search_layer_with
+pub(crate) struct LayerSearch<'p, T> {
+    pub(crate) params: Option<&'p SearchParams<'p>>,
+    /// with Some((k, d)) the traversal returns as soon as k returned points are within distance d
+    pub(crate) stop: Option<(usize, f32)>,
+    /// the query when dist_to_query is the distance of the structure to it, for a tiled distance
+    pub(crate) vector: Option<&'p [T]>,
+    /// state returned points must be in, see [SearchParams::as_of]
+    pub(crate) as_of: Option<&'p AsOf>,
+}
+
+impl<T> Clone for LayerSearch<'_, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for LayerSearch<'_, T> {}
+
 //============================================================================================
 
 //  LayerGenerator
@@ -318,7 +442,23 @@ pub struct LayerGenerator {
     unif: Uniform<f64>,
     // drives number of levels generated ~ S
     scale: f64,
-    maxlevel: usize,
+    // can grow during insertions, see crate::layergrowth
+    maxlevel: AtomicUsize,
+    // seed of rng, None if seeded by the os
+    seed: Option<u64>,
+}
+
+// a copy continues the sequence of levels of the original
+impl Clone for LayerGenerator {
+    fn clone(&self) -> Self {
+        LayerGenerator {
+            rng: Arc::new(Mutex::new(self.rng.lock().clone())),
+            unif: self.unif,
+            scale: self.scale,
+            maxlevel: AtomicUsize::new(self.get_max_level()),
+            seed: self.seed,
+        }
+    }
 }
 
 impl LayerGenerator {
@@ -328,7 +468,8 @@ impl LayerGenerator {
             rng: Arc::new(Mutex::new(StdRng::from_os_rng())),
             unif: Uniform::<f64>::new(0., 1.).unwrap(),
             scale,
-            maxlevel,
+            maxlevel: AtomicUsize::new(maxlevel),
+            seed: None,
         }
     }
 
@@ -343,7 +484,8 @@ impl LayerGenerator {
             rng: Arc::new(Mutex::new(StdRng::from_os_rng())),
             unif: Uniform::<f64>::new(0., 1.).unwrap(),
             scale: scale_default * scale_factor,
-            maxlevel,
+            maxlevel: AtomicUsize::new(maxlevel),
+            seed: None,
         }
     }
     //
@@ -360,14 +502,25 @@ impl LayerGenerator {
         let xsi = protected_rng.sample(self.unif);
         let level = -xsi.ln() * self.scale;
         let mut ulevel = level.floor() as usize;
+        let maxlevel = self.get_max_level();
         // we redispatch possibly sampled level  >= maxlevel to required range
-        if ulevel >= self.maxlevel {
+        if ulevel >= maxlevel {
             // This occurs with very low probability. Cf commentary above.
-            ulevel = protected_rng.sample(Uniform::<usize>::new(0, self.maxlevel).unwrap());
+            ulevel = protected_rng.sample(Uniform::<usize>::new(0, maxlevel).unwrap());
         }
         ulevel
     }
 
+    // levels drawn from a seeded generator, so that a serial build is reproducible
+    pub(crate) fn set_seed(&mut self, seed: u64) {
+        *self.rng.lock() = StdRng::seed_from_u64(seed);
+        self.seed = Some(seed);
+    }
+
+    pub(crate) fn get_seed(&self) -> Option<u64> {
+        self.seed
+    }
+
     /// just to try some variations on exponential level sampling. Unused.
     fn set_scale_modification(&mut self, scale_modification: f64) {
         self.scale *= scale_modification;
@@ -378,6 +531,11 @@ impl LayerGenerator {
     fn get_level_scale(&self) -> f64 {
         self.scale
     }
+
+    /// number of levels that can be generated
+    pub(crate) fn get_max_level(&self) -> usize {
+        self.maxlevel.load(AtomicOrdering::Acquire)
+    }
 } // end impl for LayerGenerator
 
 // ====================================================================
@@ -400,6 +558,12 @@ pub struct PointIndexation<'b, T: Clone + Send + Sync> {
     pub(crate) nb_point: Arc<RwLock<usize>>,
     /// curent enter_point: an Arc RwLock on a possible Arc Point
     pub(crate) entry_point: Arc<RwLock<Option<Arc<Point<'b, T>>>>>,
+    /// payloads of points, see [crate::payload]
+    pub(crate) payloads: PayloadColumn,
+    /// point ids by insertion sequence number, see [crate::sequence]
+    pub(crate) insertion_order: Arc<RwLock<Vec<PointId>>>,
+    /// data ids of points, see [crate::idset]
+    pub(crate) ids: IdSet,
 }
 
 // A point indexation may contain circular references. To deallocate these after a point indexation goes out of scope,
@@ -462,6 +626,9 @@ impl<'b, T: Clone + Send + Sync> PointIndexation<'b, T> {
             layer_g,
             nb_point: Arc::new(RwLock::new(0)),
             entry_point: Arc::new(RwLock::new(None)),
+            payloads: PayloadColumn::default(),
+            insertion_order: Arc::new(RwLock::new(Vec::with_capacity(max_elements))),
+            ids: IdSet::default(),
         }
     } // end of new
 
@@ -495,28 +662,74 @@ impl<'b, T: Clone + Send + Sync> PointIndexation<'b, T> {
     /// real insertion of point in point indexation
     // generate a new Point/ArcPoint (with neigbourhood info empty) and store it in global table
     // The function is called by Hnsw insert method
-    fn generate_new_point(&self, data: &[T], origin_id: usize) -> (Arc<Point<'b, T>>, usize) {
+    pub(crate) fn generate_new_point(
+        &self,
+        data: PointData<'b, T>,
+        origin_id: usize,
+        payload: Payload,
+    ) -> (Arc<Point<'b, T>>, usize) {
+        self.generate_new_point_in(data, origin_id, payload, &|_| None)
+    }
+
+    // as generate_new_point, but take_slot(level) can give a free slot of layer level, (rank, sequence number),
+    // where the point replaces a deleted one. See crate::tombstone.
+    pub(crate) fn generate_new_point_in(
+        &self,
+        data: PointData<'b, T>,
+        origin_id: usize,
+        payload: Payload,
+        take_slot: &dyn Fn(u8) -> Option<(i32, usize)>,
+    ) -> (Arc<Point<'b, T>>, usize) {
         // get a write lock at the beginning of the function
         let level = self.layer_g.generate();
         let new_point;
+        let slot;
         {
             // open a write lock on points_by_layer
             let mut points_by_layer_ref = self.points_by_layer.write();
+            // the slot is taken under the lock, a search as of a sequence number sees free slots and points
+            // in one state (see crate::sequence)
+            slot = take_slot(level as u8);
             let mut p_id = PointId(level as u8, -1);
-            p_id.1 = points_by_layer_ref[p_id.0 as usize].len() as i32;
+            // ranks in a layer are i32
+            p_id.1 = match slot {
+                Some((rank, _)) => rank,
+                None => {
+                    i32::try_from(points_by_layer_ref[p_id.0 as usize].len()).unwrap_or_else(|_| {
+                        panic!("layer {} is full, {} points at most", level, i32::MAX)
+                    })
+                }
+            };
             // make a Point and then an Arc<Point>
-            let point = Point::new(data.to_vec(), origin_id, p_id);
-            new_point = Arc::new(point);
+            new_point = Arc::new(Point::from_data(data, origin_id, p_id));
+            self.payloads.set(p_id, payload);
             trace!("definitive pushing of point {:?}", p_id);
-            points_by_layer_ref[p_id.0 as usize].push(Arc::clone(&new_point));
+            // under the lock on layers, sequence numbers follow the creation of points
+            let mut insertion_order = self.insertion_order.write();
+            match slot {
+                Some((rank, seq)) => {
+                    let old = std::mem::replace(
+                        &mut points_by_layer_ref[p_id.0 as usize][rank as usize],
+                        Arc::clone(&new_point),
+                    );
+                    // deleted points can link each other, the lists of the old point would keep them alive
+                    old.neighbours.write().iter_mut().for_each(|l| l.clear());
+                    insertion_order[seq] = PointId(p_id.0, -1);
+                }
+                None => points_by_layer_ref[p_id.0 as usize].push(Arc::clone(&new_point)),
+            }
+            insertion_order.push(p_id);
         } // close write lock on points_by_layer
+        self.ids.insert(origin_id);
         //
         let nb_point;
         {
             let mut lock_nb_point = self.nb_point.write();
-            *lock_nb_point += 1;
+            if slot.is_none() {
+                *lock_nb_point += 1;
+            }
             nb_point = *lock_nb_point;
-            if nb_point % 50000 == 0 {
+            if slot.is_none() && nb_point % 50000 == 0 {
                 println!(" setting number of points {:?} ", nb_point);
             }
         }
@@ -525,8 +738,23 @@ impl<'b, T: Clone + Send + Sync> PointIndexation<'b, T> {
         (Arc::clone(&new_point), nb_point)
     } // end of insert
 
+    /// adds an empty layer above the others and lets the generator draw it.
+    /// Returns false if there are already NB_LAYER_MAX layers.
+    pub(crate) fn add_layer(&self) -> bool {
+        let mut points_by_layer = self.points_by_layer.write();
+        if points_by_layer.len() >= NB_LAYER_MAX as usize {
+            return false;
+        }
+        // the layer exists before a point can be generated in it
+        points_by_layer.push(Vec::new());
+        self.layer_g
+            .maxlevel
+            .store(points_by_layer.len(), AtomicOrdering::Release);
+        true
+    }
+
     /// check if entry_point is modified
-    fn check_entry_point(&self, new_point: &Arc<Point<'b, T>>) {
+    pub(crate) fn check_entry_point(&self, new_point: &Arc<Point<'b, T>>) {
         //
         // take directly a write lock so that we are sure nobody can change anything between read and write
         // of entry_point_id
@@ -610,32 +838,145 @@ impl<'b, T: Clone + Send + Sync> PointIndexation<'b, T> {
         }
     } // end of get_point
 
+    /// returns the payload of a point given its PointId, empty if it has none
+    pub fn get_payload(&self, p_id: &PointId) -> Payload {
+        self.payloads.get(*p_id)
+    }
+
     /// get an iterator on the points stored in a given layer
     pub fn get_layer_iterator<'a>(&'a self, layer: usize) -> IterPointLayer<'a, 'b, T> {
         IterPointLayer::new(self, layer)
     } // end of get_layer_iterator
+
+    // number of points in each layer, the points visited by iterations
+    fn get_layer_sizes(&self) -> Vec<usize> {
+        self.points_by_layer
+            .read()
+            .iter()
+            .map(|l| l.len())
+            .collect()
+    }
+
+    /// iterates over the PointId and DataId of points, from layer 0 upward. See [Self::iter_points_chunked]
+    /// for concurrency, no Arc of points is cloned.
+    pub fn iter_ids(&self) -> impl Iterator<Item = (PointId, DataId)> + use<'_, 'b, T> {
+        IterChunks::new(self, ITER_CHUNK, |p: &Arc<Point<'b, T>>| {
+            (p.p_id, p.origin_id)
+        })
+        .flatten()
+    }
+
+    /// iterates over the points by chunks of at most chunk points, from layer 0 upward.
+    ///
+    /// Points present when the iterator is created are visited, points inserted later are not.
+    /// The read lock on layers is taken for each chunk and released before it is returned, so the
+    /// structure can be inserted into while chunks are processed.
+    pub fn iter_points_chunked(
+        &self,
+        chunk: usize,
+    ) -> impl Iterator<Item = Vec<Arc<Point<'b, T>>>> + '_ {
+        assert!(chunk > 0, "chunk must be positive");
+        IterChunks::new(self, chunk, Arc::clone)
+    }
+
+    /// a rayon iterator over the points present at call, with the guarantees of [Self::iter_points_chunked].
+    /// collect() gives the points in the order of the sequential iteration.
+    pub fn par_iter(&self) -> impl ParallelIterator<Item = Arc<Point<'b, T>>> + '_ {
+        let ranges: Vec<(usize, usize, usize)> = self
+            .get_layer_sizes()
+            .into_iter()
+            .enumerate()
+            .flat_map(|(l, size)| {
+                (0..size)
+                    .step_by(ITER_CHUNK)
+                    .map(move |start| (l, start, size.min(start + ITER_CHUNK)))
+            })
+            .collect();
+        ranges
+            .into_par_iter()
+            .flat_map_iter(move |(l, start, end)| {
+                self.points_by_layer.read()[l][start..end].to_vec()
+            })
+    }
 } // end of impl PointIndexation
 
 //============================================================================================
 
+// number of points read under one lock by iterations
+const ITER_CHUNK: usize = 4096;
+
+// iteration by chunks over the points present at creation, f maps a point to an item
+struct IterChunks<'a, 'b, T: Clone + Send + Sync, F> {
+    point_indexation: &'a PointIndexation<'b, T>,
+    layer_sizes: Vec<usize>,
+    layer: usize,
+    slot_in_layer: usize,
+    chunk: usize,
+    f: F,
+}
+
+impl<'a, 'b, T: Clone + Send + Sync, F> IterChunks<'a, 'b, T, F> {
+    fn new(point_indexation: &'a PointIndexation<'b, T>, chunk: usize, f: F) -> Self {
+        IterChunks {
+            point_indexation,
+            layer_sizes: point_indexation.get_layer_sizes(),
+            layer: 0,
+            slot_in_layer: 0,
+            chunk,
+            f,
+        }
+    }
+} // end of impl IterChunks
+
+impl<'b, T, F, R> Iterator for IterChunks<'_, 'b, T, F>
+where
+    T: Clone + Send + Sync,
+    F: FnMut(&Arc<Point<'b, T>>) -> R,
+{
+    type Item = Vec<R>;
+    //
+    fn next(&mut self) -> Option<Self::Item> {
+        // skip exhausted and empty layers
+        while self.layer < self.layer_sizes.len()
+            && self.slot_in_layer >= self.layer_sizes[self.layer]
+        {
+            self.layer += 1;
+            self.slot_in_layer = 0;
+        }
+        if self.layer >= self.layer_sizes.len() {
+            return None;
+        }
+        let start = self.slot_in_layer;
+        let end = self.layer_sizes[self.layer].min(start + self.chunk);
+        self.slot_in_layer = end;
+        // layers only grow while the structure is shared, so the slots are still there
+        let layers = self.point_indexation.points_by_layer.read();
+        Some(
+            layers[self.layer][start..end]
+                .iter()
+                .map(&mut self.f)
+                .collect(),
+        )
+    } // end of next
+} // end of impl Iterator
+
+type ClonePoint<'b, T> = fn(&Arc<Point<'b, T>>) -> Arc<Point<'b, T>>;
+
 /// an iterator on points stored.
 /// The iteration begins at level 0 (most populated level) and goes upward in levels.
-/// The iterator takes a ReadGuard on the PointIndexation structure
+/// It is [PointIndexation::iter_points_chunked] flattened: points inserted after creation are not visited and
+/// no lock is held between calls to next. [PointIndexation::iter_ids] and [PointIndexation::par_iter] avoid
+/// cloning points or iterate in parallel.
 pub struct IterPoint<'a, 'b, T: Clone + Send + Sync + 'b> {
-    point_indexation: &'a PointIndexation<'b, T>,
-    pi_guard: RwLockReadGuard<'a, Vec<Layer<'b, T>>>,
-    layer: i64,
-    slot_in_layer: i64,
+    chunks: IterChunks<'a, 'b, T, ClonePoint<'b, T>>,
+    current: std::vec::IntoIter<Arc<Point<'b, T>>>,
 }
 
 impl<'a, 'b, T: Clone + Send + Sync> IterPoint<'a, 'b, T> {
     pub fn new(point_indexation: &'a PointIndexation<'b, T>) -> Self {
-        let pi_guard: RwLockReadGuard<Vec<Layer<'b, T>>> = point_indexation.points_by_layer.read();
         IterPoint {
-            point_indexation,
-            pi_guard,
-            layer: -1,
-            slot_in_layer: -1,
+            chunks: IterChunks::new(point_indexation, ITER_CHUNK, Arc::clone),
+            current: Vec::new().into_iter(),
         }
     }
 } // end of block impl IterPoint
@@ -645,35 +986,11 @@ impl<'b, T: Clone + Send + Sync> Iterator for IterPoint<'_, 'b, T> {
     type Item = Arc<Point<'b, T>>;
     //
     fn next(&mut self) -> Option<Self::Item> {
-        if self.layer == -1 {
-            self.layer = 0;
-            self.slot_in_layer = 0;
-        }
-        if (self.slot_in_layer as usize) < self.pi_guard[self.layer as usize].len() {
-            let slot = self.slot_in_layer as usize;
-            self.slot_in_layer += 1;
-            Some(self.pi_guard[self.layer as usize][slot].clone())
-        } else {
-            self.slot_in_layer = 0;
-            self.layer += 1;
-            // must reach a non empty layer if possible
-            let entry_point_ref = self.point_indexation.entry_point.read();
-            let points_by_layer = self.point_indexation.points_by_layer.read();
-            let entry_point_level = entry_point_ref.as_ref().unwrap().p_id.0;
-            while (self.layer as u8) <= entry_point_level
-                && points_by_layer[self.layer as usize].is_empty()
-            {
-                self.layer += 1;
-            }
-            // now here either  (self.layer as u8) > self.point_indexation.max_level_observed
-            // or self.point_indexation.points_by_layer[self.layer as usize ].len() > 0
-            if (self.layer as u8) <= entry_point_level {
-                let slot = self.slot_in_layer as usize;
-                self.slot_in_layer += 1;
-                Some(points_by_layer[self.layer as usize][slot].clone())
-            } else {
-                None
+        loop {
+            if let Some(point) = self.current.next() {
+                return Some(point);
             }
+            self.current = self.chunks.next()?.into_iter();
         }
     } // end of next
 } // end of impl Iterator
@@ -725,13 +1042,102 @@ impl<'b, T: Clone + Send + Sync + 'b> Iterator for IterPointLayer<'_, 'b, T> {
 
 // ============================================================================================
 
+/// parameters rejected by [Hnsw::try_new]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HnswParamError {
+    /// max_nb_connection must be in 1..=256
+    MaxNbConnection(usize),
+    /// max_layer must be at least 1
+    MaxLayer(usize),
+}
+
+impl std::fmt::Display for HnswParamError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HnswParamError::MaxNbConnection(nb) => {
+                write!(f, "max_nb_connection must be in 1..=256, got {}", nb)
+            }
+            HnswParamError::MaxLayer(nb) => write!(f, "max_layer must be at least 1, got {}", nb),
+        }
+    }
+}
+
+impl std::error::Error for HnswParamError {}
+
+/// rejection of an item by [Hnsw::try_insert] and [Hnsw::try_parallel_insert], the item is not inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HnswError {
+    /// the vector of the item with this id is empty
+    EmptyVector(DataId),
+    /// the vector of the item has not the dimension of the points of the index
+    Dimension {
+        origin_id: DataId,
+        expected: usize,
+        got: usize,
+    },
+    /// the item is a duplicate of the point with id canonical (see [Hnsw::set_dedup])
+    Duplicate {
+        origin_id: DataId,
+        canonical: DataId,
+    },
+    /// the insertion panicked (NaN distance, a distance rejecting the vector...), the point was discarded
+    Panic { origin_id: DataId, cause: String },
+    /// an id of 64 bits does not fit in a DataId of the target
+    IdRange(u64),
+    /// the embedder of the item panicked or returned no vector for it (see [Embedder](crate::embed::Embedder))
+    Embedding { origin_id: DataId, cause: String },
+    /// the index is read only, see [Hnsw::set_read_only]
+    ReadOnly,
+}
+
+impl std::fmt::Display for HnswError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HnswError::EmptyVector(id) => write!(f, "vector of {} is empty", id),
+            HnswError::Dimension {
+                origin_id,
+                expected,
+                got,
+            } => write!(
+                f,
+                "vector of {} has dimension {}, index has dimension {}",
+                origin_id, got, expected
+            ),
+            HnswError::Duplicate {
+                origin_id,
+                canonical,
+            } => write!(f, "{} is a duplicate of {}", origin_id, canonical),
+            HnswError::Panic { origin_id, cause } => {
+                write!(f, "insertion of {} panicked : {}", origin_id, cause)
+            }
+            HnswError::IdRange(id) => write!(f, "id {} does not fit in usize", id),
+            HnswError::Embedding { origin_id, cause } => {
+                write!(f, "embedding of {} failed : {}", origin_id, cause)
+            }
+            HnswError::ReadOnly => write!(f, "index is read only"),
+        }
+    }
+}
+
+impl std::error::Error for HnswError {}
+
+// message of a panic caught by catch_unwind
+pub(crate) fn panic_cause(cause: Box<dyn std::any::Any + Send>) -> String {
+    cause
+        .downcast_ref::<&str>()
+        .map(|s| s.to_string())
+        .or_else(|| cause.downcast_ref::<String>().cloned())
+        .unwrap_or_default()
+}
+
 // The fields are made pub(crate) to be able to initialize struct from hnswio
 /// The Base structure for hnsw implementation.  
 /// The main useful functions are : new, insert, insert_parallel, search, parallel_search and file_dump
 /// as described in trait AnnT.  
 ///
-/// Other functions are mainly for others crate to get access to some fields.
-pub struct Hnsw<'b, T: Clone + Send + Sync + 'b, D: Distance<T>> {
+/// Other functions are mainly for others crate to get access to some fields.  
+/// T is 'static (a type of numbers), so that typed options of searches can be passed in [SearchParams].
+pub struct Hnsw<'b, T: Clone + Send + Sync + 'static, D: Distance<T>> {
     /// asked number of candidates in search
     pub(crate) ef_construction: usize,
     /// maximum number of connection by layer for a point
@@ -742,6 +1148,10 @@ pub struct Hnsw<'b, T: Clone + Send + Sync + 'b, D: Distance<T>> {
     pub(crate) extend_candidates: bool,
     /// defuault to false
     pub(crate) keep_pruned: bool,
+    /// neighbour lists grow one slot at a time, see [Hnsw::set_exact_capacity]
+    pub(crate) exact_capacity: bool,
+    /// bounds of layer 0 neighbours by point, see [Hnsw::set_connection_strategy]
+    pub(crate) adaptive: Option<AdaptiveDegree>,
     /// max layer , recall rust is in 0..maxlevel right bound excluded
     pub(crate) max_layer: usize,
     /// The global table containing points
@@ -755,14 +1165,69 @@ pub struct Hnsw<'b, T: Clone + Send + Sync + 'b, D: Distance<T>> {
     pub(crate) searching: bool,
     /// set to true if some data come from a mmap
     pub(crate) datamap_opt: bool,
+    /// user metadata (model version, embedding name...) stored in dumps.
+    pub(crate) metadata: BTreeMap<String, String>,
+    /// insertions and deletions made during a rebuild, see [Hnsw::rebuild_in_background]
+    pub(crate) rebuild_log: Arc<RebuildLog<T>>,
+    /// deduplication of inserted vectors, see [Hnsw::set_dedup]
+    pub(crate) dedup: Option<Dedup>,
+    /// alias ids of stored points, see [Hnsw::alias]
+    pub(crate) aliases: RwLock<AliasTable>,
+    /// namespaces statistics and deleted namespaces, see [Hnsw::insert_ns]
+    pub(crate) namespaces: Namespaces,
+    /// deleted points, see [Hnsw::remove_batch]
+    pub(crate) tombstones: Tombstones,
+    /// trace of construction events, see [Hnsw::record_events]
+    pub(crate) recorder: Option<EventRecorder>,
+    /// points changed since the last block dump, see [Hnsw::dump_dirty]
+    pub(crate) dirty: DirtyPoints,
+    /// early abandoning version of dist_f, see [Hnsw::set_bounded_distance]
+    pub(crate) bounded_dist: Option<BoundedDistance<T>>,
+    /// batch version of dist_f and the dimension from which it is used, see [Hnsw::set_tiled_distance]
+    pub(crate) tiled_dist: Option<(TiledDistance<T>, usize)>,
+    /// ef of a search is at least knbn * min_ef_factor, see [Hnsw::set_min_ef_factor]
+    pub(crate) min_ef_factor: f32,
+    /// temporary structures of searches, by rayon worker
+    pub(crate) scratch: ScratchPool<'b, T>,
+    /// insertions and deletions in flight, see [Hnsw::quiesce]
+    pub(crate) writes: WriteGate,
+    /// reports slow searches, see [Hnsw::set_slow_query_hook]
+    pub(crate) slow_queries: Option<SlowQueryHook>,
+    /// measured costs, see [Hnsw::calibrate_costs]
+    pub(crate) cost_model: RwLock<Option<CostModel>>,
+    /// see [Hnsw::set_graph_kind]
+    pub(crate) graph_kind: GraphKind,
+    /// statistics over time, see [Hnsw::set_stats_history]
+    pub(crate) stats_history: Option<Arc<StatsHistory>>,
+    /// planned size and layers added beyond it, see [Hnsw::set_layer_growth]
+    pub(crate) layer_plan: LayerPlan,
+    /// visits and returns of points by searches, see [Hnsw::set_access_stats]
+    pub(crate) access_stats: Option<AccessCounts>,
+    /// links a list can hold beyond its bound before a reverse link prunes it, see [Hnsw::set_link_slack]
+    pub(crate) link_slack: usize,
+    /// pruning of over-degree lists by searches, see [Hnsw::set_read_repair]
+    pub(crate) read_repair: Option<ReadRepair>,
+    /// insertions and deletions are rejected, see [Hnsw::set_read_only]
+    pub(crate) read_only: bool,
+    /// recall estimated on sampled searches, see [Hnsw::set_quality_monitor]
+    pub(crate) quality: Option<QualityState<T>>,
+    /// order of results at equal distances, see [Hnsw::set_tie_break]
+    pub(crate) tie_break: TieBreak,
 } // end of Hnsw
 
+/// A Hnsw owning all its data, without the lifetime of a mmap reload.  
+/// [Hnsw::new] and [HnswIo::load_owned](crate::hnswio::HnswIo::load_owned()) give one, it can be stored
+/// in application structures (or moved to a thread) without tying them to a reloader.
+pub type OwnedHnsw<T, D> = Hnsw<'static, T, D>;
+
 impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
     /// allocation function  
     /// . max_nb_connection : number of neighbours stored, by layer, in tables. Must be less than 256.
     /// . ef_construction : controls numbers of neighbours explored during construction. See README or paper.  
     /// . max_elements : hint to speed up allocation tables. number of elements expected.  
     /// . f : the distance function
+    ///
+    /// Panics on parameters rejected by [Hnsw::try_new].
     pub fn new(
         max_nb_connection: usize,
         max_elements: usize,
@@ -770,44 +1235,90 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         ef_construction: usize,
         f: D,
     ) -> Self {
+        Self::try_new(
+            max_nb_connection,
+            max_elements,
+            max_layer,
+            ef_construction,
+            f,
+        )
+        .unwrap_or_else(|e| panic!("Hnsw::new : {}", e))
+    } // end of new
+
+    /// allocation function as [Hnsw::new], returns an error if max_nb_connection is 0 or greater than 256
+    /// or if max_layer is 0. max_elements can be 0, it is only a hint.
+    pub fn try_new(
+        max_nb_connection: usize,
+        max_elements: usize,
+        max_layer: usize,
+        ef_construction: usize,
+        f: D,
+    ) -> Result<Self, HnswParamError> {
+        if max_nb_connection == 0 || max_nb_connection > 256 {
+            return Err(HnswParamError::MaxNbConnection(max_nb_connection));
+        }
+        if max_layer == 0 {
+            return Err(HnswParamError::MaxLayer(max_layer));
+        }
         let adjusted_max_layer = (NB_LAYER_MAX as usize).min(max_layer);
         let layer_indexed_points =
             PointIndexation::<T>::new(max_nb_connection, adjusted_max_layer, max_elements);
         let extend_candidates = false;
         let keep_pruned = false;
         //
-        if max_nb_connection > 256 {
-            println!("error max_nb_connection must be less equal than 256");
-            std::process::exit(1);
-        }
-        //
         info!("Hnsw max_nb_connection {:?}", max_nb_connection);
         info!("Hnsw nb elements {:?}", max_elements);
         info!("Hnsw ef_construction {:?}", ef_construction);
         info!("Hnsw distance {:?}", type_name::<D>());
         info!("Hnsw extend candidates {:?}", extend_candidates);
         //
-        Hnsw {
+        Ok(Hnsw {
             max_nb_connection,
             ef_construction,
             extend_candidates,
             keep_pruned,
+            exact_capacity: false,
+            adaptive: None,
             max_layer: adjusted_max_layer,
             layer_indexed_points,
             data_dimension: 0,
             dist_f: f,
             searching: false,
             datamap_opt: false,
-        }
-    } // end of new
+            metadata: BTreeMap::new(),
+            rebuild_log: Arc::default(),
+            dedup: None,
+            aliases: RwLock::new(AliasTable::default()),
+            namespaces: Namespaces::default(),
+            tombstones: Tombstones::default(),
+            recorder: None,
+            dirty: DirtyPoints::default(),
+            bounded_dist: None,
+            tiled_dist: None,
+            min_ef_factor: 1.,
+            scratch: ScratchPool::new(),
+            writes: WriteGate::default(),
+            slow_queries: None,
+            cost_model: RwLock::new(None),
+            graph_kind: GraphKind::Hierarchical,
+            stats_history: None,
+            layer_plan: LayerPlan::new(max_elements),
+            access_stats: None,
+            link_slack: 0,
+            read_repair: None,
+            read_only: false,
+            quality: None,
+            tie_break: TieBreak::default(),
+        })
+    } // end of try_new
 
     /// get ef_construction used in graph creation
     pub fn get_ef_construction(&self) -> usize {
         self.ef_construction
     }
-    /// returns the maximum layer authorized in construction
+    /// returns the maximum layer authorized in construction, with the layers added by [Hnsw::set_layer_growth]
     pub fn get_max_level(&self) -> usize {
-        self.max_layer
+        self.max_layer + self.layer_plan.get_nb_layer_added()
     }
 
     /// return the maximum level reached in the layers.
@@ -825,10 +1336,15 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
     /// set searching mode.  
     /// It is not possible to do parallel insertion and parallel searching simultaneously in different threads
     /// so to enable searching after parallel insertion the flag must be set to true.  
-    /// To resume parallel insertion reset the flag to false and so on.
+    /// To resume parallel insertion reset the flag to false and so on.  
+    /// The flag is not checked by insertions, [Hnsw::into_searcher] gives a read only structure instead.  
+    /// With [Hnsw::set_exact_capacity] switching to searching mode also shrinks neighbour lists.
     pub fn set_searching_mode(&mut self, flag: bool) {
         // must use an atomic!
         self.searching = flag;
+        if flag && self.exact_capacity {
+            self.shrink_neighbours();
+        }
     }
     /// get name if distance
     pub fn get_distance_name(&self) -> String {
@@ -841,6 +1357,86 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         self.keep_pruned = flag;
     }
 
+    /// when set, a full neighbour list grows by one slot instead of doubling its capacity, a list holds at most
+    /// one neighbour more than the maximum before its pruning (instead of nearly twice the maximum).
+    /// Insertions are a bit slower, lists use less memory. Default is false.  
+    /// Lists are shrunk to their length by [Hnsw::shrink_neighbours], called when switching to searching mode.
+    pub fn set_exact_capacity(&mut self, flag: bool) {
+        self.exact_capacity = flag;
+    }
+
+    /// shrinks the capacity of all neighbour lists to their length, returns the number of bytes released.
+    /// Lists grow again at next insertions.
+    pub fn shrink_neighbours(&self) -> usize {
+        let slot = std::mem::size_of::<Arc<PointWithOrder<T>>>();
+        let layers = self.layer_indexed_points.points_by_layer.read();
+        let released: usize = layers
+            .par_iter()
+            .flat_map_iter(|layer| layer.iter())
+            .map(|p| {
+                let mut neighbours = p.neighbours.write();
+                neighbours
+                    .iter_mut()
+                    .map(|neighbours_l| {
+                        let capacity = neighbours_l.capacity();
+                        neighbours_l.shrink_to_fit();
+                        (capacity - neighbours_l.capacity()) * slot
+                    })
+                    .sum::<usize>()
+            })
+            .sum();
+        log::info!("shrink_neighbours released {} bytes", released);
+        released
+    }
+
+    /// sets a version of the distance that can stop early (for example [crate::simd::l2_bounded] for L2 on f32).
+    /// It is used when the search loop knows a bound: a point farther than the worst of the ef points kept is rejected
+    /// without finishing the computation. It must give the same values as the distance of the structure.
+    pub fn set_bounded_distance(&mut self, bounded_dist: BoundedDistance<T>) {
+        self.bounded_dist = Some(bounded_dist);
+    }
+
+    /// sets a version of the distance evaluating a batch of vectors (for example [crate::simd::l2_tiled] for L2 on f32).
+    /// When a search (or an insertion) expands a point and the query has at least min_dimension components,
+    /// the neighbours not yet visited are evaluated with one call (by batches of 64), going through dimension blocks
+    /// across the whole batch: a block of the query stays in cache for all neighbours (for 4096-d vectors the query alone fills L1).
+    /// It must give the same values as the distance of the structure, it is not bounded (see [Hnsw::set_bounded_distance]).
+    /// Its evaluations are not counted in [SearchStats::nb_distances].
+    pub fn set_tiled_distance(&mut self, tiled_dist: TiledDistance<T>, min_dimension: usize) {
+        self.tiled_dist = Some((tiled_dist, min_dimension));
+    }
+
+    /// sets the factor giving the minimal ef of a search: ef is raised to at least knbn * factor (factor >= 1).
+    /// With the default factor 1., ef is only raised to knbn.
+    /// Asking for knbn neighbours with a smaller ef would truncate results and give a low recall.
+    pub fn set_min_ef_factor(&mut self, factor: f32) {
+        assert!(factor >= 1., "min ef factor must be >= 1");
+        self.min_ef_factor = factor;
+    }
+
+    /// returns the ef used by a search for knbn neighbours asked with ef_arg
+    pub fn get_search_ef(&self, knbn: usize, ef_arg: usize) -> usize {
+        let min_ef = (knbn as f32 * self.min_ef_factor).ceil() as usize;
+        if ef_arg < min_ef {
+            log::debug!(
+                "search ef {} raised to {} for {} neighbours",
+                ef_arg,
+                min_ef,
+                knbn
+            );
+        }
+        ef_arg.max(min_ef)
+    }
+
+    // distance from va to vb, possibly abandoned above bound
+    #[inline]
+    fn eval_bounded(&self, va: &[T], vb: &[T], bound: f32) -> f32 {
+        match self.bounded_dist {
+            Some(f) => f(va, vb, bound),
+            None => self.dist_f.eval(va, vb),
+        }
+    }
+
     /// retrieves the distance used in Hnsw construction
     pub fn get_distance(&self) -> &D {
         &self.dist_f
@@ -854,6 +1450,34 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         self.extend_candidates = flag;
     }
 
+    /// draws levels of next insertions from a generator seeded by seed, so that a serial build
+    /// (insertions one by one, in a fixed order) gives the same graph at each run. Parallel builds stay random.
+    pub fn set_level_seed(&mut self, seed: u64) {
+        self.layer_indexed_points.layer_g.set_seed(seed);
+    }
+
+    /// seed given to [Hnsw::set_level_seed], None if levels are random
+    pub fn get_level_seed(&self) -> Option<u64> {
+        self.layer_indexed_points.layer_g.get_seed()
+    }
+
+    /// associates a value to a metadata key, for example the version of the embedding model
+    /// that produced the data vectors. Metadata are stored in dumps and can be checked at reload
+    /// with [HnswIo::load_checked](crate::hnswio::HnswIo::load_checked()).
+    pub fn set_metadata(&mut self, key: &str, value: &str) {
+        self.metadata.insert(key.to_string(), value.to_string());
+    }
+
+    /// returns value associated to a metadata key if any
+    pub fn get_metadata(&self, key: &str) -> Option<&str> {
+        self.metadata.get(key).map(|v| v.as_str())
+    }
+
+    /// returns all metadata of the structure
+    pub fn get_metadata_map(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
+
     // When dumping we need to know if some file is mmapped
     pub(crate) fn get_datamap_opt(&self) -> bool {
         self.datamap_opt
@@ -916,19 +1540,103 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         entry_point: Arc<Point<'b, T>>,
         ef: usize,
         layer: u8,
-        filter: Option<&dyn FilterT>,
+        params: Option<&SearchParams>,
     ) -> BinaryHeap<Arc<PointWithOrder<'b, T>>> {
+        let dist_to_query = |v: &[T], bound: f32| self.eval_bounded(point, v, bound);
+        let search = LayerSearch {
+            params,
+            stop: None,
+            vector: Some(point),
+            as_of: None,
+        };
+        self.search_layer_with(&dist_to_query, entry_point, ef, layer, search, false)
+            .into_heap()
+    } // end of search_layer
+
+    // search_layer with the distance to the query given by dist_to_query, so that the query
+    // can be of another type than stored data (See search_asymmetric).
+    // dist_to_query gets the bound above which the point is rejected (see BoundedDistance)
+    // Points visited by a query are counted by access statistics (see crate::access), not those of an insertion.
+    pub(crate) fn search_layer_with<F>(
+        &self,
+        dist_to_query: &F,
+        entry_point: Arc<Point<'b, T>>,
+        ef: usize,
+        layer: u8,
+        search: LayerSearch<T>,
+        query: bool,
+    ) -> TopK<'b, T>
+    where
+        F: Fn(&[T], f32) -> f32,
+    {
+        let mut scratch = self.scratch.take();
+        let return_points =
+            self.search_layer_in(dist_to_query, entry_point, ef, layer, search, &mut scratch);
+        if let Some(access) = self.access_stats.as_ref().filter(|_| query) {
+            access.count_visited(scratch.visited.iter());
+        }
+        if query && !scratch.overfull.is_empty() {
+            self.repair_lists(&mut scratch.overfull);
+        }
+        self.scratch.give_back(scratch);
+        return_points
+    } // end of search_layer_with
+
+    // search_layer_with using the temporary structures of scratch
+    fn search_layer_in<F>(
+        &self,
+        dist_to_query: &F,
+        entry_point: Arc<Point<'b, T>>,
+        ef: usize,
+        layer: u8,
+        search: LayerSearch<T>,
+        scratch: &mut Scratch<'b, T>,
+    ) -> TopK<'b, T>
+    where
+        F: Fn(&[T], f32) -> f32,
+    {
+        let LayerSearch {
+            params,
+            stop,
+            vector,
+            as_of,
+        } = search;
         //
         trace!(
             "entering search_layer with entry_point_id {:?} layer : {:?} ef {:?} ",
             entry_point.p_id, layer, ef
         );
+        let buffer = params.map_or(ResultBuffer::Auto, |p| p.get_result_buffer());
+        // only constraints restricting returned points change the traversal
+        let params = params.filter(|p| p.is_constrained());
+        // deleted points, and points not in the structure as of a sequence number, are traversed but not returned
+        let has_deleted = self.has_deleted();
+        let payloads = params.map(|_| self.layer_indexed_points.payloads.read());
+        let accept = |point: &Point<T>| {
+            params
+                .zip(payloads.as_ref())
+                .is_none_or(|(p, payloads)| p.accept(point.origin_id, &payloads.get(point.p_id)))
+                && as_of.map_or(!(has_deleted && self.is_deleted(point)), |as_of| {
+                    self.is_visible_as_of(point, as_of)
+                })
+        };
+        let constrained = params.is_some() || has_deleted || as_of.is_some();
+        // neighbours of an expanded point are evaluated at once on large dimensions
+        let tiled = self
+            .tiled_dist
+            .zip(vector)
+            .filter(|((_, min_dimension), vector)| vector.len() >= *min_dimension);
+        // number of returned points within the stop distance. It can count a point pushed out of return_points,
+        // but only when all of them are within the distance.
+        let (nb_stop, stop_dist) = stop.unwrap_or((usize::MAX, f32::NEG_INFINITY));
+        let mut nb_within = 0;
         //
         // here we allocate a binary_heap on values not on reference beccause we want to return
         // log2(skiplist_size) must be greater than 1.
         let skiplist_size = ef.max(2);
         // we will store positive distances in this one
-        let mut return_points = BinaryHeap::<Arc<PointWithOrder<T>>>::with_capacity(skiplist_size);
+        let mut return_points =
+            TopK::new(buffer, skiplist_size, std::mem::take(&mut scratch.results));
         //
         if self.layer_indexed_points.points_by_layer.read()[layer as usize].is_empty() {
             // at the beginning we can have nothing in layer
@@ -940,14 +1648,13 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             return return_points;
         }
         // initialize visited points
-        let dist_to_entry_point = self.dist_f.eval(point, entry_point.data.get_v());
+        let dist_to_entry_point = dist_to_query(entry_point.data.get_v(), f32::INFINITY);
         trace!("       distance to entry point: {:?} ", dist_to_entry_point);
         // keep a list of id visited
-        let mut visited_point_id = HashMap::<PointId, Arc<Point<T>>>::new();
-        visited_point_id.insert(entry_point.p_id, Arc::clone(&entry_point));
+        let visited_point_id = &mut scratch.visited;
+        visited_point_id.insert(entry_point.p_id);
         //
-        let mut candidate_points =
-            BinaryHeap::<Arc<PointWithOrder<T>>>::with_capacity(skiplist_size);
+        let candidate_points = &mut scratch.candidates;
         candidate_points.push(Arc::new(PointWithOrder::new(
             &entry_point,
             -dist_to_entry_point,
@@ -956,6 +1663,9 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             &entry_point,
             dist_to_entry_point,
         )));
+        if dist_to_entry_point <= stop_dist && accept(&entry_point) {
+            nb_within += 1;
+        }
         // at the beginning candidate_points contains point passed as arg in layer entry_point_id.0
         while !candidate_points.is_empty() {
             // get nearest point in candidate_points
@@ -980,7 +1690,7 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                     -(c.dist_to_ref),
                     f.dist_to_ref
                 );
-                if filter.is_none() || (filter.is_some() && return_points.len() >= ef) {
+                if !constrained || return_points.len() >= ef {
                     return return_points;
                 }
             }
@@ -989,16 +1699,46 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             //
             let neighbours_c_l = &c.point_ref.neighbours.read()[layer as usize];
             let c_pid = c.point_ref.p_id;
+            if self.read_repair.is_some()
+                && neighbours_c_l.len() > self.get_max_nb_neighbours(c_pid, layer as usize)
+            {
+                scratch.overfull.push((Arc::clone(&c.point_ref), layer));
+            }
             trace!(
                 "       search_layer, {:?} has  nb neighbours  : {:?} ",
                 c_pid,
                 neighbours_c_l.len()
             );
-            for e in neighbours_c_l {
+            if let Some(((tiled_dist, _), vector)) = tiled {
+                scratch.fresh.clear();
+                scratch
+                    .fresh
+                    .extend((0..neighbours_c_l.len()).filter(|i| {
+                        !visited_point_id.contains(&neighbours_c_l[*i].point_ref.p_id)
+                    }));
+                scratch.tiled.clear();
+                scratch.tiled.resize(neighbours_c_l.len(), f32::NAN);
+                // borrowed vectors cannot be kept in the scratch, batches of them are on the stack
+                for ranks in scratch.fresh.chunks(TILED_BATCH) {
+                    let mut vectors: [&[T]; TILED_BATCH] = [&[]; TILED_BATCH];
+                    for (v, rank) in vectors.iter_mut().zip(ranks) {
+                        *v = neighbours_c_l[*rank].point_ref.data.get_v();
+                    }
+                    let mut distances = [0.; TILED_BATCH];
+                    tiled_dist(
+                        vector,
+                        &vectors[..ranks.len()],
+                        &mut distances[..ranks.len()],
+                    );
+                    for (rank, d) in ranks.iter().zip(distances) {
+                        scratch.tiled[*rank] = d;
+                    }
+                }
+            }
+            for (rank, e) in neighbours_c_l.iter().enumerate() {
                 // HERE WE sEE THAT neighbours should be stored as PointIdWithOrder !!
                 // CAVEAT what if several point_id with same distance to ref point?
-                if !visited_point_id.contains_key(&e.point_ref.p_id) {
-                    visited_point_id.insert(e.point_ref.p_id, Arc::clone(&e.point_ref));
+                if visited_point_id.insert(e.point_ref.p_id) {
                     trace!("             visited insertion {:?}", e.point_ref.p_id);
                     let f_opt = return_points.peek();
                     if f_opt.is_none() {
@@ -1007,8 +1747,16 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                         return return_points;
                     }
                     let f = f_opt.unwrap();
-                    let e_dist_to_p = self.dist_f.eval(point, e.point_ref.data.get_v());
                     let f_dist_to_p = f.dist_to_ref;
+                    let bound = if return_points.len() < ef {
+                        f32::INFINITY
+                    } else {
+                        f_dist_to_p
+                    };
+                    let e_dist_to_p = match tiled {
+                        Some(_) => scratch.tiled[rank],
+                        None => dist_to_query(e.point_ref.data.get_v(), bound),
+                    };
                     if e_dist_to_p < f_dist_to_p || return_points.len() < ef {
                         let e_prime = Arc::new(PointWithOrder::new(&e.point_ref, e_dist_to_p));
                         // a neighbour of neighbour is better, we insert it into candidate with the distance to point
@@ -1018,23 +1766,28 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                         );
                         candidate_points
                             .push(Arc::new(PointWithOrder::new(&e.point_ref, -e_dist_to_p)));
-                        if filter.is_none() {
+                        let accepted = !constrained || accept(&e_prime.point_ref);
+                        if !constrained {
                             return_points.push(Arc::clone(&e_prime));
-                        } else {
-                            let id: &usize = &e_prime.point_ref.get_origin_id();
-                            if filter.as_ref().unwrap().hnsw_filter(id) {
-                                if return_points.len() == 1 {
-                                    let only_id = return_points.peek().unwrap().point_ref.origin_id;
-                                    if !filter.as_ref().unwrap().hnsw_filter(&only_id) {
-                                        return_points.clear()
-                                    }
+                        } else if accepted {
+                            if return_points.len() == 1 {
+                                let only = &return_points.peek().unwrap().point_ref;
+                                if !accept(only) {
+                                    return_points.clear()
                                 }
-                                return_points.push(Arc::clone(&e_prime))
                             }
+                            return_points.push(Arc::clone(&e_prime))
                         }
                         if return_points.len() > ef {
                             return_points.pop();
                         }
+                        if accepted && e_dist_to_p <= stop_dist {
+                            nb_within += 1;
+                            if nb_within >= nb_stop {
+                                trace!("stop distance reached by {} points", nb_within);
+                                return return_points;
+                            }
+                        }
                     } // end if e.dist_to_ref < f.dist_to_ref
                 }
             } // end of for on neighbours_c
@@ -1045,10 +1798,10 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             return_points.len()
         );
         return_points
-    } // end of search_layer
+    } // end of search_layer_in
 
     /// insert a tuple (&Vec, usize) with its external id as given by the client.
-    ///  The insertion method gives the point an internal id.
+    ///  The insertion method gives the point an internal id. An empty vector is not inserted (an error is logged).
     #[inline]
     pub fn insert(&self, datav_with_id: (&[T], usize)) {
         self.insert_slice((datav_with_id.0, datav_with_id.1))
@@ -1059,14 +1812,154 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
     ///  The insertion method gives the point an internal id.  
     ///  The slice insertion makes integration with ndarray crate easier than the vector insertion
     pub fn insert_slice(&self, data_with_id: (&[T], usize)) {
-        //
+        self.insert_with_payload(data_with_id, Payload::default())
+    }
+
+    /// Insert a data slice with its external id and its tags.  
+    /// Tags are a bitset of 16 categories that can be required in a search with
+    /// [SearchParams::require_tags].
+    pub fn insert_with_tags(&self, data_with_id: (&[T], usize), tags: Tags) {
+        self.insert_with_payload(data_with_id, Payload::new().with_tags(tags))
+    }
+
+    /// Insert a data slice with its external id and its payload (tags and numeric attributes).
+    pub fn insert_with_payload(&self, data_with_id: (&[T], usize), payload: Payload) {
+        if let Err(e) = self.insert_canonical(data_with_id, payload) {
+            log::error!("Hnsw insert, {}, not inserted", e);
+        }
+    }
+
+    /// Insert a data slice with its external id and returns the id of the point representing it.  
+    /// It is the id given except if the data is a duplicate of an existing point (see [Hnsw::set_dedup]).
+    /// Returns [HnswError::ReadOnly] or [HnswError::EmptyVector] if nothing is inserted.
+    pub fn insert_dedup(&self, data_with_id: (&[T], usize)) -> Result<DataId, HnswError> {
+        self.insert_canonical(data_with_id, Payload::default())
+    }
+
+    pub(crate) fn insert_canonical(
+        &self,
+        data_with_id: (&[T], usize),
+        payload: Payload,
+    ) -> Result<DataId, HnswError> {
         let (data, origin_id) = data_with_id;
-        let keep_pruned = self.keep_pruned;
+        self.insert_data(PointData::new_v(data.to_vec()), origin_id, payload)
+    }
+
+    /// Insert a vector shared with the client (and possibly other structures) with its external id.
+    /// The vector is not copied, so indexes with different distances built from the same vectors share them.
+    /// Returns the id of the point representing it as [Hnsw::insert_dedup].
+    pub fn insert_arc(&self, data_with_id: (Arc<[T]>, usize)) -> Result<DataId, HnswError> {
+        let (data, origin_id) = data_with_id;
+        self.insert_data(PointData::A(data), origin_id, Payload::default())
+    }
+
+    /// parallel insertion of shared vectors, see [Hnsw::insert_arc]
+    pub fn parallel_insert_arc(&self, datas: &[(Arc<[T]>, usize)]) {
+        datas.par_iter().for_each(|(data, id)| {
+            if let Err(e) = self.insert_arc((Arc::clone(data), *id)) {
+                log::error!("Hnsw insert, {}, not inserted", e);
+            }
+        });
+    }
+
+    fn insert_data(
+        &self,
+        data: PointData<'b, T>,
+        origin_id: DataId,
+        payload: Payload,
+    ) -> Result<DataId, HnswError> {
+        if self.read_only {
+            return Err(HnswError::ReadOnly);
+        }
+        if data.get_v().is_empty() {
+            return Err(HnswError::EmptyVector(origin_id));
+        }
+        if let Some(canonical) =
+            self.find_duplicate(data.get_v(), origin_id, payload.get_namespace())
+        {
+            trace!("Hnsw insert, {} is a duplicate of {}", origin_id, canonical);
+            return Ok(canonical);
+        }
+        self.insert_point(data, origin_id, payload);
+        Ok(origin_id)
+    }
+
+    /// Insert a data slice with its external id and returns its point id and the neighbours it was linked to,
+    /// so that link quality can be checked during ingestion.
+    /// Returns an error if the index is read only, the data is empty or is a duplicate of an existing point
+    /// (see [Hnsw::set_dedup]).
+    pub fn insert_verbose(&self, data_with_id: (&[T], usize)) -> Result<InsertReport, HnswError> {
+        let (data, origin_id) = data_with_id;
+        if self.read_only {
+            return Err(HnswError::ReadOnly);
+        }
+        if data.is_empty() {
+            return Err(HnswError::EmptyVector(origin_id));
+        }
+        if let Some(canonical) = self.find_duplicate(data, origin_id, 0) {
+            return Err(HnswError::Duplicate {
+                origin_id,
+                canonical,
+            });
+        }
+        let new_point = self.insert_point(
+            PointData::new_v(data.to_vec()),
+            origin_id,
+            Payload::default(),
+        );
+        let p_id = new_point.get_point_id();
+        let mut neighbours = new_point.get_neighborhood_id();
+        neighbours.truncate(p_id.0 as usize + 1);
+        Ok(InsertReport { p_id, neighbours })
+    }
+
+    fn insert_point(
+        &self,
+        data: PointData<'b, T>,
+        origin_id: DataId,
+        payload: Payload,
+    ) -> Arc<Point<'b, T>> {
+        let _write = self.writes.enter();
+        //
         // insert in indexation and get point_id adn generate a new entry_point if necessary
-        let (new_point, point_rank) = self
-            .layer_indexed_points
-            .generate_new_point(data, origin_id);
+        let (new_point, point_rank) =
+            self.layer_indexed_points
+                .generate_new_point_in(data, origin_id, payload, &|level| {
+                    self.take_free_slot(level)
+                });
         trace!("Hnsw insert generated new point {:?} ", new_point.p_id);
+        self.check_layer_plan(point_rank);
+        if let Some(access) = &self.access_stats {
+            access.reset(new_point.p_id);
+        }
+        if let Some(recorder) = &self.recorder {
+            recorder.record(Event::Insert {
+                origin_id,
+                p_id: new_point.p_id,
+            });
+        }
+        // a panic while linking (a NaN distance, a custom distance rejecting the vector) must not leave
+        // a point that makes all later searches reaching it panic.
+        // Locks are parking_lot ones, they are released and not poisoned by the unwinding.
+        let linked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.link_point(&new_point, point_rank == 1, 0)
+        }));
+        if let Err(cause) = linked {
+            self.discard_point(&new_point);
+            std::panic::resume_unwind(cause);
+        }
+        self.record_vector(new_point.get_v(), origin_id, payload.get_namespace());
+        self.rebuild_log
+            .record_insert(new_point.get_v(), origin_id, payload);
+        new_point
+    } // end of insert
+
+    /// links a point already in the indexation at its layers from min_layer.
+    /// first is true if it was the first point inserted.
+    pub(crate) fn link_point(&self, new_point: &Arc<Point<'b, T>>, first: bool, min_layer: u8) {
+        let data = new_point.get_v();
+        let keep_pruned = self.keep_pruned;
+        self.dirty.mark(new_point.p_id);
         // now real work begins
         // allocate a binary heap
         let level = new_point.p_id.0;
@@ -1077,7 +1970,7 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             // I open a read lock on an option
             if let Some(arc_point) = self.layer_indexed_points.entry_point.read().as_ref() {
                 enter_point_copy = Some(Arc::clone(arc_point));
-                if point_rank == 1 {
+                if first {
                     debug!(
                         "Hnsw  stored first point , direct return  {:?} ",
                         new_point.p_id
@@ -1088,7 +1981,7 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             }
         }
         if enter_point_copy.is_none() {
-            self.layer_indexed_points.check_entry_point(&new_point);
+            self.layer_indexed_points.check_entry_point(new_point);
             return;
         }
         let mut dist_to_entry = self
@@ -1121,10 +2014,18 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             //
             if let Some(ep) = sorted_points.pop() {
                 // useful for projecting lower layer to upper layer. keep track of points encountered.
-                if new_point.neighbours.read()[l as usize].len()
-                    < self.get_max_nb_connection() as usize
-                {
-                    new_point.neighbours.write()[l as usize].push(Arc::clone(&ep));
+                // A relinked point can find itself or a point already in its list.
+                if ep.point_ref.p_id != new_point.p_id {
+                    let mut new_neighbours = new_point.neighbours.write();
+                    let new_neighbours_l = &mut new_neighbours[l as usize];
+                    if new_neighbours_l.len() < self.get_max_nb_connection() as usize
+                        && !new_neighbours_l
+                            .iter()
+                            .any(|n| n.point_ref.p_id == ep.point_ref.p_id)
+                    {
+                        self.record_link(new_point, &ep, l, true);
+                        new_neighbours_l.push(Arc::clone(&ep));
+                    }
                 }
                 // get the lowest distance point
                 let tmp_dist = self.dist_f.eval(data, ep.point_ref.data.get_v());
@@ -1139,7 +2040,7 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         }
         // now enter_point_id_copy contains id of nearest
         // now loop down to 0
-        for l in (0..level + 1).rev() {
+        for l in (min_layer..level + 1).rev() {
             let ef = self.ef_construction;
             // when l == level, we cannot get new_point in sorted_points as it is seen only from declared neighbours
             let mut sorted_points = self.search_layer(
@@ -1159,7 +2060,7 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                 let nb_conn;
                 let extend_c;
                 if l == 0 {
-                    nb_conn = 2 * self.max_nb_connection;
+                    nb_conn = self.adapt_nb_neighbours(new_point.p_id, &sorted_points);
                     extend_c = self.extend_candidates;
                 } else {
                     nb_conn = self.max_nb_connection;
@@ -1175,10 +2076,26 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                     keep_pruned,
                     &mut neighbours,
                 );
+                // a list never holds its own point or a point twice (see crate::linkcheck)
+                let mut seen = HashSet::<PointId>::with_capacity(neighbours.len());
+                neighbours.retain(|n| {
+                    n.point_ref.p_id != new_point.p_id && seen.insert(n.point_ref.p_id)
+                });
                 // sort neighbours
                 neighbours.sort_unstable();
                 // we must add bidirecti*onal from data i.e new_point_id to neighbours
-                new_point.neighbours.write()[l as usize].clone_from(&neighbours);
+                {
+                    let mut new_neighbours = new_point.neighbours.write();
+                    if self.recorder.is_some() {
+                        for old in &new_neighbours[l as usize] {
+                            self.record_link(new_point, old, l, false);
+                        }
+                        for n in &neighbours {
+                            self.record_link(new_point, n, l, true);
+                        }
+                    }
+                    new_neighbours[l as usize].clone_from(&neighbours);
+                }
                 // this reverse neighbour update could be done here but we put it at end to gather all code
                 // requiring a mutex guard for multi threading.
                 // update ep for loop iteration. As we sorted neighbours the nearest
@@ -1191,12 +2108,16 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         // new_point has been inserted at the beginning in table
         // so that we can call reverse_update_neighborhoodwe consitently
         // now reverse update of neighbours.
-        self.reverse_update_neighborhood_simple(Arc::clone(&new_point));
+        self.reverse_update_neighborhood_simple(Arc::clone(new_point), min_layer);
         //
-        self.layer_indexed_points.check_entry_point(&new_point);
+        self.layer_indexed_points.check_entry_point(new_point);
         //
+        #[cfg(debug_assertions)]
+        if let Err(e) = self.check_point_links(new_point) {
+            panic!("link_point : {}", e);
+        }
         trace!("Hnsw exiting insert new point {:?} ", new_point.p_id);
-    } // end of insert
+    } // end of link_point
 
     /// Insert in parallel a slice of Vec\<T\> each associated to its id.    
     /// It uses Rayon for threading so the number of insertions asked for must be large enough to be efficient.  
@@ -1218,15 +2139,82 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         datas.par_iter().for_each(|&item| self.insert_slice(item));
     } // end of parallel_insert
 
+    /// Insert in parallel slices of \[T\] each associated to its id and its tags.
+    pub fn parallel_insert_with_tags(&self, datas: &[(&[T], usize, Tags)]) {
+        datas
+            .par_iter()
+            .for_each(|&(data, id, tags)| self.insert_with_tags((data, id), tags));
+    } // end of parallel_insert_with_tags
+
+    /// Insert in parallel slices of \[T\] each associated to its id and its payload.
+    pub fn parallel_insert_with_payload(&self, datas: &[(&[T], usize, Payload)]) {
+        datas
+            .par_iter()
+            .for_each(|&(data, id, payload)| self.insert_with_payload((data, id), payload));
+    } // end of parallel_insert_with_payload
+
+    /// Insert a data slice with its external id as [Hnsw::insert_slice] but returns an error instead of
+    /// logging or panicking on a bad item: empty vector, dimension different from the points already
+    /// inserted, duplicate, panic of the distance during linking (the point is then discarded).  
+    /// Returns the [PointId] of the inserted point.
+    pub fn try_insert(&self, data_with_id: (&[T], usize)) -> Result<PointId, HnswError> {
+        let (data, origin_id) = data_with_id;
+        if self.read_only {
+            return Err(HnswError::ReadOnly);
+        }
+        if data.is_empty() {
+            return Err(HnswError::EmptyVector(origin_id));
+        }
+        let expected = self.layer_indexed_points.get_data_dimension();
+        if expected != 0 && data.len() != expected {
+            return Err(HnswError::Dimension {
+                origin_id,
+                expected,
+                got: data.len(),
+            });
+        }
+        if let Some(canonical) = self.find_duplicate(data, origin_id, 0) {
+            return Err(HnswError::Duplicate {
+                origin_id,
+                canonical,
+            });
+        }
+        // insert_point discards the point before propagating a panic
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            self.insert_point(
+                PointData::new_v(data.to_vec()),
+                origin_id,
+                Payload::default(),
+            )
+            .get_point_id()
+        }))
+        .map_err(|cause| HnswError::Panic {
+            origin_id,
+            cause: panic_cause(cause),
+        })
+    } // end of try_insert
+
+    /// Insert in parallel as [Hnsw::parallel_insert], bad items are not inserted and do not stop the others.  
+    /// Returns for each item, in the order of datas, its [PointId] or the reason it was rejected (see [Hnsw::try_insert]).
+    pub fn try_parallel_insert(
+        &self,
+        datas: &[(&Vec<T>, usize)],
+    ) -> Vec<Result<PointId, HnswError>> {
+        datas
+            .par_iter()
+            .map(|&(item, id)| self.try_insert((item.as_slice(), id)))
+            .collect()
+    } // end of try_parallel_insert
+
     /// insert new_point in neighbourhood info of point
-    fn reverse_update_neighborhood_simple(&self, new_point: Arc<Point<T>>) {
+    fn reverse_update_neighborhood_simple(&self, new_point: Arc<Point<T>>, min_layer: u8) {
         //  println!("reverse update neighbourhood for  new point {:?} ", new_point.p_id);
         trace!(
             "reverse update neighbourhood for  new point {:?} ",
             new_point.p_id
         );
         let level = new_point.p_id.0;
-        for l in (0..level + 1).rev() {
+        for l in (min_layer..level + 1).rev() {
             for q in &new_point.neighbours.read()[l as usize] {
                 if new_point.p_id != q.point_ref.p_id {
                     // as new point is in global table, do not loop and deadlock!!
@@ -1246,21 +2234,31 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                         //   panic!();
                         continue;
                     }
-                    q_point_neighbours[l_n].push(Arc::new(n_to_add));
+                    let n_to_add = Arc::new(n_to_add);
+                    self.record_link(q_point, &n_to_add, l_n as u8, true);
+                    if self.exact_capacity {
+                        q_point_neighbours[l_n].reserve_exact(1);
+                    }
+                    q_point_neighbours[l_n].push(n_to_add);
+                    self.dirty.mark(q_point.p_id);
                     let nbn_at_l = q_point_neighbours[l_n].len();
                     //
                     // if l < level, update upward chaining, insert does a sort! t_q has a neighbour not yet in global table of points!
-                    let threshold_shrinking = if l_n > 0 {
-                        self.max_nb_connection
-                    } else {
-                        2 * self.max_nb_connection
-                    };
-                    let shrink = nbn_at_l > threshold_shrinking;
+                    let threshold_shrinking = self.get_max_nb_neighbours(q_point.p_id, l_n);
+                    let shrink = nbn_at_l > threshold_shrinking + self.link_slack;
                     {
                         // sort and shring if necessary
                         q_point_neighbours[l_n].sort_unstable();
                         if shrink {
-                            q_point_neighbours[l_n].pop();
+                            // prune the farthest neighbour other than new_point, so that the link new_point
+                            // just made to q_point stays symmetric.
+                            let rank = q_point_neighbours[l_n]
+                                .iter()
+                                .rposition(|n| n.point_ref.p_id != new_point.p_id);
+                            if let Some(rank) = rank {
+                                let pruned = q_point_neighbours[l_n].remove(rank);
+                                self.record_link(q_point, &pruned, l_n as u8, false);
+                            }
                         }
                     }
                 } // end protection against point identity
@@ -1269,6 +2267,37 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         //   println!("     exitingreverse update neighbourhood for  new point {:?} ", new_point.p_id);
     } // end of reverse_update_neighborhood_simple
 
+    // records the addition (or the removal) of neighbour to the list of point at layer.
+    // Must be called with the list locked.
+    #[inline]
+    pub(crate) fn record_link(
+        &self,
+        point: &Point<T>,
+        neighbour: &PointWithOrder<T>,
+        layer: u8,
+        added: bool,
+    ) {
+        if let Some(recorder) = &self.recorder {
+            let (from, to, distance) =
+                (point.p_id, neighbour.point_ref.p_id, neighbour.dist_to_ref);
+            recorder.record(if added {
+                Event::Link {
+                    from,
+                    to,
+                    layer,
+                    distance,
+                }
+            } else {
+                Event::Prune {
+                    from,
+                    to,
+                    layer,
+                    distance,
+                }
+            });
+        }
+    }
+
     pub fn get_point_indexation(&self) -> &PointIndexation<'b, T> {
         &self.layer_indexed_points
     }
@@ -1440,7 +2469,7 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             }
         }
         // ef must be greater than knbn. Possibly it should be between knbn and self.max_nb_connection
-        let ef = ef_arg.max(knbn);
+        let ef = self.get_search_ef(knbn, ef_arg);
         // now search with asked ef in layer 0
         let neighbours_heap = self.search_layer(data, entry_point, ef, 0, None);
         // go from heap of points with negative dist to a sorted vec of increasing points with > 0 distances.
@@ -1472,31 +2501,77 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
         ef_arg: usize,
         filter: Option<&dyn FilterT>,
     ) -> Vec<Neighbour> {
-        //
-        let entry_point;
-        {
-            // a lock on an option an a Arc<Point>
-            let entry_point_opt_ref = self.layer_indexed_points.entry_point.read();
-            if entry_point_opt_ref.is_none() {
-                return Vec::<Neighbour>::new();
-            } else {
-                entry_point = Arc::clone((*entry_point_opt_ref).as_ref().unwrap());
+        let params = match filter {
+            Some(filter) => SearchParams::new().filter(filter),
+            None => SearchParams::new(),
+        };
+        self.search_with_params(data, knbn, ef_arg, &params)
+    } // end of search_filter
+
+    /// a version of [`Self::search`] driven by a [SearchParams] structure.  
+    /// The constraints (filter, tags, attribute ranges, geo radius) of params are checked during the traversal of the graph.
+    pub fn search_with_params(
+        &self,
+        data: &[T],
+        knbn: usize,
+        ef_arg: usize,
+        params: &SearchParams,
+    ) -> Vec<Neighbour> {
+        if self.slow_queries.is_some() || self.stats_history.is_some() {
+            let start = Instant::now();
+            let nb_distances = Cell::new(0);
+            let dist_to_query = |v: &[T], bound: f32| {
+                nb_distances.set(nb_distances.get() + 1);
+                self.eval_bounded(data, v, bound)
+            };
+            let neighbours = self.search_with(&dist_to_query, Some(data), knbn, ef_arg, params);
+            let stats = SearchStats {
+                duration: start.elapsed(),
+                nb_distances: nb_distances.get(),
+                knbn,
+                ef: ef_arg,
+                nb_returned: neighbours.len(),
+            };
+            if let Some(hook) = &self.slow_queries {
+                hook.observe(data, &stats);
+            }
+            if let Some(history) = &self.stats_history {
+                history.record_search(stats.duration);
             }
+            if !params.changes_results() {
+                self.observe_quality(data, knbn, &neighbours);
+            }
+            return neighbours;
         }
-        //
-        let mut dist_to_entry = self.dist_f.eval(data, entry_point.as_ref().data.get_v());
-        let mut pivot = Arc::clone(&entry_point);
-        let mut new_pivot = None;
+        let dist_to_query = |v: &[T], bound: f32| self.eval_bounded(data, v, bound);
+        let neighbours = self.search_with(&dist_to_query, Some(data), knbn, ef_arg, params);
+        if !params.changes_results() {
+            self.observe_quality(data, knbn, &neighbours);
+        }
+        neighbours
+    } // end of search_with_params
 
-        //
-        for layer in (1..=entry_point.p_id.0).rev() {
+    // greedy descent from point, in layers from_layer to 1, returns the nearest point to the query found
+    pub(crate) fn descend<F>(
+        &self,
+        dist_to_query: &F,
+        point: Arc<Point<'b, T>>,
+        from_layer: u8,
+    ) -> Arc<Point<'b, T>>
+    where
+        F: Fn(&[T], f32) -> f32,
+    {
+        let mut dist_to_entry = dist_to_query(point.as_ref().data.get_v(), f32::INFINITY);
+        let mut pivot = point;
+        let mut new_pivot = None;
+        for layer in (1..=from_layer).rev() {
             let mut has_changed = false;
             // search in stored neighbours
             {
                 let neighbours = &pivot.neighbours.read()[layer as usize];
                 for n in neighbours {
                     // get the lowest  distance point.
-                    let tmp_dist = self.dist_f.eval(data, n.point_ref.data.get_v());
+                    let tmp_dist = dist_to_query(n.point_ref.data.get_v(), dist_to_entry);
                     if tmp_dist < dist_to_entry {
                         new_pivot = Some(Arc::clone(&n.point_ref));
                         has_changed = true;
@@ -1508,8 +2583,59 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
                 pivot = Arc::clone(new_pivot.as_ref().unwrap());
             }
         } // end on for on layers
+        pivot
+    } // end of descend
+
+    // the search, with the distance to the query given by dist_to_query
+    pub(crate) fn search_with<F>(
+        &self,
+        dist_to_query: &F,
+        vector: Option<&[T]>,
+        knbn: usize,
+        ef_arg: usize,
+        params: &SearchParams,
+    ) -> Vec<Neighbour>
+    where
+        F: Fn(&[T], f32) -> f32,
+    {
+        let mut neighbours = Vec::new();
+        self.search_with_into(dist_to_query, vector, knbn, ef_arg, params, &mut neighbours);
+        neighbours
+    }
+
+    // as search_with, results are written in out (cleared first)
+    pub(crate) fn search_with_into<F>(
+        &self,
+        dist_to_query: &F,
+        vector: Option<&[T]>,
+        knbn: usize,
+        ef_arg: usize,
+        params: &SearchParams,
+        out: &mut Vec<Neighbour>,
+    ) where
+        F: Fn(&[T], f32) -> f32,
+    {
+        out.clear();
+        let entry_point;
+        {
+            // a lock on an option an a Arc<Point>
+            let entry_point_opt_ref = self.layer_indexed_points.entry_point.read();
+            if entry_point_opt_ref.is_none() {
+                return;
+            } else {
+                entry_point = Arc::clone((*entry_point_opt_ref).as_ref().unwrap());
+            }
+        }
+        //
+        // the descent can be shortened by params
+        let start_layer = params
+            .get_start_layer()
+            .map_or(entry_point.p_id.0, |l| l.min(entry_point.p_id.0));
+        let pivot = self.descend(dist_to_query, entry_point, start_layer);
         // ef must be greater than knbn. Possibly it should be between knbn and self.max_nb_connection
-        let ef = ef_arg.max(knbn);
+        let mut ef = self
+            .get_search_ef(knbn, ef_arg)
+            .max(params.get_nb_candidates(knbn));
         log::debug!("pivot changed , current pivot {:?}", pivot.get_point_id());
         // search lowest non empty layer (in case of search with incomplete lower layer at beginning of hnsw filling)
         let mut l = 0u8;
@@ -1520,25 +2646,94 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
             l += 1;
         };
         // now search with asked ef in lower layer
-        let neighbours_heap = self.search_layer(data, pivot, ef, layer_to_search, filter);
-        // go from heap of points with negative dist to a sorted vec of increasing points with > 0 distances.
-        let neighbours = neighbours_heap.into_sorted_vec();
+        let as_of = params.get_as_of().map(|seq| self.as_of(seq));
+        let stop = params.get_stop_distance().map(|d| (knbn, d));
+        let starts = self.get_search_starts(dist_to_query, pivot, start_layer, params);
+        let mut neighbours = loop {
+            // a sorted vec of increasing points with > 0 distances.
+            let search = LayerSearch {
+                params: Some(params),
+                stop,
+                vector,
+                as_of: as_of.as_ref(),
+            };
+            let mut neighbours =
+                self.search_layer_from(dist_to_query, starts.clone(), ef, layer_to_search, search);
+            let nb_candidates = neighbours.len();
+            // equal distances are ordered by data id so that results do not depend on the order of traversal
+            neighbours.sort_by(|a, b| {
+                a.dist_to_ref
+                    .total_cmp(&b.dist_to_ref)
+                    .then(a.point_ref.origin_id.cmp(&b.point_ref.origin_id))
+            });
+            self.order_ties(&mut neighbours, |p| p.dist_to_ref, |p| p.point_ref.p_id);
+            // the entry point of search_layer is kept if no point satisfies constraints
+            if params.is_constrained() {
+                let payloads = self.layer_indexed_points.payloads.read();
+                neighbours.retain(|p| {
+                    params.accept(p.point_ref.origin_id, &payloads.get(p.point_ref.p_id))
+                });
+            }
+            if let Some(as_of) = &as_of {
+                neighbours.retain(|p| self.is_visible_as_of(&p.point_ref, as_of));
+            } else if self.has_deleted() {
+                neighbours.retain(|p| !self.is_deleted(&p.point_ref));
+            }
+            // with a limit of results by key the search is widened until knbn results can be kept
+            let nb_kept =
+                params.count_kept_per_key(neighbours.iter().map(|p| p.point_ref.origin_id));
+            if nb_kept >= knbn || nb_candidates < ef || ef >= self.get_nb_point() {
+                break neighbours;
+            }
+            self.scratch.recycle_results(neighbours);
+            ef *= 2;
+        };
+        // with a penalty or a fusion function all ef candidates are rescored before truncation
+        let avoided = params.get_avoided::<T>();
+        let fusion = params.get_fusion();
+        if avoided.is_some() || fusion.is_some() {
+            out.extend(neighbours.iter().map(|p| {
+                let origin_id = p.point_ref.origin_id;
+                let mut score = p.dist_to_ref;
+                if let Some((vectors, penalty)) = &avoided {
+                    let v = p.point_ref.data.get_v();
+                    let nearest = vectors
+                        .iter()
+                        .map(|a| self.dist_f.eval(v, a))
+                        .fold(f32::INFINITY, f32::min);
+                    score -= penalty * nearest;
+                }
+                if let Some(fusion) = fusion {
+                    score = fusion(origin_id, score);
+                }
+                Neighbour::new(origin_id, score, p.point_ref.p_id)
+            }));
+            out.sort_unstable_by(|a, b| a.cmp_by_distance(b));
+            self.order_ties(out, |n| n.distance, |n| n.p_id);
+            params.keep_per_key(out, |n| n.d_id);
+            out.truncate(knbn);
+            self.scratch.recycle_results(neighbours);
+            if let Some(access) = &self.access_stats {
+                access.count_returned(out);
+            }
+            return;
+        }
+        params.keep_per_key(&mut neighbours, |p| p.point_ref.origin_id);
         // get the min of K and ef points into a vector.
         //
         let last = knbn.min(ef).min(neighbours.len());
-        let knn_neighbours: Vec<Neighbour> = neighbours[0..last]
-            .iter()
-            .map(|p| {
-                Neighbour::new(
-                    p.as_ref().point_ref.origin_id,
-                    p.as_ref().dist_to_ref,
-                    p.as_ref().point_ref.p_id,
-                )
-            })
-            .collect();
-
-        knn_neighbours
-    } // end of search_filter
+        out.extend(neighbours[0..last].iter().map(|p| {
+            Neighbour::new(
+                p.as_ref().point_ref.origin_id,
+                p.as_ref().dist_to_ref,
+                p.as_ref().point_ref.p_id,
+            )
+        }));
+        self.scratch.recycle_results(neighbours);
+        if let Some(access) = &self.access_stats {
+            access.count_returned(out);
+        }
+    } // end of search_with_into
 
     #[inline]
     pub fn search_possible_filter(
@@ -1553,49 +2748,149 @@ impl<'b, T: Clone + Send + Sync, D: Distance<T> + Send + Sync> Hnsw<'b, T, D> {
 
     /// search the first knbn nearest neigbours of a data and returns a Vector of Neighbour.   
     /// The parameter ef controls the width of the search in the lowest level, it must be greater
-    /// than number of neighbours asked.  
-    /// A rule of thumb could be between knbn and max_nb_connection.
+    /// than number of neighbours asked (it is raised if needed, see [Hnsw::set_min_ef_factor]).  
+    /// A rule of thumb could be between knbn and max_nb_connection.  
+    /// A search in an empty structure returns an empty vector.  
+    /// Searches are deterministic: no random choice, no iteration on hashed sets, no merge of threads. The same
+    /// query on the same graph (the same structure, a [Hnsw::deep_clone] or a reload of it) returns the same
+    /// neighbours in the same order, equal distances being ordered by data id (see [Neighbour::cmp_by_distance]).
+    /// The graph itself depends on the order of insertions, parallel insertions do not give the same graph twice.
     pub fn search(&self, data: &[T], knbn: usize, ef_arg: usize) -> Vec<Neighbour> {
         self.search_possible_filter(data, knbn, ef_arg, None)
     }
 
-    fn search_with_id(
-        &self,
-        request: (usize, &Vec<T>),
-        knbn: usize,
-        ef: usize,
-    ) -> (usize, Vec<Neighbour>) {
-        (request.0, self.search(request.1, knbn, ef))
+    /// search as [Hnsw::search], the neighbours are written in out (cleared first) instead of a new vector.
+    /// A caller reusing out, from a rayon worker or not, makes searches without allocation of results
+    /// once out and the scratch structures of the thread (see [crate::scratch]) have reached their size.
+    /// Searches by search_into are not observed by hooks (slow queries, statistics, quality).
+    pub fn search_into(&self, data: &[T], knbn: usize, ef_arg: usize, out: &mut Vec<Neighbour>) {
+        let dist_to_query = |v: &[T], bound: f32| self.eval_bounded(data, v, bound);
+        self.search_with_into(
+            &dist_to_query,
+            Some(data),
+            knbn,
+            ef_arg,
+            &SearchParams::new(),
+            out,
+        );
     }
 
     /// knbn is the number of nearest neigbours asked for. Returns for each data vector
     /// a Vector of Neighbour
     pub fn parallel_search(&self, datas: &[Vec<T>], knbn: usize, ef: usize) -> Vec<Vec<Neighbour>> {
-        let (sender, receiver) = channel();
-        // make up requests
-        let nb_request = datas.len();
-        let requests: Vec<(usize, &Vec<T>)> = (0..nb_request).zip(datas.iter()).collect();
-        //
-        requests.par_iter().for_each_with(sender, |s, item| {
-            s.send(self.search_with_id(*item, knbn, ef)).unwrap()
-        });
-        let req_res: Vec<(usize, Vec<Neighbour>)> = receiver.iter().collect();
-        // now sort to respect the key order of input
-        let mut answers = Vec::<Vec<Neighbour>>::with_capacity(datas.len());
-        // get a map from request id to rank
-        let mut req_hash = HashMap::<usize, usize>::new();
-        for (i, elt) in req_res.iter().enumerate() {
-            // the response of request req_res[i].0 is at rank i
-            req_hash.insert(elt.0, i);
-        }
-        for i in 0..datas.len() {
-            let answer_i = req_hash.get_key_value(&i).unwrap().1;
-            answers.push((req_res[*answer_i].1).clone());
-        }
-        answers
-    } // end of insert_parallel
+        // an indexed parallel iterator keeps the order of requests, without a channel to gather answers.
+        // Each rayon worker reuses its scratch structures (See module scratch).
+        datas
+            .par_iter()
+            .map(|data| self.search(data, knbn, ef))
+            .collect()
+    } // end of parallel_search
 } // end of Hnsw
 
+impl<'b, T: Clone + Send + Sync, D: Distance<T> + Clone + Send + Sync> Hnsw<'b, T, D> {
+    /// returns an independent copy of the structure: points, payloads, links, deleted points, aliases and parameters.  
+    /// The copy can be modified (insertions, deletions, parameters) without changing self, so two versions of an
+    /// index can be compared without a dump and a reload.  
+    /// Vectors are copied, except vectors of a mmap and vectors shared with [Hnsw::insert_arc] which stay shared.
+    /// The level generator is copied with its state, the same insertions in both copies give the same layers.
+    /// The event recorder (see [Hnsw::record_events]) and the stats history are not copied. Insertions wait for the end of the copy.
+    pub fn deep_clone(&self) -> Hnsw<'b, T, D> {
+        let cpu_start = ProcessTime::now();
+        let indexation = &self.layer_indexed_points;
+        let layers = indexation.points_by_layer.read();
+        // points without links, then links between copied points, retrieved by their point id
+        let new_layers: Vec<Layer<'b, T>> = layers
+            .iter()
+            .map(|layer| {
+                layer
+                    .par_iter()
+                    .map(|p| Arc::new(p.copy_unlinked(p.p_id)))
+                    .collect()
+            })
+            .collect();
+        let get_new = |p_id: PointId| &new_layers[p_id.0 as usize][p_id.1 as usize];
+        layers
+            .par_iter()
+            .flat_map_iter(|layer| layer.iter())
+            .for_each(|p| {
+                let neighbours = p.neighbours.read();
+                let mut new_neighbours = get_new(p.p_id).neighbours.write();
+                for (l, neighbours_l) in neighbours.iter().enumerate() {
+                    new_neighbours[l] = neighbours_l
+                        .iter()
+                        .map(|n| {
+                            Arc::new(PointWithOrder::new(
+                                get_new(n.point_ref.p_id),
+                                n.dist_to_ref,
+                            ))
+                        })
+                        .collect();
+                }
+            });
+        let entry_point = indexation
+            .entry_point
+            .read()
+            .as_ref()
+            .map(|p| Arc::clone(get_new(p.p_id)));
+        let layer_indexed_points = PointIndexation {
+            max_nb_connection: indexation.max_nb_connection,
+            max_layer: indexation.max_layer,
+            points_by_layer: Arc::new(RwLock::new(new_layers)),
+            layer_g: indexation.layer_g.clone(),
+            nb_point: Arc::new(RwLock::new(indexation.get_nb_point())),
+            entry_point: Arc::new(RwLock::new(entry_point)),
+            payloads: indexation.payloads.clone(),
+            insertion_order: Arc::new(RwLock::new(indexation.insertion_order.read().clone())),
+            ids: indexation.ids.clone(),
+        };
+        drop(layers);
+        info!(
+            "deep_clone of {} points, cpu time {:?}",
+            layer_indexed_points.get_nb_point(),
+            cpu_start.elapsed()
+        );
+        //
+        Hnsw {
+            max_nb_connection: self.max_nb_connection,
+            ef_construction: self.ef_construction,
+            extend_candidates: self.extend_candidates,
+            keep_pruned: self.keep_pruned,
+            exact_capacity: self.exact_capacity,
+            adaptive: self.adaptive.clone(),
+            max_layer: self.max_layer,
+            layer_indexed_points,
+            data_dimension: self.data_dimension,
+            dist_f: self.dist_f.clone(),
+            searching: self.searching,
+            datamap_opt: self.datamap_opt,
+            metadata: self.metadata.clone(),
+            dedup: self.dedup.clone(),
+            aliases: RwLock::new(self.aliases.read().clone()),
+            namespaces: self.namespaces.clone(),
+            tombstones: self.tombstones.clone(),
+            recorder: None,
+            dirty: DirtyPoints::default(),
+            bounded_dist: self.bounded_dist,
+            tiled_dist: self.tiled_dist,
+            min_ef_factor: self.min_ef_factor,
+            scratch: ScratchPool::new(),
+            rebuild_log: Arc::default(),
+            writes: WriteGate::default(),
+            slow_queries: self.slow_queries.clone(),
+            cost_model: RwLock::new(self.get_cost_model()),
+            graph_kind: self.graph_kind,
+            stats_history: None,
+            layer_plan: self.layer_plan.clone(),
+            access_stats: None,
+            link_slack: self.link_slack,
+            read_repair: self.read_repair.as_ref().map(|_| ReadRepair::default()),
+            read_only: self.read_only,
+            quality: None,
+            tie_break: self.tie_break,
+        }
+    } // end of deep_clone
+} // end of impl Hnsw
+
 // This function takes a binary heap with points declared with a negative distance
 // and returns a vector of points with their correct positive distance to some reference distance
 // The vector is sorted by construction
@@ -1641,6 +2936,19 @@ fn from_positive_binaryheap_to_negative_binary_heap<'b, T: Send + Sync + Clone>(
     negative_heap
 }
 
+// true if the list of point at layer holds target. Called with the list of target locked in write mode,
+// so a list locked by another thread is not waited for (and the link is considered absent).
+pub(crate) fn links_to<T: Clone + Send + Sync>(
+    point: &Point<T>,
+    target: PointId,
+    layer: usize,
+) -> bool {
+    point
+        .neighbours
+        .try_read()
+        .is_some_and(|neighbours| neighbours[layer].iter().any(|n| n.point_ref.p_id == target))
+}
+
 // essentialy to check dump/reload conssistency
 // in fact checks only equality of graph
 #[allow(unused)]
@@ -1714,7 +3022,6 @@ where
 } // end of check_reload
 
 #[cfg(test)]
-
 mod tests {
 
     use super::*;
@@ -1772,6 +3079,56 @@ mod tests {
         assert_eq!(nb_dumped, nbcolumn);
     } // end of test_iter_point
 
+    #[test]
+    fn test_iter_chunked() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(960);
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..3500)
+            .map(|_| (0..8).map(|_| rng.sample(unif)).collect())
+            .collect();
+        let hnsw = Hnsw::<f32, dist::DistL2>::new(16, 3500, 16, 100, dist::DistL2 {});
+        let indexation = hnsw.get_point_indexation();
+        assert_eq!(indexation.iter_ids().count(), 0);
+        assert_eq!(indexation.par_iter().count(), 0);
+        assert_eq!(indexation.into_iter().count(), 0);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        hnsw.parallel_insert(&data_with_id[..3000]);
+        // all points once, layer 0 first
+        let ids: Vec<(PointId, DataId)> = indexation.iter_ids().collect();
+        assert_eq!(ids.len(), 3000);
+        assert!(ids.windows(2).all(|w| w[0].0 < w[1].0));
+        let mut d_ids: Vec<DataId> = ids.iter().map(|id| id.1).collect();
+        d_ids.sort_unstable();
+        assert!(d_ids.into_iter().eq(0..3000));
+        // same order for all iterations
+        let chunks: Vec<Vec<Arc<Point<f32>>>> = indexation.iter_points_chunked(1000).collect();
+        assert!(chunks.iter().all(|c| !c.is_empty() && c.len() <= 1000));
+        let chunked = chunks
+            .iter()
+            .flatten()
+            .map(|p| (p.get_point_id(), p.get_origin_id()));
+        assert!(chunked.eq(ids.iter().copied()));
+        let par: Vec<PointId> = indexation.par_iter().map(|p| p.get_point_id()).collect();
+        assert!(par.into_iter().eq(ids.iter().map(|id| id.0)));
+        assert!(
+            indexation
+                .into_iter()
+                .map(|p| p.get_point_id())
+                .eq(ids.iter().map(|id| id.0))
+        );
+        // no lock is held between chunks, points inserted during iteration are not visited
+        let mut nb_visited = 0;
+        for (chunk, point) in indexation
+            .iter_points_chunked(100)
+            .zip(&data_with_id[3000..])
+        {
+            nb_visited += chunk.len();
+            hnsw.insert((point.0, point.1));
+        }
+        assert_eq!(nb_visited, 3000);
+        assert_eq!(indexation.iter_ids().count(), hnsw.get_nb_point());
+    } // end of test_iter_chunked
+
     #[test]
     fn test_iter_layerpoint() {
         //
@@ -1840,4 +3197,273 @@ mod tests {
             assert_eq!(result, vec![Neighbour::new(0, 0.0, PointId(0, 0))]);
         }
     }
+
+    #[test]
+    fn test_insert_verbose() {
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let hnsw: Hnsw<f32, dist::DistL1> = Hnsw::new(8, 1000, 16, 100, dist::DistL1 {});
+        let first = hnsw.insert_verbose((&[0.5; 4], 0)).unwrap();
+        assert!(first.neighbours.iter().all(|l| l.is_empty()));
+        let mut nb_linked = 0;
+        for i in 1..500 {
+            let v: Vec<f32> = (0..4).map(|_| rng.sample(unif)).collect();
+            let report = hnsw.insert_verbose((&v, i)).unwrap();
+            assert_eq!(report.neighbours.len(), report.get_layer() as usize + 1);
+            for (l, neighbours) in report.neighbours.iter().enumerate() {
+                assert!(neighbours.len() <= if l == 0 { 16 } else { 8 });
+                assert!(
+                    neighbours
+                        .windows(2)
+                        .all(|w| w[0].distance <= w[1].distance)
+                );
+                assert!(neighbours.iter().all(|n| n.d_id < i));
+            }
+            if !report.neighbours[0].is_empty() {
+                nb_linked += 1;
+            }
+            let point = hnsw.get_point_indexation().get_point(&report.p_id).unwrap();
+            assert_eq!(point.get_origin_id(), i);
+        }
+        assert!(nb_linked > 490);
+    } // end of test_insert_verbose
+
+    #[test]
+    fn test_insert_arc() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(926);
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let datas: Vec<(Arc<[f32]>, usize)> = (0..500)
+            .map(|i| ((0..8).map(|_| rng.sample(unif)).collect(), i))
+            .collect();
+        // two structures with different distances on the same buffers, serial builds with seeded levels
+        let mut hnsw_l1: Hnsw<f32, dist::DistL1> = Hnsw::new(16, 500, 16, 100, dist::DistL1 {});
+        let mut hnsw_l2: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 500, 16, 100, dist::DistL2 {});
+        hnsw_l1.set_level_seed(926);
+        hnsw_l2.set_level_seed(926);
+        for (v, i) in &datas {
+            hnsw_l1.insert_arc((Arc::clone(v), *i)).unwrap();
+            hnsw_l2.insert_arc((Arc::clone(v), *i)).unwrap();
+        }
+        assert!(datas.iter().all(|(v, _)| Arc::strong_count(v) == 3));
+        for point in hnsw_l2.get_point_indexation() {
+            let shared = &datas[point.get_origin_id()].0;
+            assert!(std::ptr::eq(point.get_v().as_ptr(), shared.as_ptr()));
+        }
+        let nb_found = datas
+            .iter()
+            .filter(|(v, i)| {
+                hnsw_l1.search(v, 1, 64)[0].d_id == *i && hnsw_l2.search(v, 1, 64)[0].d_id == *i
+            })
+            .count();
+        assert!(nb_found >= 490, "nb_found {}", nb_found);
+        // a parallel insertion shares the buffers too
+        let hnsw_par: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 500, 16, 100, dist::DistL2 {});
+        hnsw_par.parallel_insert_arc(&datas);
+        assert_eq!(hnsw_par.get_nb_point(), 500);
+        assert!(datas.iter().all(|(v, _)| Arc::strong_count(v) == 4));
+        drop(hnsw_par);
+        drop(hnsw_l1);
+        assert_eq!(Arc::strong_count(&datas[0].0), 2);
+    } // end of test_insert_arc
+
+    #[test]
+    fn test_try_parallel_insert() {
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let mut datas: Vec<Vec<f32>> = (0..500)
+            .map(|_| (0..8).map(|_| rng.sample(unif)).collect())
+            .collect();
+        datas[100].clear();
+        datas[200].truncate(5);
+        datas[300][2] = f32::NAN;
+        let hnsw: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 500, 16, 100, dist::DistL2 {});
+        // a first point fixes the dimension
+        hnsw.insert((&datas[0], 0));
+        let data_with_id: Vec<(&Vec<f32>, usize)> = datas.iter().zip(0..).skip(1).collect();
+        let results = hnsw.try_parallel_insert(&data_with_id);
+        assert_eq!(results.len(), 499);
+        assert_eq!(results[99], Err(HnswError::EmptyVector(100)));
+        assert_eq!(
+            results[199],
+            Err(HnswError::Dimension {
+                origin_id: 200,
+                expected: 8,
+                got: 5
+            })
+        );
+        assert!(matches!(
+            results[299],
+            Err(HnswError::Panic { origin_id: 300, .. })
+        ));
+        assert_eq!(results.iter().filter(|r| r.is_err()).count(), 3);
+        // the other items are inserted and found
+        for (r, (v, i)) in results.iter().zip(data_with_id.iter()) {
+            if let Ok(p_id) = r {
+                let point = hnsw.get_point_indexation().get_point(p_id).unwrap();
+                assert_eq!(point.get_origin_id(), *i);
+                assert_eq!(point.get_v(), v.as_slice());
+            }
+        }
+        let nb_found = results
+            .iter()
+            .zip(data_with_id.iter())
+            .filter(|(r, (v, i))| r.is_ok() && hnsw.search(v, 10, 64).iter().any(|n| n.d_id == *i))
+            .count();
+        assert!(nb_found >= 450, "nb_found {}", nb_found);
+    } // end of test_try_parallel_insert
+
+    #[test]
+    fn test_min_ef_factor() {
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let mut hnsw: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 500, 16, 100, dist::DistL2 {});
+        for i in 0..500 {
+            let v: Vec<f32> = (0..8).map(|_| rng.sample(unif)).collect();
+            hnsw.insert((&v, i));
+        }
+        assert_eq!(hnsw.get_search_ef(10, 4), 10);
+        assert_eq!(hnsw.get_search_ef(10, 64), 64);
+        hnsw.set_min_ef_factor(2.5);
+        assert_eq!(hnsw.get_search_ef(10, 4), 25);
+        assert_eq!(hnsw.get_search_ef(10, 64), 64);
+        // a too small ef does not truncate results
+        let query: Vec<f32> = (0..8).map(|_| rng.sample(unif)).collect();
+        let res = hnsw.search(&query, 20, 1);
+        assert_eq!(res.len(), 20);
+        assert!(res.windows(2).all(|w| w[0].distance <= w[1].distance));
+    } // end of test_min_ef_factor
+
+    #[test]
+    fn test_try_new() {
+        let new = |nb_conn, max_layer| {
+            Hnsw::<f32, dist::DistL2>::try_new(nb_conn, 0, max_layer, 100, dist::DistL2 {})
+        };
+        assert_eq!(new(0, 16).err(), Some(HnswParamError::MaxNbConnection(0)));
+        assert_eq!(
+            new(257, 16).err(),
+            Some(HnswParamError::MaxNbConnection(257))
+        );
+        assert_eq!(new(16, 0).err(), Some(HnswParamError::MaxLayer(0)));
+        // max_elements = 0 is only a hint
+        let hnsw = new(16, 16).unwrap();
+        // an empty structure answers nothing
+        assert!(hnsw.search(&[0.; 4], 10, 32).is_empty());
+        let filter: Vec<usize> = vec![0, 1];
+        assert!(
+            hnsw.search_filter(&[0.; 4], 10, 32, Some(&filter))
+                .is_empty()
+        );
+        assert!(hnsw.parallel_search(&[vec![0.; 4]], 10, 32)[0].is_empty());
+        // empty vectors are not inserted
+        hnsw.insert((&[], 0));
+        assert_eq!(
+            hnsw.insert_verbose((&[], 1)).unwrap_err(),
+            HnswError::EmptyVector(1)
+        );
+        assert_eq!(hnsw.get_nb_point(), 0);
+        hnsw.insert((&[1., 0., 0., 0.], 2));
+        let res = hnsw.search(&[0.; 4], 10, 32);
+        assert_eq!(res.len(), 1);
+        assert_eq!(res[0].d_id, 2);
+    } // end of test_try_new
+
+    #[test]
+    fn test_neighbors_sorted() {
+        // points on a line at distance 1 of 0 on both sides give ties
+        let hnsw: Hnsw<f32, dist::DistL2> = Hnsw::new(8, 50, 16, 100, dist::DistL2 {});
+        for i in 0..50 {
+            let x = (i / 2) as f32 * if i % 2 == 0 { 1. } else { -1. };
+            hnsw.insert((&[x, 0.], i));
+        }
+        let mut nb_ties = 0;
+        for point in hnsw.get_point_indexation() {
+            let sorted = point.get_neighbors_sorted(0);
+            assert_eq!(sorted.len(), point.get_neighborhood_id()[0].len());
+            for w in sorted.windows(2) {
+                assert!(w[0].distance <= w[1].distance);
+                if w[0].distance == w[1].distance {
+                    assert!(w[0].d_id < w[1].d_id);
+                    nb_ties += 1;
+                }
+            }
+            assert!(
+                point
+                    .get_neighbors_sorted(NB_LAYER_MAX as usize + 1)
+                    .is_empty()
+            );
+        }
+        assert!(nb_ties > 0);
+    } // end of test_neighbors_sorted
+
+    #[test]
+    fn test_deep_clone() {
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..1200)
+            .map(|_| (0..8).map(|_| rng.sample(unif)).collect())
+            .collect();
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 1200, 16, 100, dist::DistL2 {});
+        hnsw.parallel_insert(&data_with_id[..1000]);
+        hnsw.remove_batch(&[0, 1, 2]).unwrap();
+        //
+        let copy = hnsw.deep_clone();
+        assert_eq!(copy.get_nb_point(), 1000);
+        assert_eq!(copy.get_nb_deleted(), 3);
+        assert_eq!(copy.get_max_level_observed(), hnsw.get_max_level_observed());
+        let expected: Vec<Vec<Neighbour>> = data.iter().map(|v| hnsw.search(v, 10, 32)).collect();
+        // same graph, same answers
+        for (v, e) in data.iter().zip(&expected) {
+            assert_eq!(&copy.search(v, 10, 32), e);
+        }
+        // modifications of the copy do not reach the original
+        copy.parallel_insert(&data_with_id[1000..]);
+        copy.remove_batch(&[3, 4]).unwrap();
+        assert_eq!(copy.get_nb_point(), 1200);
+        assert_eq!(hnsw.get_nb_point(), 1000);
+        assert_eq!(hnsw.get_nb_deleted(), 3);
+        for (v, e) in data.iter().zip(&expected) {
+            assert_eq!(&hnsw.search(v, 10, 32), e);
+        }
+    } // end of test_deep_clone
+
+    #[test]
+    fn test_exact_capacity() {
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..2000)
+            .map(|_| (0..8).map(|_| rng.sample(unif)).collect())
+            .collect();
+        // total capacity and length of neighbour lists
+        let slots = |hnsw: &Hnsw<f32, dist::DistL2>| {
+            let mut slots = (0, 0);
+            for p in hnsw.get_point_indexation() {
+                for neighbours_l in p.neighbours.read().iter() {
+                    slots.0 += neighbours_l.capacity();
+                    slots.1 += neighbours_l.len();
+                }
+            }
+            slots
+        };
+        let hnsw: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 2000, 16, 100, dist::DistL2 {});
+        let mut exact: Hnsw<f32, dist::DistL2> = Hnsw::new(16, 2000, 16, 100, dist::DistL2 {});
+        exact.set_exact_capacity(true);
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+            exact.insert((v, i));
+        }
+        let (capacity, len) = slots(&hnsw);
+        let (exact_capacity, exact_len) = slots(&exact);
+        assert!(exact_capacity < capacity, "{} {}", exact_capacity, capacity);
+        // at most one free slot by list
+        assert!(exact_capacity - exact_len <= 2000 * 16);
+        assert!(capacity > len);
+        // searching mode shrinks lists
+        exact.set_searching_mode(true);
+        let (exact_capacity, exact_len) = slots(&exact);
+        assert_eq!(exact_capacity, exact_len);
+        assert!(hnsw.shrink_neighbours() > 0);
+        assert_eq!(slots(&hnsw).0, len);
+        assert_eq!(exact.search(&data[10], 1, 32)[0].d_id, 10);
+    } // end of test_exact_capacity
 } // end of module test
@@ -6,10 +6,16 @@
 //! The other file stores the ids and vector in point and can be reloaded via a mmap scheme.
 //! The graph file is suffixed by "hnsw.graph" the other is suffixed by "hnsw.data"
 //!
+//! The file dump only opens the files: [Hnsw::dump_to_writers] and [HnswIo::load_from_readers] do the same with
+//! any Write / Read (sockets, uploads to an object storage, buffers in memory...).
+//!
+//! A dump can also be a directory of blobs listed in a manifest, with payloads and other parts of an index,
+//! see [crate::manifest].
+//!
 //! Examples of dump and reload of structure Hnsw is given in the tests (see test_dump_reload, reload_with_mmap)
 // datafile
 // MAGICDATAP : u32
-// dimension : usize!!
+// dimension : u64 (sizes and ids are dumped as u64 whatever the target)
 // The for each point the triplet: (MAGICDATAP, origin_id , dimension , array of values bson encoded) ( u32, u64, ....)
 //
 // A point is dumped in graph file as given by its external id (type DataId i.e : a usize, possibly a hash value)
@@ -30,9 +36,11 @@ use std::path::{Path, PathBuf};
 use parking_lot::RwLock;
 use std::sync::Arc;
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
 use rand::Rng;
+use rayon::prelude::*;
+use roaring::{RoaringBitmap, RoaringTreemap};
 
 use anyhow::*;
 use std::any::type_name;
@@ -40,8 +48,19 @@ use std::any::type_name;
 use anndists::dist::distances::*;
 
 use self::hnsw::*;
+use crate::alias::AliasTable;
+use crate::blockdump::DirtyPoints;
 use crate::datamap::*;
+use crate::graphkind::GraphKind;
 use crate::hnsw;
+use crate::idset::IdSet;
+use crate::layergrowth::LayerPlan;
+use crate::namespace::Namespaces;
+use crate::payload::{PAYLOAD_NB_BYTES, Payload, PayloadColumn};
+use crate::quiesce::WriteGate;
+use crate::scratch::ScratchPool;
+use crate::tiebreak::TieBreak;
+use crate::tombstone::Tombstones;
 use log::{debug, error, info, trace};
 use std::io::prelude::*;
 
@@ -61,6 +80,38 @@ const MAGICDESCR_3: u32 = 0x002a6771;
 // we dump level scale modififcation factor
 const MAGICDESCR_4: u32 = 0x002a6779;
 
+// magic for v5
+// we dump user metadata after type name
+const MAGICDESCR_5: u32 = 0x002a677b;
+
+// magic for v6
+// extension blocks follow the entry point in graph file
+const MAGICDESCR_6: u32 = 0x002a677d;
+
+// magic for v7
+// neighbours of a point at a layer are sorted by origin_id and dumped as varint encoded deltas.
+const MAGICDESCR_7: u32 = 0x002a677e;
+
+// An extension block is a tag (u32), a length in bytes (u64) and the bytes.
+// The list of blocks ends with EXT_END. Unknown tags are skipped at reload.
+const EXT_END: u32 = 0;
+// point id (layer as u8, rank as i32) and payload of points having one, see Payload::to_bytes
+const EXT_PAYLOADS: u32 = 1;
+// pairs (alias, canonical id) as u64
+const EXT_ALIASES: u32 = 2;
+// namespace statistics and deleted namespaces, see Namespaces::to_bytes
+const EXT_NAMESPACES: u32 = 3;
+// data ids as u64 by insertion sequence number
+const EXT_INSERTION_ORDER: u32 = 4;
+// roaring treemap of data ids of points not deleted
+const EXT_IDS: u32 = 5;
+// present (empty) if the graph is flat
+const EXT_GRAPH_KIND: u32 = 6;
+// deleted points, see Tombstones::to_bytes
+const EXT_DELETED: u32 = 7;
+// present (empty) if the index is read only
+const EXT_READ_ONLY: u32 = 8;
+
 // magic at beginning of a layer dump
 const MAGICLAYER: u32 = 0x000a676f;
 // magic head of data file and before each data vector
@@ -74,7 +125,13 @@ pub enum DumpMode {
 
 /// The main interface for dumping struct Hnsw.
 pub(crate) trait HnswIoT {
-    fn dump(&self, mode: DumpMode, dumpinit: &mut DumpInit) -> anyhow::Result<i32>;
+    /// dumps the graph part in graph_out and vectors in data_out
+    fn dump<W: Write>(
+        &self,
+        mode: DumpMode,
+        graph_out: &mut BufWriter<W>,
+        data_out: &mut BufWriter<W>,
+    ) -> anyhow::Result<i32>;
 }
 
 /// Describe options accessible for reload
@@ -143,9 +200,9 @@ pub struct DumpInit {
     // basename dump
     basename: String,
     // to dump data
-    pub(crate) data_out: BufWriter<File>,
+    pub(crate) data_out: File,
     // to dump graph
-    pub(crate) graph_out: BufWriter<File>,
+    pub(crate) graph_out: File,
 } // end of
 
 impl DumpInit {
@@ -205,7 +262,7 @@ impl DumpInit {
             );
             std::panic::panic_any("HnswIo::init : could not open file".to_string());
         }
-        let graphfile = graphfileres.unwrap();
+        let graph_out = graphfileres.unwrap();
         //  same thing for data file
         let mut dataname = basename.clone();
         dataname.push_str(".hnsw.data");
@@ -223,10 +280,7 @@ impl DumpInit {
             );
             std::panic::panic_any("HnswIo::init : could not open file".to_string());
         }
-        let datafile = datafileres.unwrap();
-        //
-        let graph_out = BufWriter::new(graphfile);
-        let data_out = BufWriter::new(datafile);
+        let data_out = datafileres.unwrap();
         //
         DumpInit {
             basename,
@@ -307,6 +361,8 @@ pub struct HnswIo {
     /// for Hnswio to be async
     nb_point_loaded: Arc<AtomicUsize>,
     initialized: bool,
+    /// payloads given to loaded points, see [crate::manifest]
+    payloads: HashMap<DataId, Payload>,
 } // end of struct ReloadOptions
 
 impl HnswIo {
@@ -322,6 +378,7 @@ impl HnswIo {
             datamap: None,
             nb_point_loaded: Arc::new(AtomicUsize::new(0)),
             initialized: true,
+            payloads: HashMap::new(),
         }
     }
 
@@ -334,6 +391,7 @@ impl HnswIo {
             datamap: None,
             nb_point_loaded: Arc::new(AtomicUsize::new(0)),
             initialized: true,
+            payloads: HashMap::new(),
         }
     }
 
@@ -433,6 +491,109 @@ impl HnswIo {
         T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
         D: Distance<T> + Default + Send + Sync,
         'a: 'b,
+    {
+        self.load_hnsw_selected(&|_, _| true)
+    }
+
+    /// reload only points whose id is in ids (ids above u32::MAX are never loaded).  
+    /// Links to points not loaded are dropped, so the graph can be less connected than the dumped one.
+    /// Useful to inspect a slice of a large index (a tenant...) without loading it all.
+    pub fn load_subset<'b, 'a, T, D>(&'a mut self, ids: &RoaringBitmap) -> Result<Hnsw<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+        'a: 'b,
+    {
+        self.load_hnsw_selected(&|origin_id, _| {
+            u32::try_from(origin_id).is_ok_and(|id| ids.contains(id))
+        })
+    }
+
+    /// reload only points of layers greater or equal to min_layer, i.e the top of the hierarchy.
+    pub fn load_layers<'b, 'a, T, D>(&'a mut self, min_layer: u8) -> Result<Hnsw<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+        'a: 'b,
+    {
+        self.load_hnsw_selected(&|_, p_id| p_id.0 >= min_layer)
+    }
+
+    /// reload a previously dumped hnsw structure owning all its data, so it has no lifetime (See [OwnedHnsw]).  
+    /// mmap options are ignored, all vectors are loaded in memory.
+    pub fn load_owned<T, D>(&self) -> Result<OwnedHnsw<T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+    {
+        self.load_hnsw_from(None, &|_, _| true)
+    }
+
+    /// reload a structure dumped by [Hnsw::dump_to_writers] (or in files) from a reader of the graph and a reader
+    /// of the vectors. Readers are buffered here. The structure owns its vectors (See [OwnedHnsw]).
+    pub fn load_from_readers<T, D, R: Read>(graph_in: R, data_in: R) -> Result<OwnedHnsw<T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+    {
+        HnswIo::load_from_readers_with_payloads(graph_in, data_in, HashMap::new())
+    }
+
+    // as load_from_readers, a point with an id in payloads gets its payload
+    pub(crate) fn load_from_readers_with_payloads<T, D, R: Read>(
+        graph_in: R,
+        data_in: R,
+        payloads: HashMap<DataId, Payload>,
+    ) -> Result<OwnedHnsw<T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+    {
+        let mut graph_in = BufReader::new(graph_in);
+        let mut data_in = BufReader::new(data_in);
+        let description = load_description(&mut graph_in)?;
+        let hnswio = HnswIo {
+            payloads,
+            ..HnswIo::default()
+        };
+        hnswio.load_streams(description, &mut graph_in, &mut data_in, None, &|_, _| true)
+    }
+
+    // reload points for which select(origin_id, dumped point id) is true
+    fn load_hnsw_selected<'b, 'a, T, D>(
+        &'a mut self,
+        select: &dyn Fn(DataId, PointId) -> bool,
+    ) -> Result<Hnsw<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+        'a: 'b,
+    {
+        // Do we use mmap at reload
+        let use_mmap = self.options.use_mmap().0;
+        if use_mmap {
+            let datamap_res = DataMap::from_hnswdump::<T>(self.dir.as_path(), &self.basename);
+            match datamap_res {
+                std::result::Result::Ok(datamap) => {
+                    info!("reload using mmap");
+                    self.datamap = Some(datamap);
+                }
+                Err(_) => error!("load_hnsw could not initialize mmap"),
+            }
+        }
+        let datamap = self.datamap.as_ref().filter(|_| use_mmap);
+        self.load_hnsw_from(datamap, select)
+    } // end of load_hnsw_selected
+
+    // reload with vectors of points possibly in datamap
+    fn load_hnsw_from<'b, T, D>(
+        &self,
+        datamap: Option<&'b DataMap>,
+        select: &dyn Fn(DataId, PointId) -> bool,
+    ) -> Result<Hnsw<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
     {
         //
         debug!("HnswIo::load_hnsw ");
@@ -443,9 +604,32 @@ impl HnswIo {
             return Err(anyhow!("could not reload HNSW structure"));
         }
         let mut init = init.unwrap();
-        let data_in = &mut init.datafile;
-        let graph_in = &mut init.graphfile;
-        let description = init.descr;
+        let hnsw = self.load_streams(
+            init.descr,
+            &mut init.graphfile,
+            &mut init.datafile,
+            datamap,
+            select,
+        )?;
+        //
+        let elapsed_t = start_t.elapsed().unwrap().as_secs() as f32;
+        info!("reload_hnsw : elapsed system time(s) {}", elapsed_t);
+        Ok(hnsw)
+    } // end of load_hnsw_from
+
+    // reload from a graph stream positioned after the description and a data stream at its beginning
+    pub(crate) fn load_streams<'b, T, D>(
+        &self,
+        description: Description,
+        graph_in: &mut dyn Read,
+        data_in: &mut dyn Read,
+        datamap: Option<&'b DataMap>,
+        select: &dyn Fn(DataId, PointId) -> bool,
+    ) -> Result<Hnsw<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+    {
         info!("format version : {}", description.format_version);
         //  In datafile , we must read MAGICDATAP and dimension and check
         let mut it_slice = [0u8; std::mem::size_of::<u32>()];
@@ -456,9 +640,7 @@ impl HnswIo {
             "magic not equal to MAGICDATAP in load_point"
         );
         //
-        let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-        data_in.read_exact(&mut it_slice)?;
-        let dimension = usize::from_ne_bytes(it_slice);
+        let dimension = read_usize(data_in)?;
         assert_eq!(
             dimension, description.dimension,
             "data dimension incoherent {:?} {:?} ",
@@ -490,38 +672,107 @@ impl HnswIo {
         }
         let t_type = description.t_name.clone();
         debug!("T type name in dump = {:?}", t_type);
-        // Do we use mmap at reload
-        if self.options.use_mmap().0 {
-            let datamap_res = DataMap::from_hnswdump::<T>(self.dir.as_path(), &self.basename);
-            if datamap_res.is_err() {
-                error!("load_hnsw could not initialize mmap")
-            } else {
-                info!("reload using mmap");
-                self.datamap = Some(datamap_res.unwrap());
-            }
-        }
         // reloader can use datamap
-        let layer_point_indexation = self.load_point_indexation(graph_in, &description, data_in)?;
+        let (mut layer_point_indexation, loaded_ids, extensions) =
+            self.load_point_indexation(graph_in, &description, data_in, datamap, select)?;
         let data_dim = layer_point_indexation.get_data_dimension();
+        let aliases = match extensions.get(&EXT_ALIASES) {
+            Some(bytes) => AliasTable::from_bytes(bytes)?,
+            None => AliasTable::default(),
+        };
+        let namespaces = match extensions.get(&EXT_NAMESPACES) {
+            Some(bytes) => Namespaces::from_bytes(bytes)?,
+            None => Namespaces::default(),
+        };
+        if let Some(bytes) = extensions.get(&EXT_INSERTION_ORDER) {
+            layer_point_indexation.set_insertion_order_from_bytes(bytes)?;
+        }
+        if let Some(bytes) = extensions.get(&EXT_IDS) {
+            layer_point_indexation.ids.retain_from_bytes(bytes)?;
+        }
+        let (tombstones, repaired) = match extensions.get(&EXT_DELETED) {
+            Some(bytes) => Tombstones::from_bytes(bytes, &loaded_ids)?,
+            None => (Tombstones::default(), Vec::new()),
+        };
+        let graph_kind = match extensions.get(&EXT_GRAPH_KIND) {
+            Some(_) => {
+                // new points stay in layer 0
+                layer_point_indexation.layer_g =
+                    LayerGenerator::new(description.max_nb_connection as usize, 1);
+                GraphKind::Flat
+            }
+            None => GraphKind::Hierarchical,
+        };
+        let read_only = extensions.contains_key(&EXT_READ_ONLY);
+        // the reloaded size is the plan for further insertions
+        let layer_plan = LayerPlan::new(layer_point_indexation.get_nb_point());
         //
         let hnsw: Hnsw<T, D> = Hnsw {
             max_nb_connection: description.max_nb_connection as usize,
             ef_construction: description.ef,
             extend_candidates: true,
             keep_pruned: false,
+            exact_capacity: false,
+            adaptive: None,
             max_layer: description.nb_layer as usize,
             layer_indexed_points: layer_point_indexation,
             data_dimension: data_dim,
             dist_f: D::default(),
             searching: false,
             datamap_opt: true, // set datamap_opt to true
+            metadata: description.metadata,
+            rebuild_log: Arc::default(),
+            dedup: None,
+            aliases: RwLock::new(aliases),
+            namespaces,
+            tombstones,
+            recorder: None,
+            dirty: DirtyPoints::default(),
+            bounded_dist: None,
+            tiled_dist: None,
+            min_ef_factor: 1.,
+            scratch: ScratchPool::new(),
+            writes: WriteGate::default(),
+            slow_queries: None,
+            cost_model: RwLock::new(None),
+            graph_kind,
+            stats_history: None,
+            layer_plan,
+            access_stats: None,
+            link_slack: 0,
+            read_repair: None,
+            read_only,
+            quality: None,
+            tie_break: TieBreak::default(),
         };
+        hnsw.free_slots(repaired);
         //
         debug!("load_hnsw completed");
-        let elapsed_t = start_t.elapsed().unwrap().as_secs() as f32;
-        info!("reload_hnsw : elapsed system time(s) {}", elapsed_t);
         Ok(hnsw)
-    } // end of load_hnsw
+    } // end of load_streams
+
+    /// reload a previously dumped hnsw structure after checking its metadata.  
+    /// Each couple (key, value) of expected_meta must be found in the metadata stored in the dump,
+    /// otherwise an error is returned before any point is loaded.  
+    /// This is useful to ensure, for example, that the embedding model version used to fill the dump
+    /// is the one used to compute requests.
+    /// ```text
+    ///     let hnsw : Hnsw<f32,DistL2> = reloader.load_checked(&[("model_version", "v2")])?;
+    /// ```
+    pub fn load_checked<'b, 'a, T, D>(
+        &'a mut self,
+        expected_meta: &[(&str, &str)],
+    ) -> Result<Hnsw<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+        'a: 'b,
+    {
+        let init = self.init()?;
+        check_metadata(&init.descr, expected_meta)?;
+        drop(init);
+        self.load_hnsw::<T, D>()
+    } // end of load_checked
 
     /// reload a previously dumped hnsw structure
     /// This function makes reload of a Hnsw dump with a given Dist.  
@@ -555,9 +806,7 @@ impl HnswIo {
             "magic not equal to MAGICDATAP in load_point"
         );
         //
-        let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-        data_in.read_exact(&mut it_slice)?;
-        let dimension = usize::from_ne_bytes(it_slice);
+        let dimension = read_usize(data_in)?;
         assert_eq!(
             dimension, description.dimension,
             "data dimension incoherent {:?} {:?} ",
@@ -590,21 +839,84 @@ impl HnswIo {
         info!("T type name in dump = {:?}", t_type);
         //
         //
-        let layer_point_indexation = self.load_point_indexation(graph_in, &description, data_in)?;
+        let (mut layer_point_indexation, loaded_ids, extensions) = self.load_point_indexation(
+            graph_in,
+            &description,
+            data_in,
+            self.datamap.as_ref(),
+            &|_, _| true,
+        )?;
         let data_dim = layer_point_indexation.get_data_dimension();
+        let aliases = match extensions.get(&EXT_ALIASES) {
+            Some(bytes) => AliasTable::from_bytes(bytes)?,
+            None => AliasTable::default(),
+        };
+        let namespaces = match extensions.get(&EXT_NAMESPACES) {
+            Some(bytes) => Namespaces::from_bytes(bytes)?,
+            None => Namespaces::default(),
+        };
+        if let Some(bytes) = extensions.get(&EXT_INSERTION_ORDER) {
+            layer_point_indexation.set_insertion_order_from_bytes(bytes)?;
+        }
+        if let Some(bytes) = extensions.get(&EXT_IDS) {
+            layer_point_indexation.ids.retain_from_bytes(bytes)?;
+        }
+        let (tombstones, repaired) = match extensions.get(&EXT_DELETED) {
+            Some(bytes) => Tombstones::from_bytes(bytes, &loaded_ids)?,
+            None => (Tombstones::default(), Vec::new()),
+        };
+        let graph_kind = match extensions.get(&EXT_GRAPH_KIND) {
+            Some(_) => {
+                // new points stay in layer 0
+                layer_point_indexation.layer_g =
+                    LayerGenerator::new(description.max_nb_connection as usize, 1);
+                GraphKind::Flat
+            }
+            None => GraphKind::Hierarchical,
+        };
+        let read_only = extensions.contains_key(&EXT_READ_ONLY);
+        // the reloaded size is the plan for further insertions
+        let layer_plan = LayerPlan::new(layer_point_indexation.get_nb_point());
         //
         let hnsw: Hnsw<T, D> = Hnsw {
             max_nb_connection: description.max_nb_connection as usize,
             ef_construction: description.ef,
             extend_candidates: true,
             keep_pruned: false,
+            exact_capacity: false,
+            adaptive: None,
             max_layer: description.nb_layer as usize,
             layer_indexed_points: layer_point_indexation,
             data_dimension: data_dim,
             dist_f: f,
             searching: false,
             datamap_opt: false,
+            metadata: description.metadata,
+            rebuild_log: Arc::default(),
+            dedup: None,
+            aliases: RwLock::new(aliases),
+            namespaces,
+            tombstones,
+            recorder: None,
+            dirty: DirtyPoints::default(),
+            bounded_dist: None,
+            tiled_dist: None,
+            min_ef_factor: 1.,
+            scratch: ScratchPool::new(),
+            writes: WriteGate::default(),
+            slow_queries: None,
+            cost_model: RwLock::new(None),
+            graph_kind,
+            stats_history: None,
+            layer_plan,
+            access_stats: None,
+            link_slack: 0,
+            read_repair: None,
+            read_only,
+            quality: None,
+            tie_break: TieBreak::default(),
         };
+        hnsw.free_slots(repaired);
         //
         debug!("load_hnsw_with_dist completed");
         // We cannot check that the pointer function was the same as the dump
@@ -612,15 +924,25 @@ impl HnswIo {
         Ok(hnsw)
     } // end of load_hnsw_with_dist
 
-    fn load_point_indexation<'b, 'a, T>(
-        &'a self,
+    // loads points for which select(origin_id, dumped point id) is true, neighbours not loaded are dropped.
+    // vectors are taken in datamap (if given) according to options.
+    // Returns also the point id of loaded points by dumped point id, and the extension blocks following
+    // the entry point.
+    #[allow(clippy::type_complexity)]
+    fn load_point_indexation<'b, T>(
+        &self,
         graph_in: &mut dyn Read,
         descr: &Description,
         data_in: &mut dyn Read,
-    ) -> anyhow::Result<PointIndexation<'b, T>>
+        datamap: Option<&'b DataMap>,
+        select: &dyn Fn(DataId, PointId) -> bool,
+    ) -> anyhow::Result<(
+        PointIndexation<'b, T>,
+        HashMap<PointId, PointId>,
+        HashMap<u32, Vec<u8>>,
+    )>
     where
         T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
-        'a: 'b,
     {
         //
         debug!(" in load_point_indexation");
@@ -640,6 +962,9 @@ impl HnswIo {
         let mut points_by_layer: Vec<Vec<Arc<Point<T>>>> =
             Vec::with_capacity(NB_LAYER_MAX as usize);
         let mut neighbourhood_map: HashMap<PointId, Vec<Vec<Neighbour>>> = HashMap::new();
+        // dumped point id to point id of loaded point
+        let mut loaded_ids: HashMap<PointId, PointId> = HashMap::new();
+        let payloads = PayloadColumn::default();
         // load max layer
         let mut it_slice = [0u8; ::std::mem::size_of::<u8>()];
         graph_in.read_exact(&mut it_slice)?;
@@ -652,6 +977,7 @@ impl HnswIo {
         let mut nb_points_loaded: usize = 0;
         let mut nb_still_to_load = descr.nb_point as i64;
         let (use_mmap, max_nbpoint_in_memory) = self.options.use_mmap();
+        let use_mmap = use_mmap && datamap.is_some();
         //
         for l in 0..nb_layer as usize {
             // read and check magic
@@ -662,9 +988,7 @@ impl HnswIo {
             if magic != MAGICLAYER {
                 return Err(anyhow!("bad magic at layer beginning"));
             }
-            let mut it_slice = [0u8; ::std::mem::size_of::<usize>()];
-            graph_in.read_exact(&mut it_slice)?;
-            let nbpoints = usize::from_ne_bytes(it_slice);
+            let nbpoints = read_usize(graph_in)?;
             debug!(" layer {:?} , nb points {:?}", l, nbpoints);
             let mut vlayer: Vec<Arc<Point<T>>> = Vec::with_capacity(nbpoints);
             // load graph and data part of point. Points are dumped in the same order.
@@ -690,38 +1014,74 @@ impl HnswIo {
                         }
                     }
                 };
-                let load_point_res = self.load_point(graph_in, descr, data_in, point_use_mmap);
+                let load_point_res = self.load_point(
+                    graph_in,
+                    descr,
+                    data_in,
+                    datamap.filter(|_| point_use_mmap),
+                    select,
+                    vlayer.len() as i32,
+                );
                 if let Err(other) = load_point_res {
                     error!("in load_point_indexation, loading of point {} failed", r);
                     return Err(anyhow!(other));
                 }
-
-                let load_point_res = load_point_res.unwrap();
-                let point = load_point_res.0;
+                nb_still_to_load -= 1;
+                assert!(nb_still_to_load >= 0);
+                let Some((point, dumped_p_id, neighbours)) = load_point_res.unwrap() else {
+                    continue;
+                };
                 let p_id = point.get_point_id();
+                if let Some(payload) = self.payloads.get(&point.get_origin_id()) {
+                    payloads.set(p_id, *payload);
+                }
                 // some checks
                 assert_eq!(l, p_id.0 as usize);
-                if r != p_id.1 as usize {
+                if r != dumped_p_id.1 as usize {
                     debug!("Origin= {:?},  p_id = {:?}", point.get_origin_id(), p_id);
                     debug!("Storing at l {:?}, r {:?}", l, r);
                 }
-                assert_eq!(r, p_id.1 as usize);
+                assert_eq!(r, dumped_p_id.1 as usize);
                 // store neoghbour info of this point
-                neighbourhood_map.insert(p_id, load_point_res.1);
+                loaded_ids.insert(dumped_p_id, p_id);
+                neighbourhood_map.insert(p_id, neighbours);
                 vlayer.push(point);
                 nb_points_loaded += 1;
-                nb_still_to_load -= 1;
-                assert!(nb_still_to_load >= 0);
             }
             points_by_layer.push(vlayer);
         }
+        //
+        // get id of entry_point
+        // load entry point
+        info!(
+            "end of layer loading, allocating PointIndexation, nb points loaded {:?}",
+            nb_points_loaded
+        );
+        //
+        let origin_id = read_usize(graph_in)?;
+        //
+        let mut it_slice = [0u8; ::std::mem::size_of::<u8>()];
+        graph_in.read_exact(&mut it_slice)?;
+        let layer = u8::from_ne_bytes(it_slice);
+        //
+        let mut it_slice = [0u8; std::mem::size_of::<i32>()];
+        graph_in.read_exact(&mut it_slice)?;
+        let rank_in_l = i32::from_ne_bytes(it_slice);
+        //
+        let extensions = load_extensions(graph_in, descr)?;
+        if let Some(bytes) = extensions.get(&EXT_PAYLOADS) {
+            set_payloads_from_bytes(&payloads, &loaded_ids, bytes)?;
+        }
         // at this step all points are loaded , but without their neighbours fileds are not yet initialized
         let mut nbp: usize = 0;
         for (p_id, neighbours) in &neighbourhood_map {
             let point = &points_by_layer[p_id.0 as usize][p_id.1 as usize];
             for (l, neighbours) in neighbours.iter().enumerate() {
                 for n in neighbours {
-                    let n_point = &points_by_layer[n.p_id.0 as usize][n.p_id.1 as usize];
+                    let Some(n_p_id) = loaded_ids.get(&n.p_id) else {
+                        continue;
+                    };
+                    let n_point = &points_by_layer[n_p_id.0 as usize][n_p_id.1 as usize];
                     // now n_point is the Arc<Point> corresponding to neighbour n of point,
                     // construct a corresponding PointWithOrder
                     let n_pwo = PointWithOrder::<T>::new(n_point, n.distance);
@@ -731,40 +1091,33 @@ impl HnswIo {
                 point.neighbours.write()[l].sort_unstable();
             } // end of for l
             nbp += 1;
-            if nbp % 500_000 == 0 {
+            if nbp.is_multiple_of(500_000) {
                 debug!("reloading nb_points neighbourhood completed : {}", nbp);
             }
         } // end loop in neighbourhood_map
         //
-        // get id of entry_point
-        // load entry point
-        info!(
-            "end of layer loading, allocating PointIndexation, nb points loaded {:?}",
-            nb_points_loaded
-        );
-        //
-        let mut it_slice = [0u8; std::mem::size_of::<DataId>()];
-        graph_in.read_exact(&mut it_slice)?;
-        let origin_id = DataId::from_ne_bytes(it_slice);
-        //
-        let mut it_slice = [0u8; ::std::mem::size_of::<u8>()];
-        graph_in.read_exact(&mut it_slice)?;
-        let layer = u8::from_ne_bytes(it_slice);
-        //
-        let mut it_slice = [0u8; std::mem::size_of::<i32>()];
-        graph_in.read_exact(&mut it_slice)?;
-        let rank_in_l = i32::from_ne_bytes(it_slice);
-        //
         info!(
             "found entry point, origin_id {:?} , layer {:?}, rank in layer {:?} ",
             origin_id, layer, rank_in_l
         );
-        let entry_point = Arc::clone(&points_by_layer[layer as usize][rank_in_l as usize]);
-        info!(
-            " loaded entry point, origin_id {:} p_id {:?}",
-            entry_point.get_origin_id(),
-            entry_point.get_point_id()
-        );
+        // if dumped entry point is not loaded, take a point of the highest layer loaded
+        let entry_point = match loaded_ids.get(&PointId(layer, rank_in_l)) {
+            Some(p_id) => Some(Arc::clone(
+                &points_by_layer[p_id.0 as usize][p_id.1 as usize],
+            )),
+            None => points_by_layer
+                .iter()
+                .rev()
+                .find_map(|points| points.first())
+                .cloned(),
+        };
+        if let Some(entry_point) = entry_point.as_ref() {
+            info!(
+                " loaded entry point, origin_id {:} p_id {:?}",
+                entry_point.get_origin_id(),
+                entry_point.get_point_id()
+            );
+        }
         //
         let point_indexation = PointIndexation {
             max_nb_connection: descr.max_nb_connection as usize,
@@ -776,11 +1129,17 @@ impl HnswIo {
                 NB_LAYER_MAX as usize,
             ),
             nb_point: Arc::new(RwLock::new(nb_points_loaded)), // CAVEAT , we should increase , the whole thing is to be able to increment graph ?
-            entry_point: Arc::new(RwLock::new(Some(entry_point))),
+            entry_point: Arc::new(RwLock::new(entry_point)),
+            payloads,
+            insertion_order: Arc::new(RwLock::new(Vec::new())),
+            ids: IdSet::default(),
         };
+        // in layer order, unless the dump has the insertion order
+        point_indexation.reset_insertion_order();
+        point_indexation.reset_ids();
         //
         debug!("Exiting load_pointIndexation");
-        Ok(point_indexation)
+        Ok((point_indexation, loaded_ids, extensions))
     } // end of load_pointIndexation
 
     //
@@ -789,17 +1148,20 @@ impl HnswIo {
     //  The graph part is loaded from graph_in file
     // the data vector itself is loaded from data_in
     //
+    //  A point not selected is skipped and None is returned, else it is given rank new_rank in its layer.
+    //
     #[allow(clippy::type_complexity)]
-    fn load_point<'b, 'a, T>(
-        &'a self,
+    fn load_point<'b, T>(
+        &self,
         graph_in: &mut dyn Read,
         descr: &Description,
         data_in: &mut dyn Read,
-        point_use_mmap: bool,
-    ) -> Result<(Arc<Point<'b, T>>, Vec<Vec<Neighbour>>)>
+        datamap: Option<&'b DataMap>,
+        select: &dyn Fn(DataId, PointId) -> bool,
+        new_rank: i32,
+    ) -> Result<Option<(Arc<Point<'b, T>>, PointId, Vec<Vec<Neighbour>>)>>
     where
         T: 'static + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
-        'a: 'b,
     {
         //
         //    debug!(" point load {:?} {:?}  ", p_id, origin_id);
@@ -809,10 +1171,15 @@ impl HnswIo {
             error!("load_point error reading graph data for point p_id");
             return Err(anyhow!("error reading graph data for point"));
         }
-        let (origin_id, p_id, neighborhood) = load_res.unwrap();
+        let (origin_id, dumped_p_id, neighborhood) = load_res.unwrap();
+        if !select(origin_id, dumped_p_id) {
+            skip_point_data(origin_id, data_in, descr)?;
+            return Ok(None);
+        }
+        let p_id = PointId(dumped_p_id.0, new_rank);
         //
-        let point = match point_use_mmap {
-            false => {
+        let point = match datamap {
+            None => {
                 let v = load_point_data::<T>(origin_id, data_in, descr);
                 if v.is_err() {
                     error!("loading point {:?}", origin_id);
@@ -820,10 +1187,10 @@ impl HnswIo {
                 }
                 Point::<T>::new(v.unwrap(), origin_id, p_id)
             }
-            true => {
+            Some(datamap) => {
                 skip_point_data(origin_id, data_in, descr)?; // keep cohrence between data file and graph file!
                 debug!("constructing point from datamap, dataid : {:?}", origin_id);
-                let s: Option<&'b [T]> = self.datamap.as_ref().unwrap().get_data::<T>(&origin_id);
+                let s: Option<&'b [T]> = datamap.get_data::<T>(&origin_id);
                 Point::<T>::new_from_mmap(s.unwrap(), origin_id, p_id)
             }
         };
@@ -835,7 +1202,7 @@ impl HnswIo {
             descr.dimension
         );
         //
-        Ok((Arc::new(point), neighborhood))
+        Ok(Some((Arc::new(point), dumped_p_id, neighborhood)))
     } // end of load_point
 } // end of Hnswio
 
@@ -864,6 +1231,8 @@ pub struct Description {
     pub distname: String,
     /// T typename
     pub t_name: String,
+    /// user metadata (format version >= 5)
+    pub metadata: BTreeMap<String, String>,
 }
 
 impl Description {
@@ -874,10 +1243,12 @@ impl Description {
     /// . ef (search parameter used in construction) as usize
     /// . nb_point (the number points dumped) as a usize
     /// . the name of distance used. (nb byes as a usize then list of bytes)
+    /// . the name of data type. (nb byes as a usize then list of bytes)
+    /// . the number of metadata entries as a usize then for each key and value (nb bytes as a usize then list of bytes)
     ///
-    fn dump<W: Write>(&self, argmode: DumpMode, out: &mut BufWriter<W>) -> Result<i32> {
+    pub(crate) fn dump<W: Write>(&self, argmode: DumpMode, out: &mut BufWriter<W>) -> Result<i32> {
         info!("in dump of description");
-        out.write_all(&MAGICDESCR_4.to_ne_bytes())?;
+        out.write_all(&MAGICDESCR_7.to_ne_bytes())?;
         let mode: u8 = match argmode {
             DumpMode::Full => 1,
             _ => 0,
@@ -896,24 +1267,33 @@ impl Description {
         }
         //
         info!("dumping ef {:?}", self.ef);
-        out.write_all(&self.ef.to_ne_bytes())?;
+        write_usize(out, self.ef)?;
         //
         info!("dumping nb point {:?}", self.nb_point);
-        out.write_all(&self.nb_point.to_ne_bytes())?;
+        write_usize(out, self.nb_point)?;
         //
         info!("dumping dimension of data {:?}", self.dimension);
-        out.write_all(&self.dimension.to_ne_bytes())?;
+        write_usize(out, self.dimension)?;
 
         // dump of distance name
         let namelen: usize = self.distname.len();
         info!("distance name {:?} ", self.distname);
-        out.write_all(&namelen.to_ne_bytes())?;
+        write_usize(out, namelen)?;
         out.write_all(self.distname.as_bytes())?;
         // dump of T value typename
         let namelen: usize = self.t_name.len();
         info!("T name {:?} ", self.t_name);
-        out.write_all(&namelen.to_ne_bytes())?;
+        write_usize(out, namelen)?;
         out.write_all(self.t_name.as_bytes())?;
+        // dump of metadata
+        write_usize(out, self.metadata.len())?;
+        for (key, value) in &self.metadata {
+            debug!("metadata {:?} : {:?}", key, value);
+            write_usize(out, key.len())?;
+            out.write_all(key.as_bytes())?;
+            write_usize(out, value.len())?;
+            out.write_all(value.as_bytes())?;
+        }
         //
         Ok(1)
     } // end fo dump
@@ -927,8 +1307,43 @@ impl Description {
     pub fn get_dimension(&self) -> usize {
         self.dimension
     }
+
+    /// returns the metadata stored in the dump
+    pub fn get_metadata(&self) -> &BTreeMap<String, String> {
+        &self.metadata
+    }
 } // end of HnswIO impl for Descr
 
+// checks that each expected (key, value) is in description metadata
+fn check_metadata(descr: &Description, expected_meta: &[(&str, &str)]) -> Result<()> {
+    for (key, value) in expected_meta {
+        match descr.metadata.get(*key) {
+            Some(dumped) if dumped == value => {}
+            Some(dumped) => {
+                error!(
+                    "metadata mismatch for key {:?}, dump has {:?}, expected {:?}",
+                    key, dumped, value
+                );
+                return Err(anyhow!(
+                    "metadata mismatch for key {:?} : dump has {:?}, expected {:?}",
+                    key,
+                    dumped,
+                    value
+                ));
+            }
+            None => {
+                error!("metadata key {:?} not found in dump", key);
+                return Err(anyhow!(
+                    "metadata key {:?} not found in dump, expected value {:?}",
+                    key,
+                    value
+                ));
+            }
+        }
+    }
+    Ok(())
+} // end of check_metadata
+
 //
 
 /// This method is internally used by Hnswio.  
@@ -947,6 +1362,7 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
         dimension: 0,
         distname: String::from(""),
         t_name: String::from(""),
+        metadata: BTreeMap::new(),
     };
     //
     let mut it_slice = [0u8; std::mem::size_of::<u32>()];
@@ -963,6 +1379,15 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
         MAGICDESCR_4 => {
             descr.format_version = 4;
         }
+        MAGICDESCR_5 => {
+            descr.format_version = 5;
+        }
+        MAGICDESCR_6 => {
+            descr.format_version = 6;
+        }
+        MAGICDESCR_7 => {
+            descr.format_version = 7;
+        }
         _ => {
             error!("bad magic");
             return Err(anyhow!("bad magic at descr beginning"));
@@ -978,7 +1403,7 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
     descr.max_nb_connection = u8::from_ne_bytes(it_slice);
     info!(" max_nb_connection {:?} ", descr.max_nb_connection);
     //
-    if descr.format_version == 4 {
+    if descr.format_version >= 4 {
         // we read modification for level sampling
         let mut it_slice = [0u8; std::mem::size_of::<f64>()];
         io_in.read_exact(&mut it_slice)?;
@@ -991,26 +1416,18 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
     descr.nb_layer = u8::from_ne_bytes(it_slice);
     info!("nb_layer  {:?} ", descr.nb_layer);
     // ef
-    let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-    io_in.read_exact(&mut it_slice)?;
-    descr.ef = usize::from_ne_bytes(it_slice);
+    descr.ef = read_usize(io_in)?;
     info!("ef  {:?} ", descr.ef);
     // nb_point
-    let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-    io_in.read_exact(&mut it_slice)?;
-    descr.nb_point = usize::from_ne_bytes(it_slice);
+    descr.nb_point = read_usize(io_in)?;
     // read dimension
-    let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-    io_in.read_exact(&mut it_slice)?;
-    descr.dimension = usize::from_ne_bytes(it_slice);
+    descr.dimension = read_usize(io_in)?;
     info!(
         "nb_point {:?} dimension {:?} ",
         descr.nb_point, descr.dimension
     );
     // distance name
-    let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-    io_in.read_exact(&mut it_slice)?;
-    let len: usize = usize::from_ne_bytes(it_slice);
+    let len: usize = read_usize(io_in)?;
     debug!("length of distance name {:?} ", len);
     if len > 256 {
         info!(" length of distance name > 256");
@@ -1023,9 +1440,7 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
     debug!("distance name {:?} ", distname);
     descr.distname = distname;
     // reload of type name
-    let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-    io_in.read_exact(&mut it_slice)?;
-    let len: usize = usize::from_ne_bytes(it_slice);
+    let len: usize = read_usize(io_in)?;
     debug!("length of T  name {:?} ", len);
     if len > 256 {
         println!(" length of T name should not exceed 256");
@@ -1036,11 +1451,34 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
     let t_name = String::from_utf8(tnamev).unwrap();
     debug!("T type name {:?} ", t_name);
     descr.t_name = t_name;
+    // reload of metadata
+    if descr.format_version >= 5 {
+        let nb_meta = read_usize(io_in)?;
+        debug!("nb metadata {:?} ", nb_meta);
+        for _ in 0..nb_meta {
+            let key = load_string(io_in)?;
+            let value = load_string(io_in)?;
+            debug!("metadata {:?} : {:?}", key, value);
+            descr.metadata.insert(key, value);
+        }
+    }
     debug!(" end of description load \n");
     //
     Ok(descr)
 }
 
+// reads a string dumped as its length (usize) followed by its bytes
+fn load_string(io_in: &mut dyn Read) -> Result<String> {
+    let len: usize = read_usize(io_in)?;
+    if len > 1 << 20 {
+        return Err(anyhow!("bad length for string : {}", len));
+    }
+    let mut v = vec![0; len];
+    io_in.read_exact(v.as_mut_slice())?;
+    let string = String::from_utf8(v)?;
+    Ok(string)
+}
+
 //
 // dump and load of Point<T>
 // ==========================
@@ -1051,10 +1489,13 @@ pub fn load_description(io_in: &mut dyn Read) -> Result<Description> {
 ///  1. The value MAGICPOINT
 ///  2. its identity ( a usize  rank in original data , hash value or else , and PointId)
 ///  3. for each layer dump of the number of neighbours followed by :
-///     for each neighbour dump of its identity (: usize) and then distance (): u32) to point dumped.
+///     for each neighbour dump of its identity and then distance (: f32) to point dumped.
 ///
-/// identity of a point is in full mode the triplet origin_id (: usize), layer (: u8) rank_in_layer (: u32)
-///                           light mode only origin_id (: usize)
+/// identity of a point is in full mode the triplet origin_id, layer (: u8) rank_in_layer
+///                           light mode only origin_id
+///
+/// Since format v7 neighbours of a layer are sorted by origin_id, the number of neighbours, the origin_id
+/// (as a delta with previous neighbour) and rank_in_layer are varint encoded.
 ///  For data dump
 ///  1. The value MAGICDATAP (u32)
 ///  2. origin_id as a u64
@@ -1068,8 +1509,8 @@ fn dump_point<T: Serialize + Clone + Sized + Send + Sync, W: Write>(
 ) -> Result<i32> {
     //
     graphout.write_all(&MAGICPOINT.to_ne_bytes())?;
-    // dump ext_id: usize , layer : u8 , rank in layer : i32
-    graphout.write_all(&point.get_origin_id().to_ne_bytes())?;
+    // dump ext_id: u64 , layer : u8 , rank in layer : i32
+    write_usize(graphout, point.get_origin_id())?;
     let p_id = point.get_point_id();
     if mode == DumpMode::Full {
         graphout.write_all(&p_id.0.to_ne_bytes())?;
@@ -1080,19 +1521,22 @@ fn dump_point<T: Serialize + Clone + Sized + Send + Sync, W: Write>(
     let neighborhood = point.get_neighborhood_id();
     // in any case nb_layers are dumped with possibly 0 neighbours at a layer, but this does not occur by construction
     for (l, neighbours_at_l) in neighborhood.iter().enumerate() {
-        // Caution : we dump number of neighbours as a usize, even if it cannot be so large!
         let nbg_l: usize = neighbours_at_l.len();
         trace!("\t dumping nbng : {} at l {}", nbg_l, l);
-        graphout.write_all(&nbg_l.to_ne_bytes())?;
-        for n in neighbours_at_l {
-            // dump d_id : uszie , distance : f32, layer : u8, rank in layer : i32
-            graphout.write_all(&n.d_id.to_ne_bytes())?;
+        write_varint(graphout, nbg_l as u64)?;
+        // sorted ids give small deltas. Order by distance is restored at reload
+        let mut sorted: Vec<&Neighbour> = neighbours_at_l.iter().collect();
+        sorted.sort_by_key(|n| n.d_id);
+        let mut previous: DataId = 0;
+        for n in sorted {
+            // dump delta of d_id, layer : u8, rank in layer, distance : f32
+            write_varint(graphout, (n.d_id - previous) as u64)?;
+            previous = n.d_id;
             if mode == DumpMode::Full {
                 graphout.write_all(&n.p_id.0.to_ne_bytes())?;
-                graphout.write_all(&n.p_id.1.to_ne_bytes())?;
+                write_varint(graphout, n.p_id.1 as u32 as u64)?;
             }
             graphout.write_all(&n.distance.to_ne_bytes())?;
-            //                debug!("        voisins  {:?}  {:?}  {:?}", n.p_id,  n.d_id , n.distance);
         }
     }
     // now we dump data vector!
@@ -1156,7 +1600,7 @@ where
     let v: Vec<T> = if std::any::TypeId::of::<T>() != std::any::TypeId::of::<NoData>() {
         match descr.format_version {
             2 => bincode::deserialize(&v_serialized).unwrap(),
-            3 | 4 => {
+            3..=7 => {
                 let slice_t = unsafe {
                     std::slice::from_raw_parts(v_serialized.as_ptr() as *const T, descr.dimension)
                 };
@@ -1223,23 +1667,21 @@ fn load_point_graph(graph_in: &mut dyn Read, descr: &Description) -> Result<Poin
     trace!("in load_point_graph");
     // read and check magic
     let mut it_slice = [0u8; std::mem::size_of::<u32>()];
-    graph_in.read_exact(&mut it_slice).unwrap();
+    graph_in.read_exact(&mut it_slice)?;
     let magic = u32::from_ne_bytes(it_slice);
     if magic != MAGICPOINT {
         error!("got instead of MAGICPOINT {:x}", magic);
         return Err(anyhow!("bad magic at point beginning"));
     }
-    let mut it_slice = [0u8; std::mem::size_of::<DataId>()];
-    graph_in.read_exact(&mut it_slice).unwrap();
-    let origin_id = DataId::from_ne_bytes(it_slice);
+    let origin_id = read_usize(graph_in)?;
     //
     // read point_id
     let mut it_slice = [0u8; std::mem::size_of::<u8>()];
-    graph_in.read_exact(&mut it_slice).unwrap();
+    graph_in.read_exact(&mut it_slice)?;
     let layer = u8::from_ne_bytes(it_slice);
     //
     let mut it_slice = [0u8; std::mem::size_of::<i32>()];
-    graph_in.read_exact(&mut it_slice).unwrap();
+    graph_in.read_exact(&mut it_slice)?;
     let rank_in_l = i32::from_ne_bytes(it_slice);
     let p_id = PointId(layer, rank_in_l);
     debug!(
@@ -1251,27 +1693,27 @@ fn load_point_graph(graph_in: &mut dyn Read, descr: &Description) -> Result<Poin
     let nb_layer = descr.nb_layer;
     let mut neighborhood = Vec::<Vec<Neighbour>>::with_capacity(NB_LAYER_MAX as usize);
     for _l in 0..nb_layer {
+        if descr.format_version >= 7 {
+            neighborhood.push(load_neighbours_varint(graph_in, descr)?);
+            continue;
+        }
         let mut neighbour: Neighbour = Default::default();
         // read nb_neighbour as usize!!! CAUTION, then nb_neighbours times identity(depends on Full or Light) distance : f32
-        let mut it_slice = [0u8; std::mem::size_of::<usize>()];
-        graph_in.read_exact(&mut it_slice).unwrap();
-        let nb_neighbours = usize::from_ne_bytes(it_slice);
+        let nb_neighbours = read_usize(graph_in)?;
         let mut neighborhood_l: Vec<Neighbour> = Vec::with_capacity(nb_neighbours);
         for _j in 0..nb_neighbours {
-            let mut it_slice = [0u8; std::mem::size_of::<DataId>()];
-            graph_in.read_exact(&mut it_slice).unwrap();
-            neighbour.d_id = DataId::from_ne_bytes(it_slice);
+            neighbour.d_id = read_usize(graph_in)?;
             if descr.dumpmode == 1 {
                 let mut it_slice = [0u8; std::mem::size_of::<u8>()];
-                graph_in.read_exact(&mut it_slice).unwrap();
+                graph_in.read_exact(&mut it_slice)?;
                 neighbour.p_id.0 = u8::from_ne_bytes(it_slice);
                 //
                 let mut it_slice = [0u8; std::mem::size_of::<i32>()];
-                graph_in.read_exact(&mut it_slice).unwrap();
+                graph_in.read_exact(&mut it_slice)?;
                 neighbour.p_id.1 = i32::from_ne_bytes(it_slice);
             }
             let mut it_slice = [0u8; std::mem::size_of::<f32>()];
-            graph_in.read_exact(&mut it_slice).unwrap();
+            graph_in.read_exact(&mut it_slice)?;
             neighbour.distance = f32::from_ne_bytes(it_slice);
             //  debug!("        voisins  load {:?} {:?} {:?} ", neighbour.p_id, neighbour.d_id , neighbour.distance);
             // now we have a new neighbour, we must really fill neighbourhood info, so it means going from Neighbour to PointWithOrder
@@ -1288,6 +1730,145 @@ fn load_point_graph(graph_in: &mut dyn Read, descr: &Description) -> Result<Poin
     Ok(point_grap_info)
 } // end of load_point_graph
 
+// writes extension blocks and the end tag (format >= 6)
+fn dump_extensions<W: Write>(extensions: &[(u32, Vec<u8>)], out: &mut W) -> Result<()> {
+    for (tag, bytes) in extensions {
+        debug!("dumping extension {}, {} bytes", tag, bytes.len());
+        out.write_all(&tag.to_ne_bytes())?;
+        out.write_all(&(bytes.len() as u64).to_ne_bytes())?;
+        out.write_all(bytes)?;
+    }
+    out.write_all(&EXT_END.to_ne_bytes())?;
+    Ok(())
+}
+
+// payloads of points having one, as EXT_PAYLOADS
+fn payloads_to_bytes<T: Clone + Send + Sync>(points: &PointIndexation<T>) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    if points.payloads.is_empty() {
+        return bytes;
+    }
+    let payloads = points.payloads.read();
+    for point in points.into_iter() {
+        let payload = payloads.get(point.get_point_id());
+        if !payload.is_empty() {
+            let p_id = point.get_point_id();
+            bytes.extend_from_slice(&p_id.0.to_ne_bytes());
+            bytes.extend_from_slice(&p_id.1.to_ne_bytes());
+            payload.to_bytes(&mut bytes);
+        }
+    }
+    bytes
+}
+
+// sets payloads of EXT_PAYLOADS to loaded points, given by their dumped point id.
+fn set_payloads_from_bytes(
+    payloads: &PayloadColumn,
+    loaded_ids: &HashMap<PointId, PointId>,
+    bytes: &[u8],
+) -> Result<()> {
+    let record_size = 5 + PAYLOAD_NB_BYTES;
+    if !bytes.len().is_multiple_of(record_size) {
+        return Err(anyhow!(
+            "payload block of dump has bad length {}",
+            bytes.len()
+        ));
+    }
+    for record in bytes.chunks_exact(record_size) {
+        let dumped = PointId(
+            record[0],
+            i32::from_ne_bytes(record[1..5].try_into().unwrap()),
+        );
+        let Some(p_id) = loaded_ids.get(&dumped) else {
+            continue;
+        };
+        payloads.set(*p_id, Payload::from_bytes(&record[5..]));
+    }
+    Ok(())
+} // end of set_payloads_from_bytes
+
+// reads extension blocks following entry point. Empty for format < 6
+fn load_extensions(graph_in: &mut dyn Read, descr: &Description) -> Result<HashMap<u32, Vec<u8>>> {
+    let mut extensions = HashMap::new();
+    if descr.format_version < 6 {
+        return Ok(extensions);
+    }
+    loop {
+        let mut it_slice = [0u8; std::mem::size_of::<u32>()];
+        graph_in.read_exact(&mut it_slice)?;
+        let tag = u32::from_ne_bytes(it_slice);
+        if tag == EXT_END {
+            return Ok(extensions);
+        }
+        let mut it_slice = [0u8; std::mem::size_of::<u64>()];
+        graph_in.read_exact(&mut it_slice)?;
+        let mut bytes = vec![0u8; u64::from_ne_bytes(it_slice) as usize];
+        graph_in.read_exact(&mut bytes)?;
+        debug!("loaded extension {}, {} bytes", tag, bytes.len());
+        extensions.insert(tag, bytes);
+    }
+} // end of load_extensions
+
+// reads neighbours of a point at a layer in format v7 (see dump_point)
+fn load_neighbours_varint(graph_in: &mut dyn Read, descr: &Description) -> Result<Vec<Neighbour>> {
+    let nb_neighbours = read_varint(graph_in)? as usize;
+    let mut neighborhood_l: Vec<Neighbour> = Vec::with_capacity(nb_neighbours);
+    let mut previous: DataId = 0;
+    for _j in 0..nb_neighbours {
+        let d_id = previous + read_varint(graph_in)? as DataId;
+        previous = d_id;
+        let mut p_id = PointId::default();
+        if descr.dumpmode == 1 {
+            let mut it_slice = [0u8; std::mem::size_of::<u8>()];
+            graph_in.read_exact(&mut it_slice)?;
+            p_id.0 = u8::from_ne_bytes(it_slice);
+            p_id.1 = read_varint(graph_in)? as u32 as i32;
+        }
+        let mut it_slice = [0u8; std::mem::size_of::<f32>()];
+        graph_in.read_exact(&mut it_slice)?;
+        let distance = f32::from_ne_bytes(it_slice);
+        neighborhood_l.push(Neighbour::new(d_id, distance, p_id));
+    }
+    Ok(neighborhood_l)
+} // end of load_neighbours_varint
+
+// Sizes and ids are dumped as u64 whatever the width of usize (so as before on 64 bit targets):
+// a dump of a 64 bit target reloads on a 32 bit one (wasm32) if its values fit.
+pub(crate) fn write_usize<W: Write>(out: &mut W, value: usize) -> Result<()> {
+    out.write_all(&(value as u64).to_ne_bytes())?;
+    Ok(())
+}
+
+pub(crate) fn read_usize(io_in: &mut dyn Read) -> Result<usize> {
+    let mut it_slice = [0u8; std::mem::size_of::<u64>()];
+    io_in.read_exact(&mut it_slice)?;
+    let value = u64::from_ne_bytes(it_slice);
+    usize::try_from(value).map_err(|_| anyhow!("dumped value {} does not fit in usize", value))
+}
+
+// LEB128 encoding: 7 bits by byte, high bit set if more bytes follow
+pub(crate) fn write_varint<W: Write>(out: &mut W, mut value: u64) -> Result<()> {
+    while value >= 0x80 {
+        out.write_all(&[(value as u8) | 0x80])?;
+        value >>= 7;
+    }
+    out.write_all(&[value as u8])?;
+    Ok(())
+}
+
+pub(crate) fn read_varint(io_in: &mut dyn Read) -> Result<u64> {
+    let mut value: u64 = 0;
+    let mut byte = [0u8; 1];
+    for shift in (0..64).step_by(7) {
+        io_in.read_exact(&mut byte)?;
+        value |= ((byte[0] & 0x7f) as u64) << shift;
+        if byte[0] & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(anyhow!("varint too long in graph file"))
+}
+
 //
 // dump and load of PointIndexation<T>
 // ===================================
@@ -1300,9 +1881,12 @@ fn load_point_graph(graph_in: &mut dyn Read, descr: &Description) -> Result<Poin
 // dump entry point
 //
 impl<T: Serialize + DeserializeOwned + Clone + Send + Sync> HnswIoT for PointIndexation<'_, T> {
-    fn dump(&self, mode: DumpMode, dumpinit: &mut DumpInit) -> Result<i32> {
-        let graphout = &mut dumpinit.graph_out;
-        let dataout = &mut dumpinit.data_out;
+    fn dump<W: Write>(
+        &self,
+        mode: DumpMode,
+        graphout: &mut BufWriter<W>,
+        dataout: &mut BufWriter<W>,
+    ) -> Result<i32> {
         // dump max_layer
         let layers = self.points_by_layer.read();
         let nb_layer = layers.len() as u8;
@@ -1312,7 +1896,7 @@ impl<T: Serialize + DeserializeOwned + Clone + Send + Sync> HnswIoT for PointInd
             let nb_point = layers[i].len();
             debug!("dumping layer {:?}, nb_point {:?}", i, nb_point);
             graphout.write_all(&MAGICLAYER.to_ne_bytes())?;
-            graphout.write_all(&nb_point.to_ne_bytes())?;
+            write_usize(graphout, nb_point)?;
             for j in 0..layers[i].len() {
                 assert_eq!(layers[i][j].get_point_id(), PointId(i as u8, j as i32));
                 dump_point(&layers[i][j], mode, graphout, dataout)?;
@@ -1352,10 +1936,12 @@ impl<T: Serialize + DeserializeOwned + Clone + Sized + Send + Sync, D: Distance<
     /// The dump method for hnsw.  
     /// - graphout is a BufWriter dedicated to the dump of the graph part of Hnsw
     /// - dataout is a bufWriter dedicated to the dump of the data stored in the Hnsw structure.
-    fn dump(&self, mode: DumpMode, dumpinit: &mut DumpInit) -> anyhow::Result<i32> {
-        //
-        let graphout = &mut dumpinit.graph_out;
-        let dataout = &mut dumpinit.data_out;
+    fn dump<W: Write>(
+        &self,
+        mode: DumpMode,
+        graphout: &mut BufWriter<W>,
+        dataout: &mut BufWriter<W>,
+    ) -> anyhow::Result<i32> {
         // dump description , then PointIndexation
         let dumpmode: u8 = match mode {
             DumpMode::Full => 1,
@@ -1364,7 +1950,7 @@ impl<T: Serialize + DeserializeOwned + Clone + Sized + Send + Sync, D: Distance<
         let datadim: usize = self.layer_indexed_points.get_data_dimension();
         let level_scale = self.layer_indexed_points.get_level_scale();
         let description = Description {
-            format_version: 3,
+            format_version: 7,
             //  value is 1 for Full 0 for Light
             dumpmode,
             max_nb_connection: self.get_max_nb_connection(),
@@ -1375,29 +1961,625 @@ impl<T: Serialize + DeserializeOwned + Clone + Sized + Send + Sync, D: Distance<
             dimension: datadim,
             distname: self.get_distance_name(),
             t_name: type_name::<T>().to_string(),
+            metadata: self.metadata.clone(),
         };
         debug!("dump  obtained typename {:?}", type_name::<T>());
         description.dump(mode, graphout)?;
         // We must dump a header for dataout.
         dataout.write_all(&MAGICDATAP.to_ne_bytes())?;
-        dataout.write_all(&datadim.to_ne_bytes())?;
+        write_usize(dataout, datadim)?;
+        //
+        self.layer_indexed_points.dump(mode, graphout, dataout)?;
         //
-        self.layer_indexed_points.dump(mode, dumpinit)?;
+        let mut extensions = Vec::<(u32, Vec<u8>)>::new();
+        let payloads = payloads_to_bytes(&self.layer_indexed_points);
+        if !payloads.is_empty() {
+            extensions.push((EXT_PAYLOADS, payloads));
+        }
+        let aliases = self.aliases.read();
+        if !aliases.is_empty() {
+            extensions.push((EXT_ALIASES, aliases.to_bytes()));
+        }
+        if !self.namespaces.is_empty() {
+            extensions.push((EXT_NAMESPACES, self.namespaces.to_bytes()));
+        }
+        extensions.push((
+            EXT_INSERTION_ORDER,
+            self.layer_indexed_points.insertion_order_to_bytes(),
+        ));
+        extensions.push((EXT_IDS, self.layer_indexed_points.ids.to_bytes()));
+        if self.graph_kind == GraphKind::Flat {
+            extensions.push((EXT_GRAPH_KIND, Vec::new()));
+        }
+        if self.get_nb_deleted() > 0 {
+            extensions.push((EXT_DELETED, self.tombstones.to_bytes()));
+        }
+        if self.read_only {
+            extensions.push((EXT_READ_ONLY, Vec::new()));
+        }
+        dump_extensions(&extensions, graphout)?;
         Ok(1)
     }
 } // end impl block for Hnsw
 
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Serialize + DeserializeOwned + Clone + Sized + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// dumps the graph in graph_out and the vectors in data_out, as in the 2 files written by
+    /// [file_dump](crate::api::AnnT::file_dump) which just opens the files and calls this. Writers are buffered here.
+    pub fn dump_to_writers<W: Write>(&self, graph_out: W, data_out: W) -> Result<()> {
+        let mut graph_out = BufWriter::new(graph_out);
+        let mut data_out = BufWriter::new(data_out);
+        self.dump(DumpMode::Full, &mut graph_out, &mut data_out)?;
+        graph_out.flush()?;
+        data_out.flush()?;
+        Ok(())
+    }
+} // end of impl Hnsw
+
+//
+// verification of a dump
+// ======================
+//
+
+/// what [HnswIo::verify] found in a dump
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpReport {
+    pub nb_point: usize,
+    /// number of points of each layer
+    pub nb_point_by_layer: Vec<usize>,
+    /// number of links (neighbours of all points at all layers)
+    pub nb_link: usize,
+    /// crc32 (as in zip or gzip) and size in bytes of the graph file
+    pub graph_crc32: u32,
+    pub graph_size: u64,
+    /// crc32 and size in bytes of the data file
+    pub data_crc32: u32,
+    pub data_size: u64,
+}
+
+// a reader computing the crc32 and size of what it reads
+struct CrcReader<R> {
+    inner: R,
+    table: [u32; 256],
+    crc: u32,
+    size: u64,
+}
+
+impl<R: Read> CrcReader<R> {
+    fn new(inner: R) -> Self {
+        CrcReader {
+            inner,
+            table: crc32_table(),
+            crc: !0,
+            size: 0,
+        }
+    }
+
+    // crc32 and size of what was read, checks that nothing is left
+    fn finish(mut self, name: &str) -> Result<(u32, u64)> {
+        if self.inner.read(&mut [0u8; 1])? != 0 {
+            return Err(anyhow!("{} : bytes after the end of the dump", name));
+        }
+        Ok((!self.crc, self.size))
+    }
+}
+
+impl<R: Read> Read for CrcReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.crc = crc32_update(&self.table, self.crc, &buf[..n]);
+        self.size += n as u64;
+        std::io::Result::Ok(n)
+    }
+}
+
+// crc32 and size in bytes of a file
+pub(crate) fn file_crc32(path: &Path) -> Result<(u32, u64)> {
+    let file = File::open(path).map_err(|e| anyhow!("cannot open {:?} : {}", path, e))?;
+    let mut crc_in = CrcReader::new(BufReader::new(file));
+    std::io::copy(&mut crc_in, &mut std::io::sink())?;
+    crc_in.finish(&path.to_string_lossy())
+}
+
+impl HnswIo {
+    /// reads a dump from end to end and checks its structure without loading it: magics, counts and ranks of points
+    /// by layer, neighbours (number, point ids, distances), correspondence and sizes of vectors, uniqueness of ids,
+    /// entry point and extensions. Returns the first error found, or the counts and crc32 of the files.  
+    /// Neither vectors nor links are kept: memory is bounded by a bitmap of the ids.
+    pub fn verify(&self) -> Result<DumpReport> {
+        let open = |suffix: &str| {
+            let path = self.dir.join(format!("{}{}", self.basename, suffix));
+            File::open(&path).map_err(|e| anyhow!("verify : cannot open {:?} : {}", path, e))
+        };
+        let mut graph_in = CrcReader::new(BufReader::new(open(".hnsw.graph")?));
+        let mut data_in = CrcReader::new(BufReader::new(open(".hnsw.data")?));
+        let descr = load_description(&mut graph_in)?;
+        let read_u32 = |io_in: &mut dyn Read| -> Result<u32> {
+            let mut it_slice = [0u8; std::mem::size_of::<u32>()];
+            io_in.read_exact(&mut it_slice)?;
+            Ok(u32::from_ne_bytes(it_slice))
+        };
+        let read_u64 = |io_in: &mut dyn Read| -> Result<u64> {
+            let mut it_slice = [0u8; std::mem::size_of::<u64>()];
+            io_in.read_exact(&mut it_slice)?;
+            Ok(u64::from_ne_bytes(it_slice))
+        };
+        // data header
+        if read_u32(&mut data_in)? != MAGICDATAP {
+            return Err(anyhow!("bad magic at beginning of data file"));
+        }
+        let dimension = read_u64(&mut data_in)? as usize;
+        if dimension != descr.dimension {
+            return Err(anyhow!(
+                "dimension {} in data file, {} in description",
+                dimension,
+                descr.dimension
+            ));
+        }
+        // layers
+        let mut nb_layer = [0u8; 1];
+        graph_in.read_exact(&mut nb_layer)?;
+        let nb_layer = nb_layer[0];
+        if nb_layer > NB_LAYER_MAX {
+            return Err(anyhow!("inconsistent number of layers {}", nb_layer));
+        }
+        let mut ids = RoaringTreemap::new();
+        let mut nb_point_by_layer = Vec::with_capacity(nb_layer as usize);
+        // neighbour ranks can only be checked once all layers are read
+        let mut max_rank_by_layer = vec![-1i64; NB_LAYER_MAX as usize];
+        let mut nb_link = 0;
+        let mut vector_len = None;
+        for l in 0..nb_layer as usize {
+            if read_u32(&mut graph_in)? != MAGICLAYER {
+                return Err(anyhow!("bad magic at beginning of layer {}", l));
+            }
+            let nb_point = read_u64(&mut graph_in)? as usize;
+            for r in 0..nb_point {
+                let (origin_id, p_id, neighbourhood) = load_point_graph(&mut graph_in, &descr)
+                    .map_err(|e| anyhow!("layer {} point {} : {}", l, r, e))?;
+                if descr.dumpmode == 1 && p_id != PointId(l as u8, r as i32) {
+                    return Err(anyhow!("point {:?} stored at layer {} rank {}", p_id, l, r));
+                }
+                if !ids.insert(origin_id as u64) {
+                    return Err(anyhow!("id {} found twice", origin_id));
+                }
+                for (l_n, neighbours) in neighbourhood.iter().enumerate() {
+                    let max_nb = match l_n {
+                        0 => 2 * descr.max_nb_connection as usize,
+                        _ => descr.max_nb_connection as usize,
+                    };
+                    if neighbours.len() > max_nb {
+                        return Err(anyhow!(
+                            "point {} has {} neighbours at layer {}",
+                            origin_id,
+                            neighbours.len(),
+                            l_n
+                        ));
+                    }
+                    for n in neighbours {
+                        if n.distance.is_nan() || n.p_id.0 >= nb_layer || n.p_id.1 < 0 {
+                            return Err(anyhow!("point {} : invalid neighbour {:?}", origin_id, n));
+                        }
+                        let max_rank = &mut max_rank_by_layer[n.p_id.0 as usize];
+                        *max_rank = (*max_rank).max(n.p_id.1 as i64);
+                    }
+                    nb_link += neighbours.len();
+                }
+                // vector of point, in the same order in data file
+                if read_u32(&mut data_in)? != MAGICDATAP
+                    || read_u64(&mut data_in)? != origin_id as u64
+                {
+                    return Err(anyhow!(
+                        "vector of point {} not found in data file",
+                        origin_id
+                    ));
+                }
+                let len = read_u64(&mut data_in)?;
+                if *vector_len.get_or_insert(len) != len
+                    || (dimension > 0 && len % dimension as u64 != 0)
+                {
+                    return Err(anyhow!("vector of point {} has {} bytes", origin_id, len));
+                }
+                if std::io::copy(&mut (&mut data_in).take(len), &mut std::io::sink())? != len {
+                    return Err(anyhow!("data file ends in vector of point {}", origin_id));
+                }
+            }
+            nb_point_by_layer.push(nb_point);
+        }
+        let nb_point: usize = nb_point_by_layer.iter().sum();
+        if nb_point != descr.nb_point {
+            return Err(anyhow!(
+                "{} points in layers, {} in description",
+                nb_point,
+                descr.nb_point
+            ));
+        }
+        for (l, nb) in nb_point_by_layer.iter().enumerate() {
+            if max_rank_by_layer[l] >= *nb as i64 {
+                return Err(anyhow!(
+                    "neighbour of rank {} in layer {} of {} points",
+                    max_rank_by_layer[l],
+                    l,
+                    nb
+                ));
+            }
+        }
+        // entry point
+        let entry_id = read_u64(&mut graph_in)?;
+        if !ids.contains(entry_id) {
+            return Err(anyhow!("entry point {} is not a dumped point", entry_id));
+        }
+        if descr.dumpmode == 1 {
+            let mut entry = [0u8; 5];
+            graph_in.read_exact(&mut entry)?;
+            let rank = i32::from_ne_bytes(entry[1..].try_into().unwrap());
+            let layer_size = nb_point_by_layer
+                .get(entry[0] as usize)
+                .copied()
+                .unwrap_or(0);
+            if rank < 0 || rank as usize >= layer_size {
+                return Err(anyhow!("entry point at layer {} rank {}", entry[0], rank));
+            }
+        }
+        // extensions are skipped
+        if descr.format_version >= 7 {
+            while read_u32(&mut graph_in)? != EXT_END {
+                let len = read_u64(&mut graph_in)?;
+                if std::io::copy(&mut (&mut graph_in).take(len), &mut std::io::sink())? != len {
+                    return Err(anyhow!("graph file ends in an extension"));
+                }
+            }
+        }
+        let (graph_crc32, graph_size) = graph_in.finish("graph file")?;
+        let (data_crc32, data_size) = data_in.finish("data file")?;
+        info!(
+            "verify : dump {} is valid, {} points, {} links",
+            self.basename, nb_point, nb_link
+        );
+        Ok(DumpReport {
+            nb_point,
+            nb_point_by_layer,
+            nb_link,
+            graph_crc32,
+            graph_size,
+            data_crc32,
+            data_size,
+        })
+    } // end of verify
+} // end of impl HnswIo
+
+//
+// flat export in NumPy format
+// ===========================
+//
+
+/// element types that can be written in a .npy array
+pub trait NpyType: Copy {
+    /// numpy type code without byte order (f4, u8 ...)
+    const CODE: &'static str;
+}
+
+macro_rules! npy_type {
+    ($t:ty, $code:expr) => {
+        impl NpyType for $t {
+            const CODE: &'static str = $code;
+        }
+    };
+}
+
+npy_type!(f32, "f4");
+npy_type!(f64, "f8");
+npy_type!(u8, "u1");
+npy_type!(i8, "i1");
+npy_type!(u16, "u2");
+npy_type!(i16, "i2");
+npy_type!(u32, "u4");
+npy_type!(i32, "i4");
+npy_type!(u64, "u8");
+npy_type!(i64, "i8");
+
+// a .npy array (format 1.0) in native byte order
+fn npy_bytes<V: NpyType>(values: &[V], shape: &[usize]) -> Vec<u8> {
+    let order = if std::mem::size_of::<V>() == 1 {
+        '|'
+    } else if cfg!(target_endian = "little") {
+        '<'
+    } else {
+        '>'
+    };
+    let shape = match shape {
+        [n] => format!("({},)", n),
+        _ => {
+            let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+            format!("({})", dims.join(", "))
+        }
+    };
+    let mut header = format!(
+        "{{'descr': '{}{}', 'fortran_order': False, 'shape': {}, }}",
+        order,
+        V::CODE,
+        shape
+    );
+    // magic, version and header length take 10 bytes, the header ends by a newline and data are aligned on 64 bytes
+    let padding = 63 - (10 + header.len()) % 64;
+    header.extend(std::iter::repeat_n(' ', padding));
+    header.push('\n');
+    let data = unsafe {
+        std::slice::from_raw_parts(values.as_ptr() as *const u8, std::mem::size_of_val(values))
+    };
+    let mut bytes = Vec::with_capacity(10 + header.len() + data.len());
+    bytes.extend_from_slice(b"\x93NUMPY\x01\x00");
+    bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+    bytes.extend_from_slice(header.as_bytes());
+    bytes.extend_from_slice(data);
+    bytes
+} // end of npy_bytes
+
+fn crc32_table() -> [u32; 256] {
+    std::array::from_fn(|n| {
+        let mut c = n as u32;
+        for _ in 0..8 {
+            c = if c & 1 == 1 {
+                (c >> 1) ^ 0xedb8_8320
+            } else {
+                c >> 1
+            };
+        }
+        c
+    })
+}
+
+// crc (before final inversion) updated with bytes
+fn crc32_update(table: &[u32; 256], crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, &b| {
+        table[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+pub(crate) fn crc32(bytes: &[u8]) -> u32 {
+    !crc32_update(&crc32_table(), !0, bytes)
+}
+
+// writes arrays as stored (not compressed) entries of a zip archive, which numpy.load reads as a .npz
+fn write_npz<W: Write>(out: &mut W, arrays: &[(&str, Vec<u8>)]) -> Result<()> {
+    let mut central = Vec::<u8>::new();
+    let mut offset: u64 = 0;
+    for (name, bytes) in arrays {
+        let name = format!("{}.npy", name);
+        let size = u32::try_from(bytes.len())
+            .map_err(|_| anyhow!("export_npz : {} is larger than 4Gb", name))?;
+        let offset32 =
+            u32::try_from(offset).map_err(|_| anyhow!("export_npz : archive larger than 4Gb"))?;
+        // version, flags, method, time, date (1980-01-01), crc, sizes and name length,
+        // common to the local header and the central directory
+        let mut common = Vec::<u8>::with_capacity(24);
+        for field in [20u16, 0, 0, 0, 0x21] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [crc32(bytes), size, size] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        //
+        out.write_all(&0x0403_4b50u32.to_le_bytes())?;
+        out.write_all(&common)?;
+        out.write_all(&0u16.to_le_bytes())?;
+        out.write_all(name.as_bytes())?;
+        out.write_all(bytes)?;
+        //
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&common);
+        // extra, comment, disk, internal attributes, external attributes
+        central.extend_from_slice(&[0u8; 12]);
+        central.extend_from_slice(&offset32.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+        offset += (30 + name.len() + bytes.len()) as u64;
+    }
+    let offset32 =
+        u32::try_from(offset).map_err(|_| anyhow!("export_npz : archive larger than 4Gb"))?;
+    out.write_all(&central)?;
+    out.write_all(&0x0605_4b50u32.to_le_bytes())?;
+    out.write_all(&[0u8; 4])?;
+    out.write_all(&(arrays.len() as u16).to_le_bytes())?;
+    out.write_all(&(arrays.len() as u16).to_le_bytes())?;
+    out.write_all(&(central.len() as u32).to_le_bytes())?;
+    out.write_all(&offset32.to_le_bytes())?;
+    out.write_all(&0u16.to_le_bytes())?;
+    Ok(())
+} // end of write_npz
+
+/// writes vectors, ids and layer 0 links of a Hnsw in a NumPy archive prefix.npz (numpy.load in Python, NPZ.jl in Julia),
+/// to analyze a graph without rust. Row i of each array is the i-th point of the structure, arrays are :
+/// - vectors : (nb_point, dimension)
+/// - ids : origin ids (u8)
+/// - levels : layer of each point (u1)
+/// - indptr, indices, distances : layer 0 links in CSR form, the neighbours of row i are the rows
+///   indices\[indptr\[i\]..indptr\[i+1\]\] at distances distances\[indptr\[i\]..indptr\[i+1\]\].
+///
+/// Arrays are not compressed and each must be less than 4Gb. Returns the path of the file.
+pub fn export_npz<T, D>(hnsw: &Hnsw<T, D>, prefix: &Path) -> Result<PathBuf>
+where
+    T: NpyType + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    let points: Vec<Arc<Point<T>>> = hnsw.get_point_indexation().par_iter().collect();
+    let rows: HashMap<PointId, u64> = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.get_point_id(), i as u64))
+        .collect();
+    let dimension = points.first().map_or(0, |p| p.get_v().len());
+    let mut vectors = Vec::<T>::with_capacity(points.len() * dimension);
+    let mut ids = Vec::<u64>::with_capacity(points.len());
+    let mut levels = Vec::<u8>::with_capacity(points.len());
+    let mut indptr = Vec::<u64>::with_capacity(points.len() + 1);
+    let mut indices = Vec::<u64>::new();
+    let mut distances = Vec::<f32>::new();
+    indptr.push(0);
+    for p in &points {
+        if p.get_v().len() != dimension {
+            return Err(anyhow!(
+                "export_npz : point {} has dimension {}, expected {}",
+                p.get_origin_id(),
+                p.get_v().len(),
+                dimension
+            ));
+        }
+        vectors.extend_from_slice(p.get_v());
+        ids.push(p.get_origin_id() as u64);
+        levels.push(p.get_point_id().0);
+        for n in &p.neighbours.read()[0] {
+            indices.push(rows[&n.point_ref.get_point_id()]);
+            distances.push(n.dist_to_ref);
+        }
+        indptr.push(indices.len() as u64);
+    }
+    let arrays = [
+        ("vectors", npy_bytes(&vectors, &[points.len(), dimension])),
+        ("ids", npy_bytes(&ids, &[ids.len()])),
+        ("levels", npy_bytes(&levels, &[levels.len()])),
+        ("indptr", npy_bytes(&indptr, &[indptr.len()])),
+        ("indices", npy_bytes(&indices, &[indices.len()])),
+        ("distances", npy_bytes(&distances, &[distances.len()])),
+    ];
+    let mut path = prefix.as_os_str().to_owned();
+    path.push(".npz");
+    let path = PathBuf::from(path);
+    let mut out = BufWriter::new(File::create(&path)?);
+    write_npz(&mut out, &arrays)?;
+    out.flush()?;
+    info!(
+        "export_npz : {} points and {} links written in {:?}",
+        points.len(),
+        indices.len(),
+        path
+    );
+    Ok(path)
+} // end of export_npz
+
+/// writes points, payloads and links of a Hnsw in a new SQLite database at path (feature *sqlite*),
+/// so that a snapshot can be inspected with SQL. Tables are :
+/// - points (row, d_id, layer, layer_rank, deleted, tags, namespace, lat, lon, vector) : vector is a blob
+///   of the values in native byte order, tags the bitset, lat and lon are NULL without position.
+/// - tags (row, tag) : one line by tag set on a point, for counts per tag.
+/// - attributes (row, attr, value) : attributes set.
+/// - links (layer, src, dst, distance) : links of all layers from row src to row dst, for degree joins.
+/// - metadata (key, value) : metadata of the structure.
+///
+/// Rows are numbered as in [export_npz]. Returns an error if a file exists at path.
+#[cfg(feature = "sqlite")]
+pub fn export_sqlite<T, D>(hnsw: &Hnsw<T, D>, path: &Path) -> Result<()>
+where
+    T: NpyType + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    use crate::payload::{NB_ATTRIBUTES, Tags};
+    use rusqlite::params;
+    //
+    if path.exists() {
+        return Err(anyhow!("export_sqlite : {:?} exists", path));
+    }
+    let points: Vec<Arc<Point<T>>> = hnsw.get_point_indexation().par_iter().collect();
+    let rows: HashMap<PointId, i64> = points
+        .iter()
+        .enumerate()
+        .map(|(i, p)| (p.get_point_id(), i as i64))
+        .collect();
+    let mut connection = rusqlite::Connection::open(path)?;
+    connection.execute_batch(
+        "CREATE TABLE points (row INTEGER PRIMARY KEY, d_id INTEGER NOT NULL, layer INTEGER NOT NULL,
+            layer_rank INTEGER NOT NULL, deleted INTEGER NOT NULL, tags INTEGER NOT NULL,
+            namespace INTEGER NOT NULL, lat REAL, lon REAL, vector BLOB NOT NULL);
+        CREATE TABLE tags (row INTEGER NOT NULL, tag INTEGER NOT NULL);
+        CREATE TABLE attributes (row INTEGER NOT NULL, attr INTEGER NOT NULL, value REAL NOT NULL);
+        CREATE TABLE links (layer INTEGER NOT NULL, src INTEGER NOT NULL, dst INTEGER NOT NULL,
+            distance REAL NOT NULL);
+        CREATE TABLE metadata (key TEXT PRIMARY KEY, value TEXT NOT NULL);",
+    )?;
+    // one transaction, inserts are then not synced one by one
+    let transaction = connection.transaction()?;
+    let mut nb_link = 0;
+    {
+        let mut insert_point = transaction.prepare(
+            "INSERT INTO points (row, d_id, layer, layer_rank, deleted, tags, namespace, lat, lon, vector)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+        )?;
+        let mut insert_tag = transaction.prepare("INSERT INTO tags (row, tag) VALUES (?1, ?2)")?;
+        let mut insert_attribute =
+            transaction.prepare("INSERT INTO attributes (row, attr, value) VALUES (?1, ?2, ?3)")?;
+        let mut insert_link = transaction
+            .prepare("INSERT INTO links (layer, src, dst, distance) VALUES (?1, ?2, ?3, ?4)")?;
+        for (row, p) in points.iter().enumerate() {
+            let row = row as i64;
+            let p_id = p.get_point_id();
+            let payload = p.get_payload();
+            let v = p.get_v();
+            let vector = unsafe {
+                std::slice::from_raw_parts(v.as_ptr() as *const u8, std::mem::size_of_val(v))
+            };
+            let geo = payload.get_geo();
+            insert_point.execute(params![
+                row,
+                p.get_origin_id() as i64,
+                p_id.0,
+                p_id.1,
+                hnsw.is_deleted(p),
+                payload.get_tags(),
+                payload.get_namespace(),
+                geo.map(|g| g.0),
+                geo.map(|g| g.1),
+                vector,
+            ])?;
+            for tag in (0..Tags::BITS).filter(|t| payload.get_tags() & (1 << t) != 0) {
+                insert_tag.execute(params![row, tag])?;
+            }
+            for attr in 0..NB_ATTRIBUTES {
+                let value = payload.get_attribute(attr);
+                if !value.is_nan() {
+                    insert_attribute.execute(params![row, attr as i64, value])?;
+                }
+            }
+            let neighbours = p.neighbours.read();
+            for (layer, neighbours) in neighbours.iter().enumerate().take(p_id.0 as usize + 1) {
+                for n in neighbours {
+                    let dst = rows[&n.point_ref.get_point_id()];
+                    insert_link.execute(params![layer as i64, row, dst, n.dist_to_ref])?;
+                    nb_link += 1;
+                }
+            }
+        }
+        let mut insert_metadata =
+            transaction.prepare("INSERT INTO metadata (key, value) VALUES (?1, ?2)")?;
+        for (key, value) in hnsw.get_metadata_map() {
+            insert_metadata.execute(params![key, value])?;
+        }
+    }
+    transaction.commit()?;
+    info!(
+        "export_sqlite : {} points and {} links written in {:?}",
+        points.len(),
+        nb_link,
+        path
+    );
+    Ok(())
+} // end of export_sqlite
+
 //===============================================================================================================
 
 #[cfg(test)]
-
 mod tests {
     use super::*;
 
     pub use crate::api::AnnT;
+    use crate::searchparams::SearchParams;
     use anndists::dist;
     use log::error;
 
+    use rand::SeedableRng;
     use rand::distr::{Distribution, Uniform};
 
     fn log_init_test() {
@@ -1458,6 +2640,45 @@ mod tests {
         check_graph_equality(&hnsw_loaded, &hnsw);
     } // end of test_dump_reload
 
+    #[test]
+    fn test_partial_load() {
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..1000)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let hnsw = Hnsw::<f32, dist::DistL1>::new(10, 1000, 16, 25, dist::DistL1 {});
+        for (i, d) in data.iter().enumerate() {
+            hnsw.insert((d, i));
+        }
+        let fname = "partialload";
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), fname).unwrap();
+        // even ids
+        let ids: RoaringBitmap = (0..1000u32).filter(|i| i % 2 == 0).collect();
+        let mut reloader = HnswIo::new(directory.path(), fname);
+        let subset: Hnsw<f32, DistL1> = reloader.load_subset(&ids).unwrap();
+        assert_eq!(subset.get_nb_point(), 500);
+        for p in subset.get_point_indexation() {
+            assert_eq!(p.get_origin_id() % 2, 0);
+            assert_eq!(p.get_v(), data[p.get_origin_id()].as_slice());
+            for neighbours in p.get_neighborhood_id() {
+                assert!(neighbours.iter().all(|n| n.d_id % 2 == 0));
+            }
+        }
+        let res = subset.search(&data[42], 1, 32);
+        assert_eq!(res[0].d_id, 42);
+        // top of hierarchy
+        let mut reloader = HnswIo::new(directory.path(), fname);
+        let top: Hnsw<f32, DistL1> = reloader.load_layers(1).unwrap();
+        let nb_top = (1..NB_LAYER_MAX as usize)
+            .map(|l| hnsw.get_point_indexation().get_layer_nb_point(l))
+            .sum::<usize>();
+        assert_eq!(top.get_nb_point(), nb_top);
+        assert_eq!(top.get_point_indexation().get_layer_nb_point(0), 0);
+    } // end of test_partial_load
+
     #[test]
     fn test_dump_reload_myfn() {
         println!("\n\n test_dump_reload_myfn");
@@ -1665,6 +2886,234 @@ mod tests {
         }
     } // end of reload_with_mmap
 
+    #[test]
+    fn test_load_checked_metadata() {
+        println!("\n\n test_load_checked_metadata");
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let nbcolumn = 200;
+        let nbrow = 10;
+        let data: Vec<Vec<f32>> = (0..nbcolumn)
+            .map(|_| (0..nbrow).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let mut hnsw = Hnsw::<f32, dist::DistL1>::new(10, nbcolumn, 16, 25, dist::DistL1 {});
+        hnsw.set_metadata("model_version", "v1");
+        hnsw.set_metadata("embedding", "test-embedding");
+        for (i, d) in data.iter().enumerate() {
+            hnsw.insert((d, i));
+        }
+        let fname = "metadatatest";
+        let directory = tempfile::tempdir().unwrap();
+        let _res = hnsw.file_dump(directory.path(), fname).unwrap();
+        // description gives access to metadata
+        let mut reloader = HnswIo::new(directory.path(), fname);
+        let init = reloader.init().unwrap();
+        assert_eq!(init.descr.get_metadata(), hnsw.get_metadata_map());
+        drop(init);
+        // a mismatch must fail before loading
+        assert!(
+            reloader
+                .load_checked::<f32, DistL1>(&[("model_version", "v2")])
+                .is_err()
+        );
+        assert!(
+            reloader
+                .load_checked::<f32, DistL1>(&[("unknown_key", "v1")])
+                .is_err()
+        );
+        // matching metadata
+        let hnsw_loaded: Hnsw<f32, DistL1> =
+            reloader.load_checked(&[("model_version", "v1")]).unwrap();
+        assert_eq!(hnsw_loaded.get_metadata("model_version"), Some("v1"));
+        assert_eq!(
+            hnsw_loaded.get_metadata("embedding"),
+            Some("test-embedding")
+        );
+        check_graph_equality(&hnsw_loaded, &hnsw);
+    } // end of test_load_checked_metadata
+
+    #[test]
+    fn test_dump_payloads() {
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..300)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let hnsw = Hnsw::<f32, dist::DistL1>::new(10, 300, 16, 25, dist::DistL1 {});
+        // one point out of 3 without payload
+        let payload = |i: usize| {
+            Payload::new()
+                .with_tags(1 << (i % 4))
+                .with_attribute(1, i as f64)
+                .with_geo(45. + i as f64 / 100., 2.)
+                .with_namespace(i as u32 % 2)
+        };
+        for (i, d) in data.iter().enumerate() {
+            match i % 3 {
+                0 => hnsw.insert((d, i)),
+                _ => hnsw.insert_with_payload((d, i), payload(i)),
+            }
+        }
+        let fname = "payloads";
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), fname).unwrap();
+        let mut reloader = HnswIo::new(directory.path(), fname);
+        let hnsw_loaded: Hnsw<f32, DistL1> = reloader.load_hnsw::<f32, DistL1>().unwrap();
+        check_graph_equality(&hnsw_loaded, &hnsw);
+        let indexation = hnsw_loaded.get_point_indexation();
+        for point in indexation.into_iter() {
+            let i = point.get_origin_id();
+            let loaded = indexation.get_payload(&point.get_point_id());
+            if i % 3 == 0 {
+                assert!(loaded.is_empty());
+            } else {
+                let expected = payload(i);
+                assert_eq!(loaded.get_tags(), expected.get_tags());
+                assert!(loaded.get_attribute(0).is_nan());
+                assert_eq!(loaded.get_attribute(1), i as f64);
+                assert_eq!(loaded.get_geo(), expected.get_geo());
+                assert_eq!(loaded.get_namespace(), expected.get_namespace());
+            }
+        }
+        // filters of a search still apply
+        let params = SearchParams::new().require_tags(1 << 2);
+        let res = hnsw_loaded.search_with_params(&data[0], 10, 64, &params);
+        assert!(!res.is_empty());
+        assert!(res.iter().all(|n| n.d_id % 4 == 2 && n.d_id % 3 != 0));
+    } // end of test_dump_payloads
+
+    #[test]
+    fn test_load_owned() {
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..300)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let hnsw = Hnsw::<f32, dist::DistL1>::new(10, 300, 16, 25, dist::DistL1 {});
+        for (i, d) in data.iter().enumerate() {
+            hnsw.insert((d, i));
+        }
+        let fname = "ownedload";
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), fname).unwrap();
+        // an application structure without lifetime
+        struct Index {
+            hnsw: OwnedHnsw<f32, DistL1>,
+        }
+        let options = ReloadOptions::default().set_mmap(true);
+        let reloader = HnswIo::new_with_options(directory.path(), fname, options);
+        let index = Index {
+            hnsw: reloader.load_owned().unwrap(),
+        };
+        drop(reloader);
+        check_graph_equality(&index.hnsw, &hnsw);
+        // same graph, the same points are found (a point can be missed by the greedy search)
+        let found = |hnsw: &Hnsw<f32, DistL1>, data: &[Vec<f32>]| -> Vec<bool> {
+            data.iter()
+                .map(|v| hnsw.search(v, 1, 32)[0].distance == 0.)
+                .collect()
+        };
+        let expected = found(&hnsw, &data);
+        let res = std::thread::spawn(move || found(&index.hnsw, &data))
+            .join()
+            .unwrap();
+        assert_eq!(res, expected);
+    } // end of test_load_owned
+
+    #[test]
+    fn test_dump_to_writers() {
+        log_init_test();
+        // neighbours at equal distances can be reloaded in another order.
+        // Data and levels are seeded so that the graph is the same at each run.
+        let mut rng = rand::rngs::StdRng::seed_from_u64(947);
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..300)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let mut hnsw = Hnsw::<f32, dist::DistL1>::new(10, 300, 16, 25, dist::DistL1 {});
+        hnsw.set_level_seed(947);
+        for (i, d) in data.iter().enumerate() {
+            hnsw.insert((d, i));
+        }
+        // dump in memory
+        let (mut graph, mut vectors) = (Vec::new(), Vec::new());
+        hnsw.dump_to_writers(&mut graph, &mut vectors).unwrap();
+        let reloaded: OwnedHnsw<f32, DistL1> =
+            HnswIo::load_from_readers(graph.as_slice(), vectors.as_slice()).unwrap();
+        check_graph_equality(&reloaded, &hnsw);
+        // the file dump writes the same bytes
+        let directory = tempfile::tempdir().unwrap();
+        let fname = hnsw.file_dump(directory.path(), "writers").unwrap();
+        let graph_file = std::fs::read(directory.path().join(format!("{}.hnsw.graph", fname)));
+        let data_file = std::fs::read(directory.path().join(format!("{}.hnsw.data", fname)));
+        assert_eq!(graph_file.unwrap(), graph);
+        assert_eq!(data_file.unwrap(), vectors);
+    } // end of test_dump_to_writers
+
+    #[test]
+    fn test_verify() {
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..500)
+            .map(|_| (0..10).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, dist::DistL1>::new(10, 500, 16, 25, dist::DistL1 {});
+        hnsw.parallel_insert(&data_with_id);
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "verified").unwrap();
+        let report = HnswIo::new(directory.path(), "verified").verify().unwrap();
+        assert_eq!(report.nb_point, 500);
+        assert_eq!(report.nb_point_by_layer.iter().sum::<usize>(), 500);
+        let graph_path = directory.path().join("verified.hnsw.graph");
+        let data_path = directory.path().join("verified.hnsw.data");
+        let graph = std::fs::read(&graph_path).unwrap();
+        assert_eq!(report.graph_crc32, crc32(&graph));
+        assert_eq!(report.graph_size, graph.len() as u64);
+        // a truncated data file, then a vector of another point
+        let vectors = std::fs::read(&data_path).unwrap();
+        std::fs::write(&data_path, &vectors[..vectors.len() - 3]).unwrap();
+        assert!(HnswIo::new(directory.path(), "verified").verify().is_err());
+        let mut corrupted = vectors.clone();
+        corrupted[16] ^= 1;
+        std::fs::write(&data_path, &corrupted).unwrap();
+        assert!(HnswIo::new(directory.path(), "verified").verify().is_err());
+        // an id inserted twice
+        hnsw.insert((&data[0], 7));
+        hnsw.file_dump(directory.path(), "twice").unwrap();
+        let res = HnswIo::new(directory.path(), "twice").verify();
+        assert!(res.unwrap_err().to_string().contains("twice"));
+    } // end of test_verify
+
+    #[test]
+    fn test_varint() {
+        let values = [
+            0u64,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u32::MAX as u64,
+            u64::MAX,
+        ];
+        let mut buf = Vec::<u8>::new();
+        for v in values {
+            write_varint(&mut buf, v).unwrap();
+        }
+        // 1 byte up to 127, 2 bytes up to 16383, 10 bytes for u64::MAX
+        assert_eq!(buf.len(), 1 + 1 + 1 + 2 + 2 + 2 + 3 + 5 + 10);
+        let mut reader = buf.as_slice();
+        for v in values {
+            assert_eq!(read_varint(&mut reader).unwrap(), v);
+        }
+    } // end of test_varint
+
     #[test]
     fn test_bincode() {
         let mut rng = rand::rng();
@@ -1700,4 +3149,176 @@ mod tests {
         assert!(hnsw_loaded_res.is_err());
         Ok(())
     }
+
+    // entries of a stored zip archive, name and data
+    fn read_stored_zip(bytes: &[u8]) -> Vec<(String, &[u8])> {
+        let mut entries = Vec::new();
+        let mut pos = 0;
+        let u32_at = |pos: usize| u32::from_le_bytes(bytes[pos..pos + 4].try_into().unwrap());
+        let u16_at = |pos: usize| u16::from_le_bytes(bytes[pos..pos + 2].try_into().unwrap());
+        while u32_at(pos) == 0x0403_4b50 {
+            let size = u32_at(pos + 18) as usize;
+            let name_len = u16_at(pos + 26) as usize;
+            let name = String::from_utf8(bytes[pos + 30..pos + 30 + name_len].to_vec()).unwrap();
+            let data = &bytes[pos + 30 + name_len..pos + 30 + name_len + size];
+            assert_eq!(crc32(data), u32_at(pos + 14));
+            entries.push((name, data));
+            pos += 30 + name_len + size;
+        }
+        // central directory then end record
+        assert_eq!(u32_at(pos), 0x0201_4b50);
+        entries
+    }
+
+    // header and data of a .npy array
+    fn read_npy(bytes: &[u8]) -> (String, &[u8]) {
+        assert_eq!(&bytes[..8], b"\x93NUMPY\x01\x00");
+        let header_len = u16::from_le_bytes([bytes[8], bytes[9]]) as usize;
+        assert_eq!((10 + header_len) % 64, 0);
+        let header = String::from_utf8(bytes[10..10 + header_len].to_vec()).unwrap();
+        (header, &bytes[10 + header_len..])
+    }
+
+    #[test]
+    fn test_export_npz() {
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..200)
+            .map(|_| (0..6).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(1000..).collect();
+        let hnsw = Hnsw::<f32, dist::DistL2>::new(8, 200, 16, 100, dist::DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        //
+        let directory = tempfile::tempdir().unwrap();
+        let path = export_npz(&hnsw, &directory.path().join("graph")).unwrap();
+        assert!(path.ends_with("graph.npz"));
+        let bytes = std::fs::read(&path).unwrap();
+        let entries: HashMap<String, &[u8]> = read_stored_zip(&bytes).into_iter().collect();
+        assert_eq!(entries.len(), 6);
+        let (header, vectors) = read_npy(entries["vectors.npy"]);
+        assert!(header.contains("'descr': '<f4'") && header.contains("'shape': (200, 6)"));
+        let (header, ids) = read_npy(entries["ids.npy"]);
+        assert!(header.contains("'shape': (200,)"));
+        let (_, indptr) = read_npy(entries["indptr.npy"]);
+        let (_, indices) = read_npy(entries["indices.npy"]);
+        let u64_at =
+            |b: &[u8], i: usize| u64::from_le_bytes(b[8 * i..8 * i + 8].try_into().unwrap());
+        let f32_at =
+            |b: &[u8], i: usize| f32::from_le_bytes(b[4 * i..4 * i + 4].try_into().unwrap());
+        assert_eq!(u64_at(indptr, 200) as usize, indices.len() / 8);
+        // rows are coherent with vectors and links of the structure
+        for row in (0..200).step_by(17) {
+            let id = u64_at(ids, row) as usize;
+            for (j, x) in data[id - 1000].iter().enumerate() {
+                assert_eq!(f32_at(vectors, 6 * row + j), *x);
+            }
+            let neighbours: Vec<usize> = (u64_at(indptr, row)..u64_at(indptr, row + 1))
+                .map(|k| u64_at(ids, u64_at(indices, k as usize) as usize) as usize)
+                .collect();
+            let point = hnsw
+                .get_point_indexation()
+                .into_iter()
+                .find(|p| p.get_origin_id() == id)
+                .unwrap();
+            let expected: Vec<usize> = point.get_neighborhood_id()[0]
+                .iter()
+                .map(|n| n.d_id)
+                .collect();
+            assert_eq!(neighbours, expected);
+        }
+    } // end of test_export_npz
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_export_sqlite() {
+        log_init_test();
+        let mut rng = rand::rng();
+        let unif = Uniform::<f32>::new(0., 1.).unwrap();
+        let data: Vec<Vec<f32>> = (0..300)
+            .map(|_| (0..6).map(|_| unif.sample(&mut rng)).collect())
+            .collect();
+        let mut hnsw = Hnsw::<f32, dist::DistL2>::new(8, 300, 16, 100, dist::DistL2 {});
+        hnsw.set_metadata("model", "test");
+        for (i, v) in data.iter().enumerate() {
+            let payload = Payload::new()
+                .with_tags(1 << (i % 3))
+                .with_attribute(0, i as f64);
+            hnsw.insert_with_payload((v.as_slice(), i), payload);
+        }
+        hnsw.remove_batch(&[7]);
+        //
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("snapshot.sqlite");
+        export_sqlite(&hnsw, &path).unwrap();
+        assert!(export_sqlite(&hnsw, &path).is_err());
+        let connection = rusqlite::Connection::open(&path).unwrap();
+        let count = |sql: &str| -> i64 { connection.query_row(sql, [], |r| r.get(0)).unwrap() };
+        assert_eq!(count("SELECT COUNT(*) FROM points"), 300);
+        assert_eq!(count("SELECT COUNT(*) FROM points WHERE deleted"), 1);
+        assert_eq!(count("SELECT d_id FROM points WHERE deleted"), 7);
+        assert_eq!(count("SELECT COUNT(*) FROM tags WHERE tag = 1"), 100);
+        let max: f64 = connection
+            .query_row(
+                "SELECT MAX(value) FROM attributes WHERE attr = 0",
+                [],
+                |r| r.get(0),
+            )
+            .unwrap();
+        assert_eq!(max, 299.);
+        assert_eq!(
+            count("SELECT COUNT(*) FROM points WHERE lat IS NOT NULL"),
+            0
+        );
+        let model: String = connection
+            .query_row("SELECT value FROM metadata WHERE key = 'model'", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        assert_eq!(model, "test");
+        // degrees of layer 0 by a join are those of the structure
+        let mut degrees = connection
+            .prepare(
+                "SELECT p.d_id, COUNT(l.dst) FROM points p LEFT JOIN links l
+                    ON l.src = p.row AND l.layer = 0 GROUP BY p.row",
+            )
+            .unwrap();
+        let degrees: HashMap<usize, usize> = degrees
+            .query_map([], |r| {
+                Ok((r.get::<_, i64>(0)? as usize, r.get::<_, i64>(1)? as usize))
+            })
+            .unwrap()
+            .map(|r| r.unwrap())
+            .collect();
+        for point in hnsw.get_point_indexation() {
+            let degree = point.get_neighborhood_id()[0].len();
+            assert_eq!(degrees[&point.get_origin_id()], degree);
+        }
+        // vectors are kept
+        let vector: Vec<u8> = connection
+            .query_row("SELECT vector FROM points WHERE d_id = 42", [], |r| {
+                r.get(0)
+            })
+            .unwrap();
+        let vector: Vec<f32> = vector
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+        assert_eq!(vector, data[42]);
+    } // end of test_export_sqlite
+
+    #[test]
+    fn test_dumped_usize() {
+        // sizes and ids take 8 bytes on all targets
+        let mut bytes = Vec::new();
+        write_usize(&mut bytes, 1 << 20).unwrap();
+        bytes.extend_from_slice(&u64::MAX.to_ne_bytes());
+        assert_eq!(bytes.len(), 16);
+        let mut bytes_in = bytes.as_slice();
+        assert_eq!(read_usize(&mut bytes_in).unwrap(), 1 << 20);
+        let last = read_usize(&mut bytes_in);
+        assert_eq!(last.is_ok(), usize::BITS == 64);
+        assert_eq!(data_id_of(u64::MAX).is_ok(), usize::BITS == 64);
+    } // end of test_dumped_usize
 } // end module tests
@@ -0,0 +1,173 @@
+//! Replacement of a served index by a new dump without downtime.
+//!
+//! A [HotSwap] serves an index. [HotSwap::begin_load] verifies (see [HnswIo::verify]) and loads a dump on a
+//! background thread while searches go on with the current index, then swaps the served index atomically.
+//! If the dump is not valid or cannot be loaded, the current index stays served and the error is returned
+//! by [LoadHandle::join].
+//!
+//! Searches get the served index with [HotSwap::get], an index obtained before a swap stays valid until dropped,
+//! so memory holds both indexes while old searches end.
+
+use parking_lot::RwLock;
+use serde::{Serialize, de::DeserializeOwned};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::thread::JoinHandle;
+
+use anyhow::anyhow;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::OwnedHnsw;
+use crate::hnswio::HnswIo;
+
+/// Serves an index replaced by loads of dumps, see module doc.
+pub struct HotSwap<T: Clone + Send + Sync + 'static, D: Distance<T>> {
+    current: RwLock<Arc<OwnedHnsw<T, D>>>,
+    generation: AtomicU64,
+    loading: AtomicBool,
+}
+
+impl<T, D> HotSwap<T, D>
+where
+    T: 'static + Serialize + DeserializeOwned + Clone + Send + Sync + std::fmt::Debug,
+    D: Distance<T> + Default + Send + Sync + 'static,
+{
+    pub fn new(hnsw: OwnedHnsw<T, D>) -> Self {
+        HotSwap {
+            current: RwLock::new(Arc::new(hnsw)),
+            generation: AtomicU64::new(0),
+            loading: AtomicBool::new(false),
+        }
+    }
+
+    /// returns the served index. It stays valid (but is not served anymore) after a swap.
+    pub fn get(&self) -> Arc<OwnedHnsw<T, D>> {
+        Arc::clone(&self.current.read())
+    }
+
+    /// returns the number of swaps done
+    pub fn get_generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// returns true while a load is running
+    pub fn is_loading(&self) -> bool {
+        self.loading.load(Ordering::Acquire)
+    }
+
+    /// verifies and loads the dump basename of directory, then serves it. Returns the new generation number.
+    /// On error the served index is unchanged.
+    pub fn load(&self, directory: &Path, basename: &str) -> anyhow::Result<u64> {
+        let reloader = HnswIo::new(directory, basename);
+        let report = reloader
+            .verify()
+            .map_err(|e| anyhow!("hot swap of {} : invalid dump, {}", basename, e))?;
+        log::info!("hot swap, loading {} : {:?}", basename, report);
+        let loaded = reloader
+            .load_owned::<T, D>()
+            .map_err(|e| anyhow!("hot swap of {} : load failed, {}", basename, e))?;
+        *self.current.write() = Arc::new(loaded);
+        let generation = self.generation.fetch_add(1, Ordering::AcqRel) + 1;
+        log::info!("hot swap, {} served as generation {}", basename, generation);
+        Ok(generation)
+    }
+
+    /// runs [HotSwap::load] on a background thread. Fails if a load is already running.
+    pub fn begin_load(
+        self: &Arc<Self>,
+        directory: &Path,
+        basename: &str,
+    ) -> anyhow::Result<LoadHandle> {
+        if self.loading.swap(true, Ordering::AcqRel) {
+            return Err(anyhow!("a load is already running"));
+        }
+        let swap = Arc::clone(self);
+        let directory = PathBuf::from(directory);
+        let basename = basename.to_string();
+        let handle = std::thread::spawn(move || {
+            let res = swap.load(&directory, &basename);
+            swap.loading.store(false, Ordering::Release);
+            res
+        });
+        Ok(LoadHandle { handle })
+    }
+} // end of impl HotSwap
+
+/// handle on a running load
+pub struct LoadHandle {
+    handle: JoinHandle<anyhow::Result<u64>>,
+}
+
+impl LoadHandle {
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// waits for the swap and returns the new generation number, or the error that kept the served index
+    pub fn join(self) -> anyhow::Result<u64> {
+        self.handle
+            .join()
+            .map_err(|_| anyhow!("load thread panicked"))?
+    }
+} // end of impl LoadHandle
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use crate::hnsw::{Hnsw, Neighbour};
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_hot_swap() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(971);
+        let data = synthetic::uniform(1500, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let first = Hnsw::<f32, DistL2>::new(16, 1500, 16, 100, DistL2 {});
+        first.parallel_insert(&data_with_id[..500]);
+        let next = Hnsw::<f32, DistL2>::new(16, 1500, 16, 100, DistL2 {});
+        next.parallel_insert(&data_with_id);
+        let directory = tempfile::tempdir().unwrap();
+        next.file_dump(directory.path(), "next").unwrap();
+        //
+        let swap = Arc::new(HotSwap::new(first));
+        let served = swap.get();
+        let handle = swap.begin_load(directory.path(), "next").unwrap();
+        // searches go on during the load
+        assert_eq!(served.search(&data[0], 1, 16)[0].d_id, 0);
+        assert_eq!(handle.join().unwrap(), 1);
+        assert!(!swap.is_loading());
+        assert_eq!(swap.get().get_nb_point(), 1500);
+        let ids = |res: Vec<Neighbour>| res.iter().map(|n| n.d_id).collect::<Vec<_>>();
+        assert_eq!(
+            ids(swap.get().search(&data[1200], 10, 64)),
+            ids(next.search(&data[1200], 10, 64))
+        );
+        // the index got before the swap is still usable
+        assert_eq!(served.get_nb_point(), 500);
+        // a truncated dump is refused, the index loaded stays served
+        let data_path = directory.path().join("next.hnsw.data");
+        let len = std::fs::metadata(&data_path).unwrap().len();
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&data_path)
+            .unwrap()
+            .set_len(len - 10)
+            .unwrap();
+        let res = swap.begin_load(directory.path(), "next").unwrap().join();
+        assert!(res.is_err());
+        assert!(
+            swap.begin_load(directory.path(), "missing")
+                .unwrap()
+                .join()
+                .is_err()
+        );
+        assert_eq!(swap.get_generation(), 1);
+        assert_eq!(swap.get().get_nb_point(), 1500);
+    } // end of test_hot_swap
+} // end of mod tests
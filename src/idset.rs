@@ -0,0 +1,132 @@
+//! The set of data ids of a structure.
+//!
+//! [Hnsw::contains] tells in O(1) if a data id was inserted, so an ingestion pipeline can skip records
+//! already indexed, also after a restart: the set is dumped in an extension block and reloaded with the structure.
+//! Ids of points deleted by [Hnsw::remove_batch] or [Hnsw::remove_where] leave the set, aliases (see [crate::alias])
+//! are contained. Ids are expected to be unique: deleting one of two points with the same id removes the id.
+//!
+//! The set is exact (a roaring bitmap of u64), a few bytes by id for ids given by a counter.
+
+use parking_lot::RwLock;
+use roaring::RoaringTreemap;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, PointIndexation};
+
+/// data ids of points, see module doc
+#[derive(Default)]
+pub(crate) struct IdSet {
+    ids: RwLock<RoaringTreemap>,
+}
+
+impl Clone for IdSet {
+    fn clone(&self) -> Self {
+        IdSet {
+            ids: RwLock::new(self.ids.read().clone()),
+        }
+    }
+}
+
+impl IdSet {
+    #[inline]
+    pub(crate) fn insert(&self, id: DataId) {
+        self.ids.write().insert(id as u64);
+    }
+
+    pub(crate) fn remove_all(&self, ids: &[DataId]) {
+        let mut set = self.ids.write();
+        for id in ids {
+            set.remove(*id as u64);
+        }
+    }
+
+    pub(crate) fn contains(&self, id: DataId) -> bool {
+        self.ids.read().contains(id as u64)
+    }
+
+    pub(crate) fn len(&self) -> u64 {
+        self.ids.read().len()
+    }
+
+    /// roaring serialization of the set
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let ids = self.ids.read();
+        let mut bytes = Vec::with_capacity(ids.serialized_size());
+        ids.serialize_into(&mut bytes).unwrap();
+        bytes
+    }
+
+    /// keeps only ids of bytes: the set of a reloaded structure is the dumped set restricted to reloaded points
+    pub(crate) fn retain_from_bytes(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        let dumped = RoaringTreemap::deserialize_from(bytes)
+            .map_err(|e| anyhow::anyhow!("id set block of dump is not readable : {}", e))?;
+        *self.ids.write() &= dumped;
+        Ok(())
+    }
+} // end of impl IdSet
+
+impl<T: Clone + Send + Sync> PointIndexation<'_, T> {
+    /// sets ids to the ids of points
+    pub(crate) fn reset_ids(&self) {
+        let ids: RoaringTreemap = self.iter_ids().map(|(_, d_id)| d_id as u64).collect();
+        *self.ids.ids.write() = ids;
+    }
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// returns true if a point with data id id is in the structure and not deleted, or if id is an alias
+    pub fn contains(&self, id: DataId) -> bool {
+        self.layer_indexed_points.ids.contains(id)
+            || self.aliases.read().get_canonical(id).is_some()
+    }
+
+    /// returns the number of data ids of points not deleted (aliases excluded)
+    pub fn get_nb_ids(&self) -> u64 {
+        self.layer_indexed_points.ids.len()
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use crate::hnswio::HnswIo;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_contains() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(964);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip((0..).step_by(3)).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        assert!(!hnsw.contains(0));
+        hnsw.parallel_insert(&data_with_id);
+        assert_eq!(hnsw.get_nb_ids(), 1000);
+        assert!((0..3000).all(|id| hnsw.contains(id) == id.is_multiple_of(3)));
+        hnsw.alias(3, 1).unwrap();
+        assert!(hnsw.contains(1));
+        hnsw.remove_batch(&[0, 6]).unwrap();
+        assert!(!hnsw.contains(0) && !hnsw.contains(6) && hnsw.contains(9));
+        // the set is reloaded, without deleted ids
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "idset").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "idset");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert_eq!(reloaded.get_nb_ids(), 998);
+        assert!((7..3000).all(|id| reloaded.contains(id) == id.is_multiple_of(3)));
+        assert!(reloaded.contains(1) && !reloaded.contains(0) && !reloaded.contains(6));
+        // a copy has the ids of its points
+        let clone = reloaded.deep_clone();
+        assert_eq!(clone.get_nb_ids(), 998);
+        clone.insert((&data[0], 5000));
+        assert!(clone.contains(5000) && !reloaded.contains(5000));
+    } // end of test_contains
+} // end of mod tests
@@ -0,0 +1,333 @@
+//! Throttling of insertions when searches and insertions run concurrently on the same Hnsw.
+//!
+//! An [IngestController] caps the number of threads inserting at the same time and makes
+//! insertions wait while the p99 of recent search latencies is above a target.
+//! Searches must go through the controller (or report their latency with
+//! [IngestController::record_search_latency]) so that it can measure them.
+//!
+//! Insertions go in two lanes. [IngestController::insert] and [IngestController::parallel_insert] are in the
+//! foreground lane, [IngestController::backfill] (bulk loads, migrations) in the background lane: a background
+//! insertion does not start while a foreground one waits for a permit, so online insertions wait at most
+//! for the end of insertions already running. The backfill goes by chunks, threads of the pool are not all
+//! taken by it between chunks.
+
+use parking_lot::{Condvar, Mutex};
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Neighbour};
+
+/// latencies older than this are forgotten, so insertions are not blocked if searches stop
+const LATENCY_WINDOW: Duration = Duration::from_secs(1);
+/// maximum number of latencies kept
+const LATENCY_CAPACITY: usize = 4096;
+/// time an insertion sleeps before checking again search latencies
+const BACKOFF: Duration = Duration::from_millis(1);
+
+/// counters of an IngestController
+#[derive(Copy, Clone, Debug, Default)]
+pub struct IngestMetrics {
+    /// number of points inserted through the controller
+    pub nb_inserted: u64,
+    /// number of insertions that had to wait for search latency to go under target
+    pub nb_throttled: u64,
+    /// cumulated time spent by insertions waiting (for a permit or for searches)
+    pub wait_time: Duration,
+    /// number of points inserted by backfill (included in nb_inserted)
+    pub nb_backfilled: u64,
+    /// number of searches measured
+    pub nb_searches: u64,
+    /// p99 of search latencies in the current window
+    pub search_p99: Option<Duration>,
+}
+
+/// lane of an insertion, see module doc
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Lane {
+    Foreground,
+    Background,
+}
+
+// insertions running and foreground insertions waiting for a permit
+#[derive(Default)]
+struct Permits {
+    active: usize,
+    nb_foreground_waiting: usize,
+}
+
+// permit of a running insertion, given back when dropped, even by a panic of the insertion
+struct Permit<'a> {
+    controller: &'a IngestController,
+}
+
+impl Drop for Permit<'_> {
+    fn drop(&mut self) {
+        self.controller.permits.lock().active -= 1;
+        // a woken background insertion could not pass a waiting foreground one
+        self.controller.released.notify_all();
+    }
+}
+
+/// Caps concurrent insertions and yields to searches. See module doc.
+pub struct IngestController {
+    max_insert_threads: usize,
+    p99_target: Duration,
+    permits: Mutex<Permits>,
+    released: Condvar,
+    // (date of end, latency) of recent searches
+    latencies: Mutex<VecDeque<(Instant, Duration)>>,
+    nb_inserted: AtomicU64,
+    nb_throttled: AtomicU64,
+    wait_nanos: AtomicU64,
+    nb_backfilled: AtomicU64,
+    nb_searches: AtomicU64,
+}
+
+impl IngestController {
+    /// - max_insert_threads : maximum number of insertions running concurrently (at least 1)
+    /// - p99_target : insertions wait while p99 of search latencies of last second is above this.
+    pub fn new(max_insert_threads: usize, p99_target: Duration) -> Self {
+        IngestController {
+            max_insert_threads: max_insert_threads.max(1),
+            p99_target,
+            permits: Mutex::new(Permits::default()),
+            released: Condvar::new(),
+            latencies: Mutex::new(VecDeque::new()),
+            nb_inserted: AtomicU64::new(0),
+            nb_throttled: AtomicU64::new(0),
+            wait_nanos: AtomicU64::new(0),
+            nb_backfilled: AtomicU64::new(0),
+            nb_searches: AtomicU64::new(0),
+        }
+    }
+
+    /// records latency of a search not done through the controller
+    pub fn record_search_latency(&self, latency: Duration) {
+        self.nb_searches.fetch_add(1, Ordering::Relaxed);
+        let now = Instant::now();
+        let mut latencies = self.latencies.lock();
+        latencies.push_back((now, latency));
+        if latencies.len() > LATENCY_CAPACITY {
+            latencies.pop_front();
+        }
+    }
+
+    /// returns p99 of search latencies recorded in the last second, None if there was no search
+    pub fn search_p99(&self) -> Option<Duration> {
+        let now = Instant::now();
+        let mut latencies = self.latencies.lock();
+        while let Some(&(date, _)) = latencies.front() {
+            if now.duration_since(date) > LATENCY_WINDOW {
+                latencies.pop_front();
+            } else {
+                break;
+            }
+        }
+        if latencies.is_empty() {
+            return None;
+        }
+        let mut sorted: Vec<Duration> = latencies.iter().map(|&(_, l)| l).collect();
+        drop(latencies);
+        sorted.sort_unstable();
+        let rank = ((sorted.len() as f64 * 0.99).ceil() as usize).clamp(1, sorted.len()) - 1;
+        Some(sorted[rank])
+    }
+
+    // waits for searches to be fast enough and for a permit, background insertions also wait
+    // for foreground ones
+    fn acquire(&self, lane: Lane) -> Permit<'_> {
+        let start = Instant::now();
+        let mut throttled = false;
+        while self.search_p99().is_some_and(|p99| p99 > self.p99_target) {
+            throttled = true;
+            std::thread::sleep(BACKOFF);
+        }
+        {
+            let mut permits = self.permits.lock();
+            match lane {
+                Lane::Foreground => {
+                    permits.nb_foreground_waiting += 1;
+                    while permits.active >= self.max_insert_threads {
+                        self.released.wait(&mut permits);
+                    }
+                    permits.nb_foreground_waiting -= 1;
+                }
+                Lane::Background => {
+                    while permits.active >= self.max_insert_threads
+                        || permits.nb_foreground_waiting > 0
+                    {
+                        self.released.wait(&mut permits);
+                    }
+                }
+            }
+            permits.active += 1;
+        }
+        if throttled {
+            self.nb_throttled.fetch_add(1, Ordering::Relaxed);
+        }
+        self.wait_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        Permit { controller: self }
+    }
+
+    fn insert_in<T, D>(&self, hnsw: &Hnsw<T, D>, data_with_id: (&[T], usize), lane: Lane)
+    where
+        T: Clone + Send + Sync,
+        D: Distance<T> + Send + Sync,
+    {
+        let permit = self.acquire(lane);
+        hnsw.insert_slice(data_with_id);
+        drop(permit);
+        self.nb_inserted.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// inserts one point (foreground lane) when searches and the thread cap allow it
+    pub fn insert<T, D>(&self, hnsw: &Hnsw<T, D>, data_with_id: (&[T], usize))
+    where
+        T: Clone + Send + Sync,
+        D: Distance<T> + Send + Sync,
+    {
+        self.insert_in(hnsw, data_with_id, Lane::Foreground);
+    }
+
+    /// parallel insertion in the background lane, by chunks of chunk points. Insertions of the foreground lane
+    /// started meanwhile go first.
+    pub fn backfill<T, D>(&self, hnsw: &Hnsw<T, D>, datas: &[(&[T], usize)], chunk: usize)
+    where
+        T: Clone + Send + Sync,
+        D: Distance<T> + Send + Sync,
+    {
+        for chunk in datas.chunks(chunk.max(1)) {
+            chunk
+                .par_iter()
+                .for_each(|&item| self.insert_in(hnsw, item, Lane::Background));
+            self.nb_backfilled
+                .fetch_add(chunk.len() as u64, Ordering::Relaxed);
+        }
+    }
+
+    /// parallel insertion, at most max_insert_threads points are inserted at the same time
+    pub fn parallel_insert<T, D>(&self, hnsw: &Hnsw<T, D>, datas: &[(&[T], usize)])
+    where
+        T: Clone + Send + Sync,
+        D: Distance<T> + Send + Sync,
+    {
+        datas.par_iter().for_each(|&item| self.insert(hnsw, item));
+    }
+
+    /// a search whose latency is recorded
+    pub fn search<T, D>(
+        &self,
+        hnsw: &Hnsw<T, D>,
+        data: &[T],
+        knbn: usize,
+        ef_s: usize,
+    ) -> Vec<Neighbour>
+    where
+        T: Clone + Send + Sync,
+        D: Distance<T> + Send + Sync,
+    {
+        let start = Instant::now();
+        let res = hnsw.search(data, knbn, ef_s);
+        self.record_search_latency(start.elapsed());
+        res
+    }
+
+    /// returns current counters
+    pub fn get_metrics(&self) -> IngestMetrics {
+        IngestMetrics {
+            nb_inserted: self.nb_inserted.load(Ordering::Relaxed),
+            nb_throttled: self.nb_throttled.load(Ordering::Relaxed),
+            wait_time: Duration::from_nanos(self.wait_nanos.load(Ordering::Relaxed)),
+            nb_backfilled: self.nb_backfilled.load(Ordering::Relaxed),
+            nb_searches: self.nb_searches.load(Ordering::Relaxed),
+            search_p99: self.search_p99(),
+        }
+    }
+} // end of impl IngestController
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_ingest_controller() {
+        let nb_elem = 500;
+        let data: Vec<Vec<f32>> = (0..nb_elem).map(|i| vec![i as f32, 1.]).collect();
+        let datas: Vec<(&[f32], usize)> = data.iter().map(|v| v.as_slice()).zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 100, DistL2 {});
+        let controller = IngestController::new(2, Duration::from_millis(5));
+        // slow searches throttle insertion until they leave the window
+        controller.record_search_latency(Duration::from_millis(50));
+        assert!(controller.search_p99().unwrap() > Duration::from_millis(5));
+        controller.parallel_insert(&hnsw, &datas);
+        let res = controller.search(&hnsw, &[10.1, 1.], 3, 32);
+        assert_eq!(res[0].d_id, 10);
+        //
+        let metrics = controller.get_metrics();
+        assert_eq!(metrics.nb_inserted, nb_elem as u64);
+        assert!(metrics.nb_throttled > 0);
+        assert!(metrics.wait_time >= Duration::from_millis(500));
+        assert_eq!(metrics.nb_searches, 2);
+        assert_eq!(hnsw.get_nb_point(), nb_elem);
+    } // end of test_ingest_controller
+
+    #[test]
+    fn test_backfill_lane() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(967);
+        let data = synthetic::uniform(1001, 16, &mut rng);
+        let datas: Vec<(&[f32], usize)> = data.iter().map(|v| v.as_slice()).zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 1001, 16, 100, DistL2 {});
+        let controller = IngestController::new(1, Duration::from_secs(1));
+        // the only permit is taken, the backfill and an online insertion wait for it
+        let permit = controller.acquire(Lane::Foreground);
+        std::thread::scope(|s| {
+            s.spawn(|| controller.backfill(&hnsw, &datas[..1000], 100));
+            s.spawn(|| controller.insert(&hnsw, datas[1000]));
+            while controller.permits.lock().nb_foreground_waiting == 0 {
+                std::thread::yield_now();
+            }
+            drop(permit);
+        });
+        // the online insertion went first
+        assert_eq!(hnsw.points_since(0)[0], (0, 1000));
+        let metrics = controller.get_metrics();
+        assert_eq!(metrics.nb_inserted, 1001);
+        assert_eq!(metrics.nb_backfilled, 1000);
+        assert_eq!(hnsw.get_nb_point(), 1001);
+    } // end of test_backfill_lane
+
+    // a distance refusing non finite vectors
+    struct CheckedL2;
+
+    impl Distance<f32> for CheckedL2 {
+        fn eval(&self, va: &[f32], vb: &[f32]) -> f32 {
+            assert!(va.iter().chain(vb).all(|x| x.is_finite()));
+            DistL2 {}.eval(va, vb)
+        }
+    }
+
+    #[test]
+    fn test_permit_of_panicking_insertion() {
+        let hnsw = Hnsw::<f32, CheckedL2>::new(16, 10, 16, 100, CheckedL2);
+        let controller = IngestController::new(1, Duration::from_secs(1));
+        controller.insert(&hnsw, (&[0., 1.], 0));
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            controller.insert(&hnsw, (&[f32::NAN, 1.], 1))
+        }));
+        assert!(res.is_err());
+        // the only permit is given back, next insertions do not wait for it
+        assert_eq!(controller.permits.lock().active, 0);
+        controller.insert(&hnsw, (&[2., 1.], 2));
+        assert_eq!(controller.get_metrics().nb_inserted, 2);
+    } // end of test_permit_of_panicking_insertion
+} // end of mod tests
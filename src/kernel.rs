@@ -0,0 +1,168 @@
+//! Distances with per-thread mutable state.
+//!
+//! Some distances need scratch memory: the band of a dynamic time warping, the activations of a learned metric ...
+//! A [Distance] only gets `&self` and is shared by all insertion and search threads, so such a buffer would have to be
+//! allocated at each evaluation or protected by a Mutex.
+//!
+//! A [DistanceKernel] declares its State and a factory [DistanceKernel::new_state]. Wrapped in a [DistKernel]
+//! it is a [Distance] usable by [Hnsw](crate::hnsw::Hnsw): each thread allocates the state of a kernel
+//! at its first evaluation and then reuses it, without lock. States are kept in a thread local list,
+//! the ones of a dropped kernel are freed when their thread ends or allocates a new state.
+
+use std::any::Any;
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+
+use anndists::dist::distances::Distance;
+
+/// A distance computed with a mutable state owned by the calling thread.
+pub trait DistanceKernel<T>: Send + Sync {
+    /// scratch memory of a thread
+    type State: 'static;
+    /// allocates the state of a thread, called once by thread using the kernel
+    fn new_state(&self) -> Self::State;
+    /// distance between va and vb, state keeps what the previous evaluations of the thread left in it
+    fn eval(&self, state: &mut Self::State, va: &[T], vb: &[T]) -> f32;
+}
+
+// states of the current thread: kernel id, liveness of the kernel, state
+type StateEntry = (u64, Weak<()>, Box<dyn Any>);
+
+thread_local! {
+    static STATES: RefCell<Vec<StateEntry>> = const { RefCell::new(Vec::new()) };
+}
+
+static NEXT_KERNEL_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A [DistanceKernel] used as a [Distance], with a state by thread. See module doc.
+/// A clone gets its own states.
+pub struct DistKernel<K> {
+    kernel: K,
+    id: u64,
+    // threads drop states whose kernel is no longer alive
+    alive: Arc<()>,
+}
+
+impl<K> DistKernel<K> {
+    pub fn new(kernel: K) -> Self {
+        DistKernel {
+            kernel,
+            id: NEXT_KERNEL_ID.fetch_add(1, Ordering::Relaxed),
+            alive: Arc::new(()),
+        }
+    }
+
+    pub fn get_kernel(&self) -> &K {
+        &self.kernel
+    }
+} // end of impl DistKernel
+
+impl<K: Clone> Clone for DistKernel<K> {
+    fn clone(&self) -> Self {
+        DistKernel::new(self.kernel.clone())
+    }
+}
+
+impl<T, K> Distance<T> for DistKernel<K>
+where
+    T: Send + Sync,
+    K: DistanceKernel<T>,
+{
+    fn eval(&self, va: &[T], vb: &[T]) -> f32 {
+        // the state is taken out of the list during the evaluation, so a kernel evaluating another one
+        // (or itself) on the same thread gets a fresh state instead of a double borrow
+        let taken = STATES.with_borrow_mut(|states| {
+            states
+                .iter()
+                .position(|(id, _, _)| *id == self.id)
+                .map(|rank| states.swap_remove(rank))
+        });
+        let (_, alive, mut state) = taken.unwrap_or_else(|| {
+            let state: Box<dyn Any> = Box::new(self.kernel.new_state());
+            (self.id, Arc::downgrade(&self.alive), state)
+        });
+        let dist = self.kernel.eval(
+            state
+                .downcast_mut::<K::State>()
+                .expect("state of another type"),
+            va,
+            vb,
+        );
+        STATES.with_borrow_mut(|states| {
+            if states.iter().any(|(id, _, _)| *id == self.id) {
+                // a nested evaluation put its state back first, keep one
+                return;
+            }
+            states.retain(|(_, alive, _)| alive.strong_count() > 0);
+            states.push((self.id, alive, state));
+        });
+        dist
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::Hnsw;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+    use std::sync::atomic::AtomicUsize;
+
+    // L2 computed through a buffer of differences, counting the states allocated
+    struct BufferedL2 {
+        nb_state: Arc<AtomicUsize>,
+    }
+
+    impl DistanceKernel<f32> for BufferedL2 {
+        type State = Vec<f32>;
+
+        fn new_state(&self) -> Vec<f32> {
+            self.nb_state.fetch_add(1, Ordering::Relaxed);
+            Vec::new()
+        }
+
+        fn eval(&self, diffs: &mut Vec<f32>, va: &[f32], vb: &[f32]) -> f32 {
+            diffs.clear();
+            diffs.extend(va.iter().zip(vb).map(|(a, b)| a - b));
+            diffs.iter().map(|d| d * d).sum::<f32>().sqrt()
+        }
+    }
+
+    #[test]
+    fn test_dist_kernel() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(980);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let nb_state = Arc::new(AtomicUsize::new(0));
+        let dist = DistKernel::new(BufferedL2 {
+            nb_state: Arc::clone(&nb_state),
+        });
+        assert_eq!(
+            dist.eval(&data[0], &data[1]),
+            DistL2 {}.eval(&data[0], &data[1])
+        );
+        assert_eq!(nb_state.load(Ordering::Relaxed), 1);
+        let hnsw = Hnsw::<f32, DistKernel<BufferedL2>>::new(16, 1000, 16, 100, dist);
+        hnsw.parallel_insert(&data_with_id);
+        let queries: Vec<Vec<f32>> = data.iter().take(100).cloned().collect();
+        let found = hnsw.parallel_search(&queries, 10, 64);
+        let nb_found = found
+            .iter()
+            .enumerate()
+            .filter(|(i, neighbours)| neighbours[0].d_id == *i)
+            .count();
+        assert!(nb_found >= 95, "nb_found {}", nb_found);
+        // one state by thread, not by evaluation
+        assert!(nb_state.load(Ordering::Relaxed) <= rayon::current_num_threads() + 1);
+        // a dropped kernel leaves no state behind once the thread allocates another one
+        drop(hnsw);
+        let other = DistKernel::new(BufferedL2 {
+            nb_state: Arc::clone(&nb_state),
+        });
+        other.eval(&data[0], &data[1]);
+        assert_eq!(STATES.with_borrow(|states| states.len()), 1);
+    } // end of test_dist_kernel
+} // end of mod tests
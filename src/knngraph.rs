@@ -0,0 +1,200 @@
+//! Construction from a precomputed kNN graph.
+//!
+//! When an exact (or high quality) kNN graph was computed offline, for example by brute force on a GPU,
+//! [Hnsw::from_knn_graph] uses it as layer 0 instead of searching neighbours of each point.
+//! Only the points drawn in upper layers (about 1 / max_nb_connection of them) are inserted with searches,
+//! so the build is much faster than a usual insertion.
+//!
+//! The neighbours of a point in layer 0 are its kNN list (truncated to 2 * max_nb_connection), completed
+//! with the closest points having it in their kNN list, so that the graph is navigable in both directions.
+
+use std::sync::Arc;
+
+use anyhow::anyhow;
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Point, PointData, PointWithOrder};
+use crate::payload::Payload;
+use crate::rebuild::RebuildParams;
+
+impl<T, D> Hnsw<'static, T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Send + Sync,
+{
+    /// builds a structure from vectors and their kNN lists.
+    /// knn_lists\[i\] gives the ranks in vectors of the neighbours of vectors\[i\], distances are computed with dist.
+    /// Fails if lists are not given for all vectors or refer to a rank out of vectors.
+    pub fn from_knn_graph(
+        vectors: &[(&[T], DataId)],
+        knn_lists: &[Vec<usize>],
+        params: &RebuildParams,
+        dist: D,
+    ) -> anyhow::Result<Self> {
+        if knn_lists.len() != vectors.len() {
+            return Err(anyhow!(
+                "from_knn_graph : {} knn lists for {} vectors",
+                knn_lists.len(),
+                vectors.len()
+            ));
+        }
+        if let Some(bad) = knn_lists.iter().flatten().find(|&&j| j >= vectors.len()) {
+            return Err(anyhow!(
+                "from_knn_graph : neighbour rank {} out of {} vectors",
+                bad,
+                vectors.len()
+            ));
+        }
+        let mut hnsw = Hnsw::new(
+            params.max_nb_connection,
+            vectors.len().max(1),
+            params.max_layer,
+            params.ef_construction,
+            dist,
+        );
+        hnsw.set_extend_candidates(params.extend_candidates);
+        hnsw.set_keeping_pruned(params.keep_pruned);
+        if let Some(seed) = params.level_seed {
+            hnsw.set_level_seed(seed);
+        }
+        if vectors.is_empty() {
+            return Ok(hnsw);
+        }
+        log::info!(
+            "from_knn_graph : {} vectors, max_nb_connection {}",
+            vectors.len(),
+            params.max_nb_connection
+        );
+        // all points first, with their layer
+        let points: Vec<Arc<Point<'static, T>>> = vectors
+            .iter()
+            .map(|&(v, id)| {
+                hnsw.layer_indexed_points
+                    .generate_new_point(PointData::new_v(v.to_vec()), id, Payload::default())
+                    .0
+            })
+            .collect();
+        hnsw.link_layer0(&points, knn_lists);
+        // then upper layers by search, from the highest point
+        let mut upper: Vec<&Arc<Point<'static, T>>> =
+            points.iter().filter(|p| p.get_point_id().0 > 0).collect();
+        upper.sort_unstable_by_key(|p| std::cmp::Reverse(p.get_point_id().0));
+        let top = upper.first().copied().unwrap_or(&points[0]);
+        hnsw.layer_indexed_points.check_entry_point(top);
+        upper
+            .par_iter()
+            .skip(1)
+            .for_each(|p| hnsw.link_point(p, false, 1));
+        log::info!(
+            "from_knn_graph : {} points linked in upper layers",
+            upper.len()
+        );
+        Ok(hnsw)
+    } // end of from_knn_graph
+
+    // sets neighbours in layer 0 from knn lists and reverse knn lists
+    fn link_layer0(&self, points: &[Arc<Point<'static, T>>], knn_lists: &[Vec<usize>]) {
+        let max_nb = 2 * self.max_nb_connection;
+        let dist_f = self.get_distance();
+        // kNN lists without self and repetitions, with distances, sorted
+        let lists: Vec<Vec<(usize, f32)>> = knn_lists
+            .par_iter()
+            .enumerate()
+            .map(|(i, knn)| {
+                let mut list: Vec<(usize, f32)> = Vec::with_capacity(knn.len());
+                for &j in knn {
+                    if j != i && !list.iter().any(|(k, _)| *k == j) {
+                        list.push((j, dist_f.eval(points[i].get_v(), points[j].get_v())));
+                    }
+                }
+                list.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+                list.truncate(max_nb);
+                list
+            })
+            .collect();
+        let mut reverse: Vec<Vec<(usize, f32)>> = vec![Vec::new(); points.len()];
+        for (i, list) in lists.iter().enumerate() {
+            for &(j, d) in list {
+                reverse[j].push((i, d));
+            }
+        }
+        lists
+            .into_par_iter()
+            .zip(reverse.into_par_iter())
+            .enumerate()
+            .for_each(|(i, (mut list, mut reverse))| {
+                // free slots are filled with closest reverse neighbours
+                reverse.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+                for (j, d) in reverse {
+                    if list.len() >= max_nb {
+                        break;
+                    }
+                    if !list.iter().any(|(k, _)| *k == j) {
+                        list.push((j, d));
+                    }
+                }
+                let mut neighbours: Vec<Arc<PointWithOrder<'static, T>>> = list
+                    .into_iter()
+                    .map(|(j, d)| Arc::new(PointWithOrder::new(&points[j], d)))
+                    .collect();
+                neighbours.sort_unstable();
+                points[i].neighbours.write()[0] = neighbours;
+            });
+    } // end of link_layer0
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_from_knn_graph() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(925);
+        let data = synthetic::uniform(1500, 8, &mut rng);
+        let vectors: Vec<(&[f32], DataId)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.as_slice(), i))
+            .collect();
+        // exact 20-NN by brute force
+        let knn_lists: Vec<Vec<usize>> = data
+            .par_iter()
+            .map(|v| {
+                let mut dists: Vec<(usize, f32)> = data
+                    .iter()
+                    .enumerate()
+                    .map(|(j, w)| (j, DistL2 {}.eval(v, w)))
+                    .collect();
+                dists.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+                dists.iter().take(21).map(|(j, _)| *j).collect()
+            })
+            .collect();
+        let params = RebuildParams {
+            max_nb_connection: 12,
+            ef_construction: 100,
+            max_layer: 16,
+            extend_candidates: false,
+            keep_pruned: false,
+            level_seed: None,
+        };
+        let hnsw = Hnsw::from_knn_graph(&vectors, &knn_lists, &params, DistL2 {}).unwrap();
+        assert_eq!(hnsw.get_nb_point(), 1500);
+        let mut nb_found = 0;
+        for (i, v) in data.iter().enumerate() {
+            let res = hnsw.search(v, 10, 64);
+            assert_eq!(res.len(), 10);
+            if res[0].d_id == i && res.iter().any(|n| n.d_id == knn_lists[i][5]) {
+                nb_found += 1;
+            }
+        }
+        assert!(nb_found > 1450, "nb_found {}", nb_found);
+        //
+        assert!(Hnsw::from_knn_graph(&vectors, &knn_lists[1..], &params, DistL2 {}).is_err());
+    } // end of test_from_knn_graph
+} // end of mod tests
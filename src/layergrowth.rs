@@ -0,0 +1,187 @@
+//! Growth of the number of layers when insertions exceed the planned size.
+//!
+//! Levels of points are drawn with P(l >= n) = exp(-n / scale), truncated to the max_layer layers given at creation
+//! (often computed from the planned max_elements, as ln(max_elements)). When the structure grows well beyond the plan,
+//! the top layer gathers many points and the descent from the entry point degrades to a flat search in it.
+//!
+//! Each time the number of points reaches factor times the plan (10 by default), the structure computes the number
+//! of points the untruncated law puts above its top layer. When it is at least 1 the top layer is overcrowded:
+//! with [Hnsw::set_layer_growth] layers are added (16 at most) so that new points can be drawn in them
+//! and become entry points, otherwise a warning is logged. The current size is then the plan.
+//! Points already inserted keep their layer (it is part of their [PointId](crate::hnsw::PointId)),
+//! new top layers are populated by the next insertions.
+//!
+//! [Hnsw::get_layer_plan] and [StatsSample::layer_overflow](crate::statshistory::StatsSample) report the state.
+
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use anndists::dist::distances::Distance;
+
+use crate::graphkind::GraphKind;
+use crate::hnsw::Hnsw;
+
+// growth of the size before a check of the layers
+const DEFAULT_GROWTH_FACTOR: usize = 10;
+
+/// planned size of a structure and layers added beyond it, see [Hnsw::get_layer_plan]
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LayerPlanReport {
+    /// max_elements at creation, then the size at the last check
+    pub planned: usize,
+    pub nb_point: usize,
+    /// number of layers points can be drawn in
+    pub nb_layer: usize,
+    /// layers added by growth
+    pub nb_layer_added: usize,
+    /// expected number of points the level law puts above the top layer, the top layer is overcrowded above 1
+    pub overflow: f64,
+}
+
+pub(crate) struct LayerPlan {
+    planned: AtomicUsize,
+    factor: usize,
+    grow: AtomicBool,
+    nb_added: AtomicUsize,
+}
+
+impl LayerPlan {
+    pub(crate) fn new(planned: usize) -> Self {
+        LayerPlan {
+            planned: AtomicUsize::new(planned),
+            factor: DEFAULT_GROWTH_FACTOR,
+            grow: AtomicBool::new(false),
+            nb_added: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn get_nb_layer_added(&self) -> usize {
+        self.nb_added.load(Ordering::Relaxed)
+    }
+} // end of impl LayerPlan
+
+impl Clone for LayerPlan {
+    fn clone(&self) -> Self {
+        LayerPlan {
+            planned: AtomicUsize::new(self.planned.load(Ordering::Relaxed)),
+            factor: self.factor,
+            grow: AtomicBool::new(self.grow.load(Ordering::Relaxed)),
+            nb_added: AtomicUsize::new(self.get_nb_layer_added()),
+        }
+    }
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// adds layers when the number of points reaches factor times the plan and the top layer is overcrowded,
+    /// see module doc. factor must be at least 2.
+    pub fn set_layer_growth(&mut self, factor: usize) {
+        assert!(factor >= 2, "layer growth factor must be at least 2");
+        self.layer_plan.factor = factor;
+        self.layer_plan.grow.store(true, Ordering::Relaxed);
+    }
+
+    pub fn get_layer_plan(&self) -> LayerPlanReport {
+        LayerPlanReport {
+            planned: self.layer_plan.planned.load(Ordering::Relaxed),
+            nb_point: self.get_nb_point(),
+            nb_layer: self.layer_indexed_points.layer_g.get_max_level(),
+            nb_layer_added: self.layer_plan.get_nb_layer_added(),
+            overflow: self.get_layer_overflow(),
+        }
+    }
+
+    /// expected number of points the level law puts above the top layer
+    pub(crate) fn get_layer_overflow(&self) -> f64 {
+        let nb_layer = self.layer_indexed_points.layer_g.get_max_level() as f64;
+        self.get_nb_point() as f64 * (-nb_layer / self.layer_indexed_points.get_level_scale()).exp()
+    }
+
+    /// called by insertions with the number of points, checks layers when it reaches factor times the plan
+    pub(crate) fn check_layer_plan(&self, nb_point: usize) {
+        let plan = &self.layer_plan;
+        let planned = plan.planned.load(Ordering::Relaxed);
+        if nb_point < planned.max(1).saturating_mul(plan.factor) {
+            return;
+        }
+        // one insertion makes the check
+        if plan
+            .planned
+            .compare_exchange(planned, nb_point, Ordering::AcqRel, Ordering::Relaxed)
+            .is_err()
+        {
+            return;
+        }
+        // a flat graph has one layer on purpose
+        if self.graph_kind == GraphKind::Flat || self.get_layer_overflow() < 1. {
+            return;
+        }
+        if !plan.grow.load(Ordering::Relaxed) {
+            log::warn!(
+                "{} points for {} planned, top layer overcrowded ({:.1} points expected above it), see Hnsw::set_layer_growth",
+                nb_point,
+                planned,
+                self.get_layer_overflow()
+            );
+            return;
+        }
+        while self.get_layer_overflow() >= 1. && self.layer_indexed_points.add_layer() {
+            plan.nb_added.fetch_add(1, Ordering::Relaxed);
+        }
+        log::info!(
+            "{} points for {} planned, points can be drawn in {} layers",
+            nb_point,
+            planned,
+            self.layer_indexed_points.layer_g.get_max_level()
+        );
+    } // end of check_layer_plan
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_layer_growth() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(983);
+        let data = synthetic::uniform(5000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        // 2 layers are enough for 256 points with 16 connections
+        let fixed = Hnsw::<f32, DistL2>::new(16, 100, 2, 100, DistL2 {});
+        let mut growing = Hnsw::<f32, DistL2>::new(16, 100, 2, 100, DistL2 {});
+        growing.set_layer_growth(10);
+        fixed.parallel_insert(&data_with_id);
+        growing.parallel_insert(&data_with_id[..999]);
+        assert_eq!(growing.get_max_level(), 2);
+        growing.parallel_insert(&data_with_id[999..]);
+        // at 1000 points a layer is added
+        let plan = growing.get_layer_plan();
+        // the check is made by the first insertion that sees 1000 points
+        assert!((1000..1100).contains(&plan.planned));
+        assert_eq!(plan.nb_layer, 3);
+        assert_eq!(plan.nb_layer_added, 1);
+        assert_eq!(growing.get_max_level(), 3);
+        assert!(plan.overflow < 2.);
+        assert!(growing.get_point_indexation().get_max_level_observed() >= 2);
+        assert!(growing.get_point_indexation().get_layer_nb_point(2) > 0);
+        // without growth the plan is checked, layers stay
+        let plan = fixed.get_layer_plan();
+        assert!((1000..1100).contains(&plan.planned));
+        assert_eq!((plan.nb_layer, plan.nb_layer_added), (2, 0));
+        assert!(plan.overflow > 15.);
+        assert!(fixed.get_point_indexation().get_max_level_observed() <= 1);
+        //
+        let nb_found = (0..5000)
+            .step_by(10)
+            .filter(|i| growing.search(&data[*i], 10, 64)[0].d_id == *i)
+            .count();
+        assert!(nb_found >= 490, "nb_found {}", nb_found);
+    } // end of test_layer_growth
+} // end of mod tests
@@ -0,0 +1,309 @@
+//! Storage layouts of f32 vectors for distance kernels.
+//!
+//! The row layout stores each vector contiguously, it is the layout of Hnsw points and is the best for small dimensions.
+//! For high dimensions (see [VectorLayout::for_dim]) a blocked layout stores vectors by blocks of [BLOCK_LANES]:
+//! inside a block, component d of the vectors of the block are contiguous. A kernel computing distances of a query
+//! to a block then uses one SIMD lane by vector and reads memory sequentially, without the horizontal reductions
+//! of the row kernel.
+//!
+//! [VectorStore] holds vectors in the layout chosen at build and gives exact L2 neighbours
+//! (used for the brute force of [crate::bench::quick]). For a batch of queries in the row layout,
+//! [VectorStore::knn_l2_batch] scans vectors once by group of [MULTI_QUERIES] queries (see [crate::simd::l2_multi]).
+
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::simd::{DistL2Simd, MULTI_QUERIES, SimdLevel, active_level, l2_multi};
+
+/// number of vectors in a block of the blocked layout (one avx2 register of f32)
+pub const BLOCK_LANES: usize = 8;
+
+/// dimension from which [VectorLayout::for_dim] chooses the blocked layout
+pub const BLOCKED_MIN_DIM: usize = 512;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VectorLayout {
+    /// vectors one after the other
+    Row,
+    /// vectors by blocks of BLOCK_LANES, dimension major inside a block
+    Blocked,
+}
+
+impl VectorLayout {
+    /// the blocked layout for dimensions >= BLOCKED_MIN_DIM, the row layout otherwise
+    pub fn for_dim(dim: usize) -> Self {
+        if dim >= BLOCKED_MIN_DIM {
+            VectorLayout::Blocked
+        } else {
+            VectorLayout::Row
+        }
+    }
+}
+
+/// vectors in the blocked layout. The last block is completed with zeros.
+pub struct BlockedVectors {
+    dim: usize,
+    nb_vector: usize,
+    data: Vec<f32>,
+}
+
+// squared L2 distances of query to the vectors of a block
+#[inline(always)]
+fn l2_block_generic(query: &[f32], block: &[f32]) -> [f32; BLOCK_LANES] {
+    let mut acc = [0f32; BLOCK_LANES];
+    for (q, components) in query.iter().zip(block.chunks_exact(BLOCK_LANES)) {
+        for lane in 0..BLOCK_LANES {
+            let diff = components[lane] - q;
+            acc[lane] += diff * diff;
+        }
+    }
+    acc
+}
+
+// same code, vectorized by the compiler with avx2 instructions
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn l2_block_avx2(query: &[f32], block: &[f32]) -> [f32; BLOCK_LANES] {
+    l2_block_generic(query, block)
+}
+
+impl BlockedVectors {
+    pub fn new(dim: usize) -> Self {
+        BlockedVectors {
+            dim,
+            nb_vector: 0,
+            data: Vec::new(),
+        }
+    }
+
+    pub fn get_dim(&self) -> usize {
+        self.dim
+    }
+
+    pub fn len(&self) -> usize {
+        self.nb_vector
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nb_vector == 0
+    }
+
+    /// appends a vector, its rank is the number of vectors already stored
+    pub fn push(&mut self, v: &[f32]) {
+        assert_eq!(v.len(), self.dim);
+        let lane = self.nb_vector % BLOCK_LANES;
+        if lane == 0 {
+            self.data
+                .resize(self.data.len() + self.dim * BLOCK_LANES, 0.);
+        }
+        let block_start = self.data.len() - self.dim * BLOCK_LANES;
+        for (d, x) in v.iter().enumerate() {
+            self.data[block_start + d * BLOCK_LANES + lane] = *x;
+        }
+        self.nb_vector += 1;
+    }
+
+    /// copies vector of rank in a row
+    pub fn get(&self, rank: usize) -> Vec<f32> {
+        assert!(rank < self.nb_vector);
+        let block = self.block(rank / BLOCK_LANES);
+        let lane = rank % BLOCK_LANES;
+        (0..self.dim)
+            .map(|d| block[d * BLOCK_LANES + lane])
+            .collect()
+    }
+
+    fn block(&self, b: usize) -> &[f32] {
+        let size = self.dim * BLOCK_LANES;
+        &self.data[b * size..(b + 1) * size]
+    }
+
+    /// number of blocks
+    pub fn nb_block(&self) -> usize {
+        self.nb_vector.div_ceil(BLOCK_LANES)
+    }
+
+    /// L2 distances of query to the vectors of block b. Lanes after the last vector are meaningless.
+    pub fn l2_block(&self, query: &[f32], b: usize) -> [f32; BLOCK_LANES] {
+        assert_eq!(query.len(), self.dim);
+        let block = self.block(b);
+        let acc = match active_level() {
+            #[cfg(target_arch = "x86_64")]
+            // SAFETY: active_level is Avx2 or Avx512 only if cpu supports avx2 and fma
+            SimdLevel::Avx2 | SimdLevel::Avx512 => unsafe { l2_block_avx2(query, block) },
+            _ => l2_block_generic(query, block),
+        };
+        acc.map(f32::sqrt)
+    }
+} // end of impl BlockedVectors
+
+/// vectors stored in the layout chosen at build
+pub enum VectorStore {
+    Row { dim: usize, data: Vec<f32> },
+    Blocked(BlockedVectors),
+}
+
+impl VectorStore {
+    pub fn new(layout: VectorLayout, dim: usize) -> Self {
+        match layout {
+            VectorLayout::Row => VectorStore::Row {
+                dim,
+                data: Vec::new(),
+            },
+            VectorLayout::Blocked => VectorStore::Blocked(BlockedVectors::new(dim)),
+        }
+    }
+
+    /// stores vectors in the layout given by [VectorLayout::for_dim]
+    pub fn from_rows(dim: usize, rows: &[Vec<f32>]) -> Self {
+        let mut store = VectorStore::new(VectorLayout::for_dim(dim), dim);
+        rows.iter().for_each(|v| store.push(v));
+        store
+    }
+
+    pub fn get_layout(&self) -> VectorLayout {
+        match self {
+            VectorStore::Row { .. } => VectorLayout::Row,
+            VectorStore::Blocked(_) => VectorLayout::Blocked,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            VectorStore::Row { dim, data } => data.len() / (*dim).max(1),
+            VectorStore::Blocked(blocked) => blocked.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn push(&mut self, v: &[f32]) {
+        match self {
+            VectorStore::Row { dim, data } => {
+                assert_eq!(v.len(), *dim);
+                data.extend_from_slice(v);
+            }
+            VectorStore::Blocked(blocked) => blocked.push(v),
+        }
+    }
+
+    /// exact knbn nearest vectors of query for L2 distance, as (rank, distance) sorted by distance
+    pub fn knn_l2(&self, query: &[f32], knbn: usize) -> Vec<(usize, f32)> {
+        let dists: Vec<(usize, f32)> = match self {
+            VectorStore::Row { dim, data } => data
+                .chunks_exact(*dim)
+                .map(|v| DistL2Simd.eval(query, v))
+                .enumerate()
+                .collect(),
+            VectorStore::Blocked(blocked) => (0..blocked.nb_block())
+                .flat_map(|b| {
+                    let first = b * BLOCK_LANES;
+                    blocked
+                        .l2_block(query, b)
+                        .into_iter()
+                        .enumerate()
+                        .map(move |(lane, d)| (first + lane, d))
+                })
+                .take(blocked.len())
+                .collect(),
+        };
+        nearest(dists, knbn)
+    } // end of knn_l2
+
+    /// [VectorStore::knn_l2] of each query, computed in parallel. In the row layout queries are scanned
+    /// by groups of MULTI_QUERIES with a multi query kernel.
+    pub fn knn_l2_batch(&self, queries: &[Vec<f32>], knbn: usize) -> Vec<Vec<(usize, f32)>> {
+        let (dim, data) = match self {
+            VectorStore::Row { dim, data } => (*dim, data),
+            VectorStore::Blocked(_) => {
+                return queries.par_iter().map(|q| self.knn_l2(q, knbn)).collect();
+            }
+        };
+        queries
+            .par_chunks(MULTI_QUERIES)
+            .flat_map_iter(|group| {
+                if group.len() < MULTI_QUERIES {
+                    return group.iter().map(|q| self.knn_l2(q, knbn)).collect();
+                }
+                let group: [&[f32]; MULTI_QUERIES] = std::array::from_fn(|i| &group[i][..]);
+                let mut dists: [Vec<(usize, f32)>; MULTI_QUERIES] =
+                    std::array::from_fn(|_| Vec::with_capacity(self.len()));
+                for (rank, v) in data.chunks_exact(dim).enumerate() {
+                    for (d, dists_q) in l2_multi(&group, v).into_iter().zip(dists.iter_mut()) {
+                        dists_q.push((rank, d));
+                    }
+                }
+                dists
+                    .into_iter()
+                    .map(|dists_q| nearest(dists_q, knbn))
+                    .collect::<Vec<_>>()
+            })
+            .collect()
+    } // end of knn_l2_batch
+} // end of impl VectorStore
+
+// the knbn smallest distances, sorted
+fn nearest(mut dists: Vec<(usize, f32)>, knbn: usize) -> Vec<(usize, f32)> {
+    let knbn = knbn.min(dists.len());
+    if knbn == 0 {
+        return Vec::new();
+    }
+    dists.select_nth_unstable_by(knbn - 1, |a, b| a.1.total_cmp(&b.1));
+    dists.truncate(knbn);
+    dists.sort_unstable_by(|a, b| a.1.total_cmp(&b.1));
+    dists
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_blocked_layout() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(927);
+        let dim = 515;
+        let data = synthetic::uniform(203, dim, &mut rng);
+        let blocked = VectorStore::from_rows(dim, &data);
+        assert_eq!(blocked.get_layout(), VectorLayout::Blocked);
+        assert_eq!(blocked.len(), 203);
+        if let VectorStore::Blocked(b) = &blocked {
+            assert_eq!(b.get(201), data[201]);
+        }
+        let mut row = VectorStore::new(VectorLayout::Row, dim);
+        data.iter().for_each(|v| row.push(v));
+        for q in data.iter().take(10) {
+            let from_blocked = blocked.knn_l2(q, 10);
+            let from_row = row.knn_l2(q, 10);
+            assert_eq!(from_blocked.len(), 10);
+            for (b, r) in from_blocked.iter().zip(&from_row) {
+                assert_eq!(b.0, r.0);
+                assert!((b.1 - r.1).abs() <= 1.0e-4 * r.1.max(1.));
+            }
+        }
+    } // end of test_blocked_layout
+
+    #[test]
+    fn test_knn_l2_batch() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(953);
+        let data = synthetic::uniform(500, 24, &mut rng);
+        let store = VectorStore::from_rows(24, &data);
+        assert_eq!(store.get_layout(), VectorLayout::Row);
+        // 2 groups and 3 queries alone
+        let queries = synthetic::uniform(11, 24, &mut rng);
+        let batch = store.knn_l2_batch(&queries, 10);
+        assert_eq!(batch.len(), 11);
+        for (q, answer) in queries.iter().zip(&batch) {
+            let expected = store.knn_l2(q, 10);
+            assert_eq!(answer.len(), 10);
+            for (a, e) in answer.iter().zip(&expected) {
+                assert!((a.1 - e.1).abs() <= 1.0e-4 * e.1.max(1.));
+            }
+        }
+    } // end of test_knn_l2_batch
+} // end of mod tests
@@ -5,14 +5,76 @@ use env_logger::Builder;
 
 use lazy_static::lazy_static;
 
+pub mod access;
+pub mod adaptive;
+pub mod alias;
 pub mod api;
+pub mod asymmetric;
+pub mod asyncsearch;
+pub mod bench;
+pub mod blockdump;
+pub mod checkpoint;
+pub mod compose;
+pub mod concurrent;
+pub mod costmodel;
 pub mod datamap;
+pub mod datasets;
+pub mod dedup;
+pub mod diagnostics;
+#[cfg(feature = "dtw")]
+pub mod dtw;
+pub mod embed;
+pub mod ensemble;
+pub mod events;
+pub mod extract;
 pub mod filter;
+pub mod flathnsw;
 pub mod flatten;
+pub mod graphkind;
+pub mod health;
 pub mod hnsw;
 pub mod hnswio;
+pub mod hotswap;
+pub mod idset;
+pub mod ingest;
+pub mod kernel;
+pub mod knngraph;
+pub mod layergrowth;
+pub mod layout;
 pub mod libext;
+pub mod linkcheck;
+pub mod manifest;
+pub mod namespace;
+#[cfg(feature = "node")]
+pub mod node;
+pub mod pagination;
+pub mod payload;
+pub mod persist;
 pub mod prelude;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod quality;
+pub mod quantizer;
+#[cfg(feature = "qdrant")]
+pub mod qdrant;
+pub mod quiesce;
+pub mod readonly;
+pub mod readrepair;
+pub mod rebuild;
+pub mod rerank;
+pub mod scratch;
+pub mod searcher;
+pub mod searchparams;
+pub mod segments;
+pub mod sequence;
+pub mod simd;
+pub mod slowquery;
+pub mod statshistory;
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+pub mod tiebreak;
+pub mod tombstone;
+pub mod topk;
 
 // we impose our version of anndists
 pub use anndists;
@@ -22,6 +84,7 @@ lazy_static! {
 }
 
 // install a logger facility
+#[allow(dead_code)]
 fn init_log() -> u64 {
     Builder::from_default_env().init();
     println!("\n ************** initializing logger *****************\n");
@@ -2,10 +2,13 @@
 //! The AnnT trait is implemented with macros for u32, u16, u8, f32, f64 and i32.  
 //! The macro declare_myapi_type!  produces struct HnswApif32 and so on.
 //!
+//! Ids of points are u64 on all targets (as in dumps), they must fit in a usize ([DataId]) of the target.
+//!
 
 #![allow(non_camel_case_types)]
 
 use core::ffi::c_ulonglong;
+use std::cell::RefCell;
 use std::fs::OpenOptions;
 use std::io::BufReader;
 use std::path::PathBuf;
@@ -18,6 +21,11 @@ use crate::api::*;
 use crate::hnsw::*;
 use crate::hnswio::*;
 
+// id of the C api to DataId, panics if it does not fit
+fn to_data_id(id: u64) -> DataId {
+    data_id_of(id).unwrap_or_else(|e| panic!("{}", e))
+}
+
 //========== Hnswio
 
 /// returns a pointer to a Hnswio
@@ -65,7 +73,7 @@ pub struct Vec_api<T> {
 /// The basic Neighbour info returned by api
 pub struct Neighbour_api {
     /// id of neighbour
-    pub id: usize,
+    pub id: u64,
     /// distance of data sent in request to this neighbour
     pub d: f32,
 }
@@ -73,7 +81,7 @@ pub struct Neighbour_api {
 impl From<&Neighbour> for Neighbour_api {
     fn from(neighbour: &Neighbour) -> Self {
         Neighbour_api {
-            id: neighbour.d_id,
+            id: neighbour.d_id as u64,
             d: neighbour.distance,
         }
     }
@@ -109,7 +117,7 @@ macro_rules! generate_insert(
         /// The function is unsafe because it dereferences a raw pointer
         ///
         #[unsafe(no_mangle)]
-        pub unsafe extern "C" fn $function_name(hnsw_api : *mut $api_name, len:usize, data : *const $type_val, id : usize) {
+        pub unsafe extern "C" fn $function_name(hnsw_api : *mut $api_name, len:usize, data : *const $type_val, id : u64) {
         trace!("entering insert, type {:?} vec len is {:?}, id : {:?} ", stringify!($type_val), len, id);
         //  construct vector: Rust clones and take ownership.
         let data_v : Vec<$type_val>;
@@ -117,7 +125,7 @@ macro_rules! generate_insert(
             let slice = std::slice::from_raw_parts(data, len);
             data_v = Vec::from(slice);
             trace!("calling insert data");
-            (*hnsw_api).opaque.insert_data(&data_v, id);
+            (*hnsw_api).opaque.insert_data(&data_v, to_data_id(id));
         }
         trace!("exiting insert for type {:?}", stringify!($type_val));
         }  // end of insert
@@ -131,14 +139,14 @@ macro_rules! generate_parallel_insert(
         ///
         #[unsafe(no_mangle)]
         pub unsafe extern "C" fn $function_name(hnsw_api : *mut $api_name, nb_vec: usize, vec_len : usize,
-                        datas : *mut *const $type_val, ids : *const usize) {
+                        datas : *mut *const $type_val, ids : *const u64) {
             //
             trace!("entering parallel_insert type {:?}  , vec len is {:?}, nb_vec : {:?}", stringify!($type_val), vec_len, nb_vec);
             let data_ids : Vec<usize>;
             let data_ptrs : Vec<*const $type_val>;
             unsafe {
                 let slice = std::slice::from_raw_parts(ids, nb_vec);
-                data_ids = Vec::from(slice);
+                data_ids = slice.iter().map(|id| to_data_id(*id)).collect();
             }
             // debug!("got ids");
             unsafe {
@@ -202,6 +210,45 @@ macro_rules! generate_search_neighbours(
     )
 );
 
+// results of the searches of a thread by search_neighbours_into_*, reused from call to call
+thread_local! {
+    static SEARCH_RESULTS: RefCell<Vec<Neighbour>> = const { RefCell::new(Vec::new()) };
+}
+
+macro_rules! generate_search_neighbours_into(
+($function_name:ident, $api_name:ty, $type_val:ty) => (
+        /// search knbn neighbours of data (of size len) and writes their ids in out_ids and their distances
+        /// in out_dists, arrays allocated by the caller with at least knbn slots. Returns the number of neighbours
+        /// written. Neither the query nor the answer is copied in a new allocation, and the search reuses the
+        /// structures of the previous searches of the thread, so a thread calling it in a loop does not allocate
+        /// once warmed up.
+        /// # Safety
+        /// The function is unsafe because it dereferences raw pointers
+        ///
+        #[unsafe(no_mangle)]
+        pub unsafe extern "C" fn $function_name(hnsw_api : *const $api_name, len:usize, data : *const $type_val,
+                                knbn : usize, ef_search : usize, out_ids : *mut u64, out_dists : *mut f32) -> usize {
+            trace!("entering search_neighbours_into type {:?}, vec len is {:?}, knbn : {:?} ef_search {:?}", stringify!($type_val), len, knbn, ef_search);
+            SEARCH_RESULTS.with_borrow_mut(|neighbours| {
+                let nb;
+                unsafe {
+                    let query = std::slice::from_raw_parts(data, len);
+                    (*hnsw_api).opaque.search_neighbours_into(query, knbn, ef_search, neighbours);
+                    nb = neighbours.len().min(knbn);
+                    let ids = std::slice::from_raw_parts_mut(out_ids, nb);
+                    let dists = std::slice::from_raw_parts_mut(out_dists, nb);
+                    for (n, (id, d)) in neighbours.iter().zip(ids.iter_mut().zip(dists.iter_mut())) {
+                        *id = n.d_id as u64;
+                        *d = n.distance;
+                    }
+                }
+                trace!("search_neighbours_into returning nb neighbours {:?}", nb);
+                nb
+            })
+        }
+    )
+);
+
 macro_rules! generate_parallel_search_neighbours(
 ($function_name:ident, $api_name:ty, $type_val:ty) => (
         #[unsafe(no_mangle)]
@@ -662,7 +709,7 @@ pub unsafe extern "C" fn insert_f32(
     hnsw_api: *mut HnswApif32,
     len: usize,
     data: *const f32,
-    id: usize,
+    id: u64,
 ) {
     trace!("entering insert_f32, vec len is {:?}, id : {:?} ", len, id);
     //  construct vector: Rust clones and take ownership.
@@ -671,7 +718,7 @@ pub unsafe extern "C" fn insert_f32(
         let slice = std::slice::from_raw_parts(data, len);
         data_v = Vec::from(slice);
         //    debug!("calling insert data");
-        (*hnsw_api).opaque.insert_data(&data_v, id);
+        (*hnsw_api).opaque.insert_data(&data_v, to_data_id(id));
     }
     // trace!("exiting insert_f32");
 } // end of insert_f32
@@ -685,7 +732,7 @@ pub unsafe extern "C" fn parallel_insert_f32(
     nb_vec: usize,
     vec_len: usize,
     datas: *mut *const f32,
-    ids: *const usize,
+    ids: *const u64,
 ) {
     //
     // debug!("entering parallel_insert_f32 , vec len is {:?}, nb_vec : {:?}", vec_len, nb_vec);
@@ -693,7 +740,7 @@ pub unsafe extern "C" fn parallel_insert_f32(
     let data_ptrs: Vec<*const f32>;
     unsafe {
         let slice = std::slice::from_raw_parts(ids, nb_vec);
-        data_ids = Vec::from(slice);
+        data_ids = slice.iter().map(|id| to_data_id(*id)).collect();
     }
     unsafe {
         let slice = std::slice::from_raw_parts(datas, nb_vec);
@@ -767,6 +814,7 @@ pub unsafe extern "C" fn search_neighbours_f32(
 }
 // end of search_neighbours for HnswApif32
 
+generate_search_neighbours_into!(search_neighbours_into_f32, HnswApif32, f32);
 generate_parallel_search_neighbours!(parallel_search_neighbours_f32, HnswApif32, f32);
 generate_file_dump!(file_dump_f32, HnswApif32, f32);
 
@@ -831,6 +879,7 @@ super::declare_myapi_type!(HnswApii32, i32);
 generate_insert!(insert_i32, HnswApii32, i32);
 generate_parallel_insert!(parallel_insert_i32, HnswApii32, i32);
 generate_search_neighbours!(search_neighbours_i32, HnswApii32, i32);
+generate_search_neighbours_into!(search_neighbours_into_i32, HnswApii32, i32);
 generate_parallel_search_neighbours!(parallel_search_neighbours_i32, HnswApii32, i32);
 generate_file_dump!(file_dump_i32, HnswApii32, i32);
 
@@ -900,6 +949,7 @@ super::declare_myapi_type!(HnswApiu32, u32);
 generate_insert!(insert_u32, HnswApiu32, u32);
 generate_parallel_insert!(parallel_insert_u32, HnswApiu32, u32);
 generate_search_neighbours!(search_neighbours_u32, HnswApiu32, u32);
+generate_search_neighbours_into!(search_neighbours_into_u32, HnswApiu32, u32);
 generate_parallel_search_neighbours!(parallel_search_neighbours_u32, HnswApiu32, u32);
 generate_file_dump!(file_dump_u32, HnswApiu32, u32);
 
@@ -1044,6 +1094,7 @@ pub extern "C" fn init_hnsw_ptrdist_u16(
 generate_insert!(insert_u16, HnswApiu16, u16);
 generate_parallel_insert!(parallel_insert_u16, HnswApiu16, u16);
 generate_search_neighbours!(search_neighbours_u16, HnswApiu16, u16);
+generate_search_neighbours_into!(search_neighbours_into_u16, HnswApiu16, u16);
 generate_parallel_search_neighbours!(parallel_search_neighbours_u16, HnswApiu16, u16);
 generate_file_dump!(file_dump_u16, HnswApiu16, u16);
 
@@ -1112,6 +1163,7 @@ pub extern "C" fn init_hnsw_ptrdist_u8(
 generate_insert!(insert_u8, HnswApiu8, u8);
 generate_parallel_insert!(parallel_insert_u8, HnswApiu8, u8);
 generate_search_neighbours!(search_neighbours_u8, HnswApiu8, u8);
+generate_search_neighbours_into!(search_neighbours_into_u8, HnswApiu8, u8);
 generate_parallel_search_neighbours!(parallel_search_neighbours_u8, HnswApiu8, u8);
 generate_file_dump!(file_dump_u8, HnswApiu8, u8);
 
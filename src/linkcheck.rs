@@ -0,0 +1,152 @@
+//! Checks of the invariants of neighbour lists.
+//!
+//! A neighbour list never holds its own point nor the same point twice: a duplicate takes the slot of a real neighbour
+//! and, as a search skips visited points, only lowers recall. When a reverse link makes a list overflow, the pruned
+//! neighbour is the farthest one other than the inserted point, so that the links made by an insertion stay symmetric
+//! and the inserted point keeps its in-links.
+//!
+//! In debug builds (debug_assertions) the lists of an inserted point and of its neighbours are checked at the end
+//! of each insertion, so tests panic at the insertion breaking an invariant. [Hnsw::check_links] checks
+//! a whole structure.
+
+use hashbrown::HashSet;
+use std::sync::Arc;
+
+use anyhow::anyhow;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Point, PointId, PointWithOrder};
+
+// checks the list of point p_id at layer
+fn check_list<T>(p_id: PointId, layer: usize, list: &[Arc<PointWithOrder<T>>]) -> anyhow::Result<()>
+where
+    T: Clone + Send + Sync,
+{
+    let mut seen = HashSet::<PointId>::with_capacity(list.len());
+    for n in list {
+        let n_id = n.point_ref.get_point_id();
+        if n_id == p_id {
+            return Err(anyhow!(
+                "point {:?} is its own neighbour at layer {}",
+                p_id,
+                layer
+            ));
+        }
+        if !seen.insert(n_id) {
+            return Err(anyhow!(
+                "point {:?} has neighbour {:?} twice at layer {}",
+                p_id,
+                n_id,
+                layer
+            ));
+        }
+    }
+    Ok(())
+}
+
+// checks all lists of point
+pub(crate) fn check_point<T>(point: &Point<T>) -> anyhow::Result<()>
+where
+    T: Clone + Send + Sync,
+{
+    let neighbours = point.neighbours.read();
+    for (layer, list) in neighbours.iter().enumerate() {
+        check_list(point.get_point_id(), layer, list)?;
+    }
+    Ok(())
+}
+
+impl<'b, T, D> Hnsw<'b, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// checks that no neighbour list holds its own point or a point twice, returns the first violation found
+    pub fn check_links(&self) -> anyhow::Result<()> {
+        let layers = self.layer_indexed_points.points_by_layer.read();
+        for point in layers.iter().flat_map(|layer| layer.iter()) {
+            check_point(point)?;
+        }
+        Ok(())
+    }
+
+    /// checks lists of point and of its neighbours. Lists are locked one at a time.
+    pub(crate) fn check_point_links(&self, point: &Point<'b, T>) -> anyhow::Result<()> {
+        check_point(point)?;
+        let neighbours: Vec<Arc<Point<'b, T>>> = point
+            .neighbours
+            .read()
+            .iter()
+            .flat_map(|list| list.iter().map(|n| Arc::clone(&n.point_ref)))
+            .collect();
+        for n in neighbours {
+            check_point(&n)?;
+        }
+        Ok(())
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_check_links() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(977);
+        let (data, _) = synthetic::gaussian_mixture(2000, 8, 5, 0.01, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        // few connections on clustered data make lists overflow and be pruned often
+        // a serial seeded build, so that recall does not depend on thread scheduling
+        let mut hnsw = Hnsw::<f32, DistL2>::new(4, 2000, 16, 50, DistL2 {});
+        hnsw.set_level_seed(977);
+        for (v, id) in &data_with_id {
+            hnsw.insert((v.as_slice(), *id));
+        }
+        // the same vectors again: candidates at distance 0 of the inserted points
+        for (v, id) in data.iter().zip(2000..).take(200) {
+            hnsw.insert((v.as_slice(), id));
+        }
+        hnsw.check_links().unwrap();
+        let nb_found = (0..2000)
+            .step_by(5)
+            .filter(|i| {
+                hnsw.search(&data[*i], 10, 32)
+                    .iter()
+                    .any(|n| n.d_id == *i || n.d_id == *i + 2000)
+            })
+            .count();
+        // 291 found when pruning the farthest neighbour, 337 when keeping the reverse links
+        assert!(nb_found >= 330, "self recall {} / 400", nb_found);
+        // inserted points keep their reverse links, no point of layer 0 is isolated
+        let layers = hnsw.get_point_indexation().points_by_layer.read();
+        let nb_isolated = layers[0]
+            .iter()
+            .filter(|p| {
+                p.neighbours.read()[0].is_empty()
+                    && !layers[0].iter().any(|q| {
+                        q.neighbours.read()[0]
+                            .iter()
+                            .any(|n| n.point_ref.get_point_id() == p.get_point_id())
+                    })
+            })
+            .count();
+        assert_eq!(nb_isolated, 0);
+        drop(layers);
+        // a list with a duplicate is reported
+        let point = hnsw
+            .get_point_indexation()
+            .get_point(&PointId(0, 0))
+            .unwrap();
+        let mut neighbours = point.neighbours.write();
+        let first = Arc::clone(&neighbours[0][0]);
+        neighbours[0].push(first);
+        drop(neighbours);
+        assert!(hnsw.check_links().is_err());
+        assert!(hnsw.check_point_links(&point).is_err());
+    } // end of test_check_links
+} // end of mod tests
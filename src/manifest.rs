@@ -0,0 +1,463 @@
+//! Dumps in a directory described by a manifest.
+//!
+//! A file dump (see [crate::hnswio]) is the pair of files dir/name.hnsw.graph and dir/name.hnsw.data: the other
+//! parts of an index (payloads, codebooks of a quantizer, segments, a log of writes) have no place in it and a
+//! reader must know the naming convention. A manifested dump is a directory of blobs and a file manifest.json
+//! listing them with their kind, size and crc32, so that tools in any language find and check the parts of an index:
+//!
+//! ```text
+//! index/manifest.json
+//! index/graph.bin      graph of a file dump
+//! index/data.bin       vectors of a file dump
+//! index/payloads.bin   payloads of points, if some point has one
+//! ```
+//!
+//! [Hnsw::dump_manifested] writes graph, vectors and payloads, [Manifest::add_blob] adds blobs of other kinds
+//! (for example the FAISS bytes of a [Quantizer](crate::quantizer::Quantizer)), they are kept by the next dumps
+//! in the same directory. [Manifest::load_hnsw] reloads the structure with its payloads.
+//!
+//! payloads.bin is a sequence of records of little endian values: origin id u64, tags u16, namespace u32,
+//! the [NB_ATTRIBUTES] attributes f64 (NaN if not set), latitude and longitude f64 (NaN if not set).
+//!
+//! The manifest is written last (replaced by a rename): a dump interrupted by a crash is detected by the sizes
+//! and crc32 of the blobs, see [Manifest::verify].
+//!
+//! Dumps in the old layout are read by [Manifest::from_legacy], [Manifest::migrate] copies them in a directory.
+
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{Result, anyhow};
+use log::info;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, OwnedHnsw};
+use crate::hnswio::{HnswIo, crc32, file_crc32, load_description};
+use crate::payload::{NB_ATTRIBUTES, Payload};
+
+/// name of the manifest in a dump directory
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// version of the manifest written, a manifest of a later version is not read
+pub const MANIFEST_VERSION: u32 = 1;
+
+// bytes of a payload record, see module doc
+const PAYLOAD_RECORD: usize = 8 + 2 + 4 + 8 * NB_ATTRIBUTES + 16;
+
+/// kind of a blob of a manifested dump
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BlobKind {
+    /// graph of a file dump
+    Graph,
+    /// vectors of a file dump
+    Data,
+    /// payloads of points, see module doc
+    Payloads,
+    /// codebooks of a quantizer
+    Quantizer,
+    /// a part of a partitioned index
+    Segment,
+    /// a log of writes not yet in the graph
+    Wal,
+    /// a kind of a later version, ignored
+    #[serde(other)]
+    Unknown,
+}
+
+/// a file of a manifested dump
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Blob {
+    pub kind: BlobKind,
+    /// path relative to the dump directory
+    pub path: String,
+    /// size in bytes
+    pub size: u64,
+    /// crc32 (as in zip or gzip)
+    pub crc32: u32,
+}
+
+/// content of manifest.json, see module doc
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub version: u32,
+    /// type of vector values and distance, as in the description of the graph
+    pub typename: String,
+    pub distname: String,
+    pub dimension: usize,
+    pub nb_point: usize,
+    pub metadata: BTreeMap<String, String>,
+    pub blobs: Vec<Blob>,
+}
+
+impl Manifest {
+    // manifest of a graph and its vectors, from the description of the graph
+    fn describe(dir: &Path, graph: &str, data: &str) -> Result<Self> {
+        let mut graph_in = BufReader::new(File::open(dir.join(graph))?);
+        let description = load_description(&mut graph_in)?;
+        Ok(Manifest {
+            version: MANIFEST_VERSION,
+            typename: description.get_typename(),
+            distname: description.distname.clone(),
+            dimension: description.get_dimension(),
+            nb_point: description.nb_point,
+            metadata: description.get_metadata().clone(),
+            blobs: vec![
+                Manifest::blob_of(dir, BlobKind::Graph, graph)?,
+                Manifest::blob_of(dir, BlobKind::Data, data)?,
+            ],
+        })
+    }
+
+    fn blob_of(dir: &Path, kind: BlobKind, path: &str) -> Result<Blob> {
+        let (crc32, size) = file_crc32(&dir.join(path))?;
+        Ok(Blob {
+            kind,
+            path: path.to_string(),
+            size,
+            crc32,
+        })
+    }
+
+    /// reads the manifest of a dump directory
+    pub fn read(dir: &Path) -> Result<Self> {
+        let path = dir.join(MANIFEST_NAME);
+        let file = File::open(&path).map_err(|e| anyhow!("cannot open {:?} : {}", path, e))?;
+        let manifest: Manifest = serde_json::from_reader(BufReader::new(file))?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(anyhow!(
+                "manifest version {} in {:?}, {} at most can be read",
+                manifest.version,
+                dir,
+                MANIFEST_VERSION
+            ));
+        }
+        Ok(manifest)
+    }
+
+    /// the manifest of a dump in the old layout, dir/basename.hnsw.graph and dir/basename.hnsw.data.
+    /// Paths of blobs are relative to dir, nothing is written.
+    pub fn from_legacy(dir: &Path, basename: &str) -> Result<Self> {
+        Manifest::describe(
+            dir,
+            &format!("{}.hnsw.graph", basename),
+            &format!("{}.hnsw.data", basename),
+        )
+    }
+
+    /// copies a dump in the old layout in the directory index_dir (created if needed) with its manifest.
+    /// The old files are left.
+    pub fn migrate(dir: &Path, basename: &str, index_dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(index_dir)?;
+        std::fs::copy(
+            dir.join(format!("{}.hnsw.graph", basename)),
+            index_dir.join("graph.bin"),
+        )?;
+        std::fs::copy(
+            dir.join(format!("{}.hnsw.data", basename)),
+            index_dir.join("data.bin"),
+        )?;
+        let manifest = Manifest::describe(index_dir, "graph.bin", "data.bin")?;
+        manifest.write(index_dir)?;
+        info!(
+            "dump {:?} of {:?} migrated in {:?}",
+            basename, dir, index_dir
+        );
+        Ok(manifest)
+    }
+
+    // writes manifest.json by a rename
+    fn write(&self, dir: &Path) -> Result<()> {
+        let tmp = dir.join(format!("{}.tmp", MANIFEST_NAME));
+        let file = File::create(&tmp)?;
+        serde_json::to_writer_pretty(&file, self)?;
+        file.sync_all()?;
+        std::fs::rename(&tmp, dir.join(MANIFEST_NAME))?;
+        Ok(())
+    }
+
+    /// first blob of a kind
+    pub fn get_blob(&self, kind: BlobKind) -> Option<&Blob> {
+        self.blobs.iter().find(|b| b.kind == kind)
+    }
+
+    /// blobs of a kind, in the order they were added
+    pub fn get_blobs(&self, kind: BlobKind) -> impl Iterator<Item = &Blob> {
+        self.blobs.iter().filter(move |b| b.kind == kind)
+    }
+
+    /// reads a blob of the dump in dir, checks its size and crc32
+    pub fn read_blob(&self, dir: &Path, blob: &Blob) -> Result<Vec<u8>> {
+        let bytes = std::fs::read(dir.join(&blob.path))?;
+        if bytes.len() as u64 != blob.size || crc32(&bytes) != blob.crc32 {
+            return Err(anyhow!(
+                "blob {} does not match the manifest of {:?}",
+                blob.path,
+                dir
+            ));
+        }
+        Ok(bytes)
+    }
+
+    /// writes bytes in the blob dir/name and records it in the manifest of dir
+    pub fn add_blob(&mut self, dir: &Path, kind: BlobKind, name: &str, bytes: &[u8]) -> Result<()> {
+        if name.starts_with(MANIFEST_NAME) || self.blobs.iter().any(|b| b.path == name) {
+            return Err(anyhow!("blob {} is already in {:?}", name, dir));
+        }
+        std::fs::write(dir.join(name), bytes)?;
+        self.blobs.push(Blob {
+            kind,
+            path: name.to_string(),
+            size: bytes.len() as u64,
+            crc32: crc32(bytes),
+        });
+        self.write(dir)
+    }
+
+    /// checks sizes and crc32 of all blobs of the dump in dir
+    pub fn verify(&self, dir: &Path) -> Result<()> {
+        for blob in &self.blobs {
+            let (crc32, size) = file_crc32(&dir.join(&blob.path))?;
+            if (crc32, size) != (blob.crc32, blob.size) {
+                return Err(anyhow!(
+                    "blob {} does not match the manifest of {:?}",
+                    blob.path,
+                    dir
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// reloads the structure of the dump in dir, with the payloads of its points. The structure owns its vectors.
+    pub fn load_hnsw<T, D>(&self, dir: &Path) -> Result<OwnedHnsw<T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+    {
+        let path_of = |kind: BlobKind| {
+            self.get_blob(kind)
+                .map(|b| dir.join(&b.path))
+                .ok_or_else(|| anyhow!("no {:?} blob in manifest of {:?}", kind, dir))
+        };
+        let payloads = match self.get_blob(BlobKind::Payloads) {
+            Some(blob) => decode_payloads(&self.read_blob(dir, blob)?)?,
+            None => HashMap::new(),
+        };
+        let graph_in = File::open(path_of(BlobKind::Graph)?)?;
+        let data_in = File::open(path_of(BlobKind::Data)?)?;
+        HnswIo::load_from_readers_with_payloads(graph_in, data_in, payloads)
+    }
+} // end of impl Manifest
+
+fn encode_payloads(payloads: &[(DataId, Payload)]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(payloads.len() * PAYLOAD_RECORD);
+    for (d_id, payload) in payloads {
+        bytes.extend_from_slice(&(*d_id as u64).to_le_bytes());
+        bytes.extend_from_slice(&payload.get_tags().to_le_bytes());
+        bytes.extend_from_slice(&payload.get_namespace().to_le_bytes());
+        for attr in 0..NB_ATTRIBUTES {
+            bytes.extend_from_slice(&payload.get_attribute(attr).to_le_bytes());
+        }
+        let (lat, lon) = payload.get_geo().unwrap_or((f64::NAN, f64::NAN));
+        bytes.extend_from_slice(&lat.to_le_bytes());
+        bytes.extend_from_slice(&lon.to_le_bytes());
+    }
+    bytes
+}
+
+fn decode_payloads(bytes: &[u8]) -> Result<HashMap<DataId, Payload>> {
+    if !bytes.len().is_multiple_of(PAYLOAD_RECORD) {
+        return Err(anyhow!(
+            "payloads of {} bytes, not a multiple of {}",
+            bytes.len(),
+            PAYLOAD_RECORD
+        ));
+    }
+    let payloads = bytes
+        .chunks_exact(PAYLOAD_RECORD)
+        .map(|record| {
+            let mut at = 0;
+            let mut take = move |n: usize| {
+                at += n;
+                &record[at - n..at]
+            };
+            let d_id = u64::from_le_bytes(take(8).try_into().unwrap()) as DataId;
+            let mut payload = Payload::new()
+                .with_tags(u16::from_le_bytes(take(2).try_into().unwrap()))
+                .with_namespace(u32::from_le_bytes(take(4).try_into().unwrap()));
+            for attr in 0..NB_ATTRIBUTES {
+                payload =
+                    payload.with_attribute(attr, f64::from_le_bytes(take(8).try_into().unwrap()));
+            }
+            let lat = f64::from_le_bytes(take(8).try_into().unwrap());
+            let lon = f64::from_le_bytes(take(8).try_into().unwrap());
+            if !lat.is_nan() && !lon.is_nan() {
+                payload = payload.with_geo(lat, lon);
+            }
+            (d_id, payload)
+        })
+        .collect();
+    Ok(payloads)
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Serialize + DeserializeOwned + Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// dumps the structure in the directory dir (created if needed) with its manifest, see module doc.
+    /// Blobs of other kinds than graph, data and payloads in the manifest of dir are kept.
+    pub fn dump_manifested(&self, dir: &Path) -> Result<Manifest> {
+        std::fs::create_dir_all(dir)?;
+        let kept: Vec<Blob> = match Manifest::read(dir) {
+            Ok(previous) => previous
+                .blobs
+                .into_iter()
+                .filter(|b| {
+                    ![BlobKind::Graph, BlobKind::Data, BlobKind::Payloads].contains(&b.kind)
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        let graph_out = File::create(dir.join("graph.bin"))?;
+        let data_out = File::create(dir.join("data.bin"))?;
+        self.dump_to_writers(&graph_out, &data_out)?;
+        graph_out.sync_all()?;
+        data_out.sync_all()?;
+        let mut manifest = Manifest::describe(dir, "graph.bin", "data.bin")?;
+        let indexation = self.get_point_indexation();
+        let payloads: Vec<(DataId, Payload)> = indexation
+            .into_iter()
+            .map(|point| {
+                (
+                    point.get_origin_id(),
+                    indexation.get_payload(&point.get_point_id()),
+                )
+            })
+            .filter(|(_, payload)| !payload.is_empty())
+            .collect();
+        if !payloads.is_empty() {
+            let bytes = encode_payloads(&payloads);
+            std::fs::write(dir.join("payloads.bin"), &bytes)?;
+            manifest.blobs.push(Blob {
+                kind: BlobKind::Payloads,
+                path: "payloads.bin".to_string(),
+                size: bytes.len() as u64,
+                crc32: crc32(&bytes),
+            });
+        } else {
+            let _ = std::fs::remove_file(dir.join("payloads.bin"));
+        }
+        manifest.blobs.extend(kept);
+        manifest.write(dir)?;
+        info!(
+            "dump of {} points in {:?}, {} blobs",
+            manifest.nb_point,
+            dir,
+            manifest.blobs.len()
+        );
+        Ok(manifest)
+    } // end of dump_manifested
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_manifested_dump() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(987);
+        let data = synthetic::uniform(500, 8, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 500, 16, 100, DistL2 {});
+        for (i, v) in data.iter().enumerate() {
+            let payload = match i % 5 {
+                0 => Payload::new()
+                    .with_tags(1 << (i % 16))
+                    .with_attribute(1, i as f64),
+                1 => Payload::new().with_geo(45., i as f64 / 10.),
+                _ => Payload::new(),
+            };
+            hnsw.insert_with_payload((v.as_slice(), i), payload);
+        }
+        let directory = tempfile::tempdir().unwrap();
+        let index = directory.path().join("index");
+        let mut manifest = hnsw.dump_manifested(&index).unwrap();
+        assert_eq!(manifest.nb_point, 500);
+        assert_eq!(manifest.dimension, 8);
+        assert_eq!(
+            manifest.get_blob(BlobKind::Payloads).unwrap().size,
+            (200 * PAYLOAD_RECORD) as u64
+        );
+        // the manifest is plain json
+        let json: serde_json::Value =
+            serde_json::from_slice(&std::fs::read(index.join(MANIFEST_NAME)).unwrap()).unwrap();
+        assert_eq!(json["blobs"][0]["kind"], "graph");
+        // other blobs are kept by the next dump
+        manifest
+            .add_blob(&index, BlobKind::Quantizer, "codebooks.faiss", &[1, 2, 3])
+            .unwrap();
+        assert!(
+            manifest
+                .add_blob(&index, BlobKind::Wal, "codebooks.faiss", &[])
+                .is_err()
+        );
+        hnsw.dump_manifested(&index).unwrap();
+        let manifest = Manifest::read(&index).unwrap();
+        manifest.verify(&index).unwrap();
+        let blob = manifest.get_blob(BlobKind::Quantizer).unwrap();
+        assert_eq!(manifest.read_blob(&index, blob).unwrap(), vec![1, 2, 3]);
+        // reload with payloads
+        let reloaded: OwnedHnsw<f32, DistL2> = manifest.load_hnsw(&index).unwrap();
+        assert_eq!(reloaded.get_nb_point(), 500);
+        let indexation = reloaded.get_point_indexation();
+        for point in indexation {
+            let i = point.get_origin_id();
+            let payload = indexation.get_payload(&point.get_point_id());
+            match i % 5 {
+                0 => {
+                    assert_eq!(payload.get_tags(), 1 << (i % 16));
+                    assert_eq!(payload.get_attribute(1), i as f64);
+                }
+                1 => assert_eq!(payload.get_geo(), Some((45., i as f64 / 10.))),
+                _ => assert!(payload.is_empty()),
+            }
+        }
+        let ids = |h: &Hnsw<f32, DistL2>, i: usize| -> Vec<DataId> {
+            h.search(&data[i], 5, 32).iter().map(|n| n.d_id).collect()
+        };
+        for i in (0..500).step_by(25) {
+            assert_eq!(ids(&reloaded, i), ids(&hnsw, i));
+        }
+        // a corrupted blob is detected
+        let mut bytes = std::fs::read(index.join("payloads.bin")).unwrap();
+        bytes[10] ^= 1;
+        std::fs::write(index.join("payloads.bin"), bytes).unwrap();
+        assert!(manifest.verify(&index).is_err());
+        assert!(manifest.load_hnsw::<f32, DistL2>(&index).is_err());
+        // old layout
+        let basename = hnsw.file_dump(directory.path(), "legacy").unwrap();
+        let legacy = Manifest::from_legacy(directory.path(), &basename).unwrap();
+        assert_eq!(legacy.blobs.len(), 2);
+        let migrated = Manifest::migrate(
+            directory.path(),
+            &basename,
+            &directory.path().join("migrated"),
+        )
+        .unwrap();
+        assert_eq!(migrated.blobs[0].crc32, legacy.blobs[0].crc32);
+        let reloaded: OwnedHnsw<f32, DistL2> = migrated
+            .load_hnsw(&directory.path().join("migrated"))
+            .unwrap();
+        assert_eq!(ids(&reloaded, 7), ids(&hnsw, 7));
+    } // end of test_manifested_dump
+} // end of mod tests
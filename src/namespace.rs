@@ -0,0 +1,313 @@
+//! Namespaces: several tenants in one structure.
+//!
+//! Points inserted with [Hnsw::insert_ns] carry their namespace in their payload and [Hnsw::search_ns]
+//! only returns points of the namespace asked for. The namespace is checked during traversal as other
+//! constraints of [SearchParams], so many small tenants can share one graph instead of one index each.
+//!
+//! [Hnsw::delete_namespace] removes all points of a namespace from search results at once.
+//! Points stay in the graph (they are still used for navigation) until a rebuild (see [crate::rebuild]).
+//! A deleted namespace cannot receive new points.
+//! Deduplication (see [crate::dedup]) only maps a vector to a point of its own namespace.
+//! Statistics and deleted namespaces are kept by dumps and by rebuilds.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+
+use anyhow::anyhow;
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, HnswError, Neighbour, Point};
+use crate::payload::{Namespace, Payload};
+use crate::searchparams::SearchParams;
+
+/// statistics of a namespace
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct NamespaceStats {
+    /// number of points inserted with [Hnsw::insert_ns]
+    pub nb_point: usize,
+    /// number of requests made with [Hnsw::search_ns]
+    pub nb_search: usize,
+}
+
+/// namespaces known by a Hnsw
+#[derive(Default)]
+pub(crate) struct Namespaces {
+    stats: Mutex<HashMap<Namespace, NamespaceStats>>,
+    deleted: RwLock<HashSet<Namespace>>,
+}
+
+impl Clone for Namespaces {
+    fn clone(&self) -> Self {
+        Namespaces {
+            stats: Mutex::new(self.stats.lock().clone()),
+            deleted: RwLock::new(self.deleted.read().clone()),
+        }
+    }
+}
+
+impl Namespaces {
+    fn is_deleted(&self, namespace: Namespace) -> bool {
+        self.deleted.read().contains(&namespace)
+    }
+
+    pub(crate) fn has_deleted(&self) -> bool {
+        !self.deleted.read().is_empty()
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.stats.lock().is_empty() && self.deleted.read().is_empty()
+    }
+
+    /// number of namespaces with stats as u64, then for each namespace (u32), nb_point and nb_search (u64),
+    /// then deleted namespaces (u32). In native endianness as the rest of the dump.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let stats = self.stats.lock();
+        let deleted = self.deleted.read();
+        let mut bytes = Vec::with_capacity(8 + 20 * stats.len() + 4 * deleted.len());
+        bytes.extend_from_slice(&(stats.len() as u64).to_ne_bytes());
+        for (namespace, s) in stats.iter() {
+            bytes.extend_from_slice(&namespace.to_ne_bytes());
+            bytes.extend_from_slice(&(s.nb_point as u64).to_ne_bytes());
+            bytes.extend_from_slice(&(s.nb_search as u64).to_ne_bytes());
+        }
+        for namespace in deleted.iter() {
+            bytes.extend_from_slice(&namespace.to_ne_bytes());
+        }
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        let bad_length = || anyhow!("namespace block of dump has bad length {}", bytes.len());
+        let (nb_stats, rest) = bytes.split_first_chunk::<8>().ok_or_else(bad_length)?;
+        let nb_stats = u64::from_ne_bytes(*nb_stats) as usize;
+        let stats_len = nb_stats.checked_mul(20).ok_or_else(bad_length)?;
+        if rest.len() < stats_len || !(rest.len() - stats_len).is_multiple_of(4) {
+            return Err(bad_length());
+        }
+        let (stats_bytes, deleted_bytes) = rest.split_at(stats_len);
+        let u64_at = |b: &[u8]| u64::from_ne_bytes(b.try_into().unwrap()) as usize;
+        let stats = stats_bytes
+            .chunks_exact(20)
+            .map(|record| {
+                let namespace = Namespace::from_ne_bytes(record[..4].try_into().unwrap());
+                let stats = NamespaceStats {
+                    nb_point: u64_at(&record[4..12]),
+                    nb_search: u64_at(&record[12..]),
+                };
+                (namespace, stats)
+            })
+            .collect();
+        let deleted = deleted_bytes
+            .chunks_exact(4)
+            .map(|b| Namespace::from_ne_bytes(b.try_into().unwrap()))
+            .collect();
+        Ok(Namespaces {
+            stats: Mutex::new(stats),
+            deleted: RwLock::new(deleted),
+        })
+    }
+} // end of impl Namespaces
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// inserts data with its id in namespace. Fails if namespace was deleted or if the point is not inserted
+    /// (see [Hnsw::insert_dedup]).
+    pub fn insert_ns(
+        &self,
+        namespace: Namespace,
+        data_with_id: (&[T], usize),
+    ) -> anyhow::Result<()> {
+        if self.namespaces.is_deleted(namespace) {
+            return Err(anyhow!("namespace {} was deleted", namespace));
+        }
+        let origin_id = data_with_id.1;
+        let payload = Payload::new().with_namespace(namespace);
+        // a duplicate (see set_dedup) is not a new point
+        if self.insert_canonical(data_with_id, payload)? == origin_id {
+            self.namespaces
+                .stats
+                .lock()
+                .entry(namespace)
+                .or_default()
+                .nb_point += 1;
+        }
+        Ok(())
+    }
+
+    /// parallel insertion in namespace. Fails if namespace was deleted.
+    pub fn parallel_insert_ns(
+        &self,
+        namespace: Namespace,
+        datas: &[(&[T], usize)],
+    ) -> anyhow::Result<()> {
+        datas
+            .par_iter()
+            .try_for_each(|&data_with_id| self.insert_ns(namespace, data_with_id))
+    }
+
+    /// search of knbn neighbours among points of namespace
+    pub fn search_ns(
+        &self,
+        namespace: Namespace,
+        data: &[T],
+        knbn: usize,
+        ef_arg: usize,
+    ) -> Vec<Neighbour> {
+        if self.namespaces.is_deleted(namespace) {
+            return Vec::new();
+        }
+        if let Some(stats) = self.namespaces.stats.lock().get_mut(&namespace) {
+            stats.nb_search += 1;
+        }
+        let params = SearchParams::new().in_namespace(namespace);
+        self.search_with_params(data, knbn, ef_arg, &params)
+    }
+
+    /// statistics of a namespace, None if no point was inserted in it or if it was deleted
+    pub fn get_namespace_stats(&self, namespace: Namespace) -> Option<NamespaceStats> {
+        self.namespaces.stats.lock().get(&namespace).copied()
+    }
+
+    /// namespaces with points, in increasing order
+    pub fn get_namespaces(&self) -> Vec<Namespace> {
+        let mut namespaces: Vec<Namespace> = self.namespaces.stats.lock().keys().copied().collect();
+        namespaces.sort_unstable();
+        namespaces
+    }
+
+    /// removes all points of namespace from search results and returns their number,
+    /// [HnswError::ReadOnly] if the index is read only
+    pub fn delete_namespace(&self, namespace: Namespace) -> Result<usize, HnswError> {
+        if self.read_only {
+            return Err(HnswError::ReadOnly);
+        }
+        self.namespaces.deleted.write().insert(namespace);
+        let stats = self.namespaces.stats.lock().remove(&namespace);
+        log::info!("deleted namespace {}, stats : {:?}", namespace, stats);
+        Ok(stats.map(|s| s.nb_point).unwrap_or(0))
+    }
+
+    /// returns true if point is in a deleted namespace
+    pub(crate) fn is_namespace_deleted(&self, point: &Point<T>) -> bool {
+        self.namespaces.has_deleted()
+            && self.namespaces.is_deleted(
+                self.layer_indexed_points
+                    .get_payload(&point.get_point_id())
+                    .get_namespace(),
+            )
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use crate::dedup::{DedupPolicy, NearAction};
+    use crate::hnswio::HnswIo;
+    use crate::rebuild::RebuildParams;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_namespaces() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(117);
+        let data = synthetic::uniform(3000, 8, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 200, DistL2 {});
+        // 3 tenants, ids interleaved
+        for ns in 0..3u32 {
+            let datas: Vec<(&[f32], usize)> = data
+                .iter()
+                .enumerate()
+                .filter(|(i, _)| *i as u32 % 3 == ns)
+                .map(|(i, v)| (v.as_slice(), i))
+                .collect();
+            hnsw.parallel_insert_ns(ns + 1, &datas).unwrap();
+        }
+        assert_eq!(hnsw.get_namespaces(), vec![1, 2, 3]);
+        for q in data.iter().take(20) {
+            let res = hnsw.search_ns(2, q, 10, 64);
+            assert_eq!(res.len(), 10);
+            assert!(res.iter().all(|n| n.d_id % 3 == 1));
+        }
+        let stats = hnsw.get_namespace_stats(2).unwrap();
+        assert_eq!(stats.nb_point, 1000);
+        assert_eq!(stats.nb_search, 20);
+        //
+        assert_eq!(hnsw.delete_namespace(2), Ok(1000));
+        assert!(hnsw.search_ns(2, &data[1], 10, 64).is_empty());
+        let res = hnsw.search(&data[1], 10, 64);
+        assert!(!res.is_empty());
+        assert!(res.iter().all(|n| n.d_id % 3 != 1));
+        assert!(hnsw.insert_ns(2, (&data[0], 5000)).is_err());
+        assert_eq!(hnsw.get_namespaces(), vec![1, 3]);
+        // a rejected point is not counted
+        assert!(hnsw.insert_ns(1, (&[], 5000)).is_err());
+        assert_eq!(hnsw.get_namespace_stats(1).unwrap().nb_point, 1000);
+    } // end of test_namespaces
+
+    #[test]
+    fn test_namespaces_kept() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(920);
+        let data = synthetic::uniform(600, 8, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert_ns(i as u32 % 3, (v, i)).unwrap();
+        }
+        hnsw.search_ns(1, &data[0], 5, 32);
+        hnsw.delete_namespace(2).unwrap();
+        let check = |h: &Hnsw<f32, DistL2>| {
+            assert_eq!(h.get_namespaces(), vec![0, 1]);
+            let stats = h.get_namespace_stats(1).unwrap();
+            assert_eq!((stats.nb_point, stats.nb_search), (200, 1));
+            assert!(h.insert_ns(2, (&data[2], 1000)).is_err());
+            let res = h.search_ns(1, &data[1], 10, 64);
+            assert_eq!(res.len(), 10);
+            assert!(res.iter().all(|n| n.d_id % 3 == 1));
+        };
+        // by a dump
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "namespaces").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "namespaces");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        check(&reloaded);
+        // by a rebuild
+        let rebuilt = hnsw.rebuild_with(&RebuildParams::from_hnsw(&hnsw));
+        assert_eq!(rebuilt.get_nb_point(), 400);
+        check(&rebuilt);
+    } // end of test_namespaces_kept
+
+    #[test]
+    fn test_namespaces_dedup() {
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 100, 16, 100, DistL2 {});
+        hnsw.set_dedup(DedupPolicy::Near {
+            epsilon: 0.01,
+            action: NearAction::Alias,
+        });
+        let data: Vec<Vec<f32>> = (0..20).map(|i| vec![i as f32, 2., 0.]).collect();
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert_ns(1, (v, i)).unwrap();
+        }
+        // same vectors in another tenant are new points
+        hnsw.insert_ns(2, (&data[3], 100)).unwrap();
+        hnsw.insert_ns(2, (&[4.001, 2., 0.], 101)).unwrap();
+        hnsw.insert((&data[5], 102));
+        assert_eq!(hnsw.get_nb_point(), 23);
+        assert_eq!(hnsw.get_canonical_id(100), 100);
+        assert_eq!(hnsw.search_ns(2, &data[3], 1, 16)[0].d_id, 100);
+        assert_eq!(hnsw.search_ns(2, &data[4], 1, 16)[0].d_id, 101);
+        // duplicates in a tenant
+        hnsw.insert_ns(2, (&data[3], 103)).unwrap();
+        hnsw.insert_ns(1, (&[5.001, 2., 0.], 104)).unwrap();
+        assert_eq!(hnsw.get_nb_point(), 23);
+        assert_eq!(hnsw.get_canonical_id(103), 100);
+        assert_eq!(hnsw.get_canonical_id(104), 5);
+        assert_eq!(hnsw.get_namespace_stats(2).unwrap().nb_point, 2);
+    } // end of test_namespaces_dedup
+} // end of mod tests
@@ -0,0 +1,221 @@
+//! Node.js binding (feature node), made with napi-rs.
+//!
+//! The addon exposes a class `HnswIndex` on f32 vectors given as `Float32Array`:
+//!
+//! ```js
+//! const { HnswIndex } = require('./hnsw_rs.node')
+//!
+//! const index = new HnswIndex('l2', 16, data.length / dim, 200)
+//! index.insertBatch(data, dim, ids)           // data : Float32Array of ids.length * dim values
+//! const { ids, distances } = index.search(query, 10, 64)
+//! const basename = index.dump('/tmp', 'myindex')
+//! const reloaded = HnswIndex.load('/tmp', basename, 'l2')
+//! ```
+//!
+//! Arrays given by JavaScript are not copied, the vectors are read in their ArrayBuffer (they are copied once
+//! inserted in the structure). Distances are chosen by name: "l1", "l2", "cosine" or "dot".
+//! Ids are JavaScript integers (at most 2^53). Calls are synchronous, insertBatch and searchBatch use the
+//! rayon pool but block the event loop until they end.
+//!
+//! The addon is the cdylib of the crate renamed `hnsw_rs.node`, built for example with
+//! `cargo rustc --release --lib --features node --crate-type cdylib` (on macOS add
+//! `-- -C link-arg=-undefined -C link-arg=dynamic_lookup`) or with the napi cli.
+
+use std::path::Path;
+
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+use anndists::dist::distances::Distance;
+use anndists::dist::{DistCosine, DistDot, DistL1, DistL2};
+
+use crate::api::AnnT;
+use crate::hnsw::{Hnsw, Neighbour};
+use crate::hnswio::HnswIo;
+
+// the class is not generic, distances are dispatched by this enum
+enum Index {
+    L1(Hnsw<'static, f32, DistL1>),
+    L2(Hnsw<'static, f32, DistL2>),
+    Cosine(Hnsw<'static, f32, DistCosine>),
+    Dot(Hnsw<'static, f32, DistDot>),
+}
+
+// evaluates $body with $hnsw bound to the Hnsw of any variant
+macro_rules! with_hnsw {
+    ($index:expr, $hnsw:ident => $body:expr) => {
+        match $index {
+            Index::L1($hnsw) => $body,
+            Index::L2($hnsw) => $body,
+            Index::Cosine($hnsw) => $body,
+            Index::Dot($hnsw) => $body,
+        }
+    };
+}
+
+/// neighbours of a query, sorted by increasing distance
+#[napi(object)]
+pub struct SearchResult {
+    pub ids: Vec<i64>,
+    pub distances: Float32Array,
+}
+
+impl From<Vec<Neighbour>> for SearchResult {
+    fn from(neighbours: Vec<Neighbour>) -> Self {
+        SearchResult {
+            ids: neighbours.iter().map(|n| n.d_id as i64).collect(),
+            distances: neighbours
+                .iter()
+                .map(|n| n.distance)
+                .collect::<Vec<f32>>()
+                .into(),
+        }
+    }
+}
+
+/// a Hnsw on f32 vectors, see module doc
+#[napi]
+pub struct HnswIndex {
+    index: Index,
+}
+
+#[napi]
+impl HnswIndex {
+    /// distance defaults to l2, max_nb_connection to 16, max_elements to 10000 and ef_construction to 200
+    #[napi(constructor)]
+    pub fn new(
+        distance: Option<String>,
+        max_nb_connection: Option<u32>,
+        max_elements: Option<u32>,
+        ef_construction: Option<u32>,
+    ) -> Result<Self> {
+        let params = (
+            max_nb_connection.unwrap_or(16) as usize,
+            max_elements.unwrap_or(10000) as usize,
+            ef_construction.unwrap_or(200) as usize,
+        );
+        let distance = distance.as_deref().unwrap_or("l2");
+        let index = match distance {
+            "l1" => Index::L1(build(params, DistL1 {})?),
+            "l2" => Index::L2(build(params, DistL2 {})?),
+            "cosine" => Index::Cosine(build(params, DistCosine {})?),
+            "dot" => Index::Dot(build(params, DistDot {})?),
+            _ => return Err(unknown_distance(distance)),
+        };
+        Ok(HnswIndex { index })
+    }
+
+    /// reloads a dump made by dump with the same distance
+    #[napi(factory)]
+    pub fn load(directory: String, basename: String, distance: Option<String>) -> Result<Self> {
+        let reloader = HnswIo::new(Path::new(&directory), &basename);
+        let check = |e: anyhow::Error| Error::from_reason(e.to_string());
+        let distance = distance.as_deref().unwrap_or("l2");
+        let index = match distance {
+            "l1" => Index::L1(reloader.load_owned().map_err(check)?),
+            "l2" => Index::L2(reloader.load_owned().map_err(check)?),
+            "cosine" => Index::Cosine(reloader.load_owned().map_err(check)?),
+            "dot" => Index::Dot(reloader.load_owned().map_err(check)?),
+            _ => return Err(unknown_distance(distance)),
+        };
+        Ok(HnswIndex { index })
+    }
+
+    /// dumps the index in directory, returns the basename used (see [AnnT::file_dump])
+    #[napi]
+    pub fn dump(&self, directory: String, basename: String) -> Result<String> {
+        with_hnsw!(&self.index, hnsw => hnsw.file_dump(Path::new(&directory), &basename))
+            .map_err(|e| Error::from_reason(e.to_string()))
+    }
+
+    #[napi(getter)]
+    pub fn size(&self) -> u32 {
+        with_hnsw!(&self.index, hnsw => hnsw.get_nb_point()) as u32
+    }
+
+    #[napi]
+    pub fn insert(&self, vector: Float32Array, id: i64) -> Result<()> {
+        let id = to_data_id(id)?;
+        with_hnsw!(&self.index, hnsw => hnsw.insert_slice((&vector, id)));
+        Ok(())
+    }
+
+    /// inserts in parallel ids.length vectors of dimension dim stored one after the other in vectors
+    #[napi]
+    pub fn insert_batch(&self, vectors: Float32Array, dim: u32, ids: Vec<i64>) -> Result<()> {
+        let dim = dim as usize;
+        if dim == 0 || vectors.len() != dim * ids.len() {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!(
+                    "{} values for {} vectors of dimension {}",
+                    vectors.len(),
+                    ids.len(),
+                    dim
+                ),
+            ));
+        }
+        let ids = ids
+            .into_iter()
+            .map(to_data_id)
+            .collect::<Result<Vec<usize>>>()?;
+        let datas: Vec<(&[f32], usize)> = vectors.chunks_exact(dim).zip(ids).collect();
+        with_hnsw!(&self.index, hnsw => hnsw.parallel_insert_slice(&datas));
+        Ok(())
+    }
+
+    /// ef defaults to 64
+    #[napi]
+    pub fn search(&self, vector: Float32Array, knbn: u32, ef: Option<u32>) -> SearchResult {
+        let ef = ef.unwrap_or(64) as usize;
+        with_hnsw!(&self.index, hnsw => hnsw.search(&vector, knbn as usize, ef)).into()
+    }
+
+    /// searches in parallel the queries of dimension dim stored one after the other in queries
+    #[napi]
+    pub fn search_batch(
+        &self,
+        queries: Float32Array,
+        dim: u32,
+        knbn: u32,
+        ef: Option<u32>,
+    ) -> Result<Vec<SearchResult>> {
+        let dim = dim as usize;
+        if dim == 0 || !queries.len().is_multiple_of(dim) {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!("{} values for queries of dimension {}", queries.len(), dim),
+            ));
+        }
+        let queries: Vec<Vec<f32>> = queries.chunks_exact(dim).map(|q| q.to_vec()).collect();
+        let ef = ef.unwrap_or(64) as usize;
+        let answers =
+            with_hnsw!(&self.index, hnsw => hnsw.parallel_search(&queries, knbn as usize, ef));
+        Ok(answers.into_iter().map(SearchResult::from).collect())
+    }
+} // end of impl HnswIndex
+
+// params are max_nb_connection, max_elements and ef_construction
+fn build<D>(params: (usize, usize, usize), dist: D) -> Result<Hnsw<'static, f32, D>>
+where
+    D: Distance<f32> + Send + Sync,
+{
+    let (max_nb_connection, max_elements, ef_construction) = params;
+    Hnsw::try_new(max_nb_connection, max_elements, 16, ef_construction, dist)
+        .map_err(|e| Error::new(Status::InvalidArg, e.to_string()))
+}
+
+fn unknown_distance(distance: &str) -> Error {
+    Error::new(
+        Status::InvalidArg,
+        format!(
+            "unknown distance {}, expected l1, l2, cosine or dot",
+            distance
+        ),
+    )
+}
+
+// JavaScript numbers are f64, integers are given as i64
+fn to_data_id(id: i64) -> Result<usize> {
+    usize::try_from(id).map_err(|_| Error::new(Status::InvalidArg, format!("invalid id {}", id)))
+}
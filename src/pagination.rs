@@ -0,0 +1,263 @@
+//! Pages of search results.
+//!
+//! [Hnsw::search_page] returns the knbn nearest points not returned by the previous pages of a search, and a
+//! [PageToken] keeping the state of the traversal of the lowest layer: the visited points, the frontier of points
+//! not yet expanded and the points evaluated but not yet returned. The next page goes on with the expansion where
+//! the previous one stopped, so a UI fetching "the next 20 results" does not redo the search with a larger ef
+//! and each point is evaluated once for all pages.
+//!
+//! As in a search, the expansion stops when the ef nearest points not returned are nearer than the nearest point
+//! of the frontier, then the knbn nearest of them are returned. Deleted points are traversed, never returned.
+//! A token is the state of one query: it must be given back with the same query. It holds point ids,
+//! a structure modified by deletions or repairs between pages can return a point twice or miss some.
+//! A token is serializable to be kept by a client between requests, its size grows with the points visited.
+
+use serde::{Deserialize, Serialize};
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashSet};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Neighbour, Point, PointId};
+
+// a point evaluated by a paged search
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+struct Evaluated {
+    distance: f32,
+    d_id: DataId,
+    p_id: (u8, i32),
+}
+
+impl Evaluated {
+    fn new(point: &Point<impl Clone + Send + Sync>, distance: f32) -> Self {
+        let p_id = point.get_point_id();
+        Evaluated {
+            distance,
+            d_id: point.get_origin_id(),
+            p_id: (p_id.0, p_id.1),
+        }
+    }
+}
+
+// order of search results, see Neighbour::cmp_by_distance
+impl Ord for Evaluated {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.distance
+            .total_cmp(&other.distance)
+            .then(self.d_id.cmp(&other.d_id))
+    }
+}
+
+impl PartialOrd for Evaluated {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Evaluated {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Evaluated {}
+
+/// state of a paged search between pages, see module doc
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct PageToken {
+    started: bool,
+    visited: Vec<(u8, i32)>,
+    frontier: Vec<Evaluated>,
+    pool: Vec<Evaluated>,
+    nb_returned: usize,
+}
+
+impl PageToken {
+    /// true when all points reachable by the search were returned
+    pub fn is_exhausted(&self) -> bool {
+        self.started && self.frontier.is_empty() && self.pool.is_empty()
+    }
+
+    /// number of points returned by the pages of the search
+    pub fn get_nb_returned(&self) -> usize {
+        self.nb_returned
+    }
+
+    /// number of points whose distance to the query was computed
+    pub fn get_nb_visited(&self) -> usize {
+        self.visited.len()
+    }
+} // end of impl PageToken
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// returns the knbn nearest points to query not returned by the previous pages of token (None for the first page)
+    /// and the token of the next page. ef is the number of candidates of each page, as in [Hnsw::search].
+    /// See module doc.
+    pub fn search_page(
+        &self,
+        query: &[T],
+        knbn: usize,
+        ef_arg: usize,
+        token: Option<PageToken>,
+    ) -> (Vec<Neighbour>, PageToken) {
+        let mut token = token.unwrap_or_default();
+        let ef = self.get_search_ef(knbn, ef_arg);
+        let dist = |p: &Point<T>| self.get_distance().eval(query, p.get_v());
+        let accept = |p: &Point<T>| !(self.has_deleted() && self.is_deleted(p));
+        // lowest non empty layer, as in search
+        let Some(layer) = (0..self.get_max_level() as u8)
+            .find(|l| self.get_point_indexation().get_layer_nb_point(*l as usize) > 0)
+        else {
+            return (Vec::new(), token);
+        };
+        let mut visited: HashSet<(u8, i32)> = token.visited.drain(..).collect();
+        let mut frontier: BinaryHeap<Reverse<Evaluated>> =
+            token.frontier.drain(..).map(Reverse).collect();
+        // the ef nearest points not returned (a max heap) and the others
+        let mut pool = std::mem::take(&mut token.pool);
+        pool.sort_unstable();
+        let rest_start = pool.len().min(ef);
+        let mut rest: BinaryHeap<Reverse<Evaluated>> =
+            pool.drain(rest_start..).map(Reverse).collect();
+        let mut best: BinaryHeap<Evaluated> = pool.into_iter().collect();
+        let mut keep = |e: Evaluated, best: &mut BinaryHeap<Evaluated>| {
+            best.push(e);
+            if best.len() > ef {
+                rest.push(Reverse(best.pop().unwrap()));
+            }
+        };
+        if !token.started {
+            token.started = true;
+            let Some(entry_point) = self.layer_indexed_points.entry_point.read().clone() else {
+                return (Vec::new(), token);
+            };
+            let start_layer = entry_point.get_point_id().0;
+            let pivot = self.descend(
+                &|v: &[T], _| self.get_distance().eval(query, v),
+                entry_point,
+                start_layer,
+            );
+            let e = Evaluated::new(&pivot, dist(&pivot));
+            visited.insert(e.p_id);
+            frontier.push(Reverse(e));
+            if accept(&pivot) {
+                keep(e, &mut best);
+            }
+        }
+        while let Some(Reverse(c)) = frontier.peek() {
+            if best.len() >= ef && c.distance > best.peek().unwrap().distance {
+                break;
+            }
+            let p_id = PointId(c.p_id.0, c.p_id.1);
+            frontier.pop();
+            let Some(point) = self.get_point_indexation().get_point(&p_id) else {
+                continue;
+            };
+            for n in point.neighbours.read()[layer as usize].iter() {
+                let n_id = n.point_ref.get_point_id();
+                if !visited.insert((n_id.0, n_id.1)) {
+                    continue;
+                }
+                let e = Evaluated::new(&n.point_ref, dist(&n.point_ref));
+                frontier.push(Reverse(e));
+                if accept(&n.point_ref) {
+                    keep(e, &mut best);
+                }
+            }
+        }
+        let mut found = best.into_sorted_vec();
+        self.order_ties(&mut found, |e| e.distance, |e| PointId(e.p_id.0, e.p_id.1));
+        let page: Vec<Neighbour> = found
+            .drain(..knbn.min(found.len()))
+            .map(|e| Neighbour::new(e.d_id, e.distance, PointId(e.p_id.0, e.p_id.1)))
+            .collect();
+        token.nb_returned += page.len();
+        token.pool = found;
+        token.pool.extend(rest.into_iter().map(|Reverse(e)| e));
+        token.frontier = frontier.into_iter().map(|Reverse(e)| e).collect();
+        token.visited = visited.into_iter().collect();
+        (page, token)
+    } // end of search_page
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_search_page() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(989);
+        let data = synthetic::uniform(3000, 8, &mut rng);
+        // a serial build with seeded levels gives the same graph at each run
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 3000, 16, 200, DistL2 {});
+        hnsw.set_level_seed(989);
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        hnsw.remove_batch(&[5, 6, 7]).unwrap();
+        let query = synthetic::uniform(1, 8, &mut rng).pop().unwrap();
+        // 5 pages of 20 results
+        let mut pages = Vec::new();
+        let mut token = None;
+        let mut nb_visited = Vec::new();
+        for _ in 0..5 {
+            let (page, next) = hnsw.search_page(&query, 20, 40, token);
+            assert_eq!(page.len(), 20);
+            nb_visited.push(next.get_nb_visited());
+            pages.extend(page);
+            token = Some(next);
+        }
+        let token = token.unwrap();
+        assert_eq!(token.get_nb_returned(), 100);
+        assert!(!token.is_exhausted());
+        // pages are sorted, without duplicates nor deleted points
+        assert!(
+            pages
+                .chunks(20)
+                .all(|page| page.windows(2).all(|w| w[0].distance <= w[1].distance))
+        );
+        let ids: HashSet<DataId> = pages.iter().map(|n| n.d_id).collect();
+        assert_eq!(ids.len(), 100);
+        assert!(ids.iter().all(|d_id| ![5, 6, 7].contains(d_id)));
+        // a page expands the frontier a bit more, it does not restart
+        assert!(nb_visited.windows(2).all(|w| w[0] <= w[1]));
+        assert!(nb_visited[4] < 3000);
+        // results are the ones of a brute force
+        let mut exact: Vec<(f32, DataId)> = data
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| ![5, 6, 7].contains(i))
+            .map(|(i, v)| (hnsw.get_distance().eval(&query, v), i))
+            .collect();
+        exact.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let nb_found = exact[..100].iter().filter(|(_, i)| ids.contains(i)).count();
+        assert!(nb_found >= 95, "nb_found {}", nb_found);
+        // the token goes through serde
+        let json = serde_json::to_string(&token).unwrap();
+        let token: PageToken = serde_json::from_str(&json).unwrap();
+        let (page, _) = hnsw.search_page(&query, 20, 40, Some(token));
+        assert_eq!(page.len(), 20);
+        assert!(page.iter().all(|n| !ids.contains(&n.d_id)));
+        // a small index is exhausted
+        let mut small = Hnsw::<f32, DistL2>::new(8, 30, 16, 50, DistL2 {});
+        small.set_level_seed(989);
+        for (i, v) in data.iter().enumerate().take(30) {
+            small.insert((v, i));
+        }
+        let (page, token) = small.search_page(&query, 20, 20, None);
+        assert_eq!(page.len(), 20);
+        // the second page returns the points reachable by a search (a few can be cut from the lowest layer)
+        let nb_reachable = small.search(&query, 30, 64).len();
+        let (page, token) = small.search_page(&query, 20, 20, Some(token));
+        assert_eq!(page.len(), nb_reachable - 20);
+        assert!(token.is_exhausted());
+    } // end of test_search_page
+} // end of mod tests
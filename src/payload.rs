@@ -0,0 +1,254 @@
+//! This module defines the Payload associated to each point at insertion.
+//!
+//! A payload is made of tags (a bitset of 16 categories), at most [NB_ATTRIBUTES] numeric attributes
+//! (a timestamp, a price ...), an optional geographic position (latitude, longitude in degrees) and
+//! a namespace (see [crate::namespace]). Payloads are stored in a column of the structure indexed by point id,
+//! alongside the layers of points, so that constraints given in a [SearchParams](crate::searchparams::SearchParams)
+//! are checked during traversal. The column is allocated at the first non empty payload: a structure whose points
+//! have no payload pays nothing.
+
+use parking_lot::{RwLock, RwLockReadGuard};
+
+use crate::hnsw::PointId;
+
+/// Tags are a bitset of 16 categories a point can belong to. They are given at insertion.
+/// See [Hnsw::insert_with_tags](crate::hnsw::Hnsw::insert_with_tags())
+pub type Tags = u16;
+
+/// A namespace isolates points of a tenant inside a structure. Points inserted without namespace are in namespace 0.
+pub type Namespace = u32;
+
+/// maximum number of numeric attributes of a point
+pub const NB_ATTRIBUTES: usize = 2;
+
+/// number of bytes of a payload in a dump
+pub(crate) const PAYLOAD_NB_BYTES: usize = 2 + 8 * NB_ATTRIBUTES + 16 + 4;
+
+/// mean earth radius in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// haversine (great circle) distance in meters between 2 positions given as (latitude, longitude) in degrees
+pub fn haversine_distance(a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (lat_a, lat_b) = (a.0.to_radians(), b.0.to_radians());
+    let dlat = lat_b - lat_a;
+    let dlon = (b.1 - a.1).to_radians();
+    let h = (dlat / 2.).sin().powi(2) + lat_a.cos() * lat_b.cos() * (dlon / 2.).sin().powi(2);
+    2. * EARTH_RADIUS * h.sqrt().min(1.).asin()
+}
+
+/// The payload of a point.
+/// An attribute not set is NaN, it never satisfies a range filter.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Payload {
+    tags: Tags,
+    attributes: [f64; NB_ATTRIBUTES],
+    /// latitude, longitude in degrees. NaN if not set
+    geo: (f64, f64),
+    namespace: Namespace,
+}
+
+impl Default for Payload {
+    fn default() -> Self {
+        Payload {
+            tags: 0,
+            attributes: [f64::NAN; NB_ATTRIBUTES],
+            geo: (f64::NAN, f64::NAN),
+            namespace: 0,
+        }
+    }
+}
+
+impl Payload {
+    /// an empty payload: no tags and no attributes
+    pub fn new() -> Self {
+        Payload::default()
+    }
+
+    /// sets tags of payload
+    pub fn with_tags(mut self, tags: Tags) -> Self {
+        self.tags = tags;
+        self
+    }
+
+    /// sets attribute of rank attr. attr must be less than [NB_ATTRIBUTES]
+    pub fn with_attribute(mut self, attr: usize, value: f64) -> Self {
+        assert!(
+            attr < NB_ATTRIBUTES,
+            "attribute rank must be < {}",
+            NB_ATTRIBUTES
+        );
+        self.attributes[attr] = value;
+        self
+    }
+
+    /// sets position of point, latitude and longitude in degrees
+    pub fn with_geo(mut self, lat: f64, lon: f64) -> Self {
+        self.geo = (lat, lon);
+        self
+    }
+
+    /// sets namespace of point
+    pub fn with_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = namespace;
+        self
+    }
+
+    /// returns tags
+    pub fn get_tags(&self) -> Tags {
+        self.tags
+    }
+
+    /// returns value of attribute attr, NaN if not set
+    pub fn get_attribute(&self, attr: usize) -> f64 {
+        self.attributes[attr]
+    }
+
+    /// returns (latitude, longitude) if position was set
+    pub fn get_geo(&self) -> Option<(f64, f64)> {
+        if self.geo.0.is_nan() || self.geo.1.is_nan() {
+            None
+        } else {
+            Some(self.geo)
+        }
+    }
+
+    /// returns namespace
+    pub fn get_namespace(&self) -> Namespace {
+        self.namespace
+    }
+
+    /// true if nothing was set
+    pub(crate) fn is_empty(&self) -> bool {
+        self.tags == 0
+            && self.attributes.iter().all(|a| a.is_nan())
+            && self.geo.0.is_nan()
+            && self.geo.1.is_nan()
+            && self.namespace == 0
+    }
+
+    /// tags, attributes, position and namespace in native endianness as the rest of the dump
+    pub(crate) fn to_bytes(self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.tags.to_ne_bytes());
+        for a in &self.attributes {
+            bytes.extend_from_slice(&a.to_ne_bytes());
+        }
+        bytes.extend_from_slice(&self.geo.0.to_ne_bytes());
+        bytes.extend_from_slice(&self.geo.1.to_ne_bytes());
+        bytes.extend_from_slice(&self.namespace.to_ne_bytes());
+    }
+
+    /// bytes must hold [PAYLOAD_NB_BYTES] bytes written by [Self::to_bytes]
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Self {
+        let f64_at = |i: usize| f64::from_ne_bytes(bytes[i..i + 8].try_into().unwrap());
+        let mut payload =
+            Payload::new().with_tags(u16::from_ne_bytes(bytes[..2].try_into().unwrap()));
+        for attr in 0..NB_ATTRIBUTES {
+            payload.attributes[attr] = f64_at(2 + 8 * attr);
+        }
+        let geo = 2 + 8 * NB_ATTRIBUTES;
+        payload.geo = (f64_at(geo), f64_at(geo + 8));
+        payload.namespace = u32::from_ne_bytes(bytes[geo + 16..geo + 20].try_into().unwrap());
+        payload
+    }
+} // end of impl Payload
+
+// payloads by layer and rank in layer, None until a non empty payload is set
+type Column = Option<Vec<Vec<Payload>>>;
+
+/// payloads of the points of a structure, by point id. See module doc.
+#[derive(Default)]
+pub(crate) struct PayloadColumn(RwLock<Column>);
+
+/// a read access to a payload column, kept during a traversal
+pub(crate) struct PayloadsRef<'a>(RwLockReadGuard<'a, Column>);
+
+impl PayloadsRef<'_> {
+    /// payload of p_id, empty if none was set
+    pub(crate) fn get(&self, p_id: PointId) -> Payload {
+        self.0
+            .as_ref()
+            .and_then(|layers| layers.get(p_id.0 as usize))
+            .and_then(|layer| layer.get(usize::try_from(p_id.1).ok()?))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl PayloadColumn {
+    // recursive: a traversal keeping a read access can check a namespace with Self::get
+    pub(crate) fn read(&self) -> PayloadsRef<'_> {
+        PayloadsRef(self.0.read_recursive())
+    }
+
+    /// payload of p_id, empty if none was set
+    pub(crate) fn get(&self, p_id: PointId) -> Payload {
+        self.read().get(p_id)
+    }
+
+    /// sets payload of p_id, it replaces the payload of a point whose slot is reused
+    pub(crate) fn set(&self, p_id: PointId, payload: Payload) {
+        let mut column = self.0.write();
+        if column.is_none() && payload.is_empty() {
+            return;
+        }
+        let layers = column.get_or_insert_with(Vec::new);
+        let (layer, rank) = (p_id.0 as usize, p_id.1 as usize);
+        if layers.len() <= layer {
+            layers.resize_with(layer + 1, Vec::new);
+        }
+        if layers[layer].len() <= rank {
+            layers[layer].resize(rank + 1, Payload::default());
+        }
+        layers[layer][rank] = payload;
+    }
+
+    /// true if no point has a payload
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.read_recursive().is_none()
+    }
+} // end of impl PayloadColumn
+
+impl Clone for PayloadColumn {
+    fn clone(&self) -> Self {
+        PayloadColumn(RwLock::new(self.0.read().clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+
+    #[test]
+    fn test_haversine() {
+        // Paris - London is about 344 km
+        let paris = (48.8566, 2.3522);
+        let london = (51.5074, -0.1278);
+        let d = haversine_distance(paris, london);
+        assert!((d - 343_500.).abs() < 2_000., "d = {}", d);
+        assert!(haversine_distance(paris, paris) < 1.0e-6);
+        // antipodal points
+        let d = haversine_distance((0., 0.), (0., 180.));
+        assert!((d - std::f64::consts::PI * EARTH_RADIUS).abs() < 1.);
+    } // end of test_haversine
+
+    #[test]
+    fn test_payload_column() {
+        let column = PayloadColumn::default();
+        column.set(PointId(1, 3), Payload::new());
+        assert!(column.is_empty());
+        let payload = Payload::new().with_tags(5).with_attribute(1, 903.);
+        column.set(PointId(2, 4), payload);
+        assert!(!column.is_empty());
+        // attributes not set are NaN, payloads are not compared
+        let stored = column.get(PointId(2, 4));
+        assert_eq!(stored.get_tags(), 5);
+        assert_eq!(stored.get_attribute(1), 903.);
+        assert!(column.get(PointId(2, 3)).is_empty());
+        assert!(column.get(PointId(0, 0)).is_empty());
+        assert!(column.get(PointId(5, 0)).is_empty());
+        // a reused slot
+        column.set(PointId(2, 4), Payload::new());
+        assert!(column.get(PointId(2, 4)).is_empty());
+    } // end of test_payload_column
+} // end of mod tests
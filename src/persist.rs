@@ -0,0 +1,196 @@
+//! A common dump and reload interface for the index types of the crate.
+//!
+//! [Persistable] writes an index in any [Write] and reads it back from any [Read] (a file, a socket, a buffer...)
+//! so generic code can persist an index without knowing its type. A stream begins with a magic and the kind
+//! of index, reading a stream of another kind returns an error.
+//!
+//! | type           | stream after the kind |
+//! |----------------|-----------------------|
+//! | Hnsw           | graph and vectors of a file dump (see [crate::hnswio]) |
+//! | FlatHnsw       | bincode encoding |
+//! | HnswSearcher   | FlatHnsw, min_ef_factor and distance name |
+//!
+//! In a Hnsw dump graph and vectors are written together, so [Persistable::dump_to] prepares both in memory
+//! before writing them. The reload keeps only the graph in memory and reads vectors as they come.
+//! A reloaded Hnsw owns its vectors (no mmap). Reads are not buffered, give a buffered reader.
+//! Nothing is read after the index: several indexes can follow each other in a stream.
+
+use std::any::type_name;
+use std::io::{Read, Write};
+
+use anyhow::{Result, anyhow};
+use serde::{Serialize, de::DeserializeOwned};
+
+use anndists::dist::distances::Distance;
+
+use crate::flathnsw::FlatHnsw;
+use crate::hnsw::Hnsw;
+use crate::hnswio::{HnswIo, load_description};
+use crate::searcher::HnswSearcher;
+
+// magic at the beginning of a stream, followed by the kind of index as a u8
+const MAGICPERSIST: u32 = 0x00a6706e;
+
+const KIND_HNSW: u8 = 1;
+const KIND_FLAT: u8 = 2;
+const KIND_SEARCHER: u8 = 3;
+
+/// dump and reload of an index in a stream, see module doc
+pub trait Persistable: Sized {
+    /// writes self in out
+    fn dump_to(&self, out: &mut dyn Write) -> Result<()>;
+    /// reads an index written by [Persistable::dump_to]
+    fn load_from(input: &mut dyn Read) -> Result<Self>;
+}
+
+fn write_kind(out: &mut dyn Write, kind: u8) -> Result<()> {
+    out.write_all(&MAGICPERSIST.to_ne_bytes())?;
+    out.write_all(&[kind])?;
+    Ok(())
+}
+
+fn check_kind(input: &mut dyn Read, kind: u8) -> Result<()> {
+    let mut header = [0u8; 5];
+    input.read_exact(&mut header)?;
+    if u32::from_ne_bytes(header[..4].try_into().unwrap()) != MAGICPERSIST {
+        return Err(anyhow!("not a stream written by Persistable::dump_to"));
+    }
+    if header[4] != kind {
+        return Err(anyhow!(
+            "stream contains an index of kind {}, expected kind {}",
+            header[4],
+            kind
+        ));
+    }
+    Ok(())
+}
+
+impl<T, D> Persistable for Hnsw<'static, T, D>
+where
+    T: 'static + Serialize + DeserializeOwned + Clone + Send + Sync + std::fmt::Debug,
+    D: Distance<T> + Default + Send + Sync,
+{
+    fn dump_to(&self, out: &mut dyn Write) -> Result<()> {
+        let (mut graph, mut data) = (Vec::new(), Vec::new());
+        self.dump_to_writers(&mut graph, &mut data)?;
+        write_kind(out, KIND_HNSW)?;
+        out.write_all(&(graph.len() as u64).to_ne_bytes())?;
+        out.write_all(&graph)?;
+        out.write_all(&data)?;
+        Ok(())
+    }
+
+    fn load_from(input: &mut dyn Read) -> Result<Self> {
+        check_kind(input, KIND_HNSW)?;
+        let mut len = [0u8; 8];
+        input.read_exact(&mut len)?;
+        let mut graph = vec![0u8; u64::from_ne_bytes(len) as usize];
+        input.read_exact(&mut graph)?;
+        let mut graph_in = graph.as_slice();
+        let description = load_description(&mut graph_in)?;
+        HnswIo::default().load_streams(description, &mut graph_in, input, None, &|_, _| true)
+    }
+} // end of impl Persistable for Hnsw
+
+impl<T> Persistable for FlatHnsw<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    fn dump_to(&self, out: &mut dyn Write) -> Result<()> {
+        write_kind(out, KIND_FLAT)?;
+        bincode::serialize_into(out, self)?;
+        Ok(())
+    }
+
+    fn load_from(input: &mut dyn Read) -> Result<Self> {
+        check_kind(input, KIND_FLAT)?;
+        Ok(bincode::deserialize_from(input)?)
+    }
+} // end of impl Persistable for FlatHnsw
+
+impl<T, D> Persistable for HnswSearcher<T, D>
+where
+    T: Serialize + DeserializeOwned,
+    D: Default,
+{
+    fn dump_to(&self, out: &mut dyn Write) -> Result<()> {
+        write_kind(out, KIND_SEARCHER)?;
+        bincode::serialize_into(&mut *out, &self.flat)?;
+        bincode::serialize_into(&mut *out, &self.min_ef_factor)?;
+        bincode::serialize_into(out, type_name::<D>())?;
+        Ok(())
+    }
+
+    fn load_from(input: &mut dyn Read) -> Result<Self> {
+        check_kind(input, KIND_SEARCHER)?;
+        let flat: FlatHnsw<T> = bincode::deserialize_from(&mut *input)?;
+        let min_ef_factor: f32 = bincode::deserialize_from(&mut *input)?;
+        let distname: String = bincode::deserialize_from(input)?;
+        // as at reload of a Hnsw, short names of distances must be equal
+        let short_name = |name: &str| name.rsplit("::").next().unwrap_or_default().to_string();
+        if short_name(&distname) != short_name(type_name::<D>()) {
+            return Err(anyhow!(
+                "searcher dumped with distance {}, asked distance is {}",
+                distname,
+                type_name::<D>()
+            ));
+        }
+        Ok(HnswSearcher {
+            flat,
+            dist: D::default(),
+            min_ef_factor,
+        })
+    }
+} // end of impl Persistable for HnswSearcher
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::{DistL1, DistL2};
+    use rand::SeedableRng;
+
+    // generic code only knowing the trait
+    fn round_trip<I: Persistable>(index: &I, stream: &mut Vec<u8>) -> I {
+        let start = stream.len();
+        index.dump_to(stream).unwrap();
+        I::load_from(&mut &stream[start..]).unwrap()
+    }
+
+    #[test]
+    fn test_persistable() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(946);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        //
+        let mut stream = Vec::new();
+        let reloaded = round_trip(&hnsw, &mut stream);
+        assert_eq!(reloaded.get_nb_point(), 1000);
+        let found = |h: &Hnsw<f32, DistL2>| {
+            (0..1000)
+                .step_by(10)
+                .map(|i| h.search(&data[i], 5, 32)[0].d_id == i)
+                .collect::<Vec<bool>>()
+        };
+        assert_eq!(found(&hnsw), found(&reloaded));
+        let flat = round_trip(&FlatHnsw::from_hnsw(&hnsw), &mut stream);
+        assert_eq!(flat.get_nb_point(), 1000);
+        let searcher_start = stream.len();
+        let searcher = round_trip(&hnsw.into_searcher(), &mut stream);
+        let res = searcher.search(&data[10], 5, 32);
+        assert_eq!(res.len(), 5);
+        // the 3 indexes follow each other in stream, each reload stops at its end
+        let mut input = stream.as_slice();
+        assert!(Hnsw::<f32, DistL2>::load_from(&mut input).is_ok());
+        assert!(FlatHnsw::<f32>::load_from(&mut input).is_ok());
+        assert!(HnswSearcher::<f32, DistL2>::load_from(&mut input).is_ok());
+        assert!(input.is_empty());
+        // kind and distance are checked
+        assert!(FlatHnsw::<f32>::load_from(&mut stream.as_slice()).is_err());
+        let mut input = &stream[searcher_start..];
+        assert!(HnswSearcher::<f32, DistL1>::load_from(&mut input).is_err());
+    } // end of test_persistable
+} // end of mod tests
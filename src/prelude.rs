@@ -1,6 +1,10 @@
 // gathers modules to include and re-exorts all of anndists!
 
 pub use crate::api::*;
+pub use crate::asymmetric::*;
+pub use crate::checkpoint::*;
+pub use crate::concurrent::*;
+pub use crate::dedup::*;
 pub use crate::hnsw::*;
 
 #[allow(unused)]
@@ -8,4 +12,11 @@ pub use crate::filter::*;
 
 pub use crate::hnswio::*;
 
+pub use crate::namespace::*;
+pub use crate::payload::*;
+pub use crate::persist::Persistable;
+pub use crate::searcher::*;
+pub use crate::searchparams::*;
+pub use crate::topk::ResultBuffer;
+
 pub use anndists::dist::distances::*;
@@ -0,0 +1,252 @@
+//! Python binding (feature python).
+//!
+//! The module `hnsw_rs` exposes a class `HnswIndex` on f32 vectors given as NumPy arrays:
+//!
+//! ```python
+//! import numpy as np
+//! from hnsw_rs import HnswIndex
+//!
+//! index = HnswIndex("l2", max_nb_connection=16, max_elements=len(data), ef_construction=200)
+//! index.parallel_insert(data, np.arange(len(data)))
+//! ids, distances = index.search_batch(queries, knbn=10, ef=64)
+//! basename = index.dump("/tmp", "myindex")
+//! index = HnswIndex.load("/tmp", basename, "l2")
+//! ```
+//!
+//! Distances are chosen by name: "l1", "l2", "cosine" or "dot". Insertions and batch searches release the GIL
+//! and run on the rayon pool. Rows of search_batch with less than knbn neighbours are padded with id -1
+//! and distance inf.
+//!
+//! The extension is built as a cdylib named as the module, for example with maturin or with
+//! `cargo rustc --release --lib --features python,pyo3/extension-module --crate-type cdylib`
+//! and a copy of `libhnsw_rs.so` as `hnsw_rs.so` in the python path.
+
+use std::path::PathBuf;
+
+use numpy::ndarray::Array2;
+use numpy::{IntoPyArray, PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use pyo3::exceptions::{PyIOError, PyValueError};
+use pyo3::prelude::*;
+
+use anndists::dist::distances::Distance;
+use anndists::dist::{DistCosine, DistDot, DistL1, DistL2};
+
+use crate::api::AnnT;
+use crate::hnsw::{Hnsw, Neighbour};
+use crate::hnswio::HnswIo;
+
+// the class is not generic, distances are dispatched by this enum
+enum Index {
+    L1(Hnsw<'static, f32, DistL1>),
+    L2(Hnsw<'static, f32, DistL2>),
+    Cosine(Hnsw<'static, f32, DistCosine>),
+    Dot(Hnsw<'static, f32, DistDot>),
+}
+
+// ids and distances returned to python
+type Answer<'py> = (Bound<'py, PyArray1<i64>>, Bound<'py, PyArray1<f32>>);
+type BatchAnswer<'py> = (Bound<'py, PyArray2<i64>>, Bound<'py, PyArray2<f32>>);
+
+// evaluates $body with $hnsw bound to the Hnsw of any variant
+macro_rules! with_hnsw {
+    ($index:expr, $hnsw:ident => $body:expr) => {
+        match $index {
+            Index::L1($hnsw) => $body,
+            Index::L2($hnsw) => $body,
+            Index::Cosine($hnsw) => $body,
+            Index::Dot($hnsw) => $body,
+        }
+    };
+}
+
+/// a Hnsw on f32 vectors, see module doc
+#[pyclass(module = "hnsw_rs")]
+pub struct HnswIndex {
+    index: Index,
+}
+
+#[pymethods]
+impl HnswIndex {
+    #[new]
+    #[pyo3(signature = (distance = "l2", max_nb_connection = 16, max_elements = 10000, max_layer = 16, ef_construction = 200))]
+    fn new(
+        distance: &str,
+        max_nb_connection: usize,
+        max_elements: usize,
+        max_layer: usize,
+        ef_construction: usize,
+    ) -> PyResult<Self> {
+        let params = (max_nb_connection, max_elements, max_layer, ef_construction);
+        let index = match distance {
+            "l1" => Index::L1(build(params, DistL1 {})?),
+            "l2" => Index::L2(build(params, DistL2 {})?),
+            "cosine" => Index::Cosine(build(params, DistCosine {})?),
+            "dot" => Index::Dot(build(params, DistDot {})?),
+            _ => return Err(unknown_distance(distance)),
+        };
+        Ok(HnswIndex { index })
+    }
+
+    /// reloads a dump made by dump with the same distance
+    #[staticmethod]
+    #[pyo3(signature = (directory, basename, distance = "l2"))]
+    fn load(directory: PathBuf, basename: &str, distance: &str) -> PyResult<Self> {
+        let reloader = HnswIo::new(&directory, basename);
+        let check = |e: anyhow::Error| PyIOError::new_err(e.to_string());
+        let index = match distance {
+            "l1" => Index::L1(reloader.load_owned().map_err(check)?),
+            "l2" => Index::L2(reloader.load_owned().map_err(check)?),
+            "cosine" => Index::Cosine(reloader.load_owned().map_err(check)?),
+            "dot" => Index::Dot(reloader.load_owned().map_err(check)?),
+            _ => return Err(unknown_distance(distance)),
+        };
+        Ok(HnswIndex { index })
+    }
+
+    /// dumps the index in directory, returns the basename used (see [AnnT::file_dump])
+    fn dump(&self, directory: PathBuf, basename: &str) -> PyResult<String> {
+        with_hnsw!(&self.index, hnsw => hnsw.file_dump(&directory, basename))
+            .map_err(|e| PyIOError::new_err(e.to_string()))
+    }
+
+    fn __len__(&self) -> usize {
+        with_hnsw!(&self.index, hnsw => hnsw.get_nb_point())
+    }
+
+    fn insert(&self, vector: PyReadonlyArray1<f32>, id: usize) -> PyResult<()> {
+        let vector = vector.as_slice()?;
+        with_hnsw!(&self.index, hnsw => hnsw.insert_slice((vector, id)));
+        Ok(())
+    }
+
+    /// inserts the rows of a 2d array with their ids, without the GIL
+    fn parallel_insert(
+        &self,
+        py: Python<'_>,
+        vectors: PyReadonlyArray2<f32>,
+        ids: Vec<usize>,
+    ) -> PyResult<()> {
+        let (nb_row, dim) = vectors.as_array().dim();
+        if ids.len() != nb_row {
+            return Err(PyValueError::new_err(format!(
+                "{} vectors but {} ids",
+                nb_row,
+                ids.len()
+            )));
+        }
+        if dim == 0 {
+            return Err(PyValueError::new_err("vectors of dimension 0"));
+        }
+        let flat = vectors.as_slice()?;
+        let datas: Vec<(&[f32], usize)> = flat.chunks_exact(dim).zip(ids).collect();
+        py.detach(|| with_hnsw!(&self.index, hnsw => hnsw.parallel_insert_slice(&datas)));
+        Ok(())
+    }
+
+    /// returns ids and distances of the knbn neighbours of vector
+    #[pyo3(signature = (vector, knbn, ef = 64))]
+    fn search<'py>(
+        &self,
+        py: Python<'py>,
+        vector: PyReadonlyArray1<f32>,
+        knbn: usize,
+        ef: usize,
+    ) -> PyResult<Answer<'py>> {
+        let vector = vector.as_slice()?;
+        let neighbours = with_hnsw!(&self.index, hnsw => hnsw.search(vector, knbn, ef));
+        let ids = neighbours
+            .iter()
+            .map(|n| n.d_id as i64)
+            .collect::<Vec<i64>>();
+        let distances = neighbours.iter().map(|n| n.distance).collect::<Vec<f32>>();
+        Ok((ids.into_pyarray(py), distances.into_pyarray(py)))
+    }
+
+    /// searches the rows of a 2d array in parallel without the GIL, returns 2 arrays of shape (nb_query, knbn)
+    #[pyo3(signature = (queries, knbn, ef = 64))]
+    fn search_batch<'py>(
+        &self,
+        py: Python<'py>,
+        queries: PyReadonlyArray2<f32>,
+        knbn: usize,
+        ef: usize,
+    ) -> PyResult<BatchAnswer<'py>> {
+        let queries: Vec<Vec<f32>> = queries
+            .as_array()
+            .rows()
+            .into_iter()
+            .map(|q| q.to_vec())
+            .collect();
+        let (ids, distances) = py.detach(|| {
+            let answers = with_hnsw!(&self.index, hnsw => hnsw.parallel_search(&queries, knbn, ef));
+            to_arrays(&answers, knbn)
+        });
+        Ok((ids.into_pyarray(py), distances.into_pyarray(py)))
+    }
+} // end of impl HnswIndex
+
+// params are max_nb_connection, max_elements, max_layer and ef_construction
+fn build<D>(params: (usize, usize, usize, usize), dist: D) -> PyResult<Hnsw<'static, f32, D>>
+where
+    D: Distance<f32> + Send + Sync,
+{
+    let (max_nb_connection, max_elements, max_layer, ef_construction) = params;
+    Hnsw::try_new(
+        max_nb_connection,
+        max_elements,
+        max_layer,
+        ef_construction,
+        dist,
+    )
+    .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+fn unknown_distance(distance: &str) -> PyErr {
+    PyValueError::new_err(format!(
+        "unknown distance {}, expected l1, l2, cosine or dot",
+        distance
+    ))
+}
+
+// answers of a batch search in arrays (nb_query, knbn), padded with -1 and inf
+fn to_arrays(answers: &[Vec<Neighbour>], knbn: usize) -> (Array2<i64>, Array2<f32>) {
+    let mut ids = Array2::from_elem((answers.len(), knbn), -1i64);
+    let mut distances = Array2::from_elem((answers.len(), knbn), f32::INFINITY);
+    for (i, answer) in answers.iter().enumerate() {
+        for (j, n) in answer.iter().take(knbn).enumerate() {
+            ids[[i, j]] = n.d_id as i64;
+            distances[[i, j]] = n.distance;
+        }
+    }
+    (ids, distances)
+}
+
+#[pymodule]
+fn hnsw_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<HnswIndex>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hnsw::PointId;
+
+    #[test]
+    fn test_to_arrays() {
+        let answers = vec![
+            vec![
+                Neighbour::new(3, 0.5, PointId(0, 0)),
+                Neighbour::new(7, 1.5, PointId(0, 1)),
+            ],
+            vec![Neighbour::new(9, 0.25, PointId(0, 2))],
+        ];
+        let (ids, distances) = to_arrays(&answers, 2);
+        assert_eq!(ids.shape(), &[2, 2]);
+        assert_eq!(ids.row(0).to_vec(), vec![3, 7]);
+        assert_eq!(ids.row(1).to_vec(), vec![9, -1]);
+        assert_eq!(distances[[1, 0]], 0.25);
+        assert!(distances[[1, 1]].is_infinite());
+    } // end of test_to_arrays
+} // end of mod tests
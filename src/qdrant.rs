@@ -0,0 +1,192 @@
+//! Import of points exported from a Qdrant collection (feature qdrant).
+//!
+//! The binary snapshots of Qdrant store segments in RocksDB or in internal mmap formats that change between
+//! versions, so they are not read here. Points are read as Qdrant returns them through its scroll API
+//! (`POST /collections/{name}/points/scroll` with `with_vector` and `with_payload`):
+//! one JSON document by line, each being either a point `{"id": 3, "vector": [..], "payload": {..}}`
+//! or a whole scroll response `{"result": {"points": [..], ..}}`.
+//!
+//! Ids must be integers, UUID ids are rejected. With named vectors the vector to import is selected by name.
+//! The JSON payload of a point is mapped to a [Payload] by a function given by the client.
+
+use std::io::BufRead;
+
+use anyhow::{Result, anyhow};
+use rayon::prelude::*;
+use serde_json::Value;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw};
+use crate::payload::Payload;
+
+/// a point read from a Qdrant export
+#[derive(Debug, Clone)]
+pub struct QdrantPoint {
+    pub id: DataId,
+    pub vector: Vec<f32>,
+    pub payload: Payload,
+}
+
+/// reads points of a Qdrant export, see module doc.
+/// vector_name selects a named vector, it must be None for collections with one unnamed vector.
+pub fn read_qdrant_points<R, F>(
+    reader: R,
+    vector_name: Option<&str>,
+    map_payload: F,
+) -> Result<Vec<QdrantPoint>>
+where
+    R: BufRead,
+    F: Fn(&Value) -> Payload,
+{
+    let mut points = Vec::new();
+    for (num, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let doc: Value = serde_json::from_str(&line)
+            .map_err(|e| anyhow!("line {} is not a json document : {}", num + 1, e))?;
+        match doc.pointer("/result/points") {
+            Some(Value::Array(page)) => {
+                for point in page {
+                    points.push(read_point(point, vector_name, &map_payload)?);
+                }
+            }
+            Some(_) => return Err(anyhow!("line {} : result.points is not an array", num + 1)),
+            None => points.push(read_point(&doc, vector_name, &map_payload)?),
+        }
+    }
+    log::info!("read_qdrant_points : {} points read", points.len());
+    Ok(points)
+} // end of read_qdrant_points
+
+fn read_point<F>(point: &Value, vector_name: Option<&str>, map_payload: &F) -> Result<QdrantPoint>
+where
+    F: Fn(&Value) -> Payload,
+{
+    let id = match &point["id"] {
+        Value::Number(n) => n
+            .as_u64()
+            .and_then(|id| DataId::try_from(id).ok())
+            .ok_or_else(|| anyhow!("point id {} is not a valid id", n))?,
+        Value::Null => return Err(anyhow!("point without id")),
+        other => return Err(anyhow!("point id {} is not an integer", other)),
+    };
+    let vector = match (&point["vector"], vector_name) {
+        (Value::Object(named), Some(name)) => named
+            .get(name)
+            .ok_or_else(|| anyhow!("point {} has no vector named {}", id, name))?,
+        (Value::Object(_), None) => {
+            return Err(anyhow!(
+                "point {} has named vectors, a name must be given",
+                id
+            ));
+        }
+        (Value::Null, _) => {
+            return Err(anyhow!(
+                "point {} without vector (export with with_vector)",
+                id
+            ));
+        }
+        (vector, _) => vector,
+    };
+    let vector = vector
+        .as_array()
+        .ok_or_else(|| anyhow!("vector of point {} is not a dense vector", id))?
+        .iter()
+        .map(|x| x.as_f64().map(|x| x as f32))
+        .collect::<Option<Vec<f32>>>()
+        .ok_or_else(|| anyhow!("vector of point {} has a non numeric value", id))?;
+    Ok(QdrantPoint {
+        id,
+        vector,
+        payload: map_payload(&point["payload"]),
+    })
+} // end of read_point
+
+/// builds (in parallel) a Hnsw from the points of a Qdrant export, see [read_qdrant_points].
+pub fn hnsw_from_qdrant<R, F, D>(
+    reader: R,
+    vector_name: Option<&str>,
+    map_payload: F,
+    max_nb_connection: usize,
+    ef_construction: usize,
+    dist: D,
+) -> Result<Hnsw<'static, f32, D>>
+where
+    R: BufRead,
+    F: Fn(&Value) -> Payload,
+    D: Distance<f32> + Send + Sync,
+{
+    let points = read_qdrant_points(reader, vector_name, map_payload)?;
+    if let Some(point) = points
+        .iter()
+        .find(|p| p.vector.len() != points[0].vector.len())
+    {
+        return Err(anyhow!(
+            "point {} has dimension {}, first point has dimension {}",
+            point.id,
+            point.vector.len(),
+            points[0].vector.len()
+        ));
+    }
+    let hnsw = Hnsw::try_new(max_nb_connection, points.len(), 16, ef_construction, dist)?;
+    points
+        .par_iter()
+        .for_each(|p| hnsw.insert_with_payload((&p.vector, p.id), p.payload));
+    Ok(hnsw)
+} // end of hnsw_from_qdrant
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use anndists::dist::DistL2;
+
+    // tag 1 for points with "color" = "red", attribute 0 from "price"
+    fn map_payload(payload: &Value) -> Payload {
+        let mut mapped = Payload::new();
+        if payload["color"] == "red" {
+            mapped = mapped.with_tags(1);
+        }
+        if let Some(price) = payload["price"].as_f64() {
+            mapped = mapped.with_attribute(0, price);
+        }
+        mapped
+    }
+
+    #[test]
+    fn test_read_qdrant_points() {
+        let mut export = String::new();
+        // a scroll response then single points
+        export.push_str(
+            r#"{"result":{"points":[{"id":0,"vector":[0.0,0.0],"payload":{"color":"red","price":3.5}},{"id":1,"vector":[1.0,0.0],"payload":{}}],"next_page_offset":2},"status":"ok","time":0.001}"#,
+        );
+        export.push('\n');
+        for i in 2..100 {
+            export.push_str(&format!(
+                "{{\"id\":{},\"vector\":[{}.0,1.0],\"payload\":{{\"color\":\"blue\"}}}}\n",
+                i, i
+            ));
+        }
+        let points = read_qdrant_points(export.as_bytes(), None, map_payload).unwrap();
+        assert_eq!(points.len(), 100);
+        assert_eq!(points[0].payload.get_tags(), 1);
+        assert_eq!(points[0].payload.get_attribute(0), 3.5);
+        assert_eq!(points[2].payload.get_tags(), 0);
+        //
+        let hnsw =
+            hnsw_from_qdrant(export.as_bytes(), None, map_payload, 16, 100, DistL2 {}).unwrap();
+        assert_eq!(hnsw.get_nb_point(), 100);
+        let res = hnsw.search(&[50., 1.], 1, 16);
+        assert_eq!(res[0].d_id, 50);
+        // named vectors and rejected ids
+        let named = r#"{"id":7,"vector":{"image":[1.0,2.0],"text":[3.0]}}"#;
+        let points = read_qdrant_points(named.as_bytes(), Some("image"), map_payload).unwrap();
+        assert_eq!(points[0].vector, vec![1., 2.]);
+        assert!(read_qdrant_points(named.as_bytes(), None, map_payload).is_err());
+        let uuid = r#"{"id":"5c56c793-69f3-4fbf-87e6-c4bf54c28c26","vector":[1.0]}"#;
+        assert!(read_qdrant_points(uuid.as_bytes(), None, map_payload).is_err());
+    } // end of test_read_qdrant_points
+} // end of mod tests
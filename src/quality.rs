@@ -0,0 +1,381 @@
+//! Estimation of the recall of live searches.
+//!
+//! The recall of an index drifts with deletions and insertions (or with a bug) without any error, and a brute
+//! force search over all points to measure it is too costly in production. With [Hnsw::set_quality_monitor] the
+//! structure keeps a reservoir of points and checks one search out of [QualityMonitor::sample_every] against it:
+//! a reservoir point nearer to the query than the knbn-th neighbour returned should have been returned, so the
+//! fraction of these points found in the results estimates the recall of the search.
+//!
+//! The reservoir is stratified by insertion sequence (see [crate::sequence]): points are split in strata of
+//! consecutive insertions (old to recent) and each stratum gets the same number of sampled points, so recent points
+//! are checked even in a large index and each stratum has its own recall. The recall of the index weights each
+//! stratum by its number of points. The [QualityReport] of the last [QualityMonitor::report_every] sampled
+//! searches is given to the callback of the monitor (the metrics hook of the application), [Hnsw::quality_report]
+//! gives the one of all sampled searches.
+//!
+//! Searches observed are the ones of [Hnsw::search], [Hnsw::search_filter] and [Hnsw::search_with_params] whose
+//! results are plain nearest neighbours (no constraint, fusion, penalty...). Points inserted after the sampling
+//! of the reservoir are not checked, and deleted ones are skipped, [Hnsw::resample_quality] samples it again.
+//! A sampled search costs one distance by reservoir point.
+
+use parking_lot::{Mutex, RwLock};
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Neighbour};
+
+/// recall estimated on a stratum of the reservoir
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct StratumQuality {
+    /// insertion sequence number of the first point of the stratum
+    pub first_seq: u64,
+    /// points of the stratum (not deleted) when the reservoir was sampled
+    pub nb_point: usize,
+    /// points of the stratum in the reservoir
+    pub nb_sampled: usize,
+    /// reservoir points nearer to a query than its knbn-th neighbour
+    pub nb_expected: u64,
+    /// of these, points returned by the search
+    pub nb_found: u64,
+    /// nb_found / nb_expected, None if nb_expected is 0
+    pub recall: Option<f64>,
+}
+
+/// recall estimated on sampled searches, see module doc
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct QualityReport {
+    pub nb_search: u64,
+    /// recall of strata weighted by their number of points, None if no reservoir point was expected
+    pub recall: Option<f64>,
+    pub strata: Vec<StratumQuality>,
+}
+
+type QualityCallback = Arc<dyn Fn(&QualityReport) + Send + Sync>;
+
+/// sampling of searches and reservoir, and the callback receiving reports. See module doc.
+#[derive(Clone)]
+pub struct QualityMonitor {
+    sample_every: u64,
+    reservoir_size: usize,
+    nb_strata: usize,
+    report_every: u64,
+    seed: u64,
+    callback: Option<QualityCallback>,
+}
+
+impl Default for QualityMonitor {
+    /// one search out of 100, a reservoir of 1000 points in 4 strata, no callback
+    fn default() -> Self {
+        QualityMonitor {
+            sample_every: 100,
+            reservoir_size: 1000,
+            nb_strata: 4,
+            report_every: 100,
+            seed: 0,
+            callback: None,
+        }
+    }
+}
+
+impl QualityMonitor {
+    /// a default monitor calling callback with the report of each report_every sampled searches
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(&QualityReport) + Send + Sync + 'static,
+    {
+        QualityMonitor {
+            callback: Some(Arc::new(callback)),
+            ..Default::default()
+        }
+    }
+
+    /// checks one search out of nb
+    pub fn sample_every(mut self, nb: u64) -> Self {
+        assert!(nb >= 1, "sample_every needs at least 1");
+        self.sample_every = nb;
+        self
+    }
+
+    /// number of points of the reservoir, split in nb_strata strata
+    pub fn reservoir(mut self, size: usize, nb_strata: usize) -> Self {
+        assert!(nb_strata >= 1, "reservoir needs at least 1 stratum");
+        self.reservoir_size = size;
+        self.nb_strata = nb_strata;
+        self
+    }
+
+    /// number of sampled searches by report given to the callback
+    pub fn report_every(mut self, nb: u64) -> Self {
+        assert!(nb >= 1, "report_every needs at least 1");
+        self.report_every = nb;
+        self
+    }
+
+    /// seed of the sampling of the reservoir
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+} // end of impl QualityMonitor
+
+// sampled points of a stratum
+struct Stratum<T> {
+    first_seq: u64,
+    nb_point: usize,
+    points: Vec<(DataId, Vec<T>)>,
+}
+
+// reservoir points expected and found by stratum, and number of searches
+#[derive(Clone, Default)]
+struct Counts {
+    nb_search: u64,
+    by_stratum: Vec<(u64, u64)>,
+}
+
+impl Counts {
+    fn add(&mut self, other: &Counts) {
+        self.nb_search += other.nb_search;
+        self.by_stratum.resize(other.by_stratum.len(), (0, 0));
+        for (c, o) in self.by_stratum.iter_mut().zip(&other.by_stratum) {
+            c.0 += o.0;
+            c.1 += o.1;
+        }
+    }
+}
+
+/// monitor and reservoir of a structure
+pub(crate) struct QualityState<T> {
+    monitor: QualityMonitor,
+    nb_seen: AtomicU64,
+    strata: RwLock<Vec<Stratum<T>>>,
+    // counts of the report in progress and of all reports
+    window: Mutex<Counts>,
+    total: Mutex<Counts>,
+}
+
+impl<T> QualityState<T> {
+    fn report(&self, counts: &Counts) -> QualityReport {
+        let strata = self.strata.read();
+        let mut report = QualityReport {
+            nb_search: counts.nb_search,
+            ..Default::default()
+        };
+        let (mut expected, mut found) = (0., 0.);
+        for (stratum, &(nb_expected, nb_found)) in strata.iter().zip(&counts.by_stratum) {
+            report.strata.push(StratumQuality {
+                first_seq: stratum.first_seq,
+                nb_point: stratum.nb_point,
+                nb_sampled: stratum.points.len(),
+                nb_expected,
+                nb_found,
+                recall: (nb_expected > 0).then(|| nb_found as f64 / nb_expected as f64),
+            });
+            // a sampled point stands for nb_point / nb_sampled points of its stratum
+            if !stratum.points.is_empty() {
+                let weight = stratum.nb_point as f64 / stratum.points.len() as f64;
+                expected += weight * nb_expected as f64;
+                found += weight * nb_found as f64;
+            }
+        }
+        report.recall = (expected > 0.).then(|| found / expected);
+        report
+    }
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// checks a sample of searches against a reservoir of points sampled now, see module doc
+    pub fn set_quality_monitor(&mut self, monitor: QualityMonitor) {
+        self.quality = Some(QualityState {
+            monitor,
+            nb_seen: AtomicU64::new(0),
+            strata: RwLock::new(Vec::new()),
+            window: Mutex::new(Counts::default()),
+            total: Mutex::new(Counts::default()),
+        });
+        self.resample_quality();
+    }
+
+    pub fn clear_quality_monitor(&mut self) {
+        self.quality = None;
+    }
+
+    /// samples the reservoir again from the current points and resets the counts of sampled searches
+    pub fn resample_quality(&self) {
+        let Some(quality) = &self.quality else {
+            return;
+        };
+        let monitor = &quality.monitor;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(monitor.seed);
+        let mut strata = Vec::with_capacity(monitor.nb_strata);
+        {
+            let indexation = &self.layer_indexed_points;
+            let layers = indexation.points_by_layer.read();
+            let order = indexation.insertion_order.read();
+            let nb_seq = order.len();
+            for s in 0..monitor.nb_strata {
+                let (start, end) = (
+                    s * nb_seq / monitor.nb_strata,
+                    (s + 1) * nb_seq / monitor.nb_strata,
+                );
+                let points: Vec<_> = order[start..end]
+                    .iter()
+                    .filter(|p_id| p_id.1 >= 0)
+                    .map(|p_id| &layers[p_id.0 as usize][p_id.1 as usize])
+                    .filter(|p| !self.is_deleted(p))
+                    .collect();
+                let nb_sampled = (monitor.reservoir_size / monitor.nb_strata).min(points.len());
+                strata.push(Stratum {
+                    first_seq: start as u64,
+                    nb_point: points.len(),
+                    points: rand::seq::index::sample(&mut rng, points.len(), nb_sampled)
+                        .into_iter()
+                        .map(|i| (points[i].get_origin_id(), points[i].get_v().to_vec()))
+                        .collect(),
+                });
+            }
+        }
+        let counts = Counts {
+            nb_search: 0,
+            by_stratum: vec![(0, 0); strata.len()],
+        };
+        *quality.strata.write() = strata;
+        *quality.window.lock() = counts.clone();
+        *quality.total.lock() = counts;
+    } // end of resample_quality
+
+    /// recall estimated on all searches sampled since the reservoir was sampled, None without monitor
+    pub fn quality_report(&self) -> Option<QualityReport> {
+        let quality = self.quality.as_ref()?;
+        let total = quality.total.lock().clone();
+        Some(quality.report(&total))
+    }
+
+    /// counts reservoir points expected in neighbours of a search of query, if the search is sampled
+    pub(crate) fn observe_quality(&self, query: &[T], knbn: usize, neighbours: &[Neighbour]) {
+        let Some(quality) = &self.quality else {
+            return;
+        };
+        let monitor = &quality.monitor;
+        if quality.nb_seen.fetch_add(1, Ordering::Relaxed) % monitor.sample_every != 0 {
+            return;
+        }
+        // with less than knbn neighbours all the points are expected, a restricted search is not checked
+        let Some(last) = neighbours.get(knbn.max(1) - 1) else {
+            return;
+        };
+        let counts = {
+            let strata = quality.strata.read();
+            let mut counts = Counts {
+                nb_search: 1,
+                by_stratum: vec![(0, 0); strata.len()],
+            };
+            for (stratum, count) in strata.iter().zip(counts.by_stratum.iter_mut()) {
+                for (d_id, v) in &stratum.points {
+                    if !self.layer_indexed_points.ids.contains(*d_id) {
+                        continue;
+                    }
+                    let found = neighbours.iter().any(|n| n.d_id == *d_id);
+                    if found || self.get_distance().eval(query, v) < last.distance {
+                        count.0 += 1;
+                        count.1 += found as u64;
+                    }
+                }
+            }
+            counts
+        };
+        quality.total.lock().add(&counts);
+        let report = {
+            let mut window = quality.window.lock();
+            window.add(&counts);
+            if window.nb_search < monitor.report_every {
+                return;
+            }
+            std::mem::take(&mut *window)
+        };
+        if let Some(callback) = &monitor.callback {
+            callback(&quality.report(&report));
+        }
+    } // end of observe_quality
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+
+    #[test]
+    fn test_quality_monitor() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(998);
+        let data = synthetic::uniform(4000, 16, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 4000, 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let reports = Arc::new(Mutex::new(Vec::<QualityReport>::new()));
+        let sink = Arc::clone(&reports);
+        let monitor = QualityMonitor::new(move |report| sink.lock().push(report.clone()))
+            .sample_every(2)
+            .reservoir(2000, 4)
+            .report_every(50)
+            .seed(998);
+        hnsw.set_quality_monitor(monitor);
+        let queries = synthetic::uniform(400, 16, &mut rng);
+        for query in &queries {
+            hnsw.search(query, 10, 64);
+        }
+        // 200 searches sampled, a report by 50
+        let report = hnsw.quality_report().unwrap();
+        assert_eq!(report.nb_search, 200);
+        assert_eq!(reports.lock().len(), 4);
+        assert!(reports.lock().iter().all(|r| r.nb_search == 50));
+        assert_eq!(report.strata.len(), 4);
+        assert_eq!(report.strata[2].first_seq, 2000);
+        assert!(report.strata.iter().all(|s| s.nb_sampled == 500));
+        assert_eq!(
+            report.strata.iter().map(|s| s.nb_point).sum::<usize>(),
+            4000
+        );
+        // compared with a brute force recall
+        let recall = report.recall.unwrap();
+        let mut nb_found = 0;
+        for query in &queries {
+            let res = hnsw.search(query, 10, 64);
+            let mut exact: Vec<(f32, usize)> = data
+                .iter()
+                .zip(0..)
+                .map(|(v, i)| (hnsw.get_distance().eval(query, v), i))
+                .collect();
+            exact.sort_by(|a, b| a.0.total_cmp(&b.0));
+            nb_found += exact[..10]
+                .iter()
+                .filter(|(_, i)| res.iter().any(|n| n.d_id == *i))
+                .count();
+        }
+        let exact_recall = nb_found as f64 / 4000.;
+        log::info!("estimated recall {}, recall {}", recall, exact_recall);
+        assert!((recall - exact_recall).abs() < 0.1);
+        // a search with a degraded ef is seen
+        hnsw.resample_quality();
+        for query in &queries {
+            hnsw.search(query, 10, 10);
+        }
+        let degraded = hnsw.quality_report().unwrap().recall.unwrap();
+        assert!(degraded < recall);
+        // constrained searches are not observed
+        hnsw.resample_quality();
+        let filter = |id: &usize| id.is_multiple_of(2);
+        for query in &queries {
+            hnsw.search_filter(query, 10, 64, Some(&filter));
+        }
+        assert_eq!(hnsw.quality_report().unwrap().nb_search, 0);
+    } // end of test_quality_monitor
+} // end of mod tests
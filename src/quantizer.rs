@@ -0,0 +1,459 @@
+//! Product quantization with codebooks trained elsewhere.
+//!
+//! A [Quantizer] cuts a vector of dimension dim in nb_sub sub vectors and codes each one by the rank of its
+//! nearest centroid among the 256 of its sub space: a vector is stored as nb_sub u8 codes.
+//! As [Sq8L2](crate::asymmetric::Sq8L2) it gives the distance between codes to build a Hnsw on codes and an
+//! [AsymmetricDistance] (a lookup table of nb_sub * 256 squared distances by query) to search it with f32 queries
+//! by [Hnsw::search_asymmetric](crate::hnsw::Hnsw::search_asymmetric). Distances are L2.
+//!
+//! Codebooks are not trained here, [Quantizer::from_faiss_bytes] reads the ones of a FAISS file (written by
+//! `faiss.write_index` or `faiss.write_ProductQuantizer`, for example read with `open(f, 'rb').read()`):
+//! - a ProductQuantizer,
+//! - an IndexPQ (fourcc IxPq), codes stored in the index are ignored,
+//! - an IndexPreTransform (fourcc IxPT) of one linear transform (fourcc LTra as written for an OPQMatrix,
+//!   or rrot) then an IndexPQ: the rotation is applied to vectors before coding.
+//!
+//! Only codebooks of 8 bits (256 centroids) and the L2 metric are supported.
+//! Rotations are assumed orthonormal (as OPQ ones): [Quantizer::decode] goes back with the transposed matrix.
+
+use anyhow::{Result, anyhow};
+
+use anndists::dist::distances::Distance;
+
+use crate::asymmetric::AsymmetricDistance;
+
+/// number of centroids by sub space
+pub const NB_CENTROID: usize = 256;
+
+// FAISS fourcc of the structures read
+const FOURCC_INDEX_PQ: &[u8; 4] = b"IxPq";
+const FOURCC_PRE_TRANSFORM: &[u8; 4] = b"IxPT";
+const FOURCC_LINEAR: &[u8; 4] = b"LTra";
+const FOURCC_ROTATION: &[u8; 4] = b"rrot";
+
+// METRIC_L2 of FAISS
+const FAISS_METRIC_L2: i32 = 1;
+
+/// linear transform applied before coding : y = matrix * x + bias
+#[derive(Clone, Debug)]
+struct Rotation {
+    d_in: usize,
+    d_out: usize,
+    // d_out rows of d_in values
+    matrix: Vec<f32>,
+    // empty if no bias
+    bias: Vec<f32>,
+}
+
+impl Rotation {
+    fn apply(&self, x: &[f32]) -> Vec<f32> {
+        self.matrix
+            .chunks_exact(self.d_in)
+            .enumerate()
+            .map(|(i, row)| {
+                let y: f32 = row.iter().zip(x).map(|(a, b)| a * b).sum();
+                y + self.bias.get(i).copied().unwrap_or(0.)
+            })
+            .collect()
+    }
+
+    fn apply_transposed(&self, y: &[f32]) -> Vec<f32> {
+        let mut x = vec![0f32; self.d_in];
+        for (i, row) in self.matrix.chunks_exact(self.d_in).enumerate() {
+            let y_i = y[i] - self.bias.get(i).copied().unwrap_or(0.);
+            x.iter_mut().zip(row).for_each(|(x, a)| *x += a * y_i);
+        }
+        x
+    }
+}
+
+/// A product quantizer to nb_sub u8 codes, see module doc.
+#[derive(Clone, Debug)]
+pub struct Quantizer {
+    /// dimension of vectors coded (before the rotation if any)
+    dim: usize,
+    nb_sub: usize,
+    /// dimension of a sub space
+    dsub: usize,
+    /// nb_sub * 256 centroids of dimension dsub
+    centroids: Vec<f32>,
+    rotation: Option<Rotation>,
+}
+
+impl Quantizer {
+    /// quantizer of vectors of dimension dim in nb_sub sub spaces (nb_sub must divide dim).
+    /// centroids holds the 256 centroids of the first sub space, then those of the second...
+    pub fn new(dim: usize, nb_sub: usize, centroids: Vec<f32>) -> Result<Self> {
+        if nb_sub == 0 || dim == 0 || !dim.is_multiple_of(nb_sub) {
+            return Err(anyhow!(
+                "dimension {} cannot be cut in {} sub spaces",
+                dim,
+                nb_sub
+            ));
+        }
+        if centroids.len() != dim * NB_CENTROID {
+            return Err(anyhow!(
+                "expected {} centroid values for dimension {}, got {}",
+                dim * NB_CENTROID,
+                dim,
+                centroids.len()
+            ));
+        }
+        Ok(Quantizer {
+            dim,
+            nb_sub,
+            dsub: dim / nb_sub,
+            centroids,
+            rotation: None,
+        })
+    }
+
+    /// reads a quantizer written by FAISS, see module doc for the structures accepted
+    pub fn from_faiss_bytes(bytes: &[u8]) -> Result<Self> {
+        let mut input = FaissReader { bytes };
+        let fourcc = input.peek_fourcc();
+        let quantizer = if fourcc == Some(FOURCC_PRE_TRANSFORM) {
+            input.fourcc()?;
+            input.index_header()?;
+            let nb_transform = input.i32()?;
+            if nb_transform != 1 {
+                return Err(anyhow!(
+                    "faiss IndexPreTransform with {} transforms, only one rotation is supported",
+                    nb_transform
+                ));
+            }
+            let rotation = input.linear_transform()?;
+            if input.fourcc()? != FOURCC_INDEX_PQ {
+                return Err(anyhow!("faiss IndexPreTransform does not wrap an IndexPQ"));
+            }
+            input.index_header()?;
+            let mut quantizer = input.product_quantizer()?;
+            if rotation.d_out != quantizer.dim {
+                return Err(anyhow!(
+                    "faiss rotation gives dimension {}, quantizer codes dimension {}",
+                    rotation.d_out,
+                    quantizer.dim
+                ));
+            }
+            quantizer.dim = rotation.d_in;
+            quantizer.rotation = Some(rotation);
+            quantizer
+        } else if fourcc == Some(FOURCC_INDEX_PQ) {
+            input.fourcc()?;
+            input.index_header()?;
+            input.product_quantizer()?
+        } else {
+            input.product_quantizer()?
+        };
+        log::info!(
+            "faiss quantizer : dim {}, {} sub spaces, rotation {}",
+            quantizer.dim,
+            quantizer.nb_sub,
+            quantizer.rotation.is_some()
+        );
+        Ok(quantizer)
+    } // end of from_faiss_bytes
+
+    /// dimension of vectors coded
+    pub fn get_dim(&self) -> usize {
+        self.dim
+    }
+
+    /// number of sub spaces, it is also the number of u8 of a code
+    pub fn get_nb_sub(&self) -> usize {
+        self.nb_sub
+    }
+
+    // vector in the space of the centroids
+    fn rotate(&self, v: &[f32]) -> Vec<f32> {
+        assert_eq!(v.len(), self.dim, "Quantizer : bad vector dimension");
+        match &self.rotation {
+            Some(rotation) => rotation.apply(v),
+            None => v.to_vec(),
+        }
+    }
+
+    fn centroid(&self, sub: usize, code: u8) -> &[f32] {
+        let start = (sub * NB_CENTROID + code as usize) * self.dsub;
+        &self.centroids[start..start + self.dsub]
+    }
+
+    pub fn encode(&self, v: &[f32]) -> Vec<u8> {
+        let v = self.rotate(v);
+        v.chunks_exact(self.dsub)
+            .enumerate()
+            .map(|(sub, x)| {
+                (0..NB_CENTROID)
+                    .map(|c| (c as u8, l2sq(x, self.centroid(sub, c as u8))))
+                    .min_by(|a, b| a.1.total_cmp(&b.1))
+                    .unwrap()
+                    .0
+            })
+            .collect()
+    }
+
+    pub fn decode(&self, codes: &[u8]) -> Vec<f32> {
+        let y: Vec<f32> = codes
+            .iter()
+            .enumerate()
+            .flat_map(|(sub, &c)| self.centroid(sub, c).iter().copied())
+            .collect();
+        match &self.rotation {
+            Some(rotation) => rotation.apply_transposed(&y),
+            None => y,
+        }
+    }
+} // end of impl Quantizer
+
+fn l2sq(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| (x - y) * (x - y)).sum()
+}
+
+impl AsymmetricDistance<f32, u8> for Quantizer {
+    type Prepared = Vec<f32>;
+
+    fn prepare(&self, query: &[f32]) -> Vec<f32> {
+        let query = self.rotate(query);
+        let mut lut = Vec::with_capacity(self.nb_sub * NB_CENTROID);
+        for (sub, x) in query.chunks_exact(self.dsub).enumerate() {
+            lut.extend((0..NB_CENTROID).map(|c| l2sq(x, self.centroid(sub, c as u8))));
+        }
+        lut
+    }
+
+    fn eval(&self, lut: &Vec<f32>, stored: &[u8]) -> f32 {
+        assert_eq!(lut.len(), stored.len() * NB_CENTROID);
+        stored
+            .iter()
+            .enumerate()
+            .map(|(sub, &c)| lut[sub * NB_CENTROID + c as usize])
+            .sum::<f32>()
+            .sqrt()
+    }
+} // end of impl AsymmetricDistance for Quantizer
+
+/// Distance between codes of a Quantizer (between their centroids), to build a Hnsw on codes.
+impl Distance<u8> for Quantizer {
+    fn eval(&self, va: &[u8], vb: &[u8]) -> f32 {
+        va.iter()
+            .zip(vb)
+            .enumerate()
+            .map(|(sub, (&a, &b))| l2sq(self.centroid(sub, a), self.centroid(sub, b)))
+            .sum::<f32>()
+            .sqrt()
+    }
+}
+
+// reads native endian values as FAISS writes them, advancing in bytes
+struct FaissReader<'a> {
+    bytes: &'a [u8],
+}
+
+impl FaissReader<'_> {
+    fn take(&mut self, nb: usize) -> Result<&[u8]> {
+        if self.bytes.len() < nb {
+            return Err(anyhow!("faiss data truncated"));
+        }
+        let (taken, rest) = self.bytes.split_at(nb);
+        self.bytes = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(i32::from_ne_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_ne_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn peek_fourcc(&self) -> Option<&[u8; 4]> {
+        self.bytes.get(..4).map(|b| b.try_into().unwrap())
+    }
+
+    fn fourcc(&mut self) -> Result<&[u8; 4]> {
+        Ok(self.take(4)?.try_into().unwrap())
+    }
+
+    // a std::vector<float> : its size as u64 then its values
+    fn f32_vec(&mut self) -> Result<Vec<f32>> {
+        let size = self.u64()? as usize;
+        let bytes = self.take(size.checked_mul(4).ok_or(anyhow!("faiss data corrupted"))?)?;
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+
+    // d, ntotal, 2 dummies, is_trained, metric_type and metric_arg for metrics above inner product
+    fn index_header(&mut self) -> Result<()> {
+        self.i32()?;
+        self.take(3 * 8)?;
+        self.u8()?;
+        let metric = self.i32()?;
+        if metric != FAISS_METRIC_L2 {
+            return Err(anyhow!(
+                "faiss index with metric {}, only L2 (1) is supported",
+                metric
+            ));
+        }
+        Ok(())
+    }
+
+    // fields of the transform, then d_in, d_out and is_trained common to all transforms
+    fn linear_transform(&mut self) -> Result<Rotation> {
+        let fourcc = self.fourcc()?;
+        if fourcc != FOURCC_LINEAR && fourcc != FOURCC_ROTATION {
+            return Err(anyhow!(
+                "faiss transform {} is not supported, expected an OPQ or a rotation",
+                String::from_utf8_lossy(fourcc)
+            ));
+        }
+        let have_bias = self.u8()? != 0;
+        let matrix = self.f32_vec()?;
+        let mut bias = self.f32_vec()?;
+        let d_in = self.i32()?;
+        let d_out = self.i32()?;
+        self.u8()?;
+        if d_in <= 0 || d_out <= 0 || matrix.len() != d_in as usize * d_out as usize {
+            return Err(anyhow!(
+                "faiss transform {} x {} with {} values",
+                d_out,
+                d_in,
+                matrix.len()
+            ));
+        }
+        if !have_bias {
+            bias.clear();
+        } else if bias.len() != d_out as usize {
+            return Err(anyhow!("faiss transform bias of size {}", bias.len()));
+        }
+        Ok(Rotation {
+            d_in: d_in as usize,
+            d_out: d_out as usize,
+            matrix,
+            bias,
+        })
+    }
+
+    // d, M and nbits as size_t then the centroids
+    fn product_quantizer(&mut self) -> Result<Quantizer> {
+        let dim = self.u64()? as usize;
+        let nb_sub = self.u64()? as usize;
+        let nbits = self.u64()?;
+        if nbits != 8 {
+            return Err(anyhow!(
+                "faiss quantizer of {} bits, only 8 bits are supported",
+                nbits
+            ));
+        }
+        let centroids = self.f32_vec()?;
+        Quantizer::new(dim, nb_sub, centroids)
+    }
+} // end of impl FaissReader
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::Hnsw;
+    use rand::SeedableRng;
+
+    // the bytes FAISS writes for these structures
+    fn write_f32_vec(out: &mut Vec<u8>, values: &[f32]) {
+        out.extend((values.len() as u64).to_ne_bytes());
+        values.iter().for_each(|x| out.extend(x.to_ne_bytes()));
+    }
+
+    fn write_index_header(out: &mut Vec<u8>, dim: i32) {
+        out.extend(dim.to_ne_bytes());
+        out.extend(0i64.to_ne_bytes());
+        out.extend([(1i64 << 20).to_ne_bytes(), (1i64 << 20).to_ne_bytes()].concat());
+        out.push(1);
+        out.extend(FAISS_METRIC_L2.to_ne_bytes());
+    }
+
+    fn write_index_pq(out: &mut Vec<u8>, dim: usize, nb_sub: usize, centroids: &[f32]) {
+        out.extend(FOURCC_INDEX_PQ);
+        write_index_header(out, dim as i32);
+        out.extend((dim as u64).to_ne_bytes());
+        out.extend((nb_sub as u64).to_ne_bytes());
+        out.extend(8u64.to_ne_bytes());
+        write_f32_vec(out, centroids);
+        // codes, search_type, encode_signs and polysemous_ht follow and are not read
+        write_f32_vec(out, &[]);
+        out.extend([0u8; 9]);
+    }
+
+    #[test]
+    fn test_faiss_quantizer() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(955);
+        let (dim, nb_sub) = (16, 4);
+        let (data, _) = synthetic::gaussian_mixture(2000, dim, 10, 0.05, &mut rng);
+        // centroids of a sub space are sub vectors of data, as a k-means initialization
+        let mut centroids = Vec::with_capacity(dim * NB_CENTROID);
+        for sub in 0..nb_sub {
+            for v in data.iter().step_by(7).take(NB_CENTROID) {
+                centroids.extend_from_slice(&v[sub * 4..(sub + 1) * 4]);
+            }
+        }
+        let mut bytes = Vec::new();
+        write_index_pq(&mut bytes, dim, nb_sub, &centroids);
+        let pq = Quantizer::from_faiss_bytes(&bytes).unwrap();
+        assert_eq!((pq.get_dim(), pq.get_nb_sub()), (dim, nb_sub));
+        // a centroid is coded exactly
+        let codes = pq.encode(&data[7]);
+        assert_eq!(pq.decode(&codes), data[7]);
+        // lut distance equals distance to decoded vector
+        let lut = pq.prepare(&data[3]);
+        let codes: Vec<Vec<u8>> = data.iter().map(|v| pq.encode(v)).collect();
+        let direct = l2sq(&data[3], &pq.decode(&codes[11])).sqrt();
+        assert!((AsymmetricDistance::eval(&pq, &lut, &codes[11]) - direct).abs() < 1.0e-4);
+        // a Hnsw on codes searched with f32 queries : the nearest code found is as near as the code of the query
+        // (other points can have the same code)
+        let codes_with_id: Vec<(&Vec<u8>, usize)> = codes.iter().zip(0..).collect();
+        let hnsw = Hnsw::<u8, Quantizer>::new(16, data.len(), 16, 200, pq.clone());
+        hnsw.parallel_insert(&codes_with_id);
+        let nb_found = (0..2000)
+            .step_by(10)
+            .filter(|&i| {
+                let res = hnsw.search_asymmetric(&data[i], 1, 64, &pq);
+                let d_code = AsymmetricDistance::eval(&pq, &pq.prepare(&data[i]), &codes[i]);
+                res[0].distance <= d_code + 1.0e-5
+            })
+            .count();
+        assert!(nb_found >= 190, "nb_found {} / 200", nb_found);
+        // an OPQ index : a permutation of coordinates then the same IndexPQ
+        let perm: Vec<usize> = (0..dim).rev().collect();
+        let mut matrix = vec![0f32; dim * dim];
+        perm.iter()
+            .enumerate()
+            .for_each(|(i, &j)| matrix[i * dim + j] = 1.);
+        let mut opq_bytes = b"IxPT".to_vec();
+        write_index_header(&mut opq_bytes, dim as i32);
+        opq_bytes.extend(1i32.to_ne_bytes());
+        opq_bytes.extend(FOURCC_LINEAR);
+        opq_bytes.push(0);
+        write_f32_vec(&mut opq_bytes, &matrix);
+        write_f32_vec(&mut opq_bytes, &[]);
+        opq_bytes.extend([(dim as i32).to_ne_bytes(), (dim as i32).to_ne_bytes()].concat());
+        opq_bytes.push(1);
+        opq_bytes.extend(&bytes);
+        let opq = Quantizer::from_faiss_bytes(&opq_bytes).unwrap();
+        let permuted: Vec<f32> = perm.iter().map(|&j| data[7][j]).collect();
+        let codes = opq.encode(&permuted);
+        assert_eq!(codes, pq.encode(&data[7]));
+        assert_eq!(opq.decode(&codes), permuted);
+        // truncated data and codebooks of another size are rejected
+        assert!(Quantizer::from_faiss_bytes(&bytes[..100]).is_err());
+        let mut bytes_4bits = bytes.clone();
+        let nbits_at = 4 + 4 + 3 * 8 + 1 + 4 + 2 * 8;
+        bytes_4bits[nbits_at..nbits_at + 8].copy_from_slice(&4u64.to_ne_bytes());
+        assert!(Quantizer::from_faiss_bytes(&bytes_4bits).is_err());
+    } // end of test_faiss_quantizer
+} // end of mod tests
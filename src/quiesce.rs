@@ -0,0 +1,101 @@
+//! Pausing writes to a structure.
+//!
+//! [Hnsw::quiesce] waits for insertions and deletions in flight and blocks new ones until the returned
+//! [QuiesceGuard] is dropped, searches go on. Dumps, statistics or a [Hnsw::deep_clone] taken while the guard is
+//! held see one state of the structure, without an application mutex around all its writes.
+//!
+//! Writes are blocked, not refused: a thread inserting while another holds the guard waits for its drop.
+//! A thread holding the guard must not insert or delete itself, it would wait for ever.
+
+use parking_lot::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::Hnsw;
+
+/// writes in flight share the gate, a quiesce takes it alone
+#[derive(Default)]
+pub(crate) struct WriteGate {
+    gate: RwLock<()>,
+}
+
+impl WriteGate {
+    /// held by a write for its duration. A waiting quiesce blocks new writes.
+    pub(crate) fn enter(&self) -> RwLockReadGuard<'_, ()> {
+        self.gate.read()
+    }
+}
+
+/// Writes to a structure are blocked while it is held, see [Hnsw::quiesce]
+pub struct QuiesceGuard<'a> {
+    _guard: RwLockWriteGuard<'a, ()>,
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// waits for insertions and deletions in flight and blocks new ones until the guard is dropped.
+    /// Searches are not blocked. See module doc.
+    pub fn quiesce(&self) -> QuiesceGuard<'_> {
+        QuiesceGuard {
+            _guard: self.writes.gate.write(),
+        }
+    }
+
+    /// returns the guard if no write is in flight, None otherwise
+    pub fn try_quiesce(&self) -> Option<QuiesceGuard<'_>> {
+        self.writes
+            .gate
+            .try_write()
+            .map(|guard| QuiesceGuard { _guard: guard })
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn test_quiesce() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(968);
+        let data = synthetic::uniform(2000, 8, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 2000, 16, 100, DistL2 {});
+        for (i, v) in data.iter().enumerate().take(1000) {
+            hnsw.insert((v, i));
+        }
+        let stop = AtomicBool::new(false);
+        std::thread::scope(|s| {
+            s.spawn(|| {
+                for (i, v) in data.iter().enumerate().skip(1000) {
+                    hnsw.insert((v, i));
+                }
+            });
+            while hnsw.get_nb_point() < 1100 {
+                std::thread::yield_now();
+            }
+            let guard = hnsw.quiesce();
+            assert!(hnsw.try_quiesce().is_none());
+            let nb_point = hnsw.get_nb_point();
+            // searches go on, insertions wait
+            s.spawn(|| {
+                while !stop.load(Ordering::Relaxed) {
+                    assert_eq!(hnsw.search(&data[0], 1, 16)[0].d_id, 0);
+                }
+            });
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            assert_eq!(hnsw.get_nb_point(), nb_point);
+            assert_eq!(hnsw.get_insertion_seq() as usize, nb_point);
+            drop(guard);
+            stop.store(true, Ordering::Relaxed);
+        });
+        assert_eq!(hnsw.get_nb_point(), 2000);
+        assert!(hnsw.try_quiesce().is_some());
+    } // end of test_quiesce
+} // end of mod tests
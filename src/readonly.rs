@@ -0,0 +1,165 @@
+//! Read only indexes, to protect golden artifacts from accidental writes.
+//!
+//! [Hnsw::set_read_only] marks an index: insertions, deletions, repairs of lists, aliases and deletions of namespaces
+//! are then rejected with [HnswError::ReadOnly] ([Hnsw::try_insert], [Hnsw::insert_dedup], [Hnsw::insert_verbose],
+//! [Hnsw::remove_where], [Hnsw::repair], [Hnsw::alias], [Hnsw::delete_namespace]...). Insertion functions returning
+//! nothing ([Hnsw::insert]...) log the error and insert nothing.
+//! The mark is kept by a dump, so a reloaded index is read only too.
+//!
+//! [HnswIo::load_index] reloads a dump and, if it is read only, converts it to the lock-free [FlatHnsw]
+//! (the Hnsw is dropped once flattened, so the peak memory is the one of both), so services loading a golden
+//! artifact get the fastest search structure without knowing it. A dump not marked is reloaded as a Hnsw.
+
+use anyhow::Result;
+use serde::{Serialize, de::DeserializeOwned};
+
+use anndists::dist::distances::Distance;
+
+use crate::flathnsw::FlatHnsw;
+use crate::hnsw::{DataId, Hnsw, HnswError, Neighbour, PointId};
+use crate::hnswio::HnswIo;
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// rejects (or accepts again) insertions and deletions, see module doc
+    pub fn set_read_only(&mut self, on: bool) {
+        self.read_only = on;
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+} // end of impl Hnsw
+
+/// an index reloaded by [HnswIo::load_index]
+pub enum LoadedIndex<'b, T: Clone + Send + Sync + 'static, D: Distance<T> + Send + Sync> {
+    /// the dump was not read only
+    Mutable(Box<Hnsw<'b, T, D>>),
+    /// the dump was read only
+    Frozen { flat: Box<FlatHnsw<T>>, dist: D },
+}
+
+impl<T, D> LoadedIndex<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    pub fn is_frozen(&self) -> bool {
+        matches!(self, LoadedIndex::Frozen { .. })
+    }
+
+    pub fn search(&self, query: &[T], knbn: usize, ef_s: usize) -> Vec<Neighbour> {
+        match self {
+            LoadedIndex::Mutable(hnsw) => hnsw.search(query, knbn, ef_s),
+            LoadedIndex::Frozen { flat, dist } => flat.search(query, knbn, ef_s, dist),
+        }
+    }
+
+    /// inserts as [Hnsw::try_insert], a frozen index returns [HnswError::ReadOnly]
+    pub fn try_insert(&self, data_with_id: (&[T], DataId)) -> Result<PointId, HnswError> {
+        match self {
+            LoadedIndex::Mutable(hnsw) => hnsw.try_insert(data_with_id),
+            LoadedIndex::Frozen { .. } => Err(HnswError::ReadOnly),
+        }
+    }
+} // end of impl LoadedIndex
+
+impl HnswIo {
+    /// reloads a dump as a [FlatHnsw] if it is read only, as a Hnsw otherwise. See module doc.
+    pub fn load_index<'b, 'a, T, D>(&'a mut self) -> Result<LoadedIndex<'b, T, D>>
+    where
+        T: 'static + Serialize + DeserializeOwned + Clone + Sized + Send + Sync + std::fmt::Debug,
+        D: Distance<T> + Default + Send + Sync,
+        'a: 'b,
+    {
+        let hnsw: Hnsw<T, D> = self.load_hnsw()?;
+        if !hnsw.is_read_only() {
+            return Ok(LoadedIndex::Mutable(Box::new(hnsw)));
+        }
+        let flat = FlatHnsw::try_from_hnsw(&hnsw)?;
+        log::info!(
+            "load_index : read only dump loaded as a flat index of {} points",
+            flat.get_nb_point()
+        );
+        Ok(LoadedIndex::Frozen {
+            flat: Box::new(flat),
+            dist: D::default(),
+        })
+    } // end of load_index
+} // end of impl HnswIo
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_read_only() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(996);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        // a serial build with seeded levels, so that the flat index below answers as the graph
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        hnsw.set_level_seed(996);
+        for (i, v) in data.iter().enumerate().take(900) {
+            hnsw.insert((v, i));
+        }
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "mutable").unwrap();
+        hnsw.set_read_only(true);
+        // writes are rejected
+        assert_eq!(hnsw.try_insert((&data[900], 900)), Err(HnswError::ReadOnly));
+        hnsw.insert((&data[901], 901));
+        assert_eq!(
+            hnsw.insert_verbose((&data[902], 902)).unwrap_err(),
+            HnswError::ReadOnly
+        );
+        assert_eq!(
+            hnsw.insert_dedup((&data[902], 902)),
+            Err(HnswError::ReadOnly)
+        );
+        assert_eq!(hnsw.remove_batch(&[0, 1, 2]), Err(HnswError::ReadOnly));
+        assert_eq!(hnsw.repair(), Err(HnswError::ReadOnly));
+        let err = hnsw.alias(0, 1000).unwrap_err();
+        assert_eq!(err.downcast_ref::<HnswError>(), Some(&HnswError::ReadOnly));
+        assert_eq!(hnsw.get_canonical_id(1000), 1000);
+        assert_eq!(hnsw.delete_namespace(0), Err(HnswError::ReadOnly));
+        assert_eq!(hnsw.search(&data[0], 1, 64)[0].d_id, 0);
+        assert_eq!(hnsw.get_nb_point(), 900);
+        assert_eq!(hnsw.get_nb_deleted(), 0);
+        // the mark is kept by a dump
+        hnsw.file_dump(directory.path(), "golden").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "golden");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert!(reloaded.is_read_only());
+        assert!(reloaded.try_insert((&data[900], 900)).is_err());
+        // and gives a flat index with the same results
+        let mut reloader = HnswIo::new(directory.path(), "golden");
+        let index: LoadedIndex<f32, DistL2> = reloader.load_index().unwrap();
+        assert!(index.is_frozen());
+        assert_eq!(
+            index.try_insert((&data[900], 900)),
+            Err(HnswError::ReadOnly)
+        );
+        for query in data.iter().step_by(100) {
+            let res = index.search(query, 10, 64);
+            let expected = hnsw.search(query, 10, 64);
+            assert_eq!(res[0].d_id, expected[0].d_id);
+        }
+        // a dump not marked stays mutable
+        let mut reloader = HnswIo::new(directory.path(), "mutable");
+        let index: LoadedIndex<f32, DistL2> = reloader.load_index().unwrap();
+        assert!(!index.is_frozen());
+        assert!(index.try_insert((&data[900], 900)).is_ok());
+        // the mark can be removed
+        hnsw.set_read_only(false);
+        hnsw.insert((&data[901], 901));
+        assert_eq!(hnsw.get_nb_point(), 901);
+    } // end of test_read_only
+} // end of mod tests
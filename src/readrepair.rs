@@ -0,0 +1,149 @@
+//! Lazy pruning of over-degree neighbour lists by searches (read repair).
+//!
+//! An insertion adds a reverse link to the list of each of its neighbours and prunes a list beyond its bound
+//! (2 * max_nb_connection in layer 0, max_nb_connection above, see also [crate::adaptive]). During a bulk load,
+//! [Hnsw::set_link_slack] lets lists grow up to their bound plus a slack before pruning them: fewer sorts and
+//! prunings under the write lock of a list, at the cost of lists longer than their bound once the load is over.
+//!
+//! With [Hnsw::set_read_repair] a search notes the lists over their bound it expands and prunes them to their bound
+//! at its end, so the lists searches go through are repaired the first time they are visited, without a pass
+//! on the whole structure. The pruned neighbours are the farthest ones still linked back to the point, as
+//! in insertions (see [crate::linkcheck]). A list locked by another thread is left to a later search.
+//! [Hnsw::get_nb_read_repaired] counts the lists pruned.
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Point, links_to};
+
+/// state of read repair
+#[derive(Default)]
+pub(crate) struct ReadRepair {
+    nb_repaired: AtomicUsize,
+}
+
+impl<'b, T, D> Hnsw<'b, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// lets next insertions keep slack links beyond the bound of a list before pruning it, see module doc.
+    /// 0 (the default) prunes at the bound.
+    pub fn set_link_slack(&mut self, slack: usize) {
+        self.link_slack = slack;
+    }
+
+    /// starts (or stops) the pruning of over-degree lists by searches, see module doc
+    pub fn set_read_repair(&mut self, on: bool) {
+        self.read_repair = on.then(ReadRepair::default);
+    }
+
+    /// number of lists pruned by searches since read repair was set
+    pub fn get_nb_read_repaired(&self) -> usize {
+        self.read_repair
+            .as_ref()
+            .map_or(0, |r| r.nb_repaired.load(Ordering::Relaxed))
+    }
+
+    /// prunes lists of points (at layer) noted by a search to their bound, and clears points
+    pub(crate) fn repair_lists(&self, points: &mut Vec<(Arc<Point<'b, T>>, u8)>) {
+        // a read only index keeps its lists
+        let Some(read_repair) = self.read_repair.as_ref().filter(|_| !self.read_only) else {
+            points.clear();
+            return;
+        };
+        for (point, layer) in points.drain(..) {
+            let p_id = point.get_point_id();
+            let (l, bound) = (
+                layer as usize,
+                self.get_max_nb_neighbours(p_id, layer as usize),
+            );
+            let Some(mut neighbours) = point.neighbours.try_write() else {
+                continue;
+            };
+            // the list can have been repaired by another search
+            if neighbours[l].len() <= bound {
+                continue;
+            }
+            neighbours[l].sort_unstable();
+            while neighbours[l].len() > bound {
+                let rank = neighbours[l]
+                    .iter()
+                    .rposition(|n| links_to(&n.point_ref, p_id, l))
+                    .unwrap_or(neighbours[l].len() - 1);
+                let pruned = neighbours[l].remove(rank);
+                self.record_link(&point, &pruned, layer, false);
+            }
+            self.dirty.mark(p_id);
+            read_repair.nb_repaired.fetch_add(1, Ordering::Relaxed);
+        }
+    } // end of repair_lists
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    // number of layer 0 lists over 2 * max_nb_connection
+    fn nb_overfull(hnsw: &Hnsw<f32, DistL2>) -> usize {
+        let layers = hnsw.get_point_indexation().points_by_layer.read();
+        layers[0]
+            .iter()
+            .filter(|p| p.neighbours.read()[0].len() > 16)
+            .count()
+    }
+
+    #[test]
+    fn test_read_repair() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(993);
+        let (data, _) = synthetic::gaussian_mixture(3000, 8, 5, 0.02, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(8, 3000, 16, 100, DistL2 {});
+        // a relaxed bulk load
+        hnsw.set_link_slack(16);
+        hnsw.parallel_insert(&data_with_id);
+        hnsw.set_link_slack(0);
+        let before = nb_overfull(&hnsw);
+        assert!(before > 0);
+        assert_eq!(hnsw.get_nb_read_repaired(), 0);
+        let queries = synthetic::uniform(200, 8, &mut rng);
+        let plain: Vec<_> = queries.iter().map(|q| hnsw.search(q, 10, 32)).collect();
+        assert_eq!(nb_overfull(&hnsw), before);
+        // searches prune the lists they go through
+        hnsw.set_read_repair(true);
+        let repaired: Vec<_> = queries.iter().map(|q| hnsw.search(q, 10, 32)).collect();
+        let after = nb_overfull(&hnsw);
+        let nb_repaired = hnsw.get_nb_read_repaired();
+        log::info!(
+            "over-degree lists before {}, after {}, repaired {}",
+            before,
+            after,
+            nb_repaired
+        );
+        // lists of upper layers are repaired too
+        assert!(after < before);
+        assert!(nb_repaired >= before - after);
+        hnsw.check_links().unwrap();
+        // the same queries again find lists repaired
+        let again: Vec<_> = queries.iter().map(|q| hnsw.search(q, 10, 32)).collect();
+        assert!(again.iter().all(|res| res.len() == 10));
+        let nb_common = |a: &Vec<Vec<crate::hnsw::Neighbour>>| {
+            a.iter()
+                .zip(&plain)
+                .map(|(x, y)| {
+                    x.iter()
+                        .filter(|n| y.iter().any(|m| m.d_id == n.d_id))
+                        .count()
+                })
+                .sum::<usize>()
+        };
+        assert!(nb_common(&repaired) > 1800);
+        assert!(nb_common(&again) > 1800);
+    } // end of test_read_repair
+} // end of mod tests
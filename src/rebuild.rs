@@ -0,0 +1,477 @@
+//! Rebuild of the graph from stored points, possibly with new parameters.
+//!
+//! The vectors (and payloads) stored in a Hnsw are reinserted in a fresh structure, so changing
+//! max_nb_connection or ef_construction does not require reading the original data again.
+//! Deleted points and points of deleted namespaces are not reinserted, so a rebuild compacts the structure.
+//!
+//! [Hnsw::rebuild_in_background] builds a new structure on a background thread while searches, insertions and
+//! deletions go on in the current one. Insertions and deletions made during the rebuild are recorded and replayed
+//! in the new structure, [RebuildHandle::swap_into] replays the last ones and swaps it in place of the current one.
+//!
+//! With a level seed (see [RebuildParams::level_seed]) points are reinserted serially, so that a rebuild is reproducible.
+
+use parking_lot::Mutex;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread::JoinHandle;
+
+use anyhow::anyhow;
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw};
+use crate::payload::Payload;
+
+/// construction parameters of a rebuilt graph
+#[derive(Copy, Clone, Debug)]
+pub struct RebuildParams {
+    pub max_nb_connection: usize,
+    pub ef_construction: usize,
+    pub max_layer: usize,
+    pub extend_candidates: bool,
+    pub keep_pruned: bool,
+    /// seed of levels, see [Hnsw::set_level_seed]
+    pub level_seed: Option<u64>,
+}
+
+impl RebuildParams {
+    /// the parameters of an existing structure
+    pub fn from_hnsw<T, D>(hnsw: &Hnsw<T, D>) -> Self
+    where
+        T: Clone + Send + Sync,
+        D: Distance<T> + Send + Sync,
+    {
+        RebuildParams {
+            max_nb_connection: hnsw.max_nb_connection,
+            ef_construction: hnsw.ef_construction,
+            max_layer: hnsw.max_layer,
+            extend_candidates: hnsw.extend_candidates,
+            keep_pruned: hnsw.keep_pruned,
+            level_seed: hnsw.get_level_seed(),
+        }
+    }
+
+    pub fn max_nb_connection(mut self, max_nb_connection: usize) -> Self {
+        self.max_nb_connection = max_nb_connection;
+        self
+    }
+
+    pub fn ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction;
+        self
+    }
+
+    pub fn max_layer(mut self, max_layer: usize) -> Self {
+        self.max_layer = max_layer;
+        self
+    }
+
+    pub fn level_seed(mut self, seed: u64) -> Self {
+        self.level_seed = Some(seed);
+        self
+    }
+} // end of impl RebuildParams
+
+/// vectors, ids and payloads of points to (re)insert
+type PointsToInsert<T> = Vec<(Vec<T>, DataId, Payload)>;
+
+// copies vectors, ids and payloads of all points
+fn snapshot<T, D>(hnsw: &Hnsw<T, D>) -> PointsToInsert<T>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    let indexation = hnsw.get_point_indexation();
+    let payloads = indexation.payloads.read();
+    indexation
+        .par_iter()
+        .filter(|p| !hnsw.is_deleted(p))
+        .map(|p| {
+            (
+                p.get_v().to_vec(),
+                p.get_origin_id(),
+                payloads.get(p.get_point_id()),
+            )
+        })
+        .collect()
+}
+
+// builds a structure with points, parameters params, distance dist and metadata
+fn build_from<T, D>(
+    points: PointsToInsert<T>,
+    metadata: BTreeMap<String, String>,
+    dist: D,
+    params: &RebuildParams,
+) -> Hnsw<'static, T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Send + Sync,
+{
+    log::info!(
+        "rebuilding {} points with max_nb_connection {}, ef_construction {}",
+        points.len(),
+        params.max_nb_connection,
+        params.ef_construction
+    );
+    let mut rebuilt = Hnsw::<T, D>::new(
+        params.max_nb_connection,
+        points.len().max(1),
+        params.max_layer,
+        params.ef_construction,
+        dist,
+    );
+    rebuilt.set_extend_candidates(params.extend_candidates);
+    rebuilt.set_keeping_pruned(params.keep_pruned);
+    rebuilt.metadata = metadata;
+    if let Some(seed) = params.level_seed {
+        rebuilt.set_level_seed(seed);
+        points
+            .iter()
+            .for_each(|(v, id, payload)| rebuilt.insert_with_payload((v, *id), *payload));
+    } else {
+        points
+            .par_iter()
+            .for_each(|(v, id, payload)| rebuilt.insert_with_payload((v, *id), *payload));
+    }
+    rebuilt
+} // end of build_from
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Clone + Send + Sync,
+{
+    /// returns a new structure built (in parallel, serially with a level seed) from the vectors stored in self with new construction parameters.  
+    /// Ids, payloads, metadata and namespaces are kept. self is not modified and can be used during the rebuild.
+    /// This avoids reading again the original data to change max_nb_connection or ef_construction.
+    ///
+    /// ```text
+    ///     let params = RebuildParams::from_hnsw(&hnsw).max_nb_connection(32);
+    ///     let hnsw = hnsw.rebuild_with(&params);
+    /// ```
+    pub fn rebuild_with(&self, params: &RebuildParams) -> Hnsw<'static, T, D> {
+        self.rebuild_with_distance(params, self.get_distance().clone())
+    }
+
+    /// as [Self::rebuild_with] but the new structure uses distance dist.  
+    /// The distance must be meaningful for the stored vectors (for example DistCosine to DistDot on normalized vectors).
+    pub fn rebuild_with_distance<D2>(
+        &self,
+        params: &RebuildParams,
+        dist: D2,
+    ) -> Hnsw<'static, T, D2>
+    where
+        D2: Distance<T> + Send + Sync,
+    {
+        let mut rebuilt = build_from(snapshot(self), self.metadata.clone(), dist, params);
+        rebuilt.namespaces = self.namespaces.clone();
+        rebuilt
+    }
+} // end of impl Hnsw
+
+//==================================================================================
+
+// a modification made while a rebuild is running
+enum Change<T> {
+    Insert(Vec<T>, DataId, Payload),
+    Remove(Vec<DataId>),
+}
+
+/// insertions and deletions made in a structure while a rebuild of it is running, see [Hnsw::rebuild_in_background]
+pub(crate) struct RebuildLog<T> {
+    // set while a rebuild is running, avoids the lock on insertions otherwise
+    active: AtomicBool,
+    changes: Mutex<Vec<Change<T>>>,
+}
+
+impl<T> Default for RebuildLog<T> {
+    fn default() -> Self {
+        RebuildLog {
+            active: AtomicBool::new(false),
+            changes: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+impl<T: Clone> RebuildLog<T> {
+    pub(crate) fn record_insert(&self, data: &[T], origin_id: DataId, payload: Payload) {
+        if self.active.load(Ordering::Acquire) {
+            self.changes
+                .lock()
+                .push(Change::Insert(data.to_vec(), origin_id, payload));
+        }
+    }
+
+    pub(crate) fn record_removal(&self, ids: &[DataId]) {
+        if self.active.load(Ordering::Acquire) && !ids.is_empty() {
+            self.changes.lock().push(Change::Remove(ids.to_vec()));
+        }
+    }
+
+    // swaps out the changes recorded so far, the lock is not held during their replay
+    fn take(&self) -> Vec<Change<T>> {
+        std::mem::take(&mut *self.changes.lock())
+    }
+} // end of impl RebuildLog
+
+// stops the recording of changes when dropped, also when the rebuild thread panics
+// or the handle is dropped without a swap
+struct StopLog<T>(Arc<RebuildLog<T>>);
+
+impl<T> Drop for StopLog<T> {
+    fn drop(&mut self) {
+        self.0.active.store(false, Ordering::Release);
+        self.0.changes.lock().clear();
+    }
+}
+
+// replays changes in their order, consecutive insertions in parallel (serially with a level seed)
+fn replay<T, D>(hnsw: &Hnsw<T, D>, changes: Vec<Change<T>>)
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    let mut inserts = Vec::new();
+    let flush = |inserts: &mut Vec<(Vec<T>, DataId, Payload)>| {
+        if hnsw.get_level_seed().is_some() {
+            inserts
+                .iter()
+                .for_each(|(v, id, payload)| hnsw.insert_with_payload((v, *id), *payload));
+        } else {
+            inserts
+                .par_iter()
+                .for_each(|(v, id, payload)| hnsw.insert_with_payload((v, *id), *payload));
+        }
+        inserts.clear();
+    };
+    for change in changes {
+        match change {
+            Change::Insert(v, id, payload) => inserts.push((v, id, payload)),
+            Change::Remove(ids) => {
+                flush(&mut inserts);
+                if let Err(e) = hnsw.remove_batch(&ids) {
+                    log::error!("rebuild, replay of removals failed : {}", e);
+                }
+            }
+        }
+    }
+    flush(&mut inserts);
+} // end of replay
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Clone + Send + Sync + 'static,
+{
+    /// builds a new structure with params on a background thread, see module doc.  
+    /// Searches, insertions and deletions go on in self during the rebuild, [RebuildHandle::swap_into]
+    /// replays insertions and deletions made meanwhile and puts the new structure in place of self.
+    /// Fails if a rebuild of self is already running.
+    ///
+    /// ```text
+    ///     let handle = hnsw.read().rebuild_in_background(RebuildParams::from_hnsw(&hnsw.read()).max_nb_connection(32))?;
+    ///     // ... searches and insertions through hnsw.read()
+    ///     handle.swap_into(&mut hnsw.write())?;
+    /// ```
+    pub fn rebuild_in_background(
+        &self,
+        params: RebuildParams,
+    ) -> anyhow::Result<RebuildHandle<T, D>> {
+        // an insertion is either finished and in the snapshot, or recorded in the log
+        let points = {
+            let _quiet = self.quiesce();
+            if self.rebuild_log.active.swap(true, Ordering::AcqRel) {
+                return Err(anyhow!("a rebuild is already running"));
+            }
+            snapshot(self)
+        };
+        let stop = StopLog(Arc::clone(&self.rebuild_log));
+        let metadata = self.metadata.clone();
+        let dist = self.get_distance().clone();
+        let handle = std::thread::spawn(move || {
+            let rebuilt = build_from(points, metadata, dist, &params);
+            // catch up with changes made during the build, the last ones are replayed by the swap
+            loop {
+                let changes = stop.0.take();
+                if changes.is_empty() {
+                    break;
+                }
+                log::info!("rebuild done, replaying {} changes", changes.len());
+                replay(&rebuilt, changes);
+            }
+            (rebuilt, stop)
+        });
+        Ok(RebuildHandle { handle })
+    } // end of rebuild_in_background
+
+    /// starts a rebuild with current parameters if the fraction of deleted points is above max_deleted_fraction.
+    /// Returns None if no rebuild is needed or if one is already running.
+    pub fn compact_if(&self, max_deleted_fraction: f64) -> Option<RebuildHandle<T, D>> {
+        if self.get_deleted_fraction() <= max_deleted_fraction {
+            return None;
+        }
+        self.rebuild_in_background(RebuildParams::from_hnsw(self))
+            .ok()
+    }
+} // end of impl Hnsw
+
+/// handle on a running rebuild, see [Hnsw::rebuild_in_background]
+pub struct RebuildHandle<T: Clone + Send + Sync + 'static, D: Distance<T>> {
+    handle: JoinHandle<(Hnsw<'static, T, D>, StopLog<T>)>,
+}
+
+impl<T, D> RebuildHandle<T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Send + Sync,
+{
+    pub fn is_finished(&self) -> bool {
+        self.handle.is_finished()
+    }
+
+    /// waits for the rebuild, replays the last changes made in hnsw and replaces hnsw by the new structure.  
+    /// hnsw must be the structure the rebuild was started from. Changes are not recorded anymore after the swap
+    /// or if the rebuild failed.
+    pub fn swap_into(self, hnsw: &mut Hnsw<'static, T, D>) -> anyhow::Result<()> {
+        let (mut rebuilt, stop) = self
+            .handle
+            .join()
+            .map_err(|_| anyhow!("rebuild thread panicked"))?;
+        if !Arc::ptr_eq(&stop.0, &hnsw.rebuild_log) {
+            return Err(anyhow!(
+                "swap into a structure the rebuild was not started from"
+            ));
+        }
+        replay(&rebuilt, stop.0.take());
+        // namespaces as they are at the swap, deletions made during the rebuild included
+        rebuilt.namespaces = hnsw.namespaces.clone();
+        *hnsw = rebuilt;
+        Ok(())
+    }
+} // end of impl RebuildHandle
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::{DistL1, DistL2};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_rebuild_in_background() {
+        let nb_elem = 1000;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(908);
+        let data = synthetic::uniform(nb_elem, 10, &mut rng);
+        let mut hnsw = Hnsw::<f32, DistL2>::new(8, nb_elem, 16, 50, DistL2 {});
+        hnsw.set_metadata("model", "m1");
+        // serial insertions and rebuild with seeded levels, so that the searches below are exact
+        hnsw.set_level_seed(908);
+        for (i, v) in data.iter().enumerate().take(800) {
+            hnsw.insert((v, i));
+        }
+        //
+        let params = RebuildParams::from_hnsw(&hnsw)
+            .max_nb_connection(24)
+            .ef_construction(200);
+        assert_eq!(params.level_seed, Some(908));
+        let handle = hnsw.rebuild_in_background(params).unwrap();
+        assert!(hnsw.rebuild_in_background(params).is_err());
+        // insertions, deletions and searches go on during rebuild
+        for (i, v) in data.iter().enumerate().skip(800) {
+            hnsw.insert((v, i));
+        }
+        assert_eq!(hnsw.remove_batch(&[0, 1]), Ok(2));
+        assert!(!hnsw.search(&data[10], 1, 32).is_empty());
+        handle.swap_into(&mut hnsw).unwrap();
+        //
+        assert_eq!(hnsw.get_max_nb_connection(), 24);
+        assert_eq!(hnsw.get_ef_construction(), 200);
+        assert_eq!(hnsw.get_nb_point(), nb_elem);
+        assert_eq!(hnsw.get_nb_deleted(), 2);
+        assert_eq!(hnsw.get_metadata("model"), Some("m1"));
+        assert_eq!(hnsw.get_level_seed(), Some(908));
+        // points inserted during rebuild are in the new structure
+        assert_eq!(hnsw.search(&data[950], 1, 32)[0].d_id, 950);
+        // changes are not recorded anymore, a new rebuild can start
+        hnsw.insert((&data[0], nb_elem));
+        assert!(hnsw.rebuild_log.take().is_empty());
+        let handle = hnsw.rebuild_in_background(params).unwrap();
+        handle.swap_into(&mut hnsw).unwrap();
+        assert_eq!(hnsw.get_nb_point(), nb_elem - 1);
+    } // end of test_rebuild_in_background
+
+    #[test]
+    fn test_rebuild_dropped() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(908);
+        let data = synthetic::uniform(200, 10, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(8, 200, 16, 50, DistL2 {});
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        let params = RebuildParams::from_hnsw(&hnsw);
+        let handle = hnsw.rebuild_in_background(params).unwrap();
+        while !handle.is_finished() {
+            std::thread::yield_now();
+        }
+        // a handle dropped without swap stops the recording
+        drop(handle);
+        hnsw.insert((&data[0], 200));
+        assert!(hnsw.rebuild_log.take().is_empty());
+        let mut other = Hnsw::<f32, DistL2>::new(8, 200, 16, 50, DistL2 {});
+        let handle = hnsw.rebuild_in_background(params).unwrap();
+        assert!(handle.swap_into(&mut other).is_err());
+        assert!(!hnsw.rebuild_log.active.load(Ordering::Acquire));
+    } // end of test_rebuild_dropped
+
+    #[test]
+    fn test_rebuild_with() {
+        let nb_elem = 1000;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(909);
+        let data = synthetic::uniform(nb_elem, 10, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(8, nb_elem, 16, 50, DistL2 {});
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        // a serial rebuild with seeded levels, so that the searches below are exact
+        let params = RebuildParams::from_hnsw(&hnsw)
+            .max_nb_connection(32)
+            .ef_construction(400)
+            .level_seed(909);
+        let rebuilt = hnsw.rebuild_with(&params);
+        assert_eq!(rebuilt.get_nb_point(), nb_elem);
+        assert_eq!(rebuilt.get_max_nb_connection(), 32);
+        for i in [0, 333, 999] {
+            let res = rebuilt.search(&data[i], 1, 32);
+            assert_eq!(res[0].d_id, i);
+        }
+        // change of distance
+        let rebuilt = hnsw.rebuild_with_distance(&params, DistL1 {});
+        assert_eq!(rebuilt.search(&data[500], 1, 32)[0].d_id, 500);
+    } // end of test_rebuild_with
+
+    #[test]
+    fn test_compact_if() {
+        let nb_elem = 1000;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(921);
+        let data = synthetic::uniform(nb_elem, 10, &mut rng);
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 100, DistL2 {});
+        // serial insertions with seeded levels, the compacting rebuild is then serial too
+        hnsw.set_level_seed(921);
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        let removed: Vec<DataId> = (0..100).collect();
+        assert_eq!(hnsw.remove_batch(&removed), Ok(100));
+        assert!(hnsw.compact_if(0.2).is_none());
+        let removed: Vec<DataId> = (100..300).collect();
+        hnsw.remove_batch(&removed).unwrap();
+        let handle = hnsw.compact_if(0.2).unwrap();
+        handle.swap_into(&mut hnsw).unwrap();
+        assert_eq!(hnsw.get_nb_point(), 700);
+        assert_eq!(hnsw.get_nb_deleted(), 0);
+        assert_eq!(hnsw.search(&data[500], 1, 32)[0].d_id, 500);
+    } // end of test_compact_if
+} // end of mod tests
@@ -0,0 +1,348 @@
+//! Search of a quantized index reranked with full precision vectors.
+//!
+//! [Hnsw::search_reranked] searches a Hnsw storing codes (see [crate::quantizer] or [crate::asymmetric]) for ef
+//! candidates, then sorts the candidates by their exact distance to the query, computed with their original
+//! vectors given by a [VectorSource]: a [VectorFile] when originals live on disk, a slice of vectors in memory...
+//!
+//! Each search fetches ef vectors. A [CachedSource] wraps a source and keeps in memory the vectors of the points
+//! most often fetched (a fraction of the points, by count of fetches), so that frequent hits do not go to disk.
+//! Counts are halved at each update of the hot set so that the cache follows changes of the queries.
+//! [CachedSource::preload] fills the cache at once, with the hot points of an index counting accesses
+//! (see [crate::access]) for example.
+
+use hashbrown::{HashMap, HashSet};
+use parking_lot::{Mutex, RwLock};
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::{Result, anyhow};
+
+use anndists::dist::distances::Distance;
+
+use crate::asymmetric::AsymmetricDistance;
+use crate::hnsw::{DataId, Hnsw, Neighbour};
+
+/// gives the full precision vector of a data id
+pub trait VectorSource: Sync {
+    fn get_vector(&self, d_id: DataId) -> Result<Vec<f32>>;
+
+    /// vectors of several data ids, in the order of ids
+    fn get_vectors(&self, ids: &[DataId]) -> Result<Vec<Vec<f32>>> {
+        ids.iter().map(|d_id| self.get_vector(*d_id)).collect()
+    }
+}
+
+/// vectors in memory, the data id is the rank of the vector
+impl VectorSource for [Vec<f32>] {
+    fn get_vector(&self, d_id: DataId) -> Result<Vec<f32>> {
+        self.get(d_id)
+            .cloned()
+            .ok_or_else(|| anyhow!("no vector for data id {}", d_id))
+    }
+}
+
+/// vectors of dimension dim stored one after the other in a file, the data id is the rank of the vector.
+/// Each vector is read at its offset when asked for.
+pub struct VectorFile {
+    file: Mutex<File>,
+    dim: usize,
+    nb_vector: usize,
+}
+
+impl VectorFile {
+    /// writes data (vectors of the same dimension) in a file at path
+    pub fn write(path: &Path, data: &[Vec<f32>]) -> Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        for v in data {
+            v.iter().try_for_each(|x| out.write_all(&x.to_ne_bytes()))?;
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    /// opens a file of vectors of dimension dim written by [VectorFile::write]
+    pub fn open(path: &Path, dim: usize) -> Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        let vector_size = dim * std::mem::size_of::<f32>();
+        if dim == 0 || !len.is_multiple_of(vector_size) {
+            return Err(anyhow!(
+                "file {:?} of {} bytes does not hold vectors of dimension {}",
+                path,
+                len,
+                dim
+            ));
+        }
+        Ok(VectorFile {
+            file: Mutex::new(file),
+            dim,
+            nb_vector: len / vector_size,
+        })
+    }
+} // end of impl VectorFile
+
+impl VectorSource for VectorFile {
+    fn get_vector(&self, d_id: DataId) -> Result<Vec<f32>> {
+        if d_id >= self.nb_vector {
+            return Err(anyhow!("no vector for data id {}", d_id));
+        }
+        let mut bytes = vec![0u8; self.dim * std::mem::size_of::<f32>()];
+        {
+            let mut file = self.file.lock();
+            file.seek(SeekFrom::Start((d_id * bytes.len()) as u64))?;
+            file.read_exact(&mut bytes)?;
+        }
+        Ok(bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_ne_bytes(b.try_into().unwrap()))
+            .collect())
+    }
+}
+
+/// hits and misses of a [CachedSource]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    /// vectors found in the cache
+    pub nb_hit: usize,
+    /// vectors asked to the wrapped source
+    pub nb_miss: usize,
+}
+
+// fetch counts and hot set of a CachedSource
+struct Hotness {
+    counts: HashMap<DataId, u32>,
+    hot: HashSet<DataId>,
+    nb_fetch: usize,
+}
+
+/// a [VectorSource] keeping in memory the vectors most often fetched from another source, see module doc
+pub struct CachedSource<S: VectorSource> {
+    source: S,
+    capacity: usize,
+    // number of fetches between two updates of the hot set
+    period: usize,
+    hotness: Mutex<Hotness>,
+    vectors: RwLock<HashMap<DataId, Vec<f32>>>,
+    nb_hit: AtomicUsize,
+    nb_miss: AtomicUsize,
+}
+
+impl<S: VectorSource> CachedSource<S> {
+    /// caches the vectors of fraction * nb_point points (fraction in \[0, 1\]) of source
+    pub fn new(source: S, fraction: f32, nb_point: usize) -> Self {
+        assert!(
+            (0. ..=1.).contains(&fraction),
+            "cache fraction must be in [0, 1]"
+        );
+        let capacity = (fraction * nb_point as f32).ceil() as usize;
+        CachedSource {
+            source,
+            capacity,
+            period: 16 * capacity.max(64),
+            hotness: Mutex::new(Hotness {
+                counts: HashMap::new(),
+                hot: HashSet::new(),
+                nb_fetch: 0,
+            }),
+            vectors: RwLock::new(HashMap::with_capacity(capacity)),
+            nb_hit: AtomicUsize::new(0),
+            nb_miss: AtomicUsize::new(0),
+        }
+    }
+
+    /// maximum number of vectors cached
+    pub fn get_capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn get_stats(&self) -> CacheStats {
+        CacheStats {
+            nb_hit: self.nb_hit.load(Ordering::Relaxed),
+            nb_miss: self.nb_miss.load(Ordering::Relaxed),
+        }
+    }
+
+    /// puts the vectors of ids (the first ones, up to the capacity) in the cache and makes them the hot set,
+    /// for example the points most often returned by an index counting accesses (see [crate::access]).
+    pub fn preload(&self, ids: &[DataId]) -> Result<()> {
+        let ids = &ids[..ids.len().min(self.capacity)];
+        let fetched = self.source.get_vectors(ids)?;
+        let mut vectors = self.vectors.write();
+        let mut hotness = self.hotness.lock();
+        hotness.hot = ids.iter().copied().collect();
+        vectors.retain(|d_id, _| hotness.hot.contains(d_id));
+        for (d_id, v) in ids.iter().zip(fetched) {
+            vectors.insert(*d_id, v);
+        }
+        Ok(())
+    }
+
+    // counts fetches of ids, returns the new hot set when it is time to update it
+    fn count(&self, ids: &[DataId]) -> Option<HashSet<DataId>> {
+        let mut hotness = self.hotness.lock();
+        for d_id in ids {
+            *hotness.counts.entry(*d_id).or_insert(0) += 1;
+        }
+        hotness.nb_fetch += ids.len();
+        if hotness.nb_fetch < self.period {
+            return None;
+        }
+        hotness.nb_fetch = 0;
+        let mut by_count: Vec<(u32, DataId)> =
+            hotness.counts.iter().map(|(d_id, c)| (*c, *d_id)).collect();
+        if by_count.len() > self.capacity {
+            by_count.select_nth_unstable_by(self.capacity, |a, b| b.cmp(a));
+            by_count.truncate(self.capacity);
+        }
+        hotness.hot = by_count.into_iter().map(|(_, d_id)| d_id).collect();
+        // aging
+        hotness.counts.retain(|_, c| {
+            *c /= 2;
+            *c > 0
+        });
+        Some(hotness.hot.clone())
+    }
+} // end of impl CachedSource
+
+impl<S: VectorSource> VectorSource for CachedSource<S> {
+    fn get_vector(&self, d_id: DataId) -> Result<Vec<f32>> {
+        Ok(self.get_vectors(&[d_id])?.pop().unwrap())
+    }
+
+    fn get_vectors(&self, ids: &[DataId]) -> Result<Vec<Vec<f32>>> {
+        let new_hot = self.count(ids);
+        let mut found: Vec<Option<Vec<f32>>> = {
+            let vectors = self.vectors.read();
+            ids.iter().map(|d_id| vectors.get(d_id).cloned()).collect()
+        };
+        let missing: Vec<DataId> = ids
+            .iter()
+            .zip(&found)
+            .filter(|(_, v)| v.is_none())
+            .map(|(d_id, _)| *d_id)
+            .collect();
+        self.nb_hit
+            .fetch_add(ids.len() - missing.len(), Ordering::Relaxed);
+        self.nb_miss.fetch_add(missing.len(), Ordering::Relaxed);
+        let fetched = self.source.get_vectors(&missing)?;
+        {
+            let mut vectors = self.vectors.write();
+            if let Some(hot) = &new_hot {
+                vectors.retain(|d_id, _| hot.contains(d_id));
+            }
+            // until the first update of the hot set the cache is filled with any vector
+            let hot = self.hotness.lock();
+            for (d_id, v) in missing.iter().zip(&fetched) {
+                if vectors.len() < self.capacity && (hot.hot.is_empty() || hot.hot.contains(d_id)) {
+                    vectors.insert(*d_id, v.clone());
+                }
+            }
+        }
+        let mut fetched = fetched.into_iter();
+        Ok(found
+            .iter_mut()
+            .map(|v| v.take().unwrap_or_else(|| fetched.next().unwrap()))
+            .collect())
+    }
+} // end of impl VectorSource for CachedSource
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// searches ef candidates with the asymmetric distance dist (as [Hnsw::search_asymmetric]) and returns
+    /// the knbn nearest by the distance exact between the query and the vectors of source, see module doc.
+    pub fn search_reranked<A, E, S>(
+        &self,
+        query: &[f32],
+        knbn: usize,
+        ef_arg: usize,
+        dist: &A,
+        exact: &E,
+        source: &S,
+    ) -> Result<Vec<Neighbour>>
+    where
+        A: AsymmetricDistance<f32, T>,
+        E: Distance<f32>,
+        S: VectorSource + ?Sized,
+    {
+        let ef = self.get_search_ef(knbn, ef_arg);
+        let mut candidates = self.search_asymmetric(query, ef, ef, dist);
+        let ids: Vec<DataId> = candidates.iter().map(|n| n.d_id).collect();
+        let vectors = source.get_vectors(&ids)?;
+        for (n, v) in candidates.iter_mut().zip(&vectors) {
+            n.distance = exact.eval(query, v);
+        }
+        candidates.sort_unstable_by(|a, b| a.cmp_by_distance(b));
+        candidates.truncate(knbn);
+        Ok(candidates)
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::quantizer::{NB_CENTROID, Quantizer};
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_search_reranked() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(956);
+        let (nb_elem, dim, nb_sub) = (3000, 16, 2);
+        let (data, _) = synthetic::gaussian_mixture(nb_elem, dim, 10, 0.05, &mut rng);
+        let mut centroids = Vec::with_capacity(dim * NB_CENTROID);
+        for sub in 0..nb_sub {
+            for v in data.iter().step_by(11).take(NB_CENTROID) {
+                centroids.extend_from_slice(&v[sub * 8..(sub + 1) * 8]);
+            }
+        }
+        let pq = Quantizer::new(dim, nb_sub, centroids).unwrap();
+        let codes: Vec<Vec<u8>> = data.iter().map(|v| pq.encode(v)).collect();
+        let codes_with_id: Vec<(&Vec<u8>, usize)> = codes.iter().zip(0..).collect();
+        let hnsw = Hnsw::<u8, Quantizer>::new(16, nb_elem, 16, 200, pq.clone());
+        hnsw.parallel_insert(&codes_with_id);
+        //
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("originals.bin");
+        VectorFile::write(&path, &data).unwrap();
+        let file = VectorFile::open(&path, dim).unwrap();
+        assert!(file.get_vector(nb_elem).is_err());
+        // codes are not enough to find a query among points of the same code, originals are
+        let nb_found =
+            |found: &dyn Fn(usize) -> bool| (0..nb_elem).step_by(15).filter(|i| found(*i)).count();
+        let nb_quantized = nb_found(&|i| hnsw.search_asymmetric(&data[i], 1, 64, &pq)[0].d_id == i);
+        let nb_reranked = nb_found(&|i| {
+            hnsw.search_reranked(&data[i], 1, 64, &pq, &DistL2 {}, &file)
+                .unwrap()[0]
+                .d_id
+                == i
+        });
+        assert!(nb_reranked >= 190, "nb_found {} / 200", nb_reranked);
+        assert!(nb_reranked > nb_quantized);
+        // a few queries asked again and again : their candidates end in the cache
+        let cached = CachedSource::new(file, 0.05, nb_elem);
+        assert_eq!(cached.get_capacity(), 150);
+        let in_memory = data.as_slice();
+        for round in 0..40 {
+            for i in (0..nb_elem).step_by(1000) {
+                let res = hnsw.search_reranked(&data[i], 5, 32, &pq, &DistL2 {}, &cached);
+                if round % 10 == 0 {
+                    let expected =
+                        hnsw.search_reranked(&data[i], 5, 32, &pq, &DistL2 {}, in_memory);
+                    let ids = |r: Vec<Neighbour>| r.iter().map(|n| n.d_id).collect::<Vec<usize>>();
+                    assert_eq!(ids(res.unwrap()), ids(expected.unwrap()));
+                }
+            }
+        }
+        let stats = cached.get_stats();
+        log::info!("cache stats {:?}", stats);
+        assert!(stats.nb_hit > 4 * stats.nb_miss, "{:?}", stats);
+        assert!(cached.vectors.read().len() <= 150);
+    } // end of test_search_reranked
+} // end of mod tests
@@ -0,0 +1,176 @@
+//! Scratch structures of searches reused across requests.
+//!
+//! A search in a layer allocates a set of visited points, a heap of candidates and a buffer of results.
+//! At high query rates with [Hnsw::parallel_search](crate::hnsw::Hnsw::parallel_search()) these allocations
+//! make threads contend in the allocator. The pool keeps one [Scratch] by rayon worker, a search takes the
+//! scratch of its worker and gives it back cleared, keeping the capacities reached.
+//! Searches outside rayon workers (threads of an application calling through the C api...) share a stack of spare
+//! scratches, at most one by worker.
+
+use parking_lot::Mutex;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use hashbrown::HashSet;
+
+use crate::hnsw::{Point, PointId, PointWithOrder};
+
+/// temporary structures of a search in a layer
+pub(crate) struct Scratch<'b, T: Clone + Send + Sync> {
+    pub(crate) visited: HashSet<PointId>,
+    pub(crate) candidates: BinaryHeap<Arc<PointWithOrder<'b, T>>>,
+    pub(crate) results: Vec<Arc<PointWithOrder<'b, T>>>,
+    /// points (and layer) with more neighbours than their bound, see crate::readrepair
+    pub(crate) overfull: Vec<(Arc<Point<'b, T>>, u8)>,
+    /// distances of neighbours evaluated by a tiled distance, see Hnsw::set_tiled_distance
+    pub(crate) tiled: Vec<f32>,
+    /// ranks of the neighbours not yet visited, evaluated by a tiled distance
+    pub(crate) fresh: Vec<usize>,
+}
+
+impl<T: Clone + Send + Sync> Default for Scratch<'_, T> {
+    fn default() -> Self {
+        Scratch {
+            visited: HashSet::new(),
+            candidates: BinaryHeap::new(),
+            results: Vec::new(),
+            overfull: Vec::new(),
+            tiled: Vec::new(),
+            fresh: Vec::new(),
+        }
+    }
+}
+
+/// one scratch by rayon worker of the global pool, and spare ones for other threads
+pub(crate) struct ScratchPool<'b, T: Clone + Send + Sync> {
+    slots: Vec<Mutex<Scratch<'b, T>>>,
+    spare: Mutex<Vec<Scratch<'b, T>>>,
+}
+
+impl<'b, T: Clone + Send + Sync> ScratchPool<'b, T> {
+    pub(crate) fn new() -> Self {
+        ScratchPool {
+            slots: (0..rayon::current_num_threads())
+                .map(|_| Mutex::new(Scratch::default()))
+                .collect(),
+            spare: Mutex::new(Vec::new()),
+        }
+    }
+
+    // the slot of the current worker. A worker of another pool can share its index with a worker of the global pool,
+    // the mutex makes this safe (and is otherwise never contended).
+    fn slot(&self) -> Option<&Mutex<Scratch<'b, T>>> {
+        rayon::current_thread_index().and_then(|i| self.slots.get(i))
+    }
+
+    /// takes the scratch of current worker, a spare one (or an empty one) outside workers
+    pub(crate) fn take(&self) -> Scratch<'b, T> {
+        match self.slot() {
+            Some(slot) => std::mem::take(&mut *slot.lock()),
+            None => self.spare.lock().pop().unwrap_or_default(),
+        }
+    }
+
+    /// gives back the visited set and candidates of a scratch
+    pub(crate) fn give_back(&self, mut scratch: Scratch<'b, T>) {
+        scratch.visited.clear();
+        scratch.candidates.clear();
+        scratch.results.clear();
+        scratch.overfull.clear();
+        let Some(slot) = self.slot() else {
+            let mut spare = self.spare.lock();
+            if spare.len() < self.slots.len().max(1) {
+                spare.push(scratch);
+            }
+            return;
+        };
+        let mut kept = slot.lock();
+        // results can have been given back by recycle_results
+        if kept.results.capacity() > scratch.results.capacity() {
+            scratch.results = std::mem::take(&mut kept.results);
+        }
+        *kept = scratch;
+    }
+
+    /// gives back a buffer of results once the search is over
+    pub(crate) fn recycle_results(&self, mut results: Vec<Arc<PointWithOrder<'b, T>>>) {
+        results.clear();
+        let keep = move |kept: &mut Scratch<'b, T>| {
+            if results.capacity() > kept.results.capacity() {
+                kept.results = results;
+            }
+        };
+        match self.slot() {
+            Some(slot) => keep(&mut slot.lock()),
+            None => {
+                if let Some(kept) = self.spare.lock().last_mut() {
+                    keep(kept);
+                }
+            }
+        }
+    }
+} // end of impl ScratchPool
+
+#[cfg(test)]
+mod tests {
+
+    use crate::datasets::synthetic;
+    use crate::hnsw::Hnsw;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_scratch_reuse() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(930);
+        let data = synthetic::uniform(3000, 12, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        //
+        let queries = synthetic::uniform(500, 12, &mut rng);
+        // run twice so that searches of the second run get used scratches
+        for _ in 0..2 {
+            let answers = hnsw.parallel_search(&queries, 10, 64);
+            assert_eq!(answers.len(), queries.len());
+            for (q, answer) in queries.iter().zip(&answers) {
+                let expected = hnsw.search(q, 10, 64);
+                assert_eq!(answer.len(), 10);
+                for (a, e) in answer.iter().zip(&expected) {
+                    assert_eq!(a.distance, e.distance);
+                }
+            }
+        }
+        // scratches given back are cleared but keep their allocations
+        let slots = &hnsw.scratch.slots;
+        assert!(slots.iter().any(|s| s.lock().visited.capacity() > 0));
+        assert!(slots.iter().all(|s| {
+            let s = s.lock();
+            s.visited.is_empty() && s.candidates.is_empty() && s.results.is_empty()
+        }));
+    } // end of test_scratch_reuse
+
+    #[test]
+    fn test_search_into() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(1000);
+        let data = synthetic::uniform(2000, 12, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let queries = synthetic::uniform(50, 12, &mut rng);
+        // searches from this thread, outside rayon workers, reuse a spare scratch and the buffer of results
+        let mut out = Vec::new();
+        hnsw.search_into(&queries[0], 10, 64, &mut out);
+        let capacity = out.capacity();
+        assert_eq!(hnsw.scratch.spare.lock().len(), 1);
+        for q in &queries {
+            hnsw.search_into(q, 10, 64, &mut out);
+            let expected = hnsw.search(q, 10, 64);
+            assert_eq!(out.len(), 10);
+            assert!(out.iter().zip(&expected).all(|(a, e)| a.d_id == e.d_id));
+        }
+        assert_eq!(out.capacity(), capacity);
+        let spare = hnsw.scratch.spare.lock();
+        assert_eq!(spare.len(), 1);
+        assert!(spare[0].visited.capacity() > 0 && spare[0].visited.is_empty());
+    } // end of test_search_into
+} // end of mod tests
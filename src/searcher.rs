@@ -0,0 +1,116 @@
+//! Read only form of a Hnsw.
+//!
+//! [Hnsw::set_searching_mode] is only a flag, nothing prevents an insertion while other threads search.
+//! [Hnsw::into_searcher] consumes the structure and returns a [HnswSearcher] which has no insertion method,
+//! so the compiler prevents modifications after the freeze. The graph is copied in a [FlatHnsw],
+//! searches then take no lock and clone no Arc. Distances of links are not copied, a link takes the 4 bytes of a rank
+//! (see [crate::flathnsw] for the sharing of distances of reciprocal links when they are kept).
+//!
+//! Deleted points (see [crate::tombstone]) are not known by the searcher, a structure with deleted points
+//! should be rebuilt before the freeze.
+
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::flathnsw::FlatHnsw;
+use crate::hnsw::{Hnsw, Neighbour};
+
+/// a frozen Hnsw, see module doc
+pub struct HnswSearcher<T, D> {
+    pub(crate) flat: FlatHnsw<T>,
+    pub(crate) dist: D,
+    pub(crate) min_ef_factor: f32,
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// freezes the structure in a read only searcher. Vectors are copied, also those of a mmap.
+    pub fn into_searcher(self) -> HnswSearcher<T, D> {
+        if self.has_deleted() {
+            log::warn!(
+                "into_searcher : {} deleted points will be returned by searches",
+                self.get_nb_deleted()
+            );
+        }
+        let flat = FlatHnsw::from_hnsw(&self);
+        log::info!("into_searcher : {} points frozen", flat.get_nb_point());
+        HnswSearcher {
+            flat,
+            min_ef_factor: self.min_ef_factor,
+            dist: self.dist_f,
+        }
+    }
+} // end of impl Hnsw
+
+impl<T, D> HnswSearcher<T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    pub fn get_nb_point(&self) -> usize {
+        self.flat.get_nb_point()
+    }
+
+    pub fn get_distance(&self) -> &D {
+        &self.dist
+    }
+
+    /// the flat graph searched
+    pub fn get_flat(&self) -> &FlatHnsw<T> {
+        &self.flat
+    }
+
+    /// search of knbn neighbours, ef is raised as in [Hnsw::get_search_ef]
+    pub fn search(&self, data: &[T], knbn: usize, ef_arg: usize) -> Vec<Neighbour> {
+        let ef = ef_arg.max((knbn as f32 * self.min_ef_factor).ceil() as usize);
+        self.flat.search(data, knbn, ef, &self.dist)
+    }
+
+    /// searches in parallel, answers are in the order of datas
+    pub fn parallel_search(&self, datas: &[Vec<T>], knbn: usize, ef: usize) -> Vec<Vec<Neighbour>> {
+        datas
+            .par_iter()
+            .map(|data| self.search(data, knbn, ef))
+            .collect()
+    }
+} // end of impl HnswSearcher
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_into_searcher() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(933);
+        let data = synthetic::uniform(2000, 10, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 200, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let queries = synthetic::uniform(100, 10, &mut rng);
+        let expected = hnsw.parallel_search(&queries, 10, 64);
+        //
+        let searcher = hnsw.into_searcher();
+        assert_eq!(searcher.get_nb_point(), 2000);
+        let answers = searcher.parallel_search(&queries, 10, 64);
+        assert_eq!(answers.len(), 100);
+        // same graph and same greedy algorithm, the flat search keeps nearly all neighbours
+        let mut nb_same = 0;
+        for (answer, expected) in answers.iter().zip(&expected) {
+            assert_eq!(answer.len(), 10);
+            assert!(answer.windows(2).all(|w| w[0].distance <= w[1].distance));
+            nb_same += answer
+                .iter()
+                .filter(|n| expected.iter().any(|e| e.d_id == n.d_id))
+                .count();
+        }
+        assert!(nb_same >= 980, "nb_same {} / 1000", nb_same);
+    } // end of test_into_searcher
+} // end of mod tests
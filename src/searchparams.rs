@@ -0,0 +1,650 @@
+//! This module defines the structure SearchParams gathering optional constraints and options of a search request.
+//! It is passed to [Hnsw::search_with_params](crate::hnsw::Hnsw::search_with_params()).
+//!
+//! The constraints are checked inside the traversal of the graph, so that asking for k neighbours
+//! satisfying a constraint does not require post filtering of a larger request.
+
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use crate::filter::FilterT;
+use crate::hnsw::DataId;
+use crate::payload::{NB_ATTRIBUTES, Namespace, Payload, Tags, haversine_distance};
+use crate::topk::ResultBuffer;
+
+/// Options of a search request.
+///
+/// ```text
+///     let params = SearchParams::new().require_tags(0b101);
+///     let res = hnsw.search_with_params(&query, knbn, ef_search, &params);
+/// ```
+#[derive(Default)]
+pub struct SearchParams<'a> {
+    /// a user filter on DataId
+    filter: Option<&'a dyn FilterT>,
+    /// bits that must all be set in tags of a point
+    required_tags: Tags,
+    /// attribute rank, min, max (both included)
+    ranges: Vec<(usize, f64, f64)>,
+    /// center (latitude, longitude) and radius in meters
+    geo_radius: Option<((f64, f64), f64)>,
+    namespace: Option<Namespace>,
+    /// rescoring of candidates from (DataId, vector distance)
+    fusion: Option<&'a (dyn Fn(DataId, f32) -> f32 + Sync)>,
+    result_buffer: ResultBuffer,
+    /// layer where the greedy descent starts, the layer of the entry point if None
+    start_layer: Option<u8>,
+    /// insertion sequence number of the state searched, see [SearchParams::as_of]
+    as_of: Option<u64>,
+    /// candidates kept by the search are at least overfetch * knbn (no effect below 1.)
+    overfetch: f32,
+    /// the search ends when knbn points are within this distance
+    stop_distance: Option<f32>,
+    /// vectors penalizing candidates close to them
+    avoided: Option<Avoided<'a>>,
+    /// scores of candidates by an external service, see [SearchParams::rerank_async]
+    rerank: Option<&'a AsyncRerank>,
+    /// traversals of layer 0 added to the one from the entry point, see [SearchParams::restarts]
+    restarts: Option<usize>,
+    /// key of a point and max number of results by key, see [SearchParams::max_per_key]
+    max_per_key: Option<(&'a (dyn Fn(DataId) -> u64 + Sync), usize)>,
+}
+
+/// future of the scores of a [SearchParams::rerank_async] callback. It owns what it needs (ids, a client...).
+pub type RerankFuture = Pin<Box<dyn Future<Output = anyhow::Result<Vec<f32>>> + Send>>;
+
+/// callback of [SearchParams::rerank_async], from ids of candidates to their scores (lower is better)
+pub type AsyncRerank = dyn Fn(Vec<DataId>) -> RerankFuture + Sync;
+
+/// vectors of [SearchParams::avoid] and the penalty. SearchParams is not generic, each vector is seen as Any
+/// and the structure gets it back by a downcast to its type of data.
+#[derive(Clone)]
+struct Avoided<'a> {
+    vectors: Vec<&'a (dyn Any + Send + Sync)>,
+    penalty: f32,
+}
+
+impl<'a> SearchParams<'a> {
+    /// parameters with no constraint, equivalent to a plain search
+    pub fn new() -> Self {
+        SearchParams::default()
+    }
+
+    /// adds a user filter on DataId. See [FilterT]
+    pub fn filter(mut self, filter: &'a dyn FilterT) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// only points having **all** tags of mask set are returned.
+    /// The tags are checked inline during traversal, it avoids the closure dispatch of a filter for the
+    /// common case of filtering by category.
+    pub fn require_tags(mut self, mask: Tags) -> Self {
+        self.required_tags |= mask;
+        self
+    }
+
+    /// only points with attribute of rank attr in \[min, max\] are returned.  
+    /// Can be called for each attribute, a point must satisfy all ranges.
+    /// This avoids asking for an inflated number of neighbours and post filtering for time windowed searches.
+    pub fn range_filter(mut self, attr: usize, min: f64, max: f64) -> Self {
+        assert!(
+            attr < NB_ATTRIBUTES,
+            "attribute rank must be < {}",
+            NB_ATTRIBUTES
+        );
+        self.ranges.push((attr, min, max));
+        self
+    }
+
+    /// only points with a position within meters of center (latitude, longitude in degrees) are returned.  
+    /// Distances are haversine distances, points with no position are rejected.
+    pub fn within_geo_radius(mut self, center: (f64, f64), meters: f64) -> Self {
+        self.geo_radius = Some((center, meters));
+        self
+    }
+
+    /// only points of namespace are returned. See [Hnsw::search_ns](crate::hnsw::Hnsw::search_ns())
+    pub fn in_namespace(mut self, namespace: Namespace) -> Self {
+        self.namespace = Some(namespace);
+        self
+    }
+
+    /// rescores the ef candidates found by the search with fusion(id, vector_distance) before truncation to knbn.  
+    /// The returned neighbours are sorted by increasing fused score which replaces the distance,
+    /// so fusion must return lower values for better matches.  
+    /// It makes possible to blend an external keyword (BM25...) score in one pass,
+    /// ef_search should then be chosen larger than knbn.
+    pub fn fuse_scores(mut self, fusion: &'a (dyn Fn(DataId, f32) -> f32 + Sync)) -> Self {
+        self.fusion = Some(fusion);
+        self
+    }
+
+    /// chooses the structure holding the ef best points during the search. See [crate::topk].  
+    /// The default (ResultBuffer::Auto) is a sorted buffer, a heap is only used for very large ef.
+    pub fn result_buffer(mut self, buffer: ResultBuffer) -> Self {
+        self.result_buffer = buffer;
+        self
+    }
+
+    /// starts the greedy descent of upper layers at layer (or at the layer of the entry point if it is lower).  
+    /// start_layer(0) skips the descent and searches layer 0 from the entry point, which can be enough for
+    /// very small indexes. Without this option the descent starts at the layer of the entry point.
+    pub fn start_layer(mut self, layer: u8) -> Self {
+        self.start_layer = Some(layer);
+        self
+    }
+
+    /// searches the structure as it was when [Hnsw::get_insertion_seq](crate::hnsw::Hnsw::get_insertion_seq())
+    /// was seq: only points inserted before and not deleted at seq are returned. Made for reproducible evaluations
+    /// against a past state of an index updated continuously. See [crate::sequence] for what it can miss.
+    pub fn as_of(mut self, seq: u64) -> Self {
+        self.as_of = Some(seq);
+        self
+    }
+
+    /// the search keeps at least factor * knbn candidates (ef_search is raised to it if lower) and only truncates
+    /// to knbn after checking constraints, removing deleted points and rescoring (see [SearchParams::fuse_scores]).
+    /// It replaces asking for factor * knbn neighbours and trimming the result.  
+    /// Constraints (filter, tags, ranges...) are checked during the traversal: candidates all satisfy them and
+    /// rejected points do not use the factor. With a very selective constraint the factor only widens
+    /// the exploration, it is useful mainly with a fusion function or a rerank outside of the search.
+    pub fn overfetch(mut self, factor: f32) -> Self {
+        assert!(factor >= 1., "overfetch factor must be >= 1");
+        self.overfetch = factor;
+        self
+    }
+
+    /// ends the search as soon as knbn points (satisfying constraints) within distance of the query are found.  
+    /// The result is then knbn points within distance, not necessarily the nearest ones. Made for near duplicate
+    /// detection where only the existence of a close enough match matters: with knbn = 1 a search for an
+    /// indexed vector usually stops after a few distance computations. If less than knbn points are within distance,
+    /// the search is a plain one.
+    pub fn stop_at_distance(mut self, distance: f32) -> Self {
+        self.stop_distance = Some(distance);
+        self
+    }
+
+    /// penalizes candidates close to vectors (negative examples of a recommendation): the candidates kept by the search
+    /// are ranked by `distance - penalty * distance to the nearest of vectors` (distances given by the distance
+    /// of the structure) which replaces the distance in returned neighbours, and is the distance passed
+    /// to [SearchParams::fuse_scores].  
+    /// Only the ef candidates are rescored, a larger ef or [SearchParams::overfetch] gives room to the penalty.
+    /// Vectors must be of the type of the structure data (the search panics otherwise).
+    pub fn avoid<T: Send + Sync + 'static>(mut self, vectors: &'a [Vec<T>], penalty: f32) -> Self {
+        self.avoided = (!vectors.is_empty()).then(|| Avoided {
+            vectors: vectors
+                .iter()
+                .map(|v| v as &(dyn Any + Send + Sync))
+                .collect(),
+            penalty,
+        });
+        self
+    }
+
+    /// candidates of a search by [Hnsw::search_async](crate::hnsw::Hnsw::search_async()) are rescored by rerank:
+    /// it gets the ids of the ef candidates (in order of distance, after constraints, penalties and fusion) and
+    /// returns a future of their scores, in the same order. The knbn candidates with the lowest scores are returned,
+    /// scores replacing distances. Made for a cross-encoder service rescoring an over-fetched set in one call.  
+    /// Synchronous searches ignore it.
+    pub fn rerank_async(mut self, rerank: &'a AsyncRerank) -> Self {
+        self.rerank = Some(rerank);
+        self
+    }
+
+    /// runs nb more traversals of layer 0, starting from points spread over the upper layers (each one descends
+    /// greedily as the main one does), and merges their results. This raises recall on hard queries (clustered data,
+    /// a query between clusters) at the cost of latency, without rebuilding the index with a larger max_nb_connection.  
+    /// Sampled points are at regular ranks of the highest layer with more than nb points, so a search is reproducible.
+    /// For a flat graph (see [crate::graphkind]) nb replaces [crate::graphkind::FLAT_RESTARTS].
+    pub fn restarts(mut self, nb: usize) -> Self {
+        self.restarts = Some(nb);
+        self
+    }
+
+    /// returns at most n results with the same key_of(id) (for example at most 2 chunks of a document).
+    /// Results are the nearest ones (or the best ones by [SearchParams::fuse_scores]) once the extra results of
+    /// each key are dropped. If the ef candidates do not hold knbn results with this limit the search is done again
+    /// with a doubled ef, until they do or the whole layer is explored, so that knbn results are returned when
+    /// there are enough keys.
+    pub fn max_per_key(mut self, key_of: &'a (dyn Fn(DataId) -> u64 + Sync), n: usize) -> Self {
+        assert!(n >= 1, "max_per_key needs at least 1 result by key");
+        self.max_per_key = Some((key_of, n));
+        self
+    }
+
+    /// returns the mask of required tags
+    pub fn get_required_tags(&self) -> Tags {
+        self.required_tags
+    }
+
+    pub(crate) fn get_result_buffer(&self) -> ResultBuffer {
+        self.result_buffer
+    }
+
+    /// number of candidates asked by [SearchParams::overfetch] for knbn neighbours, knbn if not set
+    pub fn get_nb_candidates(&self, knbn: usize) -> usize {
+        (knbn as f32 * self.overfetch.max(1.)).ceil() as usize
+    }
+
+    pub(crate) fn get_stop_distance(&self) -> Option<f32> {
+        self.stop_distance
+    }
+
+    pub(crate) fn get_start_layer(&self) -> Option<u8> {
+        self.start_layer
+    }
+
+    pub(crate) fn get_as_of(&self) -> Option<u64> {
+        self.as_of
+    }
+
+    pub(crate) fn get_restarts(&self) -> Option<usize> {
+        self.restarts
+    }
+
+    pub(crate) fn get_rerank(&self) -> Option<&'a AsyncRerank> {
+        self.rerank
+    }
+
+    pub(crate) fn get_fusion(&self) -> Option<&'a (dyn Fn(DataId, f32) -> f32 + Sync)> {
+        self.fusion
+    }
+
+    /// vectors and penalty of [SearchParams::avoid], T being the type of data of the structure searched
+    pub(crate) fn get_avoided<T: 'static>(&self) -> Option<(Vec<&'a Vec<T>>, f32)> {
+        self.avoided.as_ref().map(|avoided| {
+            let vectors = avoided
+                .vectors
+                .iter()
+                .map(|v| {
+                    v.downcast_ref::<Vec<T>>()
+                        .expect("SearchParams::avoid, vectors are not of the type of data")
+                })
+                .collect();
+            (vectors, avoided.penalty)
+        })
+    }
+
+    /// number of ids (in order) kept by [SearchParams::max_per_key], usize::MAX without limit
+    pub(crate) fn count_kept_per_key(&self, ids: impl Iterator<Item = DataId>) -> usize {
+        let Some((key_of, n)) = self.max_per_key else {
+            return usize::MAX;
+        };
+        let mut counts = HashMap::<u64, usize>::new();
+        ids.filter(|id| {
+            let count = counts.entry(key_of(*id)).or_insert(0);
+            *count += 1;
+            *count <= n
+        })
+        .count()
+    }
+
+    /// drops the items beyond the limit of [SearchParams::max_per_key], items being in order of preference
+    pub(crate) fn keep_per_key<I>(&self, items: &mut Vec<I>, id_of: impl Fn(&I) -> DataId) {
+        let Some((key_of, n)) = self.max_per_key else {
+            return;
+        };
+        let mut counts = HashMap::<u64, usize>::new();
+        items.retain(|item| {
+            let count = counts.entry(key_of(id_of(item))).or_insert(0);
+            *count += 1;
+            *count <= n
+        });
+    }
+
+    /// returns true if some constraint restricts points returned
+    pub(crate) fn is_constrained(&self) -> bool {
+        self.filter.is_some()
+            || self.required_tags != 0
+            || !self.ranges.is_empty()
+            || self.geo_radius.is_some()
+            || self.namespace.is_some()
+    }
+
+    /// returns true if results can differ from the nearest neighbours: constraints, fusion of scores, penalties,
+    /// limit by key, stop distance or search as of a sequence number
+    pub(crate) fn changes_results(&self) -> bool {
+        self.is_constrained()
+            || self.fusion.is_some()
+            || self.avoided.is_some()
+            || self.max_per_key.is_some()
+            || self.stop_distance.is_some()
+            || self.as_of.is_some()
+    }
+
+    /// returns true if point satisfies all constraints
+    #[inline]
+    pub(crate) fn accept(&self, origin_id: DataId, payload: &Payload) -> bool {
+        if payload.get_tags() & self.required_tags != self.required_tags {
+            return false;
+        }
+        if self
+            .namespace
+            .is_some_and(|ns| ns != payload.get_namespace())
+        {
+            return false;
+        }
+        // NaN (attribute not set) fails comparisons
+        let in_ranges = self.ranges.iter().all(|&(attr, min, max)| {
+            let v = payload.get_attribute(attr);
+            v >= min && v <= max
+        });
+        if !in_ranges {
+            return false;
+        }
+        if let Some((center, meters)) = self.geo_radius {
+            match payload.get_geo() {
+                Some(pos) if haversine_distance(center, pos) <= meters => {}
+                _ => return false,
+            }
+        }
+        match self.filter {
+            Some(filter) => filter.hnsw_filter(&origin_id),
+            None => true,
+        }
+    }
+} // end of impl SearchParams
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::Hnsw;
+    use crate::payload::Payload;
+    use anndists::dist::DistL2;
+    use anndists::dist::distances::Distance;
+    use rand::SeedableRng;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_require_tags() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        //
+        let nb_elem = 2000;
+        let dim = 10;
+        let mut rng = rand::rng();
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        // point i gets tag bit i % 4, and bit 8 if i is a multiple of 3
+        let tags_of = |i: usize| -> Tags {
+            let mut t = 1u16 << (i % 4);
+            if i.is_multiple_of(3) {
+                t |= 1 << 8;
+            }
+            t
+        };
+        let with_tags: Vec<(&[f32], usize, Tags)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.as_slice(), i, tags_of(i)))
+            .collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        hnsw.parallel_insert_with_tags(&with_tags);
+        //
+        let mask: Tags = (1 << 2) | (1 << 8);
+        let params = SearchParams::new().require_tags(mask);
+        let query = synthetic::uniform(1, dim, &mut rng).remove(0);
+        let res = hnsw.search_with_params(&query, 10, 64, &params);
+        assert_eq!(res.len(), 10);
+        for n in &res {
+            assert_eq!(tags_of(n.d_id) & mask, mask);
+        }
+        // combined with a filter on ids
+        let filter = |id: &usize| *id < 1000;
+        let params = SearchParams::new().require_tags(mask).filter(&filter);
+        let res = hnsw.search_with_params(&query, 10, 64, &params);
+        assert!(!res.is_empty());
+        assert!(
+            res.iter()
+                .all(|n| n.d_id < 1000 && tags_of(n.d_id) & mask == mask)
+        );
+    } // end of test_require_tags
+
+    #[test]
+    fn test_range_filter() {
+        let _ = env_logger::builder().is_test(true).try_init();
+        //
+        let nb_elem = 2000;
+        let dim = 10;
+        let mut rng = rand::rng();
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        // attribute 0 is a timestamp, attribute 1 is set only for even points
+        let payload_of = |i: usize| -> Payload {
+            let p = Payload::new().with_attribute(0, i as f64);
+            if i.is_multiple_of(2) {
+                p.with_attribute(1, 1.)
+            } else {
+                p
+            }
+        };
+        let with_payload: Vec<(&[f32], usize, Payload)> = data
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (v.as_slice(), i, payload_of(i)))
+            .collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        hnsw.parallel_insert_with_payload(&with_payload);
+        //
+        let query = synthetic::uniform(1, dim, &mut rng).remove(0);
+        let params = SearchParams::new()
+            .range_filter(0, 500., 999.)
+            .range_filter(1, 0., 2.);
+        let res = hnsw.search_with_params(&query, 10, 64, &params);
+        assert_eq!(res.len(), 10);
+        assert!(
+            res.iter()
+                .all(|n| (500..1000).contains(&n.d_id) && n.d_id.is_multiple_of(2))
+        );
+    } // end of test_range_filter
+
+    #[test]
+    fn test_fuse_scores() {
+        let nb_elem = 1000;
+        let dim = 10;
+        let mut rng = rand::rng();
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..nb_elem).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        // a keyword score favouring ids multiple of 7
+        let fusion = |id: DataId, dist: f32| -> f32 {
+            if id.is_multiple_of(7) {
+                dist - 100.
+            } else {
+                dist
+            }
+        };
+        let params = SearchParams::new().fuse_scores(&fusion);
+        let query = synthetic::uniform(1, dim, &mut rng).remove(0);
+        let res = hnsw.search_with_params(&query, 5, 100, &params);
+        assert_eq!(res.len(), 5);
+        assert!(res.iter().all(|n| n.d_id.is_multiple_of(7)));
+        assert!(res.windows(2).all(|w| w[0].distance <= w[1].distance));
+    } // end of test_fuse_scores
+
+    #[test]
+    fn test_overfetch() {
+        let nb_elem = 1000;
+        let dim = 10;
+        let mut rng = rand::rng();
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..nb_elem).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let fusion = |id: DataId, dist: f32| -> f32 {
+            if id.is_multiple_of(7) {
+                dist - 100.
+            } else {
+                dist
+            }
+        };
+        let query = synthetic::uniform(1, dim, &mut rng).remove(0);
+        // 5 candidates are not enough to get 5 multiples of 7, 100 are
+        let params = SearchParams::new().fuse_scores(&fusion);
+        assert_eq!(params.get_nb_candidates(5), 5);
+        let res = hnsw.search_with_params(&query, 5, 5, &params);
+        assert!(!res.iter().all(|n| n.d_id.is_multiple_of(7)));
+        let params = params.overfetch(20.);
+        assert_eq!(params.get_nb_candidates(5), 100);
+        let res = hnsw.search_with_params(&query, 5, 5, &params);
+        assert_eq!(res.len(), 5);
+        assert!(res.iter().all(|n| n.d_id.is_multiple_of(7)));
+        // as asking for 100 neighbours and trimming
+        let wide =
+            hnsw.search_with_params(&query, 5, 100, &SearchParams::new().fuse_scores(&fusion));
+        assert!(res.iter().zip(&wide).all(|(a, b)| a.d_id == b.d_id));
+    } // end of test_overfetch
+
+    #[test]
+    fn test_start_layer() {
+        let nb_elem = 500;
+        let dim = 10;
+        let mut rng = rand::rng();
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..nb_elem).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        assert!(hnsw.get_max_level_observed() > 0);
+        //
+        let full = SearchParams::new().start_layer(15);
+        let no_descent = SearchParams::new().start_layer(0);
+        let mut nb_found = 0;
+        for (i, v) in data.iter().enumerate().step_by(5) {
+            let res = hnsw.search(v, 10, 64);
+            let res_full = hnsw.search_with_params(v, 10, 64, &full);
+            // starting at the top layer (or above) is the default descent
+            assert!(
+                res.iter()
+                    .zip(&res_full)
+                    .all(|(a, b)| a.distance == b.distance)
+            );
+            let res_flat = hnsw.search_with_params(v, 10, 64, &no_descent);
+            assert_eq!(res_flat.len(), 10);
+            if res_flat[0].d_id == i {
+                nb_found += 1;
+            }
+        }
+        assert!(nb_found >= 95, "nb_found {} / 100", nb_found);
+    } // end of test_start_layer
+
+    // L2 counting its evaluations
+    #[derive(Default)]
+    struct CountingL2(AtomicUsize);
+
+    impl Distance<f32> for CountingL2 {
+        fn eval(&self, va: &[f32], vb: &[f32]) -> f32 {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            DistL2 {}.eval(va, vb)
+        }
+    }
+
+    #[test]
+    fn test_stop_at_distance() {
+        let nb_elem = 2000;
+        let dim = 10;
+        let mut rng = rand::rngs::StdRng::seed_from_u64(962);
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..nb_elem).collect();
+        let hnsw = Hnsw::<f32, CountingL2>::new(16, nb_elem, 16, 200, CountingL2::default());
+        hnsw.parallel_insert(&data_with_id);
+        let nb_eval = || hnsw.get_distance().0.swap(0, Ordering::Relaxed);
+        // indexed vectors are found with much fewer distances
+        let exact = SearchParams::new().stop_at_distance(1.0e-6);
+        let (mut nb_plain, mut nb_stop) = (0, 0);
+        for i in (0..nb_elem).step_by(20) {
+            nb_eval();
+            let plain = hnsw.search_with_params(&data[i], 1, 200, &SearchParams::new());
+            nb_plain += nb_eval();
+            let res = hnsw.search_with_params(&data[i], 1, 200, &exact);
+            nb_stop += nb_eval();
+            if plain[0].d_id == i {
+                assert_eq!(res[0].d_id, i);
+            }
+            assert!(res[0].distance <= plain[0].distance);
+        }
+        log::info!("nb distances plain {} stop {}", nb_plain, nb_stop);
+        assert!(
+            4 * nb_stop < nb_plain,
+            "plain {} stop {}",
+            nb_plain,
+            nb_stop
+        );
+        // knbn points within the distance
+        let query = synthetic::uniform(1, dim, &mut rng).remove(0);
+        let plain = hnsw.search_with_params(&query, 5, 200, &SearchParams::new());
+        let d = 2. * plain[4].distance;
+        let res = hnsw.search_with_params(&query, 5, 200, &SearchParams::new().stop_at_distance(d));
+        assert_eq!(res.len(), 5);
+        assert!(res.iter().all(|n| n.distance <= d));
+        // a plain search if no point is close enough
+        let res = hnsw.search_with_params(&query, 5, 200, &exact);
+        assert!(res.iter().zip(&plain).all(|(a, b)| a.d_id == b.d_id));
+    } // end of test_stop_at_distance
+
+    #[test]
+    fn test_avoid() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(970);
+        let (nb_elem, dim) = (2000, 8);
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        hnsw.parallel_insert(&data_with_id);
+        let query = synthetic::uniform(1, dim, &mut rng).remove(0);
+        let plain = hnsw.search_with_params(&query, 10, 64, &SearchParams::new());
+        // no penalty, no change
+        let avoided = vec![data[plain[0].d_id].clone()];
+        let params = SearchParams::new().avoid(&avoided, 0.);
+        let res = hnsw.search_with_params(&query, 10, 64, &params);
+        assert!(res.iter().zip(&plain).all(|(a, b)| a.d_id == b.d_id));
+        // the avoided point gets no bonus, the others get away from it
+        let params = SearchParams::new().avoid(&avoided, 1.).overfetch(5.);
+        let res = hnsw.search_with_params(&query, 10, 64, &params);
+        assert_eq!(res.len(), 10);
+        assert_ne!(res[0].d_id, plain[0].d_id);
+        for n in &res {
+            let expected =
+                DistL2 {}.eval(&query, &data[n.d_id]) - DistL2 {}.eval(&data[n.d_id], &avoided[0]);
+            assert!((n.distance - expected).abs() < 1.0e-5);
+        }
+        assert!(res.windows(2).all(|w| w[0].distance <= w[1].distance));
+    } // end of test_avoid
+
+    #[test]
+    fn test_max_per_key() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(990);
+        let (nb_elem, dim) = (2000, 8);
+        let data = synthetic::uniform(nb_elem, dim, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        hnsw.parallel_insert(&data_with_id);
+        // 10 chunks by document, chunks of a document are near each other
+        let document_of = |id: DataId| (id / 10) as u64;
+        let query = data[55].clone();
+        let chunks: Vec<Vec<f32>> = (0..nb_elem)
+            .map(|i| data[(i / 10) * 10 + 5].clone())
+            .collect();
+        let hnsw_docs = Hnsw::<f32, DistL2>::new(16, nb_elem, 16, 200, DistL2 {});
+        let docs_with_id: Vec<(&Vec<f32>, usize)> = chunks.iter().zip(0..).collect();
+        hnsw_docs.parallel_insert(&docs_with_id);
+        for (index, ef) in [(&hnsw, 64), (&hnsw, 10), (&hnsw_docs, 10)] {
+            let params = SearchParams::new().max_per_key(&document_of, 2);
+            let res = index.search_with_params(&query, 10, ef, &params);
+            assert_eq!(res.len(), 10);
+            let mut counts = HashMap::<u64, usize>::new();
+            for n in &res {
+                *counts.entry(document_of(n.d_id)).or_insert(0) += 1;
+            }
+            assert!(counts.values().all(|c| *c <= 2));
+            assert!(res.windows(2).all(|w| w[0].distance <= w[1].distance));
+        }
+        // documents made of identical chunks: 2 chunks of the 5 nearest documents
+        let params = SearchParams::new().max_per_key(&document_of, 2);
+        let res = hnsw_docs.search_with_params(&query, 10, 10, &params);
+        assert_eq!(res[0].distance, 0.);
+        assert_eq!(res[1].distance, 0.);
+        assert!(res[..2].iter().all(|n| document_of(n.d_id) == 5));
+    } // end of test_max_per_key
+} // end of mod tests
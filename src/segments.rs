@@ -0,0 +1,204 @@
+//! Search in a collection split in several Hnsw (segments) with pruning of the segments too far from the query.
+//!
+//! A collection split by time or by shard is searched by asking knbn neighbours to each segment and merging the
+//! lists, a cost growing with the number of segments. A [SegmentRouter] keeps the centroid of each segment and
+//! its radius (largest distance of a point to the centroid). With a metric distance (L2...) a point of a segment is
+//! at least at `d(query, centroid) - radius` of the query, so segments are searched by increasing bound and the
+//! search stops at the first segment whose bound is above the distance of the current knbn-th neighbour.
+//!
+//! The radius is set by the farthest point of a segment, a few outliers make it large and the pruning weak.
+//! [SegmentRouter::set_radius_factor] scales the radius down (factor in \[0, 1\]): more segments are skipped,
+//! results become approximate (a neighbour among the outliers of a skipped segment can be missed).
+//! DataIds must be unique across segments, the router does not translate them. Equal distances are ordered by the
+//! tie break policy of the segments (see [crate::tiebreak]), which must be the same.
+
+use serde::{Deserialize, Serialize};
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Neighbour};
+use crate::tiebreak::cmp_with_keys;
+
+/// centroid and radius of a segment, see module doc
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SegmentSummary {
+    pub centroid: Vec<f32>,
+    /// largest distance from the centroid to a point of the segment
+    pub radius: f32,
+}
+
+impl SegmentSummary {
+    /// summary of the points (not deleted) of hnsw, None if it has none
+    pub fn of<D>(hnsw: &Hnsw<f32, D>) -> Option<Self>
+    where
+        D: Distance<f32> + Send + Sync,
+    {
+        let mut centroid = vec![0f64; hnsw.get_point_indexation().get_data_dimension()];
+        let mut nb_point = 0;
+        for point in hnsw.get_point_indexation() {
+            if hnsw.is_deleted(&point) {
+                continue;
+            }
+            for (c, x) in centroid.iter_mut().zip(point.get_v()) {
+                *c += *x as f64;
+            }
+            nb_point += 1;
+        }
+        if nb_point == 0 {
+            return None;
+        }
+        let centroid: Vec<f32> = centroid
+            .into_iter()
+            .map(|c| (c / nb_point as f64) as f32)
+            .collect();
+        let radius = hnsw
+            .get_point_indexation()
+            .into_iter()
+            .filter(|point| !hnsw.is_deleted(point))
+            .map(|point| hnsw.get_distance().eval(&centroid, point.get_v()))
+            .fold(0., f32::max);
+        Some(SegmentSummary { centroid, radius })
+    }
+} // end of impl SegmentSummary
+
+/// segments searched and skipped by a [SegmentRouter::search]
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct RoutingStats {
+    pub nb_searched: usize,
+    pub nb_pruned: usize,
+}
+
+/// Segments with their summary, see module doc.
+pub struct SegmentRouter<'a, 'b, D: Distance<f32> + Send + Sync> {
+    segments: Vec<(&'a Hnsw<'b, f32, D>, Option<SegmentSummary>)>,
+    radius_factor: f32,
+}
+
+impl<'a, 'b, D: Distance<f32> + Send + Sync> SegmentRouter<'a, 'b, D> {
+    /// computes the summary of each segment. Segments must use the same distance.
+    pub fn new(segments: &[&'a Hnsw<'b, f32, D>]) -> Self {
+        SegmentRouter {
+            segments: segments
+                .iter()
+                .map(|hnsw| (*hnsw, SegmentSummary::of(hnsw)))
+                .collect(),
+            radius_factor: 1.,
+        }
+    }
+
+    /// fraction of the radius used in bounds, 1 (the default) prunes only segments that cannot hold a neighbour
+    pub fn set_radius_factor(&mut self, factor: f32) {
+        assert!(
+            (0. ..=1.).contains(&factor),
+            "radius factor must be in [0, 1]"
+        );
+        self.radius_factor = factor;
+    }
+
+    /// computes again the summary of segment rank, to call after insertions or deletions in it
+    pub fn update(&mut self, rank: usize) {
+        self.segments[rank].1 = SegmentSummary::of(self.segments[rank].0);
+    }
+
+    pub fn get_summary(&self, rank: usize) -> Option<&SegmentSummary> {
+        self.segments[rank].1.as_ref()
+    }
+
+    /// searches knbn neighbours of query in segments that can hold one, each with ef_s, and merges them
+    pub fn search(
+        &self,
+        query: &[f32],
+        knbn: usize,
+        ef_s: usize,
+    ) -> (Vec<Neighbour>, RoutingStats) {
+        // lower bound of the distance to the points of each segment not empty
+        let mut bounds: Vec<(f32, &Hnsw<f32, D>)> = self
+            .segments
+            .iter()
+            .filter_map(|(hnsw, summary)| {
+                let summary = summary.as_ref()?;
+                let to_centroid = hnsw.get_distance().eval(query, &summary.centroid);
+                Some((to_centroid - self.radius_factor * summary.radius, *hnsw))
+            })
+            .collect();
+        bounds.sort_by(|a, b| a.0.total_cmp(&b.0));
+        let mut stats = RoutingStats {
+            nb_pruned: self.segments.len() - bounds.len(),
+            ..Default::default()
+        };
+        // equal distances are ordered by the tie break policy of segments
+        let mut neighbours: Vec<(Neighbour, f64)> = Vec::new();
+        for (rank, (bound, hnsw)) in bounds.iter().enumerate() {
+            if neighbours.len() >= knbn && *bound > neighbours[knbn - 1].0.distance {
+                stats.nb_pruned += bounds.len() - rank;
+                break;
+            }
+            let found = hnsw.search(query, knbn, ef_s);
+            let keys = hnsw.tie_keys(&found);
+            neighbours.extend(found.into_iter().zip(keys));
+            neighbours.sort_unstable_by(cmp_with_keys);
+            neighbours.truncate(knbn);
+            stats.nb_searched += 1;
+        }
+        (neighbours.into_iter().map(|(n, _)| n).collect(), stats)
+    } // end of search
+} // end of impl SegmentRouter
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_segment_router() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(995);
+        // one cluster by segment
+        let (data, labels) = synthetic::gaussian_mixture(4000, 8, 8, 0.01, &mut rng);
+        let segments: Vec<Hnsw<f32, DistL2>> = (0..8)
+            .map(|s| {
+                // a serial build with seeded levels, self queries below are exact
+                let mut hnsw = Hnsw::<f32, DistL2>::new(16, 500, 16, 100, DistL2 {});
+                hnsw.set_level_seed(995 + s as u64);
+                for (v, i) in data.iter().zip(0..).filter(|(_, i)| labels[*i] == s) {
+                    hnsw.insert((v, i));
+                }
+                hnsw
+            })
+            .collect();
+        let segment_refs: Vec<&Hnsw<f32, DistL2>> = segments.iter().collect();
+        let mut router = SegmentRouter::new(&segment_refs);
+        let summary = router.get_summary(0).unwrap();
+        assert_eq!(summary.centroid.len(), 8);
+        assert!(summary.radius > 0.);
+        // a query in a cluster needs few segments, results are the ones of all segments
+        let mut nb_searched = 0;
+        for i in (0..4000).step_by(200) {
+            let query = &data[i];
+            let (res, stats) = router.search(query, 10, 64);
+            assert_eq!(stats.nb_searched + stats.nb_pruned, 8);
+            nb_searched += stats.nb_searched;
+            let mut all: Vec<Neighbour> = segments
+                .iter()
+                .flat_map(|hnsw| hnsw.search(query, 10, 64))
+                .collect();
+            all.sort_unstable_by(|a, b| a.cmp_by_distance(b));
+            assert!(res.iter().zip(&all).all(|(a, b)| a.d_id == b.d_id));
+            assert_eq!(res[0].d_id, i);
+        }
+        log::info!("segments searched by query {}", nb_searched as f32 / 20.);
+        assert!(nb_searched < 20 * 8);
+        // a smaller radius prunes more
+        router.set_radius_factor(0.5);
+        let (res, stats) = router.search(&data[0], 10, 64);
+        assert_eq!(res[0].d_id, 0);
+        assert!(stats.nb_searched <= 8);
+        // an empty segment is never searched
+        let empty = Hnsw::<f32, DistL2>::new(16, 10, 16, 100, DistL2 {});
+        let router = SegmentRouter::new(&[&segments[0], &empty]);
+        let (_, stats) = router.search(&data[0], 10, 64);
+        assert_eq!(stats.nb_pruned, 1);
+    } // end of test_segment_router
+} // end of mod tests
@@ -0,0 +1,246 @@
+//! Insertion sequence numbers of points.
+//!
+//! Each point gets at insertion a sequence number, the number of points inserted before it, so a client
+//! syncing a copy downstream can ask with [Hnsw::points_since] for the points added since its last pull
+//! and keep [Hnsw::get_insertion_seq] as the next starting point. With parallel insertions numbers follow
+//! the creation of points, not the order of the data slice.
+//!
+//! Numbers are kept by dumps (file_dump) and by [Hnsw::deep_clone]. Structures made from other structures
+//! (extract, block dumps, FlatHnsw, rebuild, dumps of older versions) number their points again, layer by layer.
+//! A reload of a subset of a dump numbers points in their dumped order without gaps.
+//!
+//! An insertion in the slot of a deleted point (see [crate::tombstone]) leaves the number of the deleted point stale:
+//! points_since skips it.
+//!
+//! A search with [SearchParams::as_of](crate::searchparams::SearchParams::as_of()) sees the structure as it was at a
+//! sequence number: points numbered below it and not deleted at it (a deletion keeps the sequence number at which it
+//! was done). It filters the traversal of the current graph, so a point whose slot was taken by a later insertion,
+//! or a deleted point unlinked by a repair, cannot be returned. Points of a deleted namespace are not returned.
+
+use anyhow::anyhow;
+use hashbrown::HashMap;
+use std::collections::HashSet;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, Point, PointId, PointIndexation};
+
+// data id of a stale sequence number in dumps
+const STALE: u64 = u64::MAX;
+
+/// state of a structure searched as of a sequence number
+pub(crate) struct AsOf {
+    seq: u64,
+    /// points numbered seq or more
+    newer: HashSet<PointId>,
+    /// number of points by layer, points of higher rank were created after
+    nb_by_layer: Vec<usize>,
+    /// free slots, a point in one of them was created after
+    free: HashSet<PointId>,
+}
+
+impl<T: Clone + Send + Sync> PointIndexation<'_, T> {
+    /// numbers points layer by layer, as the iterations
+    pub(crate) fn reset_insertion_order(&self) {
+        let order: Vec<PointId> = self.iter_ids().map(|(p_id, _)| p_id).collect();
+        *self.insertion_order.write() = order;
+    }
+
+    /// data ids as u64 by sequence number, in native endianness as the rest of the dump
+    pub(crate) fn insertion_order_to_bytes(&self) -> Vec<u8> {
+        let layers = self.points_by_layer.read();
+        let order = self.insertion_order.read();
+        let mut bytes = Vec::with_capacity(8 * order.len());
+        for p_id in order.iter() {
+            let d_id = match p_id.1 {
+                rank if rank < 0 => STALE,
+                rank => layers[p_id.0 as usize][rank as usize].get_origin_id() as u64,
+            };
+            bytes.extend_from_slice(&d_id.to_ne_bytes());
+        }
+        bytes
+    }
+
+    /// orders loaded points as data ids of bytes. Data ids not loaded are skipped,
+    /// loaded points not in bytes come last in layer order.
+    pub(crate) fn set_insertion_order_from_bytes(&self, bytes: &[u8]) -> anyhow::Result<()> {
+        if !bytes.len().is_multiple_of(8) {
+            return Err(anyhow!(
+                "insertion order block of dump has bad length {}",
+                bytes.len()
+            ));
+        }
+        let mut loaded: HashMap<DataId, PointId> =
+            self.iter_ids().map(|(p_id, d_id)| (d_id, p_id)).collect();
+        let mut order: Vec<PointId> = bytes
+            .chunks_exact(8)
+            .filter_map(|b| match u64::from_ne_bytes(b.try_into().unwrap()) {
+                STALE => Some(PointId(0, -1)),
+                d_id => loaded.remove(&(d_id as DataId)),
+            })
+            .collect();
+        if !loaded.is_empty() {
+            let mut missing: Vec<PointId> = loaded.into_values().collect();
+            missing.sort_unstable();
+            order.extend(missing);
+        }
+        *self.insertion_order.write() = order;
+        Ok(())
+    }
+} // end of impl PointIndexation
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// returns the sequence number of the next insertion, the number of points numbered
+    pub fn get_insertion_seq(&self) -> u64 {
+        self.layer_indexed_points.insertion_order.read().len() as u64
+    }
+
+    /// returns (sequence number, data id) of points numbered seq or more, by sequence number.
+    /// Deleted points are skipped.
+    pub fn points_since(&self, seq: u64) -> Vec<(u64, DataId)> {
+        let indexation = &self.layer_indexed_points;
+        // locks in the order of insertions
+        let layers = indexation.points_by_layer.read();
+        let order = indexation.insertion_order.read();
+        let start = (seq as usize).min(order.len());
+        order[start..]
+            .iter()
+            .zip(seq..)
+            .filter(|(p_id, _)| p_id.1 >= 0)
+            .map(|(p_id, s)| (s, &layers[p_id.0 as usize][p_id.1 as usize]))
+            .filter(|(_, p)| !self.is_deleted(p))
+            .map(|(s, p)| (s, p.get_origin_id()))
+            .collect()
+    }
+
+    // state of the structure for a search as of seq
+    pub(crate) fn as_of(&self, seq: u64) -> AsOf {
+        let indexation = &self.layer_indexed_points;
+        // locks in the order of insertions
+        let layers = indexation.points_by_layer.read();
+        let order = indexation.insertion_order.read();
+        let start = (seq as usize).min(order.len());
+        AsOf {
+            seq,
+            newer: order[start..]
+                .iter()
+                .filter(|p_id| p_id.1 >= 0)
+                .copied()
+                .collect(),
+            nb_by_layer: layers.iter().map(|layer| layer.len()).collect(),
+            free: self.tombstones.get_free_ids(),
+        }
+    }
+
+    /// returns true if point was in the structure, and not deleted, at sequence number of as_of
+    pub(crate) fn is_visible_as_of(&self, point: &Point<T>, as_of: &AsOf) -> bool {
+        let p_id = point.get_point_id();
+        let nb_in_layer = as_of.nb_by_layer.get(p_id.0 as usize).copied();
+        !as_of.newer.contains(&p_id)
+            && nb_in_layer.is_some_and(|nb| (p_id.1 as usize) < nb)
+            && !as_of.free.contains(&p_id)
+            && self
+                .tombstones
+                .get_deletion_seq(&p_id)
+                .is_none_or(|seq| seq > as_of.seq)
+            && !self.is_namespace_deleted(point)
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use crate::hnswio::HnswIo;
+    use crate::searchparams::SearchParams;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_points_since() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(961);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        assert_eq!(hnsw.get_insertion_seq(), 0);
+        assert!(hnsw.points_since(0).is_empty());
+        for (i, v) in data.iter().enumerate().take(500) {
+            hnsw.insert((v, i));
+        }
+        let first = hnsw.points_since(0);
+        assert!(first.iter().enumerate().all(|(i, p)| *p == (i as u64, i)));
+        // a parallel insertion gives the next numbers, in some order
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        hnsw.parallel_insert(&data_with_id[500..]);
+        assert_eq!(hnsw.get_insertion_seq(), 1000);
+        let next = hnsw.points_since(500);
+        assert!(next.iter().map(|p| p.0).eq(500..1000));
+        let mut d_ids: Vec<DataId> = next.iter().map(|p| p.1).collect();
+        d_ids.sort_unstable();
+        assert!(d_ids.into_iter().eq(500..1000));
+        assert!(hnsw.points_since(1000).is_empty());
+        // deleted points are skipped
+        hnsw.remove_batch(&[next[0].1, 3]).unwrap();
+        assert_eq!(hnsw.points_since(0).len(), 998);
+        assert_eq!(hnsw.points_since(500)[0], next[1]);
+        // numbers and deletions are kept by a dump
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "sequence").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "sequence");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert_eq!(reloaded.get_insertion_seq(), 1000);
+        assert_eq!(reloaded.points_since(500), next[1..]);
+        reloaded.insert((&data[0], 1000));
+        assert_eq!(reloaded.points_since(1000), vec![(1000, 1000)]);
+    } // end of test_points_since
+
+    #[test]
+    fn test_search_as_of() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(941);
+        let data = synthetic::uniform(600, 8, &mut rng);
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 600, 16, 100, DistL2 {});
+        hnsw.set_level_seed(941);
+        for (i, v) in data.iter().enumerate().take(400) {
+            hnsw.insert((v, i));
+        }
+        let seq = hnsw.get_insertion_seq();
+        assert_eq!(seq, 400);
+        for (i, v) in data.iter().enumerate().skip(400) {
+            hnsw.insert((v, i));
+        }
+        hnsw.remove_batch(&[0, 1, 450]).unwrap();
+        // points inserted after seq are not returned, points deleted after seq are
+        let params = SearchParams::new().as_of(seq);
+        for i in (0..400).step_by(10) {
+            let res = hnsw.search_with_params(&data[i], 10, 64, &params);
+            assert_eq!(res[0].d_id, i);
+            assert!(res.iter().all(|n| n.d_id < 400));
+        }
+        for i in (400..600).step_by(10) {
+            let res = hnsw.search_with_params(&data[i], 10, 64, &params);
+            assert!(res.len() == 10 && res.iter().all(|n| n.d_id < 400));
+        }
+        assert_ne!(hnsw.search(&data[0], 1, 64)[0].d_id, 0);
+        // as of now, deleted points are not returned
+        let params = SearchParams::new().as_of(hnsw.get_insertion_seq());
+        for i in [0, 1, 450] {
+            let res = hnsw.search_with_params(&data[i], 10, 64, &params);
+            assert!(res.iter().all(|n| n.d_id != i));
+        }
+        assert_eq!(
+            hnsw.search_with_params(&data[599], 1, 64, &params)[0].d_id,
+            599
+        );
+        assert!(
+            hnsw.search_with_params(&data[0], 10, 64, &SearchParams::new().as_of(0))
+                .is_empty()
+        );
+        // a copy keeps the numbers
+        assert_eq!(hnsw.deep_clone().get_insertion_seq(), 600);
+    } // end of test_search_as_of
+} // end of mod tests
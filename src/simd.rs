@@ -0,0 +1,427 @@
+//! Report of SIMD features used and possibility to force a SIMD level.
+//!
+//! Distances of anndists choose their SIMD path according to the cargo features (*simdeez_f*, *stdsimd*)
+//! and the cpu. [active_features] reports what was compiled and what the cpu supports, so performance
+//! differences between machines can be diagnosed.
+//!
+//! The level can be forced (for example to scalar) with [force]. It is obeyed by distances of this crate
+//! such as [DistL2Simd], distances of anndists keep their own dispatch.
+//!
+//! [l2_multi] computes distances of [MULTI_QUERIES] queries to one vector: each component of the vector is loaded
+//! once for all queries, which keep their sums in registers. Scanning vectors for a batch of queries
+//! (see [VectorStore::knn_l2_batch](crate::layout::VectorStore::knn_l2_batch())) then reads memory once by group of queries.
+//! Graph searches visit different points for each query and do not use it.
+//!
+//! [l2_tiled] computes distances of one query to a batch of vectors by blocks of components. On large dimensions
+//! a search given it by [Hnsw::set_tiled_distance](crate::hnsw::Hnsw::set_tiled_distance()) evaluates
+//! the neighbours of a point in one call, keeping each block of the query in cache across the batch.
+
+use std::fmt;
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use anndists::dist::distances::Distance;
+
+/// SIMD instruction sets a kernel can use, in increasing order on x86_64.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SimdLevel {
+    Scalar = 0,
+    Avx2 = 1,
+    Avx512 = 2,
+    Neon = 3,
+}
+
+impl SimdLevel {
+    fn from_u8(v: u8) -> Self {
+        match v {
+            1 => SimdLevel::Avx2,
+            2 => SimdLevel::Avx512,
+            3 => SimdLevel::Neon,
+            _ => SimdLevel::Scalar,
+        }
+    }
+}
+
+// u8::MAX means no override
+static FORCED: AtomicU8 = AtomicU8::new(u8::MAX);
+
+/// returns true if the cpu running the program supports level
+pub fn is_supported(level: SimdLevel) -> bool {
+    match level {
+        SimdLevel::Scalar => true,
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx2 => is_x86_feature_detected!("avx2") && is_x86_feature_detected!("fma"),
+        #[cfg(target_arch = "x86_64")]
+        SimdLevel::Avx512 => is_x86_feature_detected!("avx512f") && is_supported(SimdLevel::Avx2),
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => std::arch::is_aarch64_feature_detected!("neon"),
+        #[allow(unreachable_patterns)]
+        _ => false,
+    }
+}
+
+/// best level supported by the cpu
+pub fn detected_level() -> SimdLevel {
+    [SimdLevel::Avx512, SimdLevel::Avx2, SimdLevel::Neon]
+        .into_iter()
+        .find(|l| is_supported(*l))
+        .unwrap_or(SimdLevel::Scalar)
+}
+
+/// forces level for kernels of this crate. Fails if the cpu does not support it.
+pub fn force(level: SimdLevel) -> anyhow::Result<()> {
+    if !is_supported(level) {
+        return Err(anyhow::anyhow!(
+            "simd level {:?} not supported by cpu",
+            level
+        ));
+    }
+    FORCED.store(level as u8, Ordering::Relaxed);
+    log::info!("simd level forced to {:?}", level);
+    Ok(())
+}
+
+/// go back to the detected level
+pub fn clear_override() {
+    FORCED.store(u8::MAX, Ordering::Relaxed);
+}
+
+/// level used by kernels of this crate: the forced one if any, else the detected one.
+pub fn active_level() -> SimdLevel {
+    match FORCED.load(Ordering::Relaxed) {
+        u8::MAX => detected_level(),
+        v => SimdLevel::from_u8(v),
+    }
+}
+
+/// What was compiled and what is used.
+#[derive(Clone, Debug)]
+pub struct SimdReport {
+    /// cargo features related to simd enabled at compilation
+    pub compiled_features: Vec<&'static str>,
+    /// target architecture
+    pub arch: &'static str,
+    /// best level supported by the cpu
+    pub detected: SimdLevel,
+    /// level used by kernels of this crate
+    pub active: SimdLevel,
+    /// true if active level was forced
+    pub forced: bool,
+}
+
+impl fmt::Display for SimdReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "arch : {}, compiled features : {:?}, detected : {:?}, active : {:?}{}",
+            self.arch,
+            self.compiled_features,
+            self.detected,
+            self.active,
+            if self.forced { " (forced)" } else { "" }
+        )
+    }
+}
+
+/// returns the report of simd features
+pub fn active_features() -> SimdReport {
+    let mut compiled_features = Vec::new();
+    if cfg!(feature = "simdeez_f") {
+        compiled_features.push("simdeez_f");
+    }
+    if cfg!(feature = "stdsimd") {
+        compiled_features.push("stdsimd");
+    }
+    SimdReport {
+        compiled_features,
+        arch: std::env::consts::ARCH,
+        detected: detected_level(),
+        active: active_level(),
+        forced: FORCED.load(Ordering::Relaxed) != u8::MAX,
+    }
+}
+
+//=======================================================================================
+
+/// L2 distance on f32 following [active_level]. Avx512 level uses the avx2 kernel.
+#[derive(Default, Copy, Clone)]
+pub struct DistL2Simd;
+
+// kernels return the squared distance
+fn l2sq_scalar(va: &[f32], vb: &[f32]) -> f32 {
+    va.iter()
+        .zip(vb)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum::<f32>()
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn l2sq_avx2(va: &[f32], vb: &[f32]) -> f32 {
+    use std::arch::x86_64::*;
+    let n = va.len().min(vb.len());
+    let nb_block = n / 8;
+    let mut acc = _mm256_setzero_ps();
+    for i in 0..nb_block {
+        // SAFETY: i * 8 + 8 <= n
+        let (a, b) = unsafe {
+            (
+                _mm256_loadu_ps(va.as_ptr().add(i * 8)),
+                _mm256_loadu_ps(vb.as_ptr().add(i * 8)),
+            )
+        };
+        let diff = _mm256_sub_ps(a, b);
+        acc = _mm256_fmadd_ps(diff, diff, acc);
+    }
+    let mut lanes = [0f32; 8];
+    // SAFETY: lanes has 8 f32
+    unsafe { _mm256_storeu_ps(lanes.as_mut_ptr(), acc) };
+    let tail: f32 = va[nb_block * 8..n]
+        .iter()
+        .zip(&vb[nb_block * 8..n])
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+    lanes.iter().sum::<f32>() + tail
+}
+
+#[cfg(target_arch = "aarch64")]
+fn l2sq_neon(va: &[f32], vb: &[f32]) -> f32 {
+    use std::arch::aarch64::*;
+    let n = va.len().min(vb.len());
+    let nb_block = n / 4;
+    // SAFETY: neon is always present on aarch64 targets supported by rust, loads are in bounds
+    let sum = unsafe {
+        let mut acc = vdupq_n_f32(0.);
+        for i in 0..nb_block {
+            let diff = vsubq_f32(
+                vld1q_f32(va.as_ptr().add(i * 4)),
+                vld1q_f32(vb.as_ptr().add(i * 4)),
+            );
+            acc = vfmaq_f32(acc, diff, diff);
+        }
+        vaddvq_f32(acc)
+    };
+    let tail: f32 = va[nb_block * 4..n]
+        .iter()
+        .zip(&vb[nb_block * 4..n])
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+    sum + tail
+}
+
+fn l2sq(va: &[f32], vb: &[f32]) -> f32 {
+    match active_level() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: active_level is Avx2 or Avx512 only if cpu supports avx2 and fma (checked for Avx512 too)
+        SimdLevel::Avx2 | SimdLevel::Avx512 => unsafe { l2sq_avx2(va, vb) },
+        #[cfg(target_arch = "aarch64")]
+        SimdLevel::Neon => l2sq_neon(va, vb),
+        _ => l2sq_scalar(va, vb),
+    }
+}
+
+impl Distance<f32> for DistL2Simd {
+    fn eval(&self, va: &[f32], vb: &[f32]) -> f32 {
+        assert_eq!(va.len(), vb.len());
+        l2sq(va, vb).sqrt()
+    }
+}
+
+/// number of components summed between two checks of the bound in [l2_bounded]
+const L2_BOUNDED_CHUNK: usize = 64;
+
+/// L2 distance (as [DistL2Simd]) abandoned as soon as the partial sum exceeds bound,
+/// the partial distance (greater than bound) is then returned.
+/// To be given to [Hnsw::set_bounded_distance](crate::hnsw::Hnsw::set_bounded_distance()),
+/// it mostly helps in high dimensions.
+pub fn l2_bounded(va: &[f32], vb: &[f32], bound: f32) -> f32 {
+    assert_eq!(va.len(), vb.len());
+    let bound_sq = bound * bound;
+    let mut sum = 0.;
+    for (ca, cb) in va.chunks(L2_BOUNDED_CHUNK).zip(vb.chunks(L2_BOUNDED_CHUNK)) {
+        sum += l2sq(ca, cb);
+        if sum > bound_sq {
+            break;
+        }
+    }
+    sum.sqrt()
+}
+
+/// number of components of a block of [l2_tiled], 1 KiB of f32
+pub const TILE: usize = 256;
+
+/// dimension from which [l2_tiled] is worth it, to give to [Hnsw::set_tiled_distance](crate::hnsw::Hnsw::set_tiled_distance())
+pub const TILING_DIMENSION: usize = 1024;
+
+/// L2 distances (as [DistL2Simd]) of query to vectors, written in out. The components are summed by blocks of
+/// [TILE]: a block of the query is loaded once for all vectors before the next block.
+/// To be given to [Hnsw::set_tiled_distance](crate::hnsw::Hnsw::set_tiled_distance()).
+pub fn l2_tiled(query: &[f32], vectors: &[&[f32]], out: &mut [f32]) {
+    assert_eq!(vectors.len(), out.len());
+    assert!(vectors.iter().all(|v| v.len() == query.len()));
+    out.fill(0.);
+    for start in (0..query.len()).step_by(TILE) {
+        let end = (start + TILE).min(query.len());
+        let q_block = &query[start..end];
+        for (v, sum) in vectors.iter().zip(out.iter_mut()) {
+            *sum += l2sq(q_block, &v[start..end]);
+        }
+    }
+    out.iter_mut().for_each(|sum| *sum = sum.sqrt());
+}
+
+/// number of queries of a [l2_multi] kernel
+pub const MULTI_QUERIES: usize = 4;
+
+// lanes of the accumulators of a query in l2sq_multi
+const MULTI_LANES: usize = 8;
+
+// squared distances of queries to v, MULTI_QUERIES * MULTI_LANES accumulators fit in avx2 registers
+#[inline(always)]
+fn l2sq_multi_generic(queries: &[&[f32]; MULTI_QUERIES], v: &[f32]) -> [f32; MULTI_QUERIES] {
+    let mut acc = [[0f32; MULTI_LANES]; MULTI_QUERIES];
+    let nb_chunk = v.len() / MULTI_LANES;
+    for (c, v_chunk) in v.chunks_exact(MULTI_LANES).enumerate() {
+        for (q, acc_q) in queries.iter().zip(acc.iter_mut()) {
+            let q_chunk = &q[c * MULTI_LANES..(c + 1) * MULTI_LANES];
+            for lane in 0..MULTI_LANES {
+                let diff = q_chunk[lane] - v_chunk[lane];
+                acc_q[lane] += diff * diff;
+            }
+        }
+    }
+    let tail = nb_chunk * MULTI_LANES;
+    let mut sums = [0f32; MULTI_QUERIES];
+    for (i, q) in queries.iter().enumerate() {
+        sums[i] = acc[i].iter().sum::<f32>() + l2sq_scalar(&q[tail..], &v[tail..]);
+    }
+    sums
+}
+
+// same code, vectorized by the compiler with avx2 instructions
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,fma")]
+unsafe fn l2sq_multi_avx2(queries: &[&[f32]; MULTI_QUERIES], v: &[f32]) -> [f32; MULTI_QUERIES] {
+    l2sq_multi_generic(queries, v)
+}
+
+/// L2 distances (as [DistL2Simd]) of MULTI_QUERIES queries to v, see module doc
+pub fn l2_multi(queries: &[&[f32]; MULTI_QUERIES], v: &[f32]) -> [f32; MULTI_QUERIES] {
+    assert!(queries.iter().all(|q| q.len() == v.len()));
+    let sums = match active_level() {
+        #[cfg(target_arch = "x86_64")]
+        // SAFETY: active_level is Avx2 or Avx512 only if cpu supports avx2 and fma
+        SimdLevel::Avx2 | SimdLevel::Avx512 => unsafe { l2sq_multi_avx2(queries, v) },
+        _ => l2sq_multi_generic(queries, v),
+    };
+    sums.map(f32::sqrt)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::hnsw::{Hnsw, Neighbour};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_force_level() {
+        let va: Vec<f32> = (0..37).map(|i| i as f32 * 0.5).collect();
+        let vb: Vec<f32> = (0..37).map(|i| (i as f32).sqrt()).collect();
+        let expected = l2sq_scalar(&va, &vb).sqrt();
+        let d_detected = DistL2Simd.eval(&va, &vb);
+        assert!((d_detected - expected).abs() < 1.0e-3 * expected);
+        //
+        force(SimdLevel::Scalar).unwrap();
+        let report = active_features();
+        println!("{}", report);
+        assert!(report.forced);
+        assert_eq!(report.active, SimdLevel::Scalar);
+        assert_eq!(DistL2Simd.eval(&va, &vb), expected);
+        clear_override();
+        assert_eq!(active_level(), detected_level());
+    } // end of test_force_level
+
+    #[test]
+    fn test_l2_bounded() {
+        let va: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.1).sin()).collect();
+        let vb: Vec<f32> = (0..1000).map(|i| (i as f32 * 0.1).cos()).collect();
+        let d = DistL2Simd.eval(&va, &vb);
+        assert!((l2_bounded(&va, &vb, f32::INFINITY) - d).abs() <= 1.0e-4 * d);
+        assert!((l2_bounded(&va, &vb, 2. * d) - d).abs() <= 1.0e-4 * d);
+        let abandoned = l2_bounded(&va, &vb, d / 4.);
+        assert!(abandoned > d / 4. && abandoned < d);
+        // in a search
+        let mut rng = rand::rngs::StdRng::seed_from_u64(928);
+        let data = crate::datasets::synthetic::uniform(1000, 256, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2Simd>::new(16, data.len(), 16, 100, DistL2Simd);
+        hnsw.parallel_insert(&data_with_id);
+        let queries = crate::datasets::synthetic::uniform(50, 256, &mut rng);
+        let plain: Vec<Vec<Neighbour>> = queries.iter().map(|q| hnsw.search(q, 10, 64)).collect();
+        hnsw.set_bounded_distance(l2_bounded);
+        let mut nb_same = 0;
+        for (q, expected) in queries.iter().zip(&plain) {
+            let res = hnsw.search(q, 10, 64);
+            assert_eq!(res[0].d_id, expected[0].d_id);
+            nb_same += res
+                .iter()
+                .filter(|n| expected.iter().any(|e| e.d_id == n.d_id))
+                .count();
+        }
+        assert!(nb_same >= 490, "nb_same {}", nb_same);
+    } // end of test_l2_bounded
+
+    #[test]
+    fn test_l2_multi() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(953);
+        for dim in [3, 8, 37, 128] {
+            let data = crate::datasets::synthetic::uniform(5, dim, &mut rng);
+            let queries = [&data[0][..], &data[1][..], &data[2][..], &data[3][..]];
+            let distances = l2_multi(&queries, &data[4]);
+            for (q, d) in queries.iter().zip(distances) {
+                let expected = DistL2Simd.eval(q, &data[4]);
+                assert!((d - expected).abs() <= 1.0e-4 * expected.max(1.));
+            }
+        }
+    } // end of test_l2_multi
+
+    #[test]
+    fn test_l2_tiled() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(994);
+        let data = crate::datasets::synthetic::uniform(20, 1000, &mut rng);
+        let vectors: Vec<&[f32]> = data[1..].iter().map(|v| v.as_slice()).collect();
+        let mut out = vec![0.; vectors.len()];
+        l2_tiled(&data[0], &vectors, &mut out);
+        for (v, d) in vectors.iter().zip(&out) {
+            let expected = DistL2Simd.eval(&data[0], v);
+            assert!((d - expected).abs() <= 1.0e-4 * expected);
+        }
+        // in a search on large vectors
+        let dim = 2 * TILING_DIMENSION;
+        let data = crate::datasets::synthetic::uniform(600, dim, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2Simd>::new(16, data.len(), 16, 64, DistL2Simd);
+        hnsw.parallel_insert(&data_with_id);
+        let queries = crate::datasets::synthetic::uniform(20, dim, &mut rng);
+        let plain: Vec<Vec<Neighbour>> = queries.iter().map(|q| hnsw.search(q, 10, 32)).collect();
+        hnsw.set_tiled_distance(l2_tiled, TILING_DIMENSION);
+        let mut nb_same = 0;
+        for (q, expected) in queries.iter().zip(&plain) {
+            let res = hnsw.search(q, 10, 32);
+            assert_eq!(res[0].d_id, expected[0].d_id);
+            assert!(
+                (res[0].distance - expected[0].distance).abs() <= 1.0e-4 * expected[0].distance
+            );
+            nb_same += res
+                .iter()
+                .filter(|n| expected.iter().any(|e| e.d_id == n.d_id))
+                .count();
+        }
+        assert!(nb_same >= 195, "nb_same {}", nb_same);
+        // insertions go through the tiled distance too
+        let more = crate::datasets::synthetic::uniform(10, dim, &mut rng);
+        let more_with_id: Vec<(&Vec<f32>, usize)> = more.iter().zip(600..).collect();
+        hnsw.parallel_insert(&more_with_id);
+        assert_eq!(hnsw.search(&more[3], 1, 32)[0].d_id, 603);
+    } // end of test_l2_tiled
+} // end of mod tests
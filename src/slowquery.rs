@@ -0,0 +1,129 @@
+//! Report of slow searches.
+//!
+//! A [SlowQueryHook] set by [Hnsw::set_slow_query_hook] is called after each search taking more than a duration or
+//! more than a number of distance evaluations, with a hash of the query and the [SearchStats] of the search.
+//! Operators can so log (or keep) the queries that are pathological for an index in production.
+//!
+//! Searches by [Hnsw::search], [Hnsw::search_filter], [Hnsw::search_with_params] and [Hnsw::parallel_search]
+//! are observed. Without a hook searches do not count distances.
+
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use std::time::Duration;
+
+use anndists::dist::distances::Distance;
+
+use crate::dedup::hash_bytes;
+use crate::hnsw::Hnsw;
+
+/// statistics of a search given to a [SlowQueryHook]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SearchStats {
+    pub duration: Duration,
+    /// distances between the query and points computed
+    pub nb_distances: usize,
+    pub knbn: usize,
+    /// ef asked
+    pub ef: usize,
+    /// number of neighbours returned
+    pub nb_returned: usize,
+}
+
+type SlowQueryCallback = Arc<dyn Fn(u64, &SearchStats) + Send + Sync>;
+
+/// thresholds of slow searches and the callback receiving (query hash, stats). See module doc.
+#[derive(Clone)]
+pub struct SlowQueryHook {
+    max_duration: Option<Duration>,
+    max_distances: Option<usize>,
+    callback: SlowQueryCallback,
+}
+
+impl SlowQueryHook {
+    /// a hook with no threshold (no search reported) calling callback
+    pub fn new<F>(callback: F) -> Self
+    where
+        F: Fn(u64, &SearchStats) + Send + Sync + 'static,
+    {
+        SlowQueryHook {
+            max_duration: None,
+            max_distances: None,
+            callback: Arc::new(callback),
+        }
+    }
+
+    /// reports searches lasting more than duration
+    pub fn max_duration(mut self, duration: Duration) -> Self {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    /// reports searches computing more than nb distances
+    pub fn max_distances(mut self, nb: usize) -> Self {
+        self.max_distances = Some(nb);
+        self
+    }
+
+    /// calls the callback if stats pass a threshold
+    pub(crate) fn observe<T>(&self, query: &[T], stats: &SearchStats) {
+        let slow = self.max_duration.is_some_and(|d| stats.duration > d)
+            || self.max_distances.is_some_and(|nb| stats.nb_distances > nb);
+        if slow {
+            (self.callback)(hash_bytes(query) as u64, stats);
+        }
+    }
+} // end of impl SlowQueryHook
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// searches passing a threshold of hook are reported to its callback, see module doc
+    pub fn set_slow_query_hook(&mut self, hook: SlowQueryHook) {
+        self.slow_queries = Some(hook);
+    }
+
+    pub fn clear_slow_query_hook(&mut self) {
+        self.slow_queries = None;
+    }
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use parking_lot::Mutex;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_slow_query_hook() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(972);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        let reported = Arc::new(Mutex::new(Vec::<(u64, SearchStats)>::new()));
+        let sink = Arc::clone(&reported);
+        let hook = SlowQueryHook::new(move |hash, stats| sink.lock().push((hash, *stats)));
+        hnsw.set_slow_query_hook(hook.max_distances(200));
+        // small searches are not reported, large ones are, with the same hash for the same query
+        hnsw.search(&data[0], 1, 10);
+        assert!(reported.lock().is_empty());
+        let res = hnsw.search(&data[0], 10, 200);
+        hnsw.search(&data[0], 10, 200);
+        hnsw.search(&data[1], 10, 200);
+        let reported = reported.lock();
+        assert_eq!(reported.len(), 3);
+        let stats = reported[0].1;
+        assert!(stats.nb_distances > 200);
+        assert_eq!(
+            (stats.knbn, stats.ef, stats.nb_returned),
+            (10, 200, res.len())
+        );
+        assert_eq!(reported[0].0, reported[1].0);
+        assert_ne!(reported[0].0, reported[2].0);
+    } // end of test_slow_query_hook
+} // end of mod tests
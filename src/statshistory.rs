@@ -0,0 +1,262 @@
+//! Time series of index statistics.
+//!
+//! With [Hnsw::set_stats_history] the structure records the duration of its searches and keeps the last samples
+//! of its statistics (size, deleted fraction, layer 0 degrees and their drift since the first sample, search latency
+//! percentiles) in a ring buffer read by [Hnsw::stats_history]. A sample is taken by [Hnsw::sample_stats],
+//! or periodically by the background thread of [Hnsw::start_stats_sampler], so a self hosted deployment can feed
+//! a dashboard without external instrumentation.
+//!
+//! Latencies are counted in buckets of powers of 2 microseconds, without lock. A percentile is the upper bound
+//! of its bucket, so it is exact within a factor 2. Searches observed are the ones of [crate::slowquery].
+//! A sample computes degrees by a pass over layer 0.
+
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use anndists::dist::distances::Distance;
+
+use crate::adaptive::DegreeStats;
+use crate::hnsw::Hnsw;
+
+// bucket i counts durations in [2^i, 2^(i+1)) microseconds, the first one also shorter ones
+const NB_LATENCY_BUCKET: usize = 32;
+
+/// statistics of a structure at a time, see module doc
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatsSample {
+    pub time: SystemTime,
+    pub nb_point: usize,
+    pub nb_deleted: usize,
+    pub deleted_fraction: f64,
+    pub degrees: DegreeStats,
+    /// mean degree of layer 0 minus the one of the first sample recorded
+    pub degree_drift: f32,
+    /// searches since the previous sample
+    pub nb_search: u64,
+    /// latency percentiles of these searches, zero without search
+    pub search_p50: Duration,
+    pub search_p90: Duration,
+    pub search_p99: Duration,
+    /// expected number of points above the top layer, see [crate::layergrowth]
+    pub layer_overflow: f64,
+}
+
+/// ring buffer of samples and latencies of searches since the last sample
+pub(crate) struct StatsHistory {
+    capacity: usize,
+    samples: Mutex<VecDeque<StatsSample>>,
+    first_mean_degree: Mutex<Option<f32>>,
+    latencies: [AtomicU64; NB_LATENCY_BUCKET],
+}
+
+impl StatsHistory {
+    fn new(capacity: usize) -> Self {
+        StatsHistory {
+            capacity,
+            samples: Mutex::new(VecDeque::with_capacity(capacity)),
+            first_mean_degree: Mutex::new(None),
+            latencies: std::array::from_fn(|_| AtomicU64::new(0)),
+        }
+    }
+
+    pub(crate) fn record_search(&self, duration: Duration) {
+        let micros = duration.as_micros().max(1);
+        let bucket = (micros.ilog2() as usize).min(NB_LATENCY_BUCKET - 1);
+        self.latencies[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    // number of searches and percentiles of latencies counted since the last call
+    fn take_latencies(&self) -> (u64, [Duration; 3]) {
+        let counts: Vec<u64> = self
+            .latencies
+            .iter()
+            .map(|c| c.swap(0, Ordering::Relaxed))
+            .collect();
+        let nb_search: u64 = counts.iter().sum();
+        let percentile = |q: f64| {
+            if nb_search == 0 {
+                return Duration::ZERO;
+            }
+            let rank = (q * nb_search as f64).ceil().max(1.) as u64;
+            let mut cumul = 0;
+            let bucket = counts
+                .iter()
+                .position(|c| {
+                    cumul += c;
+                    cumul >= rank
+                })
+                .unwrap_or(NB_LATENCY_BUCKET - 1);
+            Duration::from_micros(1 << (bucket + 1))
+        };
+        (
+            nb_search,
+            [percentile(0.5), percentile(0.9), percentile(0.99)],
+        )
+    }
+
+    fn push(&self, sample: StatsSample) {
+        let mut samples = self.samples.lock();
+        if samples.len() == self.capacity {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+} // end of impl StatsHistory
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// records search latencies and keeps the last capacity samples of statistics. Samples recorded before are lost.
+    pub fn set_stats_history(&mut self, capacity: usize) {
+        assert!(capacity > 0, "stats history needs a capacity");
+        self.stats_history = Some(Arc::new(StatsHistory::new(capacity)));
+    }
+
+    /// takes a sample of statistics, adds it to the history and returns it. None if no history is set.
+    pub fn sample_stats(&self) -> Option<StatsSample> {
+        let history = self.stats_history.as_ref()?;
+        let degrees = self.get_degree_stats();
+        let first_mean_degree = *history
+            .first_mean_degree
+            .lock()
+            .get_or_insert(degrees.mean_degree);
+        let (nb_search, [search_p50, search_p90, search_p99]) = history.take_latencies();
+        let sample = StatsSample {
+            time: SystemTime::now(),
+            nb_point: self.get_nb_point(),
+            nb_deleted: self.get_nb_deleted(),
+            deleted_fraction: self.get_deleted_fraction(),
+            degrees,
+            degree_drift: degrees.mean_degree - first_mean_degree,
+            nb_search,
+            search_p50,
+            search_p90,
+            search_p99,
+            layer_overflow: self.get_layer_overflow(),
+        };
+        history.push(sample);
+        Some(sample)
+    }
+
+    /// samples recorded, oldest first. Empty if no history is set.
+    pub fn stats_history(&self) -> Vec<StatsSample> {
+        self.stats_history
+            .as_ref()
+            .map(|history| history.samples.lock().iter().copied().collect())
+            .unwrap_or_default()
+    }
+} // end of impl Hnsw
+
+impl<T, D> Hnsw<'static, T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Send + Sync + 'static,
+{
+    /// takes a sample now and then every period on a background thread, until the returned sampler is dropped
+    /// or stopped. The history must be set (see [Hnsw::set_stats_history]), nothing is sampled otherwise.
+    pub fn start_stats_sampler(self: &Arc<Self>, period: Duration) -> StatsSampler {
+        let hnsw = Arc::clone(self);
+        let (stop, stopped) = std::sync::mpsc::channel::<()>();
+        let handle = std::thread::spawn(move || {
+            loop {
+                hnsw.sample_stats();
+                match stopped.recv_timeout(period) {
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    _ => break,
+                }
+            }
+        });
+        StatsSampler {
+            stop: Some(stop),
+            handle: Some(handle),
+        }
+    }
+} // end of impl Hnsw
+
+/// background sampling of statistics, it ends when the sampler is dropped
+pub struct StatsSampler {
+    stop: Option<Sender<()>>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl StatsSampler {
+    /// stops sampling and waits for the thread
+    pub fn stop(mut self) {
+        self.end();
+    }
+
+    fn end(&mut self) {
+        // the thread ends when the channel is closed
+        self.stop.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+} // end of impl StatsSampler
+
+impl Drop for StatsSampler {
+    fn drop(&mut self) {
+        self.end();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_stats_history() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(979);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, 1000, 16, 100, DistL2 {});
+        assert!(hnsw.sample_stats().is_none());
+        hnsw.set_stats_history(3);
+        hnsw.parallel_insert(&data_with_id[..500]);
+        let first = hnsw.sample_stats().unwrap();
+        assert_eq!((first.nb_point, first.nb_search), (500, 0));
+        assert_eq!(first.degree_drift, 0.);
+        assert_eq!(first.search_p99, Duration::ZERO);
+        // searches between 2 samples
+        for v in data.iter().take(100) {
+            hnsw.search(v, 10, 64);
+        }
+        hnsw.parallel_insert(&data_with_id[500..]);
+        hnsw.remove_batch(&(0..100).collect::<Vec<_>>()).unwrap();
+        let second = hnsw.sample_stats().unwrap();
+        assert_eq!(second.nb_search, 100);
+        assert!(second.search_p50 > Duration::ZERO);
+        assert!(second.search_p50 <= second.search_p90 && second.search_p90 <= second.search_p99);
+        assert_eq!((second.nb_point, second.nb_deleted), (1000, 100));
+        assert_eq!(second.deleted_fraction, 0.1);
+        assert_eq!(
+            second.degree_drift,
+            second.degrees.mean_degree - first.degrees.mean_degree
+        );
+        // the ring buffer keeps the last 3 samples
+        hnsw.sample_stats();
+        hnsw.sample_stats();
+        let history = hnsw.stats_history();
+        assert_eq!(history.len(), 3);
+        assert_eq!(history[0], second);
+        assert!(serde_json::to_string(&history).is_ok());
+        // background sampling takes a sample at start
+        let hnsw = Arc::new(hnsw);
+        let sampler = hnsw.start_stats_sampler(Duration::from_secs(60));
+        sampler.stop();
+        assert_eq!(hnsw.stats_history().len(), 3);
+        assert_ne!(hnsw.stats_history()[0], second);
+    } // end of test_stats_history
+} // end of mod tests
@@ -0,0 +1,242 @@
+//! Small graphs given point by point and edge by edge, to test code built on this crate.
+//!
+//! A Hnsw built by insertions depends on the random levels of its points and on the order of insertions in
+//! parallel, so its search results can only be checked against a brute force search. A [GraphFixture] sets the
+//! level of each point, the neighbour lists of each layer and the entry point, and builds the Hnsw without any
+//! insertion: a search goes exactly through the given edges and its results are known in advance (a point no
+//! edge leads to is never found). Dependent crates get the module with the feature `testing`.
+//!
+//! Edges are directed, [GraphFixture::link] adds both directions. Point ids of the Hnsw are given by the order
+//! of declaration of points in their layer. The built structure can then be used as any Hnsw (searches with
+//! parameters, dumps, insertions...).
+
+use anyhow::{Result, anyhow};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, NB_LAYER_MAX, Point, PointId, PointWithOrder};
+
+/// points and edges of a Hnsw to build, see module doc
+pub struct GraphFixture<T, D> {
+    dist: D,
+    max_nb_connection: usize,
+    ef_construction: usize,
+    // origin id, vector, level
+    points: Vec<(DataId, Vec<T>, u8)>,
+    // from, to, layer
+    edges: Vec<(DataId, DataId, u8)>,
+    entry: Option<DataId>,
+}
+
+impl<T, D> GraphFixture<T, D>
+where
+    T: Clone + Send + Sync + 'static,
+    D: Distance<T> + Send + Sync,
+{
+    /// a fixture without points, max_nb_connection 16 and ef_construction 100 (used by later insertions)
+    pub fn new(dist: D) -> Self {
+        GraphFixture {
+            dist,
+            max_nb_connection: 16,
+            ef_construction: 100,
+            points: Vec::new(),
+            edges: Vec::new(),
+            entry: None,
+        }
+    }
+
+    pub fn max_nb_connection(mut self, max_nb_connection: usize) -> Self {
+        self.max_nb_connection = max_nb_connection;
+        self
+    }
+
+    pub fn ef_construction(mut self, ef_construction: usize) -> Self {
+        self.ef_construction = ef_construction;
+        self
+    }
+
+    /// adds a point present in layers 0 to level
+    pub fn point(mut self, origin_id: DataId, v: Vec<T>, level: u8) -> Self {
+        self.points.push((origin_id, v, level));
+        self
+    }
+
+    /// adds to the list of from at layer the point to
+    pub fn edge(mut self, from: DataId, to: DataId, layer: u8) -> Self {
+        self.edges.push((from, to, layer));
+        self
+    }
+
+    /// adds edges in both directions between a and b at layer
+    pub fn link(self, a: DataId, b: DataId, layer: u8) -> Self {
+        self.edge(a, b, layer).edge(b, a, layer)
+    }
+
+    /// sets the entry point of searches, it must have the highest level.
+    /// The default is the first point declared with the highest level.
+    pub fn entry_point(mut self, origin_id: DataId) -> Self {
+        self.entry = Some(origin_id);
+        self
+    }
+
+    /// builds the Hnsw, returns an error on a point declared twice, vectors of different (or null) dimensions,
+    /// a level above the maximum, an edge with an unknown point, a point to itself, a layer above the level of
+    /// its points or given twice, or an entry point without the highest level.
+    pub fn build(self) -> Result<Hnsw<'static, T, D>> {
+        let nb_point = self.points.len();
+        let hnsw = Hnsw::try_new(
+            self.max_nb_connection,
+            nb_point,
+            NB_LAYER_MAX as usize,
+            self.ef_construction,
+            self.dist,
+        )?;
+        let dim = self.points.first().map_or(0, |(_, v, _)| v.len());
+        let mut layers: Vec<Vec<Arc<Point<'static, T>>>> = vec![Vec::new(); NB_LAYER_MAX as usize];
+        let mut by_id: HashMap<DataId, Arc<Point<'static, T>>> = HashMap::with_capacity(nb_point);
+        for (origin_id, v, level) in self.points {
+            if v.is_empty() || v.len() != dim {
+                return Err(anyhow!(
+                    "point {} has dimension {}, first point has dimension {}",
+                    origin_id,
+                    v.len(),
+                    dim
+                ));
+            }
+            if level >= NB_LAYER_MAX {
+                return Err(anyhow!(
+                    "point {} has level {}, maximum is {}",
+                    origin_id,
+                    level,
+                    NB_LAYER_MAX - 1
+                ));
+            }
+            let layer = &mut layers[level as usize];
+            let p_id = PointId(level, layer.len() as i32);
+            let point = Arc::new(Point::new(v, origin_id, p_id));
+            if by_id.insert(origin_id, Arc::clone(&point)).is_some() {
+                return Err(anyhow!("point {} declared twice", origin_id));
+            }
+            layer.push(point);
+        }
+        for (from, to, layer) in self.edges {
+            let (Some(from_point), Some(to_point)) = (by_id.get(&from), by_id.get(&to)) else {
+                return Err(anyhow!("edge {} -> {} has an unknown point", from, to));
+            };
+            if from == to {
+                return Err(anyhow!("edge from point {} to itself", from));
+            }
+            if layer > from_point.get_point_id().0 || layer > to_point.get_point_id().0 {
+                return Err(anyhow!(
+                    "edge {} -> {} at layer {} above the level of a point",
+                    from,
+                    to,
+                    layer
+                ));
+            }
+            let mut neighbours = from_point.neighbours.write();
+            let list = &mut neighbours[layer as usize];
+            if list.iter().any(|n| Arc::ptr_eq(&n.point_ref, to_point)) {
+                return Err(anyhow!(
+                    "edge {} -> {} at layer {} given twice",
+                    from,
+                    to,
+                    layer
+                ));
+            }
+            let dist = hnsw
+                .get_distance()
+                .eval(from_point.get_v(), to_point.get_v());
+            list.push(Arc::new(PointWithOrder::new(to_point, dist)));
+        }
+        for point in by_id.values() {
+            for list in point.neighbours.write().iter_mut() {
+                list.sort_unstable();
+            }
+        }
+        let top = layers.iter().rposition(|layer| !layer.is_empty());
+        let entry = match (self.entry, top) {
+            (Some(origin_id), Some(top)) => {
+                let point = by_id
+                    .get(&origin_id)
+                    .ok_or_else(|| anyhow!("entry point {} is unknown", origin_id))?;
+                if point.get_point_id().0 as usize != top {
+                    return Err(anyhow!(
+                        "entry point {} has level {}, highest level is {}",
+                        origin_id,
+                        point.get_point_id().0,
+                        top
+                    ));
+                }
+                Some(Arc::clone(point))
+            }
+            (Some(origin_id), None) => return Err(anyhow!("entry point {} is unknown", origin_id)),
+            (None, top) => top.map(|top| Arc::clone(&layers[top][0])),
+        };
+        let mut hnsw = hnsw;
+        hnsw.data_dimension = dim;
+        {
+            let indexation = &hnsw.layer_indexed_points;
+            *indexation.entry_point.write() = entry;
+            *indexation.points_by_layer.write() = layers;
+            *indexation.nb_point.write() = nb_point;
+            indexation.reset_insertion_order();
+            indexation.reset_ids();
+        }
+        Ok(hnsw)
+    } // end of build
+} // end of impl GraphFixture
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use anndists::dist::DistL1;
+
+    // points 0..8 on a line, 0 and 4 also in layer 1, 9 linked to nothing
+    fn line() -> GraphFixture<f32, DistL1> {
+        let mut fixture = GraphFixture::new(DistL1 {});
+        for i in 0..10 {
+            let level = if i % 4 == 0 && i < 8 { 1 } else { 0 };
+            fixture = fixture.point(i, vec![i as f32], level);
+        }
+        for i in 0..8 {
+            fixture = fixture.link(i, i + 1, 0);
+        }
+        fixture.link(0, 4, 1)
+    }
+
+    #[test]
+    fn test_graph_fixture() {
+        let hnsw = line().build().unwrap();
+        assert_eq!(hnsw.get_nb_point(), 10);
+        assert_eq!(hnsw.get_max_level_observed(), 1);
+        hnsw.check_links().unwrap();
+        // layer 1 leads to 4, then the line to the neighbours of the query
+        let res = hnsw.search(&[5.2], 3, 16);
+        let ids: Vec<DataId> = res.iter().map(|n| n.d_id).collect();
+        assert_eq!(ids, vec![5, 6, 4]);
+        assert!((res[0].distance - 0.2).abs() < 1.0e-6);
+        // 9 is the nearest point but no edge leads to it
+        let res = hnsw.search(&[9.], 3, 16);
+        assert_eq!(res[0].d_id, 8);
+        assert!(res.iter().all(|n| n.d_id != 9));
+        // the entry point can be any point of the highest level
+        let res = line().entry_point(4).build().unwrap().search(&[3.], 1, 4);
+        assert_eq!(res[0].d_id, 3);
+        // the structure takes insertions
+        hnsw.insert((&[9.5], 10));
+        assert_eq!(hnsw.get_nb_point(), 11);
+        // errors
+        assert!(line().point(3, vec![3.], 0).build().is_err());
+        assert!(line().point(11, vec![1., 2.], 0).build().is_err());
+        assert!(line().edge(1, 12, 0).build().is_err());
+        assert!(line().edge(1, 1, 0).build().is_err());
+        assert!(line().edge(1, 2, 0).build().is_err());
+        assert!(line().edge(1, 2, 1).build().is_err());
+        assert!(line().entry_point(1).build().is_err());
+        assert!(line().point(11, vec![1.], NB_LAYER_MAX).build().is_err());
+    } // end of test_graph_fixture
+} // end of mod tests
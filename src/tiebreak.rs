@@ -0,0 +1,172 @@
+//! Order of search results at equal distances.
+//!
+//! Results are sorted by increasing distance, equal distances (duplicated vectors, integer or binary distances)
+//! by data id (see [Neighbour::cmp_by_distance]). [Hnsw::set_tie_break] sets another order for them: by insertion
+//! sequence (the first inserted point first, see [crate::sequence]) or by an attribute of the payload. Ties are
+//! ordered before results are truncated to knbn, so the policy also decides which of tied points are returned,
+//! in [Hnsw::search], [Hnsw::search_with_params], [Hnsw::parallel_search] and the pages of [Hnsw::search_page].
+//!
+//! Results of several structures are merged by (distance, [Hnsw::tie_keys], data id), as done by
+//! [SegmentRouter](crate::segments::SegmentRouter), so merged lists follow the policy of the structures (they must
+//! share it). The order by insertion costs a pass over the insertion sequence for the searches having ties.
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{Hnsw, Neighbour, PointId};
+
+/// order of results at equal distances, see module doc
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum TieBreak {
+    /// increasing data id
+    #[default]
+    ById,
+    /// increasing insertion sequence number
+    ByInsertion,
+    /// increasing value of the attribute of this rank, points without it last
+    ByAttribute(usize),
+}
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    pub fn set_tie_break(&mut self, tie_break: TieBreak) {
+        self.tie_break = tie_break;
+    }
+
+    pub fn get_tie_break(&self) -> TieBreak {
+        self.tie_break
+    }
+
+    /// keys ordering neighbours (results of this structure) at equal distance before their data id, see module doc
+    pub fn tie_keys(&self, neighbours: &[Neighbour]) -> Vec<f64> {
+        let p_ids: Vec<PointId> = neighbours.iter().map(|n| n.p_id).collect();
+        self.tie_keys_of(&p_ids)
+    }
+
+    fn tie_keys_of(&self, p_ids: &[PointId]) -> Vec<f64> {
+        let indexation = &self.layer_indexed_points;
+        match self.tie_break {
+            TieBreak::ById => vec![0.; p_ids.len()],
+            TieBreak::ByInsertion => {
+                let mut seqs: HashMap<PointId, f64> =
+                    p_ids.iter().map(|p_id| (*p_id, f64::INFINITY)).collect();
+                for (seq, p_id) in indexation.insertion_order.read().iter().enumerate() {
+                    if let Some(s) = seqs.get_mut(p_id) {
+                        *s = seq as f64;
+                    }
+                }
+                p_ids.iter().map(|p_id| seqs[p_id]).collect()
+            }
+            TieBreak::ByAttribute(attr) => {
+                let payloads = indexation.payloads.read();
+                p_ids
+                    .iter()
+                    .map(|p_id| {
+                        let value = payloads.get(*p_id).get_attribute(attr);
+                        if value.is_nan() { f64::INFINITY } else { value }
+                    })
+                    .collect()
+            }
+        }
+    } // end of tie_keys_of
+
+    /// reorders runs of equal distances of items, sorted by distance then data id, by the tie break policy
+    pub(crate) fn order_ties<I>(
+        &self,
+        items: &mut [I],
+        dist_of: impl Fn(&I) -> f32,
+        p_id_of: impl Fn(&I) -> PointId,
+    ) {
+        if self.tie_break == TieBreak::ById {
+            return;
+        }
+        // ranges of runs of equal distances
+        let mut runs = Vec::new();
+        let mut start = 0;
+        for i in 1..=items.len() {
+            if i == items.len() || dist_of(&items[i]) != dist_of(&items[start]) {
+                if i - start > 1 {
+                    runs.push(start..i);
+                }
+                start = i;
+            }
+        }
+        if runs.is_empty() {
+            return;
+        }
+        let p_ids: Vec<PointId> = runs
+            .iter()
+            .flat_map(|run| items[run.clone()].iter().map(&p_id_of))
+            .collect();
+        let keys: HashMap<PointId, f64> = p_ids
+            .iter()
+            .copied()
+            .zip(self.tie_keys_of(&p_ids))
+            .collect();
+        // a stable sort keeps the order by data id of equal keys
+        for run in runs {
+            items[run].sort_by(|a, b| keys[&p_id_of(a)].total_cmp(&keys[&p_id_of(b)]));
+        }
+    } // end of order_ties
+} // end of impl Hnsw
+
+/// order of merged results, by distance, key of [Hnsw::tie_keys] and data id
+pub fn cmp_with_keys(a: &(Neighbour, f64), b: &(Neighbour, f64)) -> Ordering {
+    a.0.distance
+        .total_cmp(&b.0.distance)
+        .then(a.1.total_cmp(&b.1))
+        .then(a.0.d_id.cmp(&b.0.d_id))
+}
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::payload::Payload;
+    use crate::segments::SegmentRouter;
+    use anndists::dist::DistL1;
+
+    #[test]
+    fn test_tie_break() {
+        // 20 copies of the same vector inserted by decreasing id, and a far point
+        let mut hnsw = Hnsw::<f32, DistL1>::new(16, 100, 16, 100, DistL1 {});
+        // copies of a point can be cut from the lowest layer, all are reachable with these levels
+        hnsw.set_level_seed(991);
+        for id in (0..20).rev() {
+            let payload = Payload::new().with_attribute(0, ((id * 7) % 20) as f64);
+            hnsw.insert_with_payload((&[1., 1.], id), payload);
+        }
+        hnsw.insert((&[10., 10.], 100));
+        let ids = |res: &[Neighbour]| res.iter().map(|n| n.d_id).collect::<Vec<_>>();
+        // by id, the default
+        let res = hnsw.search(&[1., 1.], 5, 32);
+        assert_eq!(ids(&res), vec![0, 1, 2, 3, 4]);
+        // by insertion, ties decide which points are returned
+        hnsw.set_tie_break(TieBreak::ByInsertion);
+        let res = hnsw.search(&[1., 1.], 5, 32);
+        assert_eq!(ids(&res), vec![19, 18, 17, 16, 15]);
+        let batch = hnsw.parallel_search(&[vec![1., 1.], vec![1., 1.]], 5, 32);
+        assert!(batch.iter().all(|r| ids(r) == ids(&res)));
+        let (page, token) = hnsw.search_page(&[1., 1.], 5, 32, None);
+        assert_eq!(ids(&page), ids(&res));
+        let (page, _) = hnsw.search_page(&[1., 1.], 5, 32, Some(token));
+        assert_eq!(ids(&page), vec![14, 13, 12, 11, 10]);
+        // by attribute
+        hnsw.set_tie_break(TieBreak::ByAttribute(0));
+        let res = hnsw.search(&[1., 1.], 3, 32);
+        assert_eq!(ids(&res), vec![0, 3, 6]);
+        assert_eq!(hnsw.tie_keys(&res), vec![0., 1., 2.]);
+        // results merged from segments follow the policy
+        let mut other = Hnsw::<f32, DistL1>::new(16, 100, 16, 100, DistL1 {});
+        other.insert_with_payload((&[1., 1.], 50), Payload::new().with_attribute(0, 0.5));
+        other.set_tie_break(TieBreak::ByAttribute(0));
+        let router = SegmentRouter::new(&[&hnsw, &other]);
+        let (res, _) = router.search(&[1., 1.], 3, 32);
+        assert_eq!(ids(&res), vec![0, 50, 3]);
+    } // end of test_tie_break
+} // end of mod tests
@@ -0,0 +1,599 @@
+//! Deletion of points by tombstones.
+//!
+//! [Hnsw::remove_batch] and [Hnsw::remove_where] mark points as deleted in one pass over the structure.
+//! Deleted points are never returned by searches nor chosen as neighbours of new points, but they stay in the graph
+//! and are still traversed so that the graph stays navigable.
+//!
+//! Repair is deferred: [Hnsw::repair] replaces, in neighbour lists of remaining points, deleted points by the
+//! remaining points they lead to, following chains of deleted points. When the fraction of deleted points gets high, a rebuild (see [crate::rebuild])
+//! compacts the structure, [Hnsw::compact_if] schedules it.
+//!
+//! Once repaired, no list of a remaining point leads to a deleted point: its slot is freed and the next insertion
+//! drawn in its layer takes it, so a structure with a steady flow of deletions and insertions does not grow
+//! until a compaction. A reused slot keeps its point id, the new point gets a new insertion sequence number
+//! (see [crate::sequence]). The entry point is not freed while it is the entry point.
+//!
+//! Each deleted point keeps the insertion sequence number at its deletion, for searches as of a sequence number
+//! (see [crate::sequence]). The set of deleted points, their sequence numbers and whether they are repaired,
+//! is saved in dumps.
+//!
+//! A point whose insertion panics (for example a distance panicking on a NaN) is deleted and unlinked at once,
+//! the panic then goes on to the caller and the structure stays usable by other threads.
+
+use parking_lot::{Mutex, RwLock};
+use std::collections::hash_map::Entry;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use anyhow::anyhow;
+use rayon::prelude::*;
+
+use anndists::dist::distances::Distance;
+
+use crate::hnsw::{DataId, Hnsw, HnswError, Point, PointId, PointWithOrder};
+use crate::payload::Payload;
+
+/// points deleted in a Hnsw
+#[derive(Default)]
+pub(crate) struct Tombstones {
+    /// deleted points and the insertion sequence number at their deletion
+    deleted: RwLock<HashMap<PointId, u64>>,
+    /// size of deleted, to avoid the lock when nothing is deleted
+    nb_deleted: AtomicUsize,
+    /// deleted points that can still be in neighbour lists
+    to_repair: RwLock<HashSet<PointId>>,
+    /// slots of repaired points, (rank, insertion sequence number) by layer
+    free: Mutex<HashMap<u8, Vec<(i32, usize)>>>,
+}
+
+// in dumps a deleted point is two u64: the point, rank in the low 32 bits, layer in the next 8,
+// REPAIRED_BIT set if repaired, then the sequence number of its deletion
+const REPAIRED_BIT: u64 = 1 << 40;
+
+impl Clone for Tombstones {
+    fn clone(&self) -> Self {
+        // one lock at a time, take_free holds free while it locks deleted
+        let deleted = self.deleted.read().clone();
+        let to_repair = self.to_repair.read().clone();
+        let free = self.free.lock().clone();
+        Tombstones {
+            nb_deleted: AtomicUsize::new(deleted.len()),
+            deleted: RwLock::new(deleted),
+            to_repair: RwLock::new(to_repair),
+            free: Mutex::new(free),
+        }
+    }
+}
+
+impl Tombstones {
+    #[inline]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.nb_deleted.load(Ordering::Acquire) == 0
+    }
+
+    pub(crate) fn contains(&self, p_id: &PointId) -> bool {
+        !self.is_empty() && self.deleted.read().contains_key(p_id)
+    }
+
+    /// insertion sequence number at the deletion of p_id, None if it is not deleted
+    pub(crate) fn get_deletion_seq(&self, p_id: &PointId) -> Option<u64> {
+        if self.is_empty() {
+            return None;
+        }
+        self.deleted.read().get(p_id).copied()
+    }
+
+    /// points whose slot is free
+    pub(crate) fn get_free_ids(&self) -> HashSet<PointId> {
+        let free = self.free.lock();
+        free.iter()
+            .flat_map(|(layer, slots)| slots.iter().map(|slot| PointId(*layer, slot.0)))
+            .collect()
+    }
+
+    // deletes p_ids at insertion sequence number seq
+    fn insert(&self, p_ids: Vec<PointId>, seq: u64) -> usize {
+        let mut deleted = self.deleted.write();
+        let mut to_repair = self.to_repair.write();
+        let mut nb_new = 0;
+        for p_id in p_ids {
+            if let Entry::Vacant(entry) = deleted.entry(p_id) {
+                entry.insert(seq);
+                to_repair.insert(p_id);
+                nb_new += 1;
+            }
+        }
+        self.nb_deleted.store(deleted.len(), Ordering::Release);
+        nb_new
+    }
+
+    // takes a free slot of layer, the point of the slot is not deleted anymore
+    fn take_free(&self, layer: u8) -> Option<(i32, usize)> {
+        if self.is_empty() {
+            return None;
+        }
+        let slot = self.free.lock().get_mut(&layer)?.pop()?;
+        let mut deleted = self.deleted.write();
+        deleted.remove(&PointId(layer, slot.0));
+        self.nb_deleted.store(deleted.len(), Ordering::Release);
+        Some(slot)
+    }
+
+    /// number of free slots
+    fn get_nb_free(&self) -> usize {
+        self.free.lock().values().map(|slots| slots.len()).sum()
+    }
+
+    /// deleted points for an extension block of a dump, sorted
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let to_repair = self.to_repair.read();
+        let mut deleted: Vec<(u64, u64)> = self
+            .deleted
+            .read()
+            .iter()
+            .map(|(p_id, seq)| {
+                let repaired = if to_repair.contains(p_id) {
+                    0
+                } else {
+                    REPAIRED_BIT
+                };
+                (
+                    repaired | ((p_id.0 as u64) << 32) | (p_id.1 as u32 as u64),
+                    *seq,
+                )
+            })
+            .collect();
+        deleted.sort_unstable();
+        deleted
+            .iter()
+            .flat_map(|(x, seq)| x.to_ne_bytes().into_iter().chain(seq.to_ne_bytes()))
+            .collect()
+    }
+
+    /// reads a block of to_bytes, loaded gives the point id of a loaded point from its dumped point id.
+    /// Returns the deleted points repaired, their slots must be freed by [Hnsw::free_slots].
+    pub(crate) fn from_bytes(
+        bytes: &[u8],
+        loaded: &HashMap<PointId, PointId>,
+    ) -> anyhow::Result<(Tombstones, Vec<PointId>)> {
+        if !bytes.len().is_multiple_of(16) {
+            return Err(anyhow!(
+                "deleted points block of dump has bad length {}",
+                bytes.len()
+            ));
+        }
+        let mut deleted = HashMap::new();
+        let mut to_repair = HashSet::new();
+        let mut repaired = Vec::new();
+        for b in bytes.chunks_exact(16) {
+            let x = u64::from_ne_bytes(b[..8].try_into().unwrap());
+            let seq = u64::from_ne_bytes(b[8..].try_into().unwrap());
+            let dumped = PointId((x >> 32) as u8, x as u32 as i32);
+            let Some(p_id) = loaded.get(&dumped) else {
+                continue;
+            };
+            deleted.insert(*p_id, seq);
+            if x & REPAIRED_BIT != 0 {
+                repaired.push(*p_id);
+            } else {
+                to_repair.insert(*p_id);
+            }
+        }
+        let tombstones = Tombstones {
+            nb_deleted: AtomicUsize::new(deleted.len()),
+            deleted: RwLock::new(deleted),
+            to_repair: RwLock::new(to_repair),
+            free: Mutex::new(HashMap::new()),
+        };
+        Ok((tombstones, repaired))
+    }
+} // end of impl Tombstones
+
+impl<T, D> Hnsw<'_, T, D>
+where
+    T: Clone + Send + Sync,
+    D: Distance<T> + Send + Sync,
+{
+    /// deletes points with an id in ids and returns the number of points deleted,
+    /// [HnswError::ReadOnly] if the index is read only
+    pub fn remove_batch(&self, ids: &[DataId]) -> Result<usize, HnswError> {
+        let ids: HashSet<DataId> = ids.iter().copied().collect();
+        self.remove_where(|origin_id, _| ids.contains(&origin_id))
+    }
+
+    /// deletes points for which pred(origin_id, payload) is true and returns the number of points deleted,
+    /// [HnswError::ReadOnly] if the index is read only
+    pub fn remove_where<F>(&self, pred: F) -> Result<usize, HnswError>
+    where
+        F: Fn(DataId, &Payload) -> bool + Sync,
+    {
+        if self.read_only {
+            return Err(HnswError::ReadOnly);
+        }
+        let _write = self.writes.enter();
+        // points already deleted are skipped, a live point can have the same id and vector
+        let points: Vec<Arc<Point<T>>> = {
+            let layers = self.layer_indexed_points.points_by_layer.read();
+            let payloads = self.layer_indexed_points.payloads.read();
+            layers
+                .par_iter()
+                .flat_map_iter(|layer| layer.iter())
+                .filter(|p| !self.tombstones.contains(&p.get_point_id()))
+                .filter(|p| pred(p.get_origin_id(), &payloads.get(p.get_point_id())))
+                .cloned()
+                .collect()
+        };
+        let (p_ids, d_ids): (Vec<PointId>, Vec<DataId>) = points
+            .iter()
+            .map(|p| (p.get_point_id(), p.get_origin_id()))
+            .unzip();
+        self.forget_vectors(&points);
+        let nb_deleted = self.tombstones.insert(p_ids, self.get_insertion_seq());
+        self.layer_indexed_points.ids.remove_all(&d_ids);
+        self.rebuild_log.record_removal(&d_ids);
+        log::info!(
+            "remove_where deleted {} points, total deleted : {}",
+            nb_deleted,
+            self.get_nb_deleted()
+        );
+        Ok(nb_deleted)
+    }
+
+    /// number of deleted points still in the structure
+    pub fn get_nb_deleted(&self) -> usize {
+        self.tombstones.nb_deleted.load(Ordering::Acquire)
+    }
+
+    /// number of slots of deleted points that next insertions can take, see module doc
+    pub fn get_nb_free_slots(&self) -> usize {
+        self.tombstones.get_nb_free()
+    }
+
+    pub(crate) fn take_free_slot(&self, layer: u8) -> Option<(i32, usize)> {
+        self.tombstones.take_free(layer)
+    }
+
+    /// frees the slots of deleted points reached by no list of a remaining point.
+    /// The entry point stays to be repaired, it is freed by a repair once it is not the entry point anymore.
+    pub(crate) fn free_slots(&self, p_ids: Vec<PointId>) {
+        if p_ids.is_empty() {
+            return;
+        }
+        let entry_point = self
+            .layer_indexed_points
+            .entry_point
+            .read()
+            .as_ref()
+            .map(|p| p.get_point_id());
+        let mut to_free: HashSet<PointId> = p_ids.into_iter().collect();
+        if let Some(entry_point) = entry_point.filter(|p_id| to_free.remove(p_id)) {
+            self.tombstones.to_repair.write().insert(entry_point);
+        }
+        // one pass on sequence numbers for all slots
+        let order = self.layer_indexed_points.insertion_order.read();
+        let mut free = self.tombstones.free.lock();
+        for (seq, p_id) in order.iter().enumerate() {
+            if to_free.contains(p_id) {
+                free.entry(p_id.0).or_default().push((p_id.1, seq));
+            }
+        }
+    }
+
+    /// fraction of points of the structure that are deleted
+    pub fn get_deleted_fraction(&self) -> f64 {
+        self.get_nb_deleted() as f64 / self.get_nb_point().max(1) as f64
+    }
+
+    /// returns true if some points or namespaces are deleted
+    #[inline]
+    pub(crate) fn has_deleted(&self) -> bool {
+        !self.tombstones.is_empty() || self.namespaces.has_deleted()
+    }
+
+    /// returns true if point must not be returned by searches
+    pub(crate) fn is_deleted(&self, point: &Point<T>) -> bool {
+        self.tombstones.contains(&point.get_point_id()) || self.is_namespace_deleted(point)
+    }
+
+    /// replaces deleted points, in neighbour lists of remaining points, by the remaining points they lead to,
+    /// directly or through other deleted points (the best ones, up to the size of lists).
+    /// Returns the number of lists modified.
+    /// It is deferred so that a batch of deletions costs one pass over the structure.
+    /// Returns [HnswError::ReadOnly] if the index is read only.
+    pub fn repair(&self) -> Result<usize, HnswError> {
+        if self.read_only {
+            return Err(HnswError::ReadOnly);
+        }
+        let to_repair: HashSet<PointId> = std::mem::take(&mut *self.tombstones.to_repair.write());
+        if to_repair.is_empty() {
+            return Ok(0);
+        }
+        let nb_repaired = AtomicUsize::new(0);
+        // searches start at the entry point, even deleted it must not lead to a freed slot
+        let entry_point = self
+            .layer_indexed_points
+            .entry_point
+            .read()
+            .as_ref()
+            .map(|p| p.get_point_id());
+        let layers = self.layer_indexed_points.points_by_layer.read();
+        layers
+            .par_iter()
+            .flat_map_iter(|layer| layer.iter())
+            .filter(|p| {
+                !self.tombstones.contains(&p.get_point_id())
+                    || entry_point == Some(p.get_point_id())
+            })
+            .for_each(|point| {
+                let mut neighbours = point.neighbours.write();
+                for (l, neighbours_l) in neighbours.iter_mut().enumerate() {
+                    if !neighbours_l
+                        .iter()
+                        .any(|n| to_repair.contains(&n.point_ref.get_point_id()))
+                    {
+                        continue;
+                    }
+                    let max_nb = self.get_max_nb_neighbours(point.get_point_id(), l);
+                    self.relink(point, neighbours_l, l, max_nb);
+                    self.dirty.mark(point.get_point_id());
+                    nb_repaired.fetch_add(1, Ordering::Relaxed);
+                }
+            });
+        drop(layers);
+        self.free_slots(to_repair.into_iter().collect());
+        let nb_repaired = nb_repaired.into_inner();
+        log::info!(
+            "repair modified {} neighbour lists, free slots : {}",
+            nb_repaired,
+            self.get_nb_free_slots()
+        );
+        Ok(nb_repaired)
+    } // end of repair
+
+    /// deletes a point whose insertion panicked and unlinks it from the lists of the points it was linked to.
+    /// No distance is computed, the vector of the point can be the cause of the panic.
+    pub(crate) fn discard_point<'b>(&self, point: &Arc<Point<'b, T>>) {
+        let p_id = point.get_point_id();
+        log::error!(
+            "insertion of {} panicked, point {:?} is deleted",
+            point.get_origin_id(),
+            p_id
+        );
+        // deleted before any sequence number, a search as of a sequence number does not return it either
+        self.tombstones.insert(vec![p_id], 0);
+        self.tombstones.to_repair.write().remove(&p_id);
+        self.layer_indexed_points
+            .ids
+            .remove_all(&[point.get_origin_id()]);
+        self.forget_vectors(std::slice::from_ref(point));
+        // reverse links only go to neighbours of the point
+        let neighbours: Vec<Arc<Point<'b, T>>> = {
+            let mut neighbours = point.neighbours.write();
+            neighbours
+                .iter_mut()
+                .flat_map(|neighbours_l| neighbours_l.drain(..))
+                .map(|n| Arc::clone(&n.point_ref))
+                .collect()
+        };
+        for q in neighbours.iter().filter(|q| q.get_point_id() != p_id) {
+            for neighbours_l in q.neighbours.write().iter_mut() {
+                neighbours_l.retain(|n| n.point_ref.get_point_id() != p_id);
+            }
+            self.dirty.mark(q.get_point_id());
+        }
+        self.free_slots(vec![p_id]);
+    } // end of discard_point
+
+    // replaces deleted points in neighbours of point at layer l by the remaining points they lead to,
+    // through chains of deleted points (at most max_nb deleted points are gone through)
+    fn relink<'b>(
+        &self,
+        point: &Arc<Point<'b, T>>,
+        neighbours: &mut Vec<Arc<PointWithOrder<'b, T>>>,
+        l: usize,
+        max_nb: usize,
+    ) {
+        let mut kept: Vec<Arc<PointWithOrder<'b, T>>> = Vec::with_capacity(max_nb);
+        let mut candidates: Vec<Arc<Point<'b, T>>> = Vec::new();
+        let mut deleted: Vec<Arc<Point<'b, T>>> = Vec::new();
+        for n in neighbours.iter() {
+            if self.tombstones.contains(&n.point_ref.get_point_id()) {
+                deleted.push(Arc::clone(&n.point_ref));
+            } else {
+                kept.push(Arc::clone(n));
+            }
+        }
+        let mut seen: HashSet<PointId> = deleted.iter().map(|d| d.get_point_id()).collect();
+        let mut nb_gone_through = 0;
+        while let Some(d) = deleted.pop() {
+            nb_gone_through += 1;
+            // a deleted point is not the point being repaired, its lock is free
+            if let Some(neighbours_d) = d.neighbours.read().get(l) {
+                for nn in neighbours_d {
+                    let nn_id = nn.point_ref.get_point_id();
+                    if nn_id == point.get_point_id() || !seen.insert(nn_id) {
+                        continue;
+                    }
+                    if !self.tombstones.contains(&nn_id) {
+                        candidates.push(Arc::clone(&nn.point_ref));
+                    } else if nb_gone_through + deleted.len() < max_nb {
+                        deleted.push(Arc::clone(&nn.point_ref));
+                    }
+                }
+            }
+        }
+        for c in candidates {
+            let c_id = c.get_point_id();
+            if c_id == point.get_point_id()
+                || self.tombstones.contains(&c_id)
+                || kept.iter().any(|k| k.point_ref.get_point_id() == c_id)
+            {
+                continue;
+            }
+            let dist = self.get_distance().eval(point.get_v(), c.get_v());
+            kept.push(Arc::new(PointWithOrder::new(&c, dist)));
+        }
+        kept.sort_unstable();
+        kept.truncate(max_nb);
+        *neighbours = kept;
+    } // end of relink
+} // end of impl Hnsw
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::api::AnnT;
+    use crate::datasets::synthetic;
+    use crate::hnswio::HnswIo;
+    use crate::searchparams::SearchParams;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_remove_and_repair() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(4321);
+        let data = synthetic::uniform(2000, 8, &mut rng);
+        // a serial build with seeded levels, so that the check of reachability below is exact
+        let mut hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 200, DistL2 {});
+        hnsw.set_level_seed(4321);
+        for (i, v) in data.iter().enumerate() {
+            hnsw.insert((v, i));
+        }
+        //
+        let removed: Vec<DataId> = (0..2000).filter(|i| i % 4 == 0).collect();
+        assert_eq!(hnsw.remove_batch(&removed), Ok(500));
+        assert_eq!(hnsw.remove_where(|id, _| id < 8), Ok(6));
+        assert_eq!(hnsw.get_nb_deleted(), 506);
+        let check = |hnsw: &Hnsw<f32, DistL2>| {
+            for i in (1..2000).step_by(97) {
+                let res = hnsw.search(&data[i], 10, 64);
+                assert_eq!(res.len(), 10);
+                assert!(res.iter().all(|n| n.d_id % 4 != 0 && n.d_id >= 8));
+                if i >= 8 && i % 4 != 0 {
+                    assert_eq!(res[0].d_id, i);
+                }
+            }
+        };
+        check(&hnsw);
+        assert!(hnsw.repair().unwrap() > 0);
+        // no list of a remaining point refers to a deleted point
+        for p in hnsw.get_point_indexation() {
+            if p.get_origin_id() % 4 != 0 && p.get_origin_id() >= 8 {
+                for neighbours in p.get_neighborhood_id() {
+                    assert!(neighbours.iter().all(|n| n.d_id % 4 != 0 && n.d_id >= 8));
+                }
+            }
+        }
+        check(&hnsw);
+        assert_eq!(hnsw.repair(), Ok(0));
+    } // end of test_remove_and_repair
+
+    #[test]
+    fn test_deleted_dump_and_slot_reuse() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(978);
+        let data = synthetic::uniform(1200, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, 1200, 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id[..1000]);
+        let removed: Vec<DataId> = (0..1000).filter(|i| i % 5 == 0).collect();
+        // as of the last insertion, before the removal
+        let before_removal = SearchParams::new().as_of(hnsw.get_insertion_seq() - 1);
+        assert_eq!(hnsw.remove_batch(&removed), Ok(200));
+        assert_eq!(hnsw.get_nb_free_slots(), 0);
+        // deleted points are kept by a dump, still to be repaired
+        let directory = tempfile::tempdir().unwrap();
+        hnsw.file_dump(directory.path(), "deleted").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "deleted");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert_eq!(reloaded.get_nb_deleted(), 200);
+        assert!(
+            reloaded
+                .search(&data[5], 10, 64)
+                .iter()
+                .all(|n| n.d_id % 5 != 0)
+        );
+        // with the sequence number of their deletion
+        assert!(
+            reloaded
+                .search_with_params(&data[5], 10, 64, &before_removal)
+                .iter()
+                .any(|n| n.d_id % 5 == 0)
+        );
+        assert!(reloaded.repair().unwrap() > 0);
+        // repaired points free their slot, except the entry point
+        assert!(hnsw.repair().unwrap() > 0);
+        let nb_free = hnsw.get_nb_free_slots();
+        assert!(nb_free >= 199, "free slots {}", nb_free);
+        // and so are free slots
+        hnsw.file_dump(directory.path(), "repaired").unwrap();
+        let mut reloader = HnswIo::new(directory.path(), "repaired");
+        let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+        assert_eq!(reloaded.get_nb_free_slots(), nb_free);
+        // new points take free slots of their layer
+        let seq = hnsw.get_insertion_seq();
+        for (v, id) in &data_with_id[1000..] {
+            hnsw.insert((v.as_slice(), *id));
+        }
+        let nb_reused = nb_free - hnsw.get_nb_free_slots();
+        assert!(nb_reused > 150, "reused slots {}", nb_reused);
+        assert_eq!(hnsw.get_nb_point(), 1200 - nb_reused);
+        assert_eq!(hnsw.get_nb_deleted(), 200 - nb_reused);
+        hnsw.check_links().unwrap();
+        // 67 queries, as in test_remove_and_repair a few can be missed
+        let nb_found = (1000..1200)
+            .step_by(3)
+            .filter(|i| hnsw.search(&data[*i], 1, 64)[0].d_id == *i)
+            .count();
+        assert!(nb_found >= 60, "nb_found {}", nb_found);
+        // new points have their own sequence numbers, the ones of reused slots are skipped
+        let since: Vec<DataId> = hnsw.points_since(seq).iter().map(|(_, id)| *id).collect();
+        assert_eq!(since, (1000..1200).collect::<Vec<_>>());
+        assert_eq!(hnsw.points_since(0).len(), 1000);
+    } // end of test_deleted_dump_and_slot_reuse
+
+    // a distance refusing non finite vectors, as a custom distance of a server could
+    struct CheckedL2;
+
+    impl Distance<f32> for CheckedL2 {
+        fn eval(&self, va: &[f32], vb: &[f32]) -> f32 {
+            assert!(
+                va.iter().chain(vb).all(|x| x.is_finite()),
+                "non finite vector"
+            );
+            DistL2 {}.eval(va, vb)
+        }
+    }
+
+    #[test]
+    fn test_panicking_insertion() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(936);
+        let data = synthetic::uniform(1000, 8, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, CheckedL2>::new(16, 1001, 16, 100, CheckedL2);
+        hnsw.parallel_insert(&data_with_id[..500]);
+        //
+        let bad = vec![f32::NAN; 8];
+        let res =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hnsw.insert((&bad, 9999))));
+        assert!(res.is_err());
+        assert_eq!(hnsw.get_nb_deleted(), 1);
+        // the structure is still usable and the point is reached by no one
+        hnsw.parallel_insert(&data_with_id[500..]);
+        // the slot of the point is taken by a new point
+        assert_eq!(hnsw.get_nb_point(), 1000);
+        for i in (0..1000).step_by(5) {
+            let res = hnsw.search(&data[i], 10, 64);
+            assert_eq!(res.len(), 10);
+            assert!(res.iter().all(|n| n.d_id != 9999));
+        }
+        let indexation = hnsw.get_point_indexation();
+        assert!(indexation.par_iter().all(|p| {
+            p.get_neighborhood_id()
+                .iter()
+                .flatten()
+                .all(|n| n.d_id != 9999)
+        }));
+    } // end of test_panicking_insertion
+} // end of mod tests
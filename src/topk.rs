@@ -0,0 +1,160 @@
+//! Management of the ef best points of a search in a layer.
+//!
+//! The points returned by a search in a layer were kept in a BinaryHeap. As in hnswlib, for the usual values of ef
+//! a vector kept sorted by increasing distance is faster: the farthest point is the last one, an insertion
+//! is a binary search and a short memmove of pointers, and no sort is needed at the end.
+//! For very large ef the O(log ef) insertions of the heap win, [ResultBuffer] chooses between both.
+
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use crate::hnsw::PointWithOrder;
+
+/// ef up to which [ResultBuffer::Auto] uses a sorted buffer
+pub const SORTED_BUFFER_MAX_EF: usize = 256;
+
+/// structure holding the best points of a search. See [SearchParams::result_buffer](crate::searchparams::SearchParams::result_buffer())
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ResultBuffer {
+    /// sorted buffer up to ef = SORTED_BUFFER_MAX_EF, heap above
+    #[default]
+    Auto,
+    /// a vector sorted by distance
+    Sorted,
+    /// a binary heap
+    Heap,
+}
+
+impl ResultBuffer {
+    // the buffer used for ef points
+    fn resolve(self, ef: usize) -> ResultBuffer {
+        match self {
+            ResultBuffer::Auto if ef <= SORTED_BUFFER_MAX_EF => ResultBuffer::Sorted,
+            ResultBuffer::Auto => ResultBuffer::Heap,
+            other => other,
+        }
+    }
+}
+
+/// the best points of a search, with positive distances
+pub(crate) enum TopK<'b, T: Clone + Send + Sync> {
+    /// sorted by increasing distance
+    Sorted(Vec<Arc<PointWithOrder<'b, T>>>),
+    Heap(BinaryHeap<Arc<PointWithOrder<'b, T>>>),
+}
+
+impl<'b, T: Clone + Send + Sync> TopK<'b, T> {
+    /// a buffer for ef points, storage is an empty vector whose allocation is reused
+    pub(crate) fn new(
+        buffer: ResultBuffer,
+        ef: usize,
+        mut storage: Vec<Arc<PointWithOrder<'b, T>>>,
+    ) -> Self {
+        // one more point is pushed before the farthest is popped
+        storage.reserve(ef + 1);
+        match buffer.resolve(ef) {
+            ResultBuffer::Heap => TopK::Heap(BinaryHeap::from(storage)),
+            _ => TopK::Sorted(storage),
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        match self {
+            TopK::Sorted(v) => v.len(),
+            TopK::Heap(h) => h.len(),
+        }
+    }
+
+    /// the farthest point
+    #[inline]
+    pub(crate) fn peek(&self) -> Option<&Arc<PointWithOrder<'b, T>>> {
+        match self {
+            TopK::Sorted(v) => v.last(),
+            TopK::Heap(h) => h.peek(),
+        }
+    }
+
+    #[inline]
+    pub(crate) fn push(&mut self, point: Arc<PointWithOrder<'b, T>>) {
+        match self {
+            TopK::Sorted(v) => {
+                let pos = v.partition_point(|p| p.dist_to_ref <= point.dist_to_ref);
+                v.insert(pos, point);
+            }
+            TopK::Heap(h) => h.push(point),
+        }
+    }
+
+    /// removes the farthest point
+    #[inline]
+    pub(crate) fn pop(&mut self) -> Option<Arc<PointWithOrder<'b, T>>> {
+        match self {
+            TopK::Sorted(v) => v.pop(),
+            TopK::Heap(h) => h.pop(),
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        match self {
+            TopK::Sorted(v) => v.clear(),
+            TopK::Heap(h) => h.clear(),
+        }
+    }
+
+    pub(crate) fn into_heap(self) -> BinaryHeap<Arc<PointWithOrder<'b, T>>> {
+        match self {
+            TopK::Sorted(v) => BinaryHeap::from(v),
+            TopK::Heap(h) => h,
+        }
+    }
+
+    /// points sorted by increasing distance
+    pub(crate) fn into_sorted_vec(self) -> Vec<Arc<PointWithOrder<'b, T>>> {
+        match self {
+            TopK::Sorted(v) => v,
+            TopK::Heap(h) => h.into_sorted_vec(),
+        }
+    }
+} // end of impl TopK
+
+#[cfg(test)]
+mod tests {
+
+    use super::*;
+    use crate::datasets::synthetic;
+    use crate::hnsw::Hnsw;
+    use crate::searchparams::SearchParams;
+    use anndists::dist::DistL2;
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_sorted_and_heap_buffers() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(929);
+        let data = synthetic::uniform(2000, 10, &mut rng);
+        let data_with_id: Vec<(&Vec<f32>, usize)> = data.iter().zip(0..).collect();
+        let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 100, DistL2 {});
+        hnsw.parallel_insert(&data_with_id);
+        //
+        let sorted = SearchParams::new().result_buffer(ResultBuffer::Sorted);
+        let heap = SearchParams::new().result_buffer(ResultBuffer::Heap);
+        let queries = synthetic::uniform(50, 10, &mut rng);
+        for q in &queries {
+            for (knbn, ef) in [(10, 48), (300, 400)] {
+                let res_sorted = hnsw.search_with_params(q, knbn, ef, &sorted);
+                let res_heap = hnsw.search_with_params(q, knbn, ef, &heap);
+                let res_auto = hnsw.search(q, knbn, ef);
+                assert_eq!(res_sorted.len(), knbn);
+                assert!(
+                    res_sorted
+                        .windows(2)
+                        .all(|w| w[0].distance <= w[1].distance)
+                );
+                // traversals are the same, only ties can be ordered differently
+                for ((s, h), a) in res_sorted.iter().zip(&res_heap).zip(&res_auto) {
+                    assert_eq!(s.distance, h.distance);
+                    assert_eq!(s.distance, a.distance);
+                }
+            }
+        }
+    } // end of test_sorted_and_heap_buffers
+} // end of mod tests
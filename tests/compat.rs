@@ -0,0 +1,124 @@
+//! Compatibility of dumps across versions.
+//!
+//! tests/data contains small dumps written by previous versions (compat_v4 by 0.3.2, compat_v5 to compat_v7 by 0.3.3)
+//! with the neighbours they answered (.expected files). Current code must reload them and answer the same.
+//!
+//! When the dump format changes, generate a new fixture with:
+//!     cargo test --test compat -- --ignored
+//! and add the corresponding test.
+
+use std::fs;
+use std::path::PathBuf;
+
+use anndists::dist::*;
+use hnsw_rs::prelude::*;
+
+const NB_POINT: usize = 300;
+const DIM: usize = 8;
+const NB_QUERY: usize = 10;
+const KNBN: usize = 5;
+const EF_SEARCH: usize = 32;
+
+fn data_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/data")
+}
+
+// deterministic data, so fixtures do not depend on a random generator
+fn compat_data() -> Vec<Vec<f32>> {
+    (0..NB_POINT)
+        .map(|i| {
+            (0..DIM)
+                .map(|j| ((i * (j + 3)) as f32 * 0.37 + j as f32).sin())
+                .collect()
+        })
+        .collect()
+}
+
+fn compat_queries() -> Vec<Vec<f32>> {
+    (0..NB_QUERY)
+        .map(|q| {
+            (0..DIM)
+                .map(|j| ((q * 11 + j) as f32 * 0.53).cos())
+                .collect()
+        })
+        .collect()
+}
+
+fn search_all(hnsw: &Hnsw<f32, DistL2>) -> Vec<Vec<usize>> {
+    compat_queries()
+        .iter()
+        .map(|q| {
+            hnsw.search(q, KNBN, EF_SEARCH)
+                .iter()
+                .map(|n| n.d_id)
+                .collect()
+        })
+        .collect()
+}
+
+fn check_fixture(basename: &str) {
+    let mut reloader = HnswIo::new(&data_dir(), basename);
+    let hnsw: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+    assert_eq!(hnsw.get_nb_point(), NB_POINT);
+    // data are reloaded unchanged
+    let data = compat_data();
+    for p in hnsw.get_point_indexation() {
+        assert_eq!(p.get_v(), data[p.get_origin_id()].as_slice());
+    }
+    // same answers as the version that wrote the dump
+    let expected = fs::read_to_string(data_dir().join(format!("{}.expected", basename))).unwrap();
+    let expected: Vec<Vec<usize>> = expected
+        .lines()
+        .map(|l| l.split_whitespace().map(|id| id.parse().unwrap()).collect())
+        .collect();
+    assert_eq!(search_all(&hnsw), expected);
+}
+
+#[test]
+fn compat_v4() {
+    check_fixture("compat_v4");
+}
+
+#[test]
+fn compat_v5() {
+    check_fixture("compat_v5");
+}
+
+#[test]
+fn compat_v6() {
+    check_fixture("compat_v6");
+}
+
+#[test]
+fn compat_v7() {
+    check_fixture("compat_v7");
+}
+
+// writes a fixture with the current format version
+#[test]
+#[ignore]
+fn generate_fixture() {
+    let basename = "compat_v7";
+    let hnsw = Hnsw::<f32, DistL2>::new(12, NB_POINT, 16, 100, DistL2 {});
+    for (i, v) in compat_data().iter().enumerate() {
+        hnsw.insert((v, i));
+    }
+    hnsw.file_dump(&data_dir(), basename).unwrap();
+    // expected answers are those of the reloaded graph
+    let mut reloader = HnswIo::new(&data_dir(), basename);
+    let reloaded: Hnsw<f32, DistL2> = reloader.load_hnsw().unwrap();
+    let lines: Vec<String> = search_all(&reloaded)
+        .iter()
+        .map(|ids| {
+            ids.iter()
+                .map(|id| id.to_string())
+                .collect::<Vec<_>>()
+                .join(" ")
+        })
+        .collect();
+    fs::write(
+        data_dir().join(format!("{}.expected", basename)),
+        lines.join("\n") + "\n",
+    )
+    .unwrap();
+}
@@ -0,0 +1,81 @@
+//! Searches are deterministic: identical queries on an identical index return identical ordered results,
+//! equal distances being ordered by data id. Evaluation pipelines rely on it to be reproducible.
+
+use anndists::dist::*;
+use hnsw_rs::datasets::synthetic;
+use hnsw_rs::prelude::*;
+use rand::SeedableRng;
+
+const NB_POINT: usize = 3000;
+const DIM: usize = 12;
+const NB_DUPLICATE: usize = 6;
+const KNBN: usize = 10;
+const EF_SEARCH: usize = 48;
+
+// results with distances as bits, so that comparisons are exact
+fn answers(res: &[Neighbour]) -> Vec<(usize, u32)> {
+    res.iter().map(|n| (n.d_id, n.distance.to_bits())).collect()
+}
+
+// copies of data 0 found are first and sorted by id
+fn check_ties(res: &[Neighbour]) {
+    let ids: Vec<usize> = res
+        .iter()
+        .take_while(|n| n.distance == 0.)
+        .map(|n| n.d_id)
+        .collect();
+    assert!(ids.len() >= 2, "{:?}", res);
+    assert!(ids.windows(2).all(|w| w[0] < w[1]), "{:?}", ids);
+    assert!(ids.iter().all(|&id| id == 0 || id >= NB_POINT));
+}
+
+#[test]
+fn test_search_determinism() {
+    let mut rng = rand::rngs::StdRng::seed_from_u64(958);
+    let mut data = synthetic::uniform(NB_POINT, DIM, &mut rng);
+    // copies of one vector, inserted last : all at distance 0 of it
+    let duplicated = data[0].clone();
+    data.extend((0..NB_DUPLICATE).map(|_| duplicated.clone()));
+    let data_with_id: Vec<(&Vec<f32>, usize)> =
+        data.iter().enumerate().map(|(i, v)| (v, i)).rev().collect();
+    let hnsw = Hnsw::<f32, DistL2>::new(16, data.len(), 16, 200, DistL2 {});
+    hnsw.parallel_insert(&data_with_id);
+    let queries = synthetic::uniform(200, DIM, &mut rng);
+    //
+    let reference: Vec<Vec<(usize, u32)>> = queries
+        .iter()
+        .map(|q| answers(&hnsw.search(q, KNBN, EF_SEARCH)))
+        .collect();
+    // again, in parallel, on a copy and on a reload
+    for (q, expected) in queries.iter().zip(&reference) {
+        assert_eq!(&answers(&hnsw.search(q, KNBN, EF_SEARCH)), expected);
+    }
+    let parallel = hnsw.parallel_search(&queries, KNBN, EF_SEARCH);
+    assert!(
+        parallel
+            .iter()
+            .zip(&reference)
+            .all(|(r, e)| answers(r) == *e)
+    );
+    let copy = hnsw.deep_clone();
+    let mut stream = Vec::new();
+    hnsw.dump_to(&mut stream).unwrap();
+    let reloaded = Hnsw::<f32, DistL2>::load_from(&mut stream.as_slice()).unwrap();
+    for (q, expected) in queries.iter().zip(&reference) {
+        assert_eq!(&answers(&copy.search(q, KNBN, EF_SEARCH)), expected);
+        assert_eq!(&answers(&reloaded.search(q, KNBN, EF_SEARCH)), expected);
+    }
+    // equal distances are ordered by data id, whatever the order of insertion.
+    // Copies prune each other at insertion, a search does not always reach all of them.
+    let res = hnsw.search(&duplicated, NB_DUPLICATE + 1, 4 * EF_SEARCH);
+    check_ties(&res);
+    // the same for the read only searcher
+    let searcher = hnsw.into_searcher();
+    let frozen: Vec<Vec<(usize, u32)>> = queries
+        .iter()
+        .map(|q| answers(&searcher.search(q, KNBN, EF_SEARCH)))
+        .collect();
+    let again = searcher.parallel_search(&queries, KNBN, EF_SEARCH);
+    assert!(again.iter().zip(&frozen).all(|(r, e)| answers(r) == *e));
+    check_ties(&searcher.search(&duplicated, NB_DUPLICATE + 1, 4 * EF_SEARCH));
+} // end of test_search_determinism